@@ -1,9 +1,11 @@
 //! A very basic HTTP server:
-//! When it receives any request on port 25898, it will read the full request, ignore what's inside and then:
+//! When it receives any request on port 25898, it will read the full request and then:
 //! - Kill the ADB server
 //! - Give a basic response saying that it has killed the ADB server.
 //! - Wait a second
 //! - Restart the ADB server.
+//! - If the request has a `connect` query parameter, e.g. `GET /?connect=192.168.1.5:5555`, run `adb connect` with its value
+//!   so that a headset being debugged over Wi-Fi is reconnected.
 //!
 //! This is used by the MBF site (in development mode only) to avoid a developer having to manually kill the ADB server whenever they want to use MBF.
 
@@ -28,7 +30,12 @@ fn main() -> Result<()> {
         println!("Got connection from {addr:?}");
         println!("Reading request");
 
-        // Read all lines of the request until CRLFCRLF to mark its end
+        // The first line of the request is the request line, which contains the path and query
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let connect_target = get_connect_target(&request_line);
+
+        // Read the remaining lines of the request until CRLFCRLF to mark its end
         let mut line_buf = String::new();
         while reader.read_line(&mut line_buf)? > 2 {
             line_buf.clear();
@@ -51,5 +58,37 @@ fn main() -> Result<()> {
         // Restart the ADB server so that the developer doesn't have to do this manually later, which takes a few seconds
         println!("Restarting ADB server");
         Command::new("adb").arg("start-server").status()?;
+
+        if let Some(target) = connect_target {
+            println!("Connecting to {target}");
+            Command::new("adb").arg("connect").arg(target).status()?;
+        }
+    }
+}
+
+/// Gets the device to `adb connect` to from the request line of an HTTP request, e.g. `GET /?connect=192.168.1.5:5555 HTTP/1.1`.
+/// Returns None if no `connect` query parameter is given, or if its value is not a valid address.
+fn get_connect_target(request_line: &str) -> Option<String> {
+    let target = request_line.split_whitespace().nth(1)?;
+    let (_, query) = target.split_once('?')?;
+
+    let address = query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| *key == "connect")
+        .map(|(_, value)| value.replace("%3A", ":").replace("%3a", ":"))?;
+
+    // Only allow characters found in a hostname, IP address or port so that the value can't be interpreted as another ADB option.
+    let is_valid = !address.is_empty()
+        && !address.starts_with('-')
+        && address
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-' | '[' | ']'));
+
+    if is_valid {
+        Some(address)
+    } else {
+        println!("Ignoring invalid connect address {address:?}");
+        None
     }
 }
@@ -1,19 +1,42 @@
 //! Module that allows downloading of files in a reasonably flexible and reliable way
 //! Features:
-//! - Resuming downloads if they fail partway through
-//! - Downloading to a Vec or to a file.
+//! - Resuming downloads if they fail partway through, even across restarts of the agent.
+//! - Downloading to a file or any other stream.
 //! - Multiple download attempts.
 //! - Progress reporting to the MBF logger
+//! - Downloading files over several connections at once, if the server supports ranges.
+//! - Verifying the SHA-256 hash of downloaded files.
+//! - Limiting the download speed.
+//! - Falling back to mirrors if the server refuses the request or cannot be reached.
+//! - Accepting gzip or zstd compressed responses when downloading whole files, to reduce bandwidth.
+//! - Cancelling downloads while they are in progress.
 
 use anyhow::{anyhow, Context, Result};
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
+use mbf_res_man::mirrors::{self, Mirrors};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
+    collections::VecDeque,
     fs::OpenOptions,
-    io::{self, Cursor, Read, Seek, Write},
-    path::Path,
-    time::Instant,
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+use crate::models::response::ErrorCode;
+
+// Files smaller than this are always downloaded over a single connection, as the overhead of
+// making several requests would outweigh any benefit.
+const MIN_PARALLEL_DOWNLOAD_SIZE: usize = 4 * 1024 * 1024;
+
+// The number of times a file is downloaded again if its hash doesn't match the expected hash.
+const HASH_MISMATCH_RETRIES: u32 = 2;
+
 /// Various configuration settings for the file downloader.
 pub struct DownloadConfig<'a> {
     /// The number of times the connection can fail before the downloader will give up.
@@ -26,85 +49,277 @@ pub struct DownloadConfig<'a> {
     pub disconnect_wait_time: std::time::Duration,
     /// The amount of time between download progress updates. Set to None to disable.
     pub progress_update_interval: Option<std::time::Duration>,
-    /// Specifies the ureq agent used to carry out the downloads
+    /// If this is greater than 1, files downloaded with [download_file_with_attempts] are split into this many
+    /// ranges which are downloaded concurrently, provided the server supports ranges.
+    /// Each range is retried independently, with its own count of disconnections.
+    pub parallel_connections: usize,
+    /// If specified, limits the total download speed of all downloads using this config.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Mirrors to try, in order, if a file cannot be downloaded from its original URL because the server
+    /// refused the request (403/429) or could not be reached after `max_disconnections` attempts.
+    /// The count of disconnections starts again from 0 for each mirror.
+    pub mirrors: Mirrors,
+    /// The maximum number of redirects followed for each request before the download fails.
+    pub max_redirects: u32,
+    /// Specifies the ureq agent used to carry out the downloads.
+    /// Redirects are followed by the downloader, so that each one is logged, so this agent must not follow redirects
+    /// itself (i.e. must be built with `redirects(0)`).
     pub ureq_agent: &'a ureq::Agent,
+    /// If specified, downloads stop with an error (with [ErrorCode::Cancelled]) once this is set to true.
+    /// This is checked between each read from the response body, so downloads stop promptly.
+    /// Partial downloads are kept, so a cancelled download can be resumed later.
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl DownloadConfig<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// Limits the rate at which data is downloaded, by sleeping between reads from the response body.
+/// The rate is measured over a short sliding window, so that downloads are paced smoothly rather than in bursts.
+/// A single limiter is shared between every connection using it, so concurrent downloads are limited in total.
+pub struct RateLimiter {
+    max_bytes_per_sec: u64,
+    // The number of bytes read at each point in time within the sliding window, and the total number of bytes read within it.
+    recent_reads: Mutex<(VecDeque<(Instant, usize)>, usize)>,
+}
+
+impl RateLimiter {
+    // The period of time over which the download rate is measured.
+    const WINDOW: Duration = Duration::from_millis(500);
+
+    pub fn new(max_bytes_per_sec: u64) -> Self {
+        Self {
+            max_bytes_per_sec: max_bytes_per_sec.max(1),
+            recent_reads: Mutex::new((VecDeque::new(), 0)),
+        }
+    }
+
+    /// Records that `bytes` have just been read, sleeping if this puts the download rate above the limit.
+    fn record_read(&self, bytes: usize) {
+        let sleep_time = {
+            let mut guard = self.recent_reads.lock().unwrap();
+            let (reads, total) = &mut *guard;
+
+            let now = Instant::now();
+            reads.push_back((now, bytes));
+            *total += bytes;
+            while let Some(&(time, old_bytes)) = reads.front() {
+                if now.duration_since(time) <= Self::WINDOW {
+                    break;
+                }
+                reads.pop_front();
+                *total -= old_bytes;
+            }
+
+            // Sleep long enough that the bytes over the allowance for the window would have been read at the maximum rate.
+            let allowed = self.max_bytes_per_sec as f64 * Self::WINDOW.as_secs_f64();
+            let excess = *total as f64 - allowed;
+            if excess > 0.0 {
+                Duration::from_secs_f64(excess / self.max_bytes_per_sec as f64)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !sleep_time.is_zero() {
+            std::thread::sleep(sleep_time);
+        }
+    }
 }
 
+/// Error indicating that a file could not be downloaded from a particular URL,
+/// rather than the download failing for some other reason, e.g. being unable to write the file.
+#[derive(Debug)]
+enum UrlError {
+    // The server responded with an unsuccessful status code.
+    Status(u16),
+    // The connection failed for each of the attempts allowed.
+    AttemptsExhausted,
+}
+
+impl std::fmt::Display for UrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Status(code) => write!(f, "Request failed as got status {code} from server."),
+            Self::AttemptsExhausted => {
+                write!(f, "Failed to connect to server: all attempts exhausted")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
+
 enum DownloadFileError {
     // An error occured when making the initial request to the server, before the body was read back.
     InitialRequest(ureq::Error),
     // Once the initial request succeeded, but before the whole body had been read, the connection was lost.
     LostConnDuringDownload(io::Error),
+    // A specific range of the file was requested, but the server did not respond with partial content.
+    RangeNotSatisfied(u16),
+    // The body of the response was compressed with a `Content-Encoding` that MBF cannot decompress.
+    UnsupportedEncoding(String),
+    // The server redirected more than `max_redirects` times. Contains the last URL redirected to.
+    TooManyRedirects(String),
+    // The server redirected to a URL that could not be parsed. Contains the `Location` given.
+    InvalidRedirect(String),
+    // The download was cancelled with the `cancel` token of the download config.
+    Cancelled,
+}
+
+/// Makes the request created by `make_request` for the given URL, following up to `cfg.max_redirects` redirects
+/// and logging each one.
+fn call_following_redirects(
+    cfg: &DownloadConfig,
+    url: &str,
+    make_request: impl Fn(&str) -> ureq::Request,
+) -> Result<ureq::Response, DownloadFileError> {
+    let mut url = url.to_string();
+    for _ in 0..=cfg.max_redirects {
+        let resp = make_request(&url)
+            .call()
+            .map_err(DownloadFileError::InitialRequest)?;
+        if !matches!(resp.status(), 301 | 302 | 303 | 307 | 308) {
+            return Ok(resp);
+        }
+
+        let location = match resp.header("Location") {
+            Some(location) => location,
+            None => return Ok(resp),
+        };
+        // The location may be relative to the URL redirected from.
+        let next_url = url::Url::parse(resp.get_url())
+            .and_then(|base| base.join(location))
+            .map_err(|_| DownloadFileError::InvalidRedirect(location.to_string()))?;
+        debug!("Redirected ({}) from {url} to {next_url}", resp.status());
+        url = next_url.to_string();
+    }
+
+    Err(DownloadFileError::TooManyRedirects(url))
 }
 
 /// Carries out one attempt to download a file from a URL to the specified stream
 /// `file_offset` is the number of bytes to skip from the start of the file when downloading (using the http `Range` header)
+/// `range_end`, if specified, is the (inclusive) index of the last byte to download. The server must respond with partial content if this is given.
 /// `progress_update` is called regularly with the number of bytes successfully written to the `to` stream thus far.
 /// If the download fails partway, the caller should truncate the `to` stream to the number of successfully written bytes. They can then try to download again.
 /// If the response headers indicate that the server supports partial requests, then `out_supports_ranges` will be set to `true`, otherwise it is set `false`.
-/// `out_content_length` is where the content length header is written to, if specified.
-/// None may be written to `out_content_length` if no content length is given in the response.
+/// When downloading the whole file, the server may compress the response with gzip or zstd, in which case it is decompressed
+/// before being written to `to`. The lengths given to `progress_update` are always the decompressed lengths, so the total
+/// length is only given if the response is not compressed.
 fn download_file_to_stream<T: FnMut(usize, Option<usize>) -> ()>(
     cfg: &DownloadConfig,
     file_offset: usize,
+    range_end: Option<usize>,
     url: &str,
     out_supports_ranges: &mut bool,
     out_filename: &mut Option<String>,
     mut progress_update: T,
     to: impl Write,
 ) -> Result<(), DownloadFileError> {
-    let mut req = cfg
-        .ureq_agent
-        .get(url)
-        // We can't properly process gzipped or any other compressed data when using ranges to carry out a partial download.
-        .set("Accept-Encoding", "identity");
-    if file_offset != 0 {
-        // No need for range header if downloading the whole file.
-        // Specify that we only want the portion of the file from the specified offset
-        req = req.set("Range", &format!("bytes={file_offset}-"));
-    }
+    // We can't properly process gzipped or any other compressed data when using ranges to carry out a partial download,
+    // since the range would refer to the compressed data, and so compression is only accepted for the whole file.
+    let whole_file = file_offset == 0 && range_end.is_none();
+    let resp = call_following_redirects(cfg, url, |url| {
+        let req = cfg.ureq_agent.get(url).set(
+            "Accept-Encoding",
+            if whole_file { "gzip, zstd" } else { "identity" },
+        );
+        if let Some(end) = range_end {
+            req.set("Range", &format!("bytes={file_offset}-{end}"))
+        } else if file_offset != 0 {
+            // No need for range header if downloading the whole file.
+            // Specify that we only want the portion of the file from the specified offset
+            req.set("Range", &format!("bytes={file_offset}-"))
+        } else {
+            req
+        }
+    })?;
 
-    let resp = req
-        .call()
-        .map_err(|err| DownloadFileError::InitialRequest(err))?;
+    // If the server ignores the range and sends the whole file, the data would be written in the wrong place.
+    if range_end.is_some() && resp.status() != 206 {
+        return Err(DownloadFileError::RangeNotSatisfied(resp.status()));
+    }
 
     *out_supports_ranges = resp.header("Accept-Ranges") == Some("bytes");
     *out_filename = get_filename_from_headers(&resp);
 
-    let content_length: Option<usize> = match resp.header("Content-Length") {
-        Some(length_str) => length_str.parse().ok(),
-        None => None,
+    // ureq decompresses gzip itself, removing the Content-Encoding and Content-Length headers, so any encoding left
+    // must be decompressed here.
+    let encoding = resp
+        .header("Content-Encoding")
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| encoding != "identity");
+
+    // The Content-Length is that of the compressed data, which can't be compared against the decompressed bytes written.
+    let content_length: Option<usize> = match (resp.header("Content-Length"), &encoding) {
+        (Some(length_str), None) => length_str.parse().ok(),
+        _ => None,
     };
 
     // Successfully got the response, now turn it into a reader and begin to copy it to the output
-    let mut reader = resp.into_reader();
+    let mut reader: Box<dyn Read> = match encoding.as_deref() {
+        None => Box::new(resp.into_reader()),
+        Some("zstd") => Box::new(
+            ruzstd::StreamingDecoder::new(resp.into_reader())
+                .map_err(|err| DownloadFileError::LostConnDuringDownload(io::Error::other(err)))?,
+        ),
+        Some(other) => return Err(DownloadFileError::UnsupportedEncoding(other.to_string())),
+    };
 
-    if let None = content_length {
+    if encoding.is_some() {
+        debug!("Decompressing response with encoding {encoding:?}");
+    } else if let None = content_length {
         warn!(
             "No Content-Length header provided, so MBF cannot update you on the download progress"
         );
     }
 
     // Copy as many bytes as we can, regularly updating the caller on how many bytes have downloaded successfully.
-    copy_stream_progress(&mut reader, to, |bytes_written| {
-        progress_update(bytes_written, content_length)
-    })
-    .map_err(|err| DownloadFileError::LostConnDuringDownload(err))?;
+    copy_stream_progress(
+        &mut reader,
+        to,
+        cfg.rate_limiter.as_ref(),
+        cfg.cancel,
+        |bytes_written| progress_update(bytes_written, content_length),
+    )
+    .map_err(|err| {
+        if cfg.is_cancelled() {
+            DownloadFileError::Cancelled
+        } else {
+            DownloadFileError::LostConnDuringDownload(err)
+        }
+    })?;
 
     Ok(())
 }
 
 /// Copies bytes from the `from` stream to the `to` stream.
 /// As each buffer of data is copied, the `progress` function is called to update the caller on the number of bytes that have been copied thus far.
+/// If `rate_limiter` is specified, reads are paced so as not to exceed its limit.
+/// If `cancel` is set to true, copying stops with an [io::ErrorKind::Interrupted] error before the next read.
 fn copy_stream_progress<T: FnMut(usize) -> ()>(
     from: &mut impl Read,
     mut to: impl Write,
+    rate_limiter: Option<&RateLimiter>,
+    cancel: Option<&AtomicBool>,
     mut progress: T,
 ) -> Result<(), io::Error> {
     let mut buffer = vec![0u8; 8192];
 
     let mut total_read = 0;
     loop {
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            break Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Download cancelled",
+            ));
+        }
+
         let bytes_read = from.read(&mut buffer)?;
         to.write_all(&buffer[0..bytes_read])?;
 
@@ -113,6 +328,9 @@ fn copy_stream_progress<T: FnMut(usize) -> ()>(
         } else {
             total_read += bytes_read;
             progress(total_read);
+            if let Some(limiter) = rate_limiter {
+                limiter.record_read(bytes_read);
+            }
         }
     }
 }
@@ -151,20 +369,75 @@ fn get_filename_from_headers(resp: &ureq::Response) -> Option<String> {
     }
 }
 
+/// Called with the number of bytes downloaded so far and the total number of bytes to download, each time
+/// download progress is reported.
+pub type ProgressCallback<'a> = &'a (dyn Fn(u64, u64) + Sync);
+
+/// The [ProgressCallback] used by downloads that don't give their own, which reports progress through the `Downloading` stage.
+pub fn report_download_progress(bytes_done: u64, bytes_total: u64) {
+    crate::report_bytes_progress("Downloading", bytes_done, Some(bytes_total));
+}
+
 /// Attempts to download a file with support for multiple attempts, continuing failed downloads,
-/// and progress reporting.
+/// and progress reporting through `on_progress`.
 /// Returns the filename, if it was provided within the response.
 pub fn download_with_attempts(
+    cfg: &DownloadConfig,
+    to: impl Write + Seek,
+    url: &str,
+    on_progress: ProgressCallback,
+) -> Result<Option<String>> {
+    let mut last_progress_update = Instant::now();
+    download_range_with_attempts(cfg, to, url, None, |bytes_valid, total_bytes| {
+        match (cfg.progress_update_interval, total_bytes) {
+            (Some(interval), Some(length)) => {
+                let now = Instant::now();
+                if now.duration_since(last_progress_update) > interval {
+                    last_progress_update = now;
+                    log_progress(bytes_valid, length, on_progress);
+                }
+            }
+            // Cannot do progress updates, we need them to be enabled and we need the content length
+            _ => {}
+        }
+    })
+}
+
+fn log_progress(bytes_valid: usize, total_bytes: usize, on_progress: ProgressCallback) {
+    info!(
+        "Progress: {:.2}%",
+        (bytes_valid as f32 / total_bytes as f32) * 100.0
+    );
+    on_progress(bytes_valid as u64, total_bytes as u64);
+}
+
+/// Downloads a file (or, if `range` is specified, the given range of a file) with multiple attempts
+/// and continuing of failed downloads.
+/// `range` is the index of the first byte and (inclusive) index of the last byte to download.
+/// Data is written to `to` starting at its current position.
+/// `progress` is called with the number of bytes successfully downloaded thus far and the total number
+/// of bytes to download, if known.
+/// Returns the filename, if it was provided within the response.
+fn download_range_with_attempts(
     cfg: &DownloadConfig,
     mut to: impl Write + Seek,
     url: &str,
+    range: Option<(usize, usize)>,
+    mut progress: impl FnMut(usize, Option<usize>),
 ) -> Result<Option<String>> {
     let mut failed_attempts = 0;
+    let stream_start = to.stream_position()?;
+    let range_start = range.map(|(start, _)| start).unwrap_or(0);
+    let range_end = range.map(|(_, end)| end);
     let mut bytes_valid: usize = 0; // The number of bytes successfully downloaded thus far.
     let mut file_name: Option<String> = None;
     let mut ranges_supported = false;
 
     loop {
+        if cfg.is_cancelled() {
+            return Err(anyhow!("Download cancelled").context(ErrorCode::Cancelled));
+        }
+
         if failed_attempts > 0 {
             if ranges_supported {
                 info!("Continuing download");
@@ -174,45 +447,32 @@ pub fn download_with_attempts(
         }
 
         // Skip back to the point in the stream where the last valid byte was written
-        to.seek(io::SeekFrom::Start(bytes_valid as u64))?;
+        to.seek(io::SeekFrom::Start(stream_start + bytes_valid as u64))?;
         let bytes_valid_before_req = bytes_valid;
 
         let request_time = Instant::now();
-        let mut last_progress_update = Instant::now();
         let result = download_file_to_stream(
             cfg,
-            bytes_valid,
+            range_start + bytes_valid,
+            range_end,
             url,
             &mut ranges_supported,
             &mut file_name,
             |bytes_written, total_bytes| {
                 bytes_valid = bytes_valid_before_req + bytes_written;
 
-                let now = Instant::now();
-
                 // Reset the number of failed attempts if downloading successfully for last X seconds.
                 if let Some(reset_time) = cfg.disconnection_reset_time {
-                    if (now - request_time) > reset_time && failed_attempts > 0 {
+                    if (Instant::now() - request_time) > reset_time && failed_attempts > 0 {
                         info!("Resetting failed attempts as download appears to be completing successfully");
                         failed_attempts = 0;
                     }
                 }
 
-                match (cfg.progress_update_interval, total_bytes) {
-                    (Some(interval), Some(length)) => {
-                        if now.duration_since(last_progress_update) > interval {
-                            last_progress_update = now;
-                            info!(
-                                "Progress: {:.2}%",
-                                ((bytes_written + bytes_valid_before_req) as f32
-                                    / (bytes_valid_before_req + length) as f32)
-                                    * 100.0
-                            );
-                        }
-                    }
-                    // Cannot do progress updates, we need them to be enabled and we need the content length
-                    _ => {}
-                }
+                progress(
+                    bytes_valid,
+                    total_bytes.map(|length| bytes_valid_before_req + length),
+                );
             },
             &mut to,
         );
@@ -225,7 +485,8 @@ pub fn download_with_attempts(
                 let dl_failed = failed_attempts > cfg.max_disconnections;
 
                 // No support for ranges so we need to redownload the whole file.
-                if !ranges_supported {
+                // (if a range was requested, the server must support ranges as it responded with partial content)
+                if !ranges_supported && range.is_none() {
                     bytes_valid = 0;
                 }
 
@@ -233,12 +494,11 @@ pub fn download_with_attempts(
                     DownloadFileError::InitialRequest(ureq_err) => match ureq_err {
                         // Do not attempt to download again if the error is not network related
                         ureq::Error::Status(code, _resp) => {
-                            return Err(anyhow!("Request failed as got status {code} from server."))
+                            return Err(UrlError::Status(code).into())
                         }
                         ureq::Error::Transport(transport_err) => {
                             if dl_failed {
-                                return Err(transport_err)
-                                    .context("Downloading file: all attempts exhausted");
+                                return Err(transport_err).context(UrlError::AttemptsExhausted);
                             }
 
                             // Error occured due to internet connection, can make another attempt
@@ -247,12 +507,34 @@ pub fn download_with_attempts(
                     },
                     DownloadFileError::LostConnDuringDownload(io_error) => {
                         if dl_failed {
-                            return Err(io_error).context(
+                            return Err(io_error).context(UrlError::AttemptsExhausted).context(
                                 "Lost connection mid download and ran out of download attempts",
                             );
                         }
                         error!("Failed to complete file download: {io_error}");
                     }
+                    DownloadFileError::RangeNotSatisfied(code) => {
+                        return Err(anyhow!(
+                            "Requested part of the file but got status {code} from server"
+                        ))
+                    }
+                    DownloadFileError::UnsupportedEncoding(encoding) => {
+                        return Err(anyhow!(
+                            "Server sent the file with unsupported Content-Encoding `{encoding}`"
+                        ))
+                    }
+                    DownloadFileError::TooManyRedirects(last_url) => {
+                        return Err(anyhow!(
+                            "Redirected more than {} times, last to {last_url}",
+                            cfg.max_redirects
+                        ))
+                    }
+                    DownloadFileError::InvalidRedirect(location) => {
+                        return Err(anyhow!("Server redirected to invalid URL `{location}`"))
+                    }
+                    DownloadFileError::Cancelled => {
+                        return Err(anyhow!("Download cancelled").context(ErrorCode::Cancelled))
+                    }
                 };
 
                 // Wait a little bit in the hope that the connection loss is temporary
@@ -263,33 +545,397 @@ pub fn download_with_attempts(
     }
 }
 
+/// Details of a file found by making a HEAD request, used to decide whether it can be downloaded in ranges.
+struct RemoteFileInfo {
+    length: usize,
+    file_name: Option<String>,
+}
+
+/// Makes a HEAD request to the given URL.
+/// Returns `None` if the request fails, or the server does not support ranges or give the content length.
+fn get_rangeable_file_info(cfg: &DownloadConfig, url: &str) -> Option<RemoteFileInfo> {
+    let resp = match call_following_redirects(cfg, url, |url| {
+        cfg.ureq_agent.head(url).set("Accept-Encoding", "identity")
+    }) {
+        Ok(resp) => resp,
+        Err(DownloadFileError::InitialRequest(err)) => {
+            debug!("HEAD request failed, not downloading in parallel: {err}");
+            return None;
+        }
+        Err(_) => {
+            debug!("HEAD request could not follow redirects, not downloading in parallel");
+            return None;
+        }
+    };
+
+    if resp.header("Accept-Ranges") != Some("bytes") {
+        return None;
+    }
+
+    Some(RemoteFileInfo {
+        length: resp.header("Content-Length")?.parse().ok()?,
+        file_name: get_filename_from_headers(&resp),
+    })
+}
+
+/// The progress of a download to a `.part` file, saved alongside it so that the download can be resumed
+/// if the agent is restarted.
+#[derive(Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    length: usize,
+    chunks: Vec<ChunkProgress>,
+}
+
+/// A range of a file being downloaded, and how much of it has been downloaded already.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct ChunkProgress {
+    start: usize,
+    // Inclusive index of the last byte of the chunk.
+    end: usize,
+    downloaded: usize,
+}
+
+impl ChunkProgress {
+    fn is_complete(&self) -> bool {
+        self.start + self.downloaded > self.end
+    }
+}
+
+impl PartialDownload {
+    /// Creates a new partial download for a file of the given length, split into chunks based on `cfg.parallel_connections`
+    fn new(cfg: &DownloadConfig, url: &str, length: usize) -> Self {
+        let chunk_count = if length >= MIN_PARALLEL_DOWNLOAD_SIZE {
+            cfg.parallel_connections.max(1)
+        } else {
+            1
+        };
+
+        let chunk_size = length.div_ceil(chunk_count).max(1);
+        Self {
+            url: url.to_string(),
+            length,
+            chunks: (0..length)
+                .step_by(chunk_size)
+                .map(|start| ChunkProgress {
+                    start,
+                    end: (start + chunk_size).min(length) - 1,
+                    downloaded: 0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Loads the progress of a download from `state_path`, if it exists and matches the given URL, file length and `.part` file.
+    fn load_matching(
+        state_path: &Path,
+        part_path: &Path,
+        url: &str,
+        length: usize,
+    ) -> Option<Self> {
+        let state: Self = serde_json::from_slice(&std::fs::read(state_path).ok()?).ok()?;
+        let part_len = std::fs::metadata(part_path).ok()?.len();
+
+        if state.url == url && state.length == length && part_len == length as u64 {
+            Some(state)
+        } else {
+            debug!("Existing partial download did not match, starting again");
+            None
+        }
+    }
+
+    fn save(&self, state_path: &Path) -> Result<()> {
+        std::fs::write(state_path, serde_json::to_vec(self)?)
+            .context("Saving partial download progress")
+    }
+
+    fn total_downloaded(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.downloaded).sum()
+    }
+}
+
+// Gets the path of `path` with the given suffix added to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Downloads the incomplete chunks of `state` to `part_path`, which must already be allocated to the full file length.
+/// The chunks are downloaded concurrently, and the progress of each is saved to `state_path` at the
+/// progress update interval, and when the download fails.
+fn download_chunks(
+    cfg: &DownloadConfig,
+    part_path: &Path,
+    state_path: &Path,
+    state: PartialDownload,
+    on_progress: ProgressCallback,
+) -> Result<()> {
+    let remaining: Vec<usize> = (0..state.chunks.len())
+        .filter(|idx| !state.chunks[*idx].is_complete())
+        .collect();
+    if remaining.len() > 1 {
+        info!("Downloading in {} parts", remaining.len());
+    }
+
+    let state = Mutex::new(state);
+    let last_progress_update = Mutex::new(Instant::now());
+
+    let result = std::thread::scope(|scope| {
+        let handles: Vec<_> = remaining
+            .iter()
+            .map(|&idx| {
+                let (state, last_progress_update) = (&state, &last_progress_update);
+                scope.spawn(move || -> Result<()> {
+                    let (url, chunk) = {
+                        let state = state.lock().unwrap();
+                        (state.url.clone(), state.chunks[idx])
+                    };
+                    let start = chunk.start + chunk.downloaded;
+
+                    // Each chunk needs its own handle, as a cloned handle would share the same position in the file.
+                    let mut handle = OpenOptions::new()
+                        .write(true)
+                        .open(part_path)
+                        .context("Opening partial download")?;
+                    handle.seek(io::SeekFrom::Start(start as u64))?;
+
+                    download_range_with_attempts(
+                        cfg,
+                        handle,
+                        &url,
+                        Some((start, chunk.end)),
+                        |bytes_valid, _| {
+                            let mut state = state.lock().unwrap();
+                            state.chunks[idx].downloaded = chunk.downloaded + bytes_valid;
+
+                            let interval = match cfg.progress_update_interval {
+                                Some(interval) => interval,
+                                None => return,
+                            };
+                            let mut last_update = last_progress_update.lock().unwrap();
+                            let now = Instant::now();
+                            if now.duration_since(*last_update) > interval {
+                                *last_update = now;
+                                log_progress(state.total_downloaded(), state.length, on_progress);
+                                if let Err(err) = state.save(state_path) {
+                                    warn!("{err}");
+                                }
+                            }
+                        },
+                    )
+                    .with_context(|| format!("Downloading bytes {start}-{}", chunk.end))?;
+                    Ok(())
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| anyhow!("Download thread panicked"))?
+            })
+            .collect::<Result<Vec<()>>>()
+    });
+
+    if result.is_err() {
+        // Make sure the progress is up to date so the next attempt can resume from where this one got to.
+        state.into_inner().unwrap().save(state_path)?;
+    }
+    result.map(|_| ())
+}
+
 /// Attempts to download a file from `url` to the file at `to` with multiple attempts, progress
 /// reporting, and resuming of failed downloads.
 /// If the file already exists, it is overwritten.
+/// Partial downloads are kept in `<to>.part`, so that calling this again for the same URL and destination
+/// can resume the download if a previous call was interrupted.
+/// If `expected_sha256` is given, the downloaded file is checked against it and downloaded again if it does not match.
 /// Upon success, returns the filename supplied by the server, or None if no filename was supplied.
 /// If all attempts fail, the error from the last request is returned.
 pub fn download_file_with_attempts(
     cfg: &DownloadConfig,
     to: impl AsRef<Path>,
     url: &str,
+    expected_sha256: Option<[u8; 32]>,
 ) -> Result<Option<String>> {
-    let writer = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(to)
-        .context("Creating destination file")?;
+    download_file_with_progress(cfg, to, url, expected_sha256, &report_download_progress)
+}
 
-    download_with_attempts(cfg, writer, url)
+/// Downloads a file in the same way as [download_file_with_attempts], but reports progress through `on_progress`
+/// rather than as the `Downloading` stage.
+pub fn download_file_with_progress(
+    cfg: &DownloadConfig,
+    to: impl AsRef<Path>,
+    url: &str,
+    expected_sha256: Option<[u8; 32]>,
+    on_progress: ProgressCallback,
+) -> Result<Option<String>> {
+    let to = to.as_ref();
+    retry_on_hash_mismatch(expected_sha256, || {
+        let file_name = try_mirrors(cfg, url, |url| {
+            download_file_unverified(cfg, to, url, on_progress)
+        })?;
+        let hash = hash_stream(std::fs::File::open(to).context("Opening downloaded file")?)?;
+        Ok((file_name, hash))
+    })
 }
 
-/// Attempts to download a file from `url` to a Vec with multiple attempts, progress
-/// reporting, and resuming of failed downloads.
-/// Upon success, returns the filename supplied by the server, or None if no filename was supplied.
-/// If all attempts fail, the error from the last request is returned.
-pub fn download_to_vec_with_attempts(cfg: &DownloadConfig, url: &str) -> Result<Vec<u8>> {
-    let mut output = Vec::new();
-    download_with_attempts(cfg, Cursor::new(&mut output), url)?;
+// Downloads the file to `<to>.part`, moving it to `to` once complete.
+// If the server supports ranges, the progress is saved to `<to>.part.json` so that
+// the download can be continued by a later call if this one fails or the agent is killed.
+fn download_file_unverified(
+    cfg: &DownloadConfig,
+    to: &Path,
+    url: &str,
+    on_progress: ProgressCallback,
+) -> Result<Option<String>> {
+    let part_path = with_suffix(to, ".part");
+    let state_path = with_suffix(to, ".part.json");
+
+    let file_name = match get_rangeable_file_info(cfg, url) {
+        Some(info) => {
+            let state =
+                match PartialDownload::load_matching(&state_path, &part_path, url, info.length) {
+                    Some(existing) => {
+                        info!(
+                            "Resuming previous download ({:.2}% complete)",
+                            existing.total_downloaded() as f32 / info.length as f32 * 100.0
+                        );
+                        existing
+                    }
+                    None => {
+                        OpenOptions::new()
+                            .write(true)
+                            .truncate(true)
+                            .create(true)
+                            .open(&part_path)
+                            .context("Creating destination file")?
+                            .set_len(info.length as u64)
+                            .context("Allocating destination file")?;
+
+                        let new_state = PartialDownload::new(cfg, url, info.length);
+                        new_state.save(&state_path)?;
+                        new_state
+                    }
+                };
+
+            download_chunks(cfg, &part_path, &state_path, state, on_progress)?;
+            info.file_name
+        }
+        None => {
+            debug!(
+                "Server does not support ranges, download cannot be resumed if MBF is restarted"
+            );
+            let writer = OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .create(true)
+                .open(&part_path)
+                .context("Creating destination file")?;
+
+            download_with_attempts(cfg, writer, url, on_progress)?
+        }
+    };
+
+    std::fs::rename(&part_path, to).context("Moving completed download into place")?;
+    if state_path.exists() {
+        std::fs::remove_file(&state_path).context("Removing partial download progress")?;
+    }
+    Ok(file_name)
+}
+
+// Calls `download` with `url`, then with each mirror of `url` in turn until the download succeeds
+// or fails for a reason that another mirror would not fix.
+fn try_mirrors<T>(
+    cfg: &DownloadConfig,
+    url: &str,
+    mut download: impl FnMut(&str) -> Result<T>,
+) -> Result<T> {
+    let urls = cfg.mirrors.get_urls(url);
+    for (idx, mirror_url) in urls.iter().enumerate() {
+        match download(mirror_url) {
+            Ok(result) => return Ok(result),
+            Err(err) if idx + 1 < urls.len() && should_try_mirror(&err) => {
+                warn!("Failed to download from {mirror_url} ({err}), trying next mirror")
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    unreachable!("There is always at least one URL to try")
+}
+
+fn should_try_mirror(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<UrlError>()
+        .is_some_and(|url_err| match url_err {
+            UrlError::Status(code) => mirrors::should_try_mirror(*code),
+            UrlError::AttemptsExhausted => true,
+        })
+}
+
+/// Returns true if `err` was caused by failing to download from a server, e.g. due to there being no internet connection,
+/// rather than the download failing for some other reason.
+pub fn is_network_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<UrlError>().is_some() || err.chain().any(|cause| cause.is::<ureq::Error>())
+}
+
+// Calls `download` (which returns a result and the SHA-256 of the data downloaded) until the hash matches `expected_sha256`,
+// or HASH_MISMATCH_RETRIES retries have been made.
+// If `expected_sha256` is None, the result of the first download is returned.
+fn retry_on_hash_mismatch<T>(
+    expected_sha256: Option<[u8; 32]>,
+    mut download: impl FnMut() -> Result<(T, [u8; 32])>,
+) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        let (result, hash) = download()?;
+        let expected = match expected_sha256 {
+            Some(expected) => expected,
+            None => return Ok(result),
+        };
+
+        if hash == expected {
+            return Ok(result);
+        }
+
+        if retries >= HASH_MISMATCH_RETRIES {
+            return Err(anyhow!(
+                "Downloaded file was corrupt: SHA-256 {} did not match expected {}",
+                to_hex(&hash),
+                to_hex(&expected)
+            ));
+        }
+        retries += 1;
+        warn!("Downloaded file was corrupt (SHA-256 did not match), downloading again");
+    }
+}
+
+fn hash_stream(mut stream: impl Read) -> Result<[u8; 32]> {
+    let mut sha = Sha256::new();
+    io::copy(&mut stream, &mut sha).context("Hashing downloaded file")?;
+    Ok(sha.finalize().into())
+}
+
+/// Formats the given bytes, e.g. a hash, as lowercase hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Parses a SHA-256 hash given as a hexadecimal string, e.g. from a resource index.
+pub fn parse_sha256(hex: &str) -> Result<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(anyhow!("SHA-256 hash `{hex}` was not 64 hex digits"));
+    }
+
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .with_context(|| format!("Invalid SHA-256 hash `{hex}`"))?;
+    }
 
-    Ok(output)
+    Ok(hash)
 }
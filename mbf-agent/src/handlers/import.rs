@@ -1,29 +1,75 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     downloads,
-    mod_man::ModManager,
+    mod_man::{self, ModManager},
     models::response::{self, ImportResultType, Response},
     paths,
 };
 use anyhow::{anyhow, Context, Result};
 use log::{debug, info, warn};
+use mbf_res_man::github::{self, ReleaseFile, ReleasePage};
 use mbf_zip::ZipFile;
 
 /// Handles `ImportUrl` [Requests](requests::Request).
 ///
 /// # Returns
 /// The [Response](requests::Response) to the request (variant `ImportResult`)
-pub(super) fn handle_import_mod_url(from_url: String) -> Result<Response> {
+pub(super) fn handle_import_mod_url(
+    from_url: String,
+    override_mod_repo_url: Option<String>,
+) -> Result<Response> {
     std::fs::create_dir_all(paths::MBF_DOWNLOADS)?;
     let download_path = Path::new(paths::MBF_DOWNLOADS).join("import_from_url");
 
+    // Users often give the page of a GitHub release rather than a link to the QMOD attached to it.
+    let (from_url, release_file_name) = match github::parse_release_url(&from_url) {
+        Some(release) => {
+            info!("{from_url} is a GitHub release page, finding the QMOD attached to it");
+            let qmod = find_release_qmod(&release)?;
+            (qmod.download_url, Some(qmod.name))
+        }
+        None => (from_url, None),
+    };
+
     info!("Downloading {}", from_url);
-    let filename: Option<String> =
-        downloads::download_file_with_attempts(&crate::get_dl_cfg(), &download_path, &from_url)?;
+    let filename: Option<String> = downloads::download_file_with_attempts(
+        &crate::get_dl_cfg(),
+        &download_path,
+        &from_url,
+        None,
+    )?
+    .or(release_file_name);
 
     // Attempt to import the downloaded file as a qmod, removing the temporary file if this fails.
-    handle_import(&download_path, filename)
+    handle_import(&download_path, filename, override_mod_repo_url, None)
+}
+
+// Finds the QMOD attached to the given GitHub release, failing if there is not exactly one.
+fn find_release_qmod(release: &ReleasePage) -> Result<ReleaseFile> {
+    let files = github::get_release_files(mbf_res_man::default_agent::get_agent(), release)
+        .context("Finding files attached to GitHub release")?;
+    let (owner, repo) = (&release.repo.owner, &release.repo.repo);
+    let release_name = match &release.tag {
+        Some(tag) => format!("Release {tag} of {owner}/{repo}"),
+        None => format!("The latest release of {owner}/{repo}"),
+    };
+
+    let mut qmods: Vec<ReleaseFile> = files
+        .into_iter()
+        .filter(|file| file.name.to_lowercase().ends_with(".qmod"))
+        .collect();
+    match qmods.len() {
+        0 => Err(anyhow!("{release_name} has no QMOD attached, so there is no mod to import")),
+        1 => Ok(qmods.remove(0)),
+        _ => Err(anyhow!(
+            "{release_name} has several QMODs attached ({}). Copy the link of the one to import instead",
+            qmods.iter().map(|file| file.name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
 }
 
 /// Handles `Import` [Requests](requests::Request).
@@ -33,10 +79,17 @@ pub(super) fn handle_import_mod_url(from_url: String) -> Result<Response> {
 pub(super) fn handle_import(
     from_path: impl AsRef<Path> + std::fmt::Debug,
     override_filename: Option<String>,
+    override_mod_repo_url: Option<String>,
+    expected_size: Option<u64>,
 ) -> Result<Response> {
+    if let Some(expected_size) = expected_size {
+        check_upload_size(from_path.as_ref(), expected_size)?;
+    }
+
     // Load the installed mods.
     let res_cache = crate::load_res_cache()?;
     let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.set_override_mod_repo_url(override_mod_repo_url);
     mod_manager.load_mods()?;
 
     let filename = match override_filename {
@@ -89,6 +142,89 @@ pub(super) fn handle_import(
     }
 }
 
+/// Gives an error if the file at the given path is not `expected_size` bytes long, deleting the file.
+/// This catches uploads that were interrupted, which would otherwise fail to import with a less helpful error,
+/// e.g. because a truncated QMOD is not a valid ZIP.
+fn check_upload_size(path: &Path, expected_size: u64) -> Result<()> {
+    let size = std::fs::metadata(path)
+        .context("Uploaded file did not exist")?
+        .len();
+    if size == expected_size {
+        return Ok(());
+    }
+
+    if let Err(err) = std::fs::remove_file(path) {
+        warn!("Failed to remove incomplete upload: {err}");
+    }
+    Err(anyhow!(
+        "Size mismatch: uploaded file was {size} bytes, but {expected_size} bytes were sent. The upload was likely interrupted, so try importing again"
+    ))
+}
+
+/// Handles `ImportDirectory` [Requests](requests::Request).
+///
+/// # Returns
+/// The [Response](requests::Response) to the request (variant `ImportedDirectory`)
+pub(super) fn handle_import_directory(
+    path: String,
+    override_mod_repo_url: Option<String>,
+) -> Result<Response> {
+    if !Path::new(&path).is_dir() {
+        return Err(anyhow!("{path} does not exist or is not a directory"));
+    }
+
+    let mut qmod_paths = Vec::new();
+    for stat_result in std::fs::read_dir(&path).context("Reading directory to import")? {
+        let entry_path = stat_result?.path();
+        if entry_path.is_file()
+            && entry_path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("qmod"))
+        {
+            qmod_paths.push(entry_path);
+        }
+    }
+    qmod_paths.sort();
+
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.set_override_mod_repo_url(override_mod_repo_url);
+    mod_manager.load_mods()?;
+
+    info!("Importing {} QMODs from {path}", qmod_paths.len());
+    let mut imported = BTreeMap::new();
+    let mut failures = BTreeMap::new();
+    for (idx, qmod_path) in qmod_paths.iter().enumerate() {
+        crate::report_progress("Importing mods", Some(idx as f32 / qmod_paths.len() as f32));
+        let file_name = qmod_path
+            .file_name()
+            .expect("Directory entries should have a file name")
+            .to_string_lossy()
+            .to_string();
+
+        // Attempt to load a mod from each file
+        match std::fs::File::open(qmod_path)
+            .context("Opening QMOD")
+            .and_then(|mod_stream| mod_manager.try_load_new_mod(mod_stream))
+        {
+            Ok(id) => {
+                info!("Imported {id} from {file_name}");
+                imported.insert(file_name, id);
+            }
+            Err(err) => {
+                warn!("Failed to import {file_name}: {err:#}");
+                failures.insert(file_name, format!("{err:#}"));
+            }
+        }
+    }
+
+    Ok(Response::ImportedDirectory {
+        installed_mods: super::mod_management::get_mod_models(mod_manager)?,
+        imported,
+        failures,
+    })
+}
+
 /// Attempts to import the given path as a QMOD
 /// The file will be deleted if this results in a success.
 ///
@@ -101,7 +237,17 @@ pub(super) fn handle_import(
 /// and the new full list of installed mods.
 fn handle_import_qmod(mut mod_manager: ModManager, from_path: PathBuf) -> Result<ImportResultType> {
     debug!("Loading {from_path:?} as a QMOD");
-    let id = mod_manager.try_load_new_mod(std::fs::File::open(&from_path)?)?;
+    let id = mod_manager.try_load_new_mod_with_progress(
+        std::fs::File::open(&from_path)?,
+        |progress| {
+            crate::report_bytes_progress(
+                "Extracting mod",
+                progress.bytes_done,
+                Some(progress.bytes_total),
+            )
+        },
+        Some(crate::get_cancel_token()),
+    )?;
     std::fs::remove_file(from_path)?; // Delete temporary file.
 
     let installed_mods = super::mod_management::get_mod_models(mod_manager)?;
@@ -140,6 +286,8 @@ fn attempt_file_copy(
             .next()
         {
             Some(copy_ext) => {
+                mod_man::check_copy_destination(&copy_ext.destination)
+                    .context("Copy extension destination is not allowed")?;
                 info!("Copying to {}", copy_ext.destination);
                 let dest_folder = Path::new(&copy_ext.destination);
                 std::fs::create_dir_all(dest_folder)
@@ -165,10 +313,12 @@ fn attempt_file_copy(
     ))
 }
 
-/// Attempts to import a file as a song.
+/// Attempts to import a file as a song, or a pack of several songs.
 ///
-/// This function will check that the file is a valid ZIP file and that it contains a file named `info.dat` or `Info.dat`.
-/// It will not do any further verification that the song file is valid.
+/// This function will check that the file is a valid ZIP file and that it contains at least one file named `info.dat` or `Info.dat`.
+/// If this is at the root of the ZIP, the whole ZIP is extracted as a single song.
+/// Otherwise, each folder within the ZIP that contains an `info.dat` is extracted as a separate song.
+/// It will not do any further verification that the song files are valid.
 ///
 /// If successful, the file is deleted.
 ///
@@ -176,36 +326,164 @@ fn attempt_file_copy(
 /// * `from_path` - The path to the song file.
 ///
 /// # Returns
-/// If successful, an [ImportResultType] of variant `ImportedFileCopy`, detailing the destination path the file was copied to
-/// and the mod that specified this destination path.
+/// If successful, an [ImportResultType] of variant `ImportedSong`, detailing the number of songs that were imported.
 fn attempt_song_import(from_path: PathBuf) -> Result<ImportResultType> {
     let song_handle = std::fs::File::open(&from_path)?;
-    let mut zip = ZipFile::open(song_handle).context("Song was invalid ZIP file")?;
+    let zip = ZipFile::open(song_handle).context("Song was invalid ZIP file")?;
 
-    if zip.contains_file("info.dat") || zip.contains_file("Info.dat") {
-        let extract_path = Path::new(paths::CUSTOM_LEVELS)
-            .join(from_path.file_stem().expect("Must have file stem"));
+    let song_folders = get_song_folders(&zip);
+    if song_folders.is_empty() {
+        return Err(anyhow!(
+            "ZIP file was not a song; Unclear know how to import it"
+        ));
+    }
+
+    let entry_names = zip
+        .iter_entry_names()
+        // TODO: This is not nice for performance
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
 
+    // Every path is checked before anything is deleted or extracted, so that a malicious ZIP cannot
+    // delete or write files outside of the song's folder.
+    let mut songs = Vec::new();
+    let mut to_extract = Vec::new();
+    for folder in &song_folders {
+        check_song_path(folder.trim_end_matches('/'), true)
+            .with_context(|| format!("Song folder {folder} is not allowed"))?;
+
+        // A song at the root of the ZIP is named after the ZIP, otherwise songs are named after their folder.
+        let song_name = match folder.trim_end_matches('/').rsplit('/').next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => from_path
+                .file_stem()
+                .ok_or(anyhow!("Song file had no name"))?
+                .to_string_lossy()
+                .to_string(),
+        };
+        check_song_path(&song_name, false)
+            .with_context(|| format!("Song name {song_name} is not allowed"))?;
+        let extract_path = Path::new(paths::CUSTOM_LEVELS).join(&song_name);
+
+        for entry_name in &entry_names {
+            match entry_name.strip_prefix(folder.as_str()) {
+                Some(path) if !path.is_empty() && !path.ends_with('/') => {
+                    check_song_path(path, false)
+                        .with_context(|| format!("Song file {entry_name} is not allowed"))?;
+                    to_extract.push((entry_name.clone(), extract_path.join(path)))
+                }
+                _ => {}
+            }
+        }
+        songs.push((song_name, extract_path));
+    }
+
+    for (song_name, extract_path) in &songs {
+        info!("Importing song {song_name}");
         if extract_path.exists() {
-            std::fs::remove_dir_all(&extract_path).context("Deleting existing song")?;
+            std::fs::remove_dir_all(extract_path).context("Deleting existing song")?;
         }
 
-        std::fs::create_dir_all(&extract_path)?;
-        let entry_names = zip
-            .iter_entry_names()
-            // TODO: This is not nice for performance
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
-        for entry_name in entry_names {
-            zip.extract_file_to(&entry_name, extract_path.join(&entry_name))?;
+        std::fs::create_dir_all(extract_path)?;
+    }
+
+    // Large songs can contain many files, so these are extracted in parallel.
+    let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+    zip.extract_files_parallel(&to_extract, threads)
+        .context("Extracting song files")?;
+
+    drop(zip);
+    std::fs::remove_file(from_path)?;
+    Ok(ImportResultType::ImportedSong {
+        imported_count: song_folders.len(),
+    })
+}
+
+/// Checks that a relative path from a song ZIP (separated by `/`) stays within the folder it is joined to,
+/// i.e. that it is not absolute and has no `..`, `.` or empty components.
+/// If `allow_empty` is true, an empty path (referring to the folder itself) is allowed.
+fn check_song_path(path: &str, allow_empty: bool) -> Result<()> {
+    if path.is_empty() {
+        return if allow_empty {
+            Ok(())
+        } else {
+            Err(anyhow!("Path is empty"))
+        };
+    }
+    if path.starts_with('/') {
+        return Err(anyhow!("Path {path} is absolute"));
+    }
+
+    match path
+        .split('/')
+        .find(|component| component.is_empty() || *component == "." || *component == "..")
+    {
+        Some(component) => Err(anyhow!("Path {path} contains component `{component}`")),
+        None => Ok(()),
+    }
+}
+
+/// Finds the folders within a ZIP file that contain a song, i.e. that contain an `info.dat` or `Info.dat` file.
+///
+/// # Returns
+/// The path of each folder, including a trailing `/`, or an empty string if the song is at the root of the ZIP.
+/// If there is a song at the root of the ZIP, this is the only folder returned since the whole ZIP is the song.
+fn get_song_folders<T: std::io::Read + std::io::Seek>(zip: &ZipFile<T>) -> Vec<String> {
+    if zip.contains_file("info.dat") || zip.contains_file("Info.dat") {
+        return vec![String::new()];
+    }
+
+    let mut folders: Vec<String> = zip
+        .iter_entry_names()
+        .filter_map(|name| {
+            let (folder, file_name) = name.rsplit_once('/')?;
+            if file_name.eq_ignore_ascii_case("info.dat") {
+                Some(format!("{folder}/"))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    // Songs nested within another song's folder are extracted along with that song.
+    folders.sort();
+    folders.dedup();
+    let mut top_level: Vec<String> = Vec::new();
+    for folder in folders {
+        if !top_level
+            .iter()
+            .any(|parent| folder.starts_with(parent.as_str()))
+        {
+            top_level.push(folder);
         }
+    }
 
-        drop(zip);
-        std::fs::remove_file(from_path)?;
-        Ok(ImportResultType::ImportedSong)
-    } else {
-        Err(anyhow!(
-            "ZIP file was not a song; Unclear know how to import it"
-        ))
+    top_level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_song_path;
+
+    #[test]
+    fn song_paths_within_folder_are_allowed() {
+        assert!(check_song_path("Info.dat", false).is_ok());
+        assert!(check_song_path("song/cover.png", false).is_ok());
+        assert!(check_song_path("", true).is_ok());
+    }
+
+    #[test]
+    fn song_paths_escaping_folder_are_rejected() {
+        for path in [
+            "..",
+            ".",
+            "",
+            "/sdcard/x",
+            "song/../../x",
+            "song//x",
+            "./Info.dat",
+        ] {
+            assert!(check_song_path(path, false).is_err(), "{path} was allowed");
+        }
     }
 }
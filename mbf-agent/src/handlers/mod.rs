@@ -1,17 +1,24 @@
 //! Responsible for handling all requests sent to the backend (`mbf-agent`) from the frontend.
 
-use std::{io::Cursor, process::Command};
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    path::Path,
+    process::Command,
+};
 
 use crate::{
     downloads,
-    mod_man::ModManager,
+    manifest::ManifestOptions,
+    mod_man::{CoreModBundle, ModManager},
     models::{
-        request::Request,
-        response::{self, Response},
+        request::{OperationLock, Request, RequestEnvelope},
+        response::{self, ErrorCode, Response},
+        PROTOCOL_VERSION,
     },
+    paths,
 };
 use anyhow::{anyhow, Context, Result};
-use log::info;
+use log::{info, warn};
 use mbf_res_man::res_cache::ResCache;
 
 mod import;
@@ -21,14 +28,31 @@ mod patching;
 mod utility;
 
 /// Handles a [Request] from the MBF frontend.
+/// Requests sent with a different protocol version to [PROTOCOL_VERSION] are rejected with [ErrorCode::IncompatibleProtocol].
 ///
 /// # Arguments
-/// * `request` - The request to be handled.
+/// * `envelope` - The request to be handled, along with the protocol version of the frontend that sent it.
 ///
 /// # Returns
 /// If successful, a [Response] to be sent back to the frontend.
-pub fn handle_request(request: Request) -> Result<Response> {
-    match request {
+pub fn handle_request(envelope: RequestEnvelope) -> Result<Response> {
+    if let Some(protocol_version) = envelope.protocol_version {
+        if protocol_version != PROTOCOL_VERSION {
+            return Err(anyhow!(
+                "Frontend uses protocol version {protocol_version}, but this agent (v{}) uses protocol version {PROTOCOL_VERSION}",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .context(ErrorCode::IncompatibleProtocol);
+        }
+    }
+
+    // The lock is released once the request has been handled and the file is closed.
+    let _lock = match envelope.request.operation_lock() {
+        OperationLock::None => None,
+        lock => Some(lock_operations(lock)?),
+    };
+
+    match envelope.request {
         Request::GetModStatus {
             override_core_mod_url,
         } => mod_status::handle_get_mod_status(override_core_mod_url),
@@ -36,29 +60,125 @@ pub fn handle_request(request: Request) -> Result<Response> {
             downgrade_to,
             remodding,
             manifest_mod,
+            extra_permissions,
+            debuggable,
+            extract_native_libs,
             allow_no_core_mods,
             override_core_mod_url,
+            override_mod_repo_url,
             vr_splash_path,
+            temp_dir,
+            skip_player_data,
+            core_mods_bundle,
         } => patching::handle_patch(
             downgrade_to,
             remodding,
             manifest_mod,
+            ManifestOptions {
+                extra_permissions,
+                debuggable,
+                extract_native_libs,
+            },
             allow_no_core_mods,
             override_core_mod_url,
+            override_mod_repo_url,
             vr_splash_path,
+            temp_dir,
+            skip_player_data,
+            core_mods_bundle,
         ),
+        Request::GetAgentVersion => utility::handle_get_agent_version(),
+        Request::CheckPrivileges => utility::handle_check_privileges(),
+        Request::VerifyInstall {
+            override_core_mod_url,
+        } => mod_status::handle_verify_install(override_core_mod_url),
+        Request::GetCurrentManifest => patching::handle_get_current_manifest(),
         Request::GetDowngradedManifest { version } => {
             patching::handle_get_downgraded_manifest(version)
         }
         Request::RemoveMod { id } => mod_management::handle_remove_mod(id),
-        Request::SetModsEnabled { statuses } => mod_management::handle_set_mods_enabled(statuses),
-        Request::Import { from_path } => import::handle_import(from_path, None),
-        Request::ImportUrl { from_url } => import::handle_import_mod_url(from_url),
+        Request::GetUninstallImpact { id } => mod_management::handle_get_uninstall_impact(id),
+        Request::ReinstallMod { id } => mod_management::handle_reinstall_mod(id),
+        Request::GetModFiles { id } => mod_management::handle_get_mod_files(id),
+        Request::GetModConfig { id } => mod_management::handle_get_mod_config(id),
+        Request::SetModConfig { id, config } => mod_management::handle_set_mod_config(id, config),
+        Request::SetModPolicy { policy } => mod_management::handle_set_mod_policy(policy),
+        Request::RepairMods => mod_management::handle_repair_mods(),
+        Request::GetVersionModDirs => mod_management::handle_get_version_mod_dirs(),
+        Request::DeleteOtherVersionMods => mod_management::handle_delete_other_version_mods(),
+        Request::SetModsEnabled {
+            statuses,
+            override_mod_repo_url,
+        } => mod_management::handle_set_mods_enabled(statuses, override_mod_repo_url),
+        Request::SetModsDisabled { statuses } => mod_management::handle_set_mods_disabled(statuses),
+        Request::Import {
+            from_path,
+            override_mod_repo_url,
+            expected_size,
+        } => import::handle_import(from_path, None, override_mod_repo_url, expected_size),
+        Request::ImportDirectory {
+            path,
+            override_mod_repo_url,
+        } => import::handle_import_directory(path, override_mod_repo_url),
+        Request::ImportUrl {
+            from_url,
+            override_mod_repo_url,
+        } => import::handle_import_mod_url(from_url, override_mod_repo_url),
         Request::FixPlayerData => utility::handle_fix_player_data(),
+        Request::DeleteOrphanedObbs => utility::handle_delete_orphaned_obbs(),
+        Request::GetCoreModsForVersion {
+            version,
+            override_core_mod_url,
+        } => mod_status::handle_get_core_mods_for_version(version, override_core_mod_url),
+        Request::ReadLogs { max_bytes } => utility::handle_read_logs(max_bytes),
+        Request::ExportDiagnostics {
+            override_core_mod_url,
+        } => utility::handle_export_diagnostics(override_core_mod_url),
         Request::QuickFix {
             override_core_mod_url,
+            override_mod_repo_url,
             wipe_existing_mods,
-        } => utility::handle_quick_fix(override_core_mod_url, wipe_existing_mods),
+            core_mods_bundle,
+        } => utility::handle_quick_fix(
+            override_core_mod_url,
+            override_mod_repo_url,
+            wipe_existing_mods,
+            core_mods_bundle,
+        ),
+    }
+}
+
+/// Takes a lock on [paths::OPERATION_LOCK], so that requests that modify the installation cannot run at the same time
+/// as each other or as requests that read the installed mods, e.g. if the frontend sends a patch request or polls the
+/// mod status while an import is still running. See [OperationLock] for which requests take which lock.
+/// The lock is held until the returned file is closed, which also happens if the agent exits or crashes.
+///
+/// # Returns
+/// The locked file if successful, or an error with [ErrorCode::OperationInProgress] if another request holds a
+/// conflicting lock.
+fn lock_operations(lock: OperationLock) -> Result<File> {
+    let lock_path = Path::new(paths::OPERATION_LOCK);
+    if let Some(parent) = lock_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)
+        .context("Opening operation lock file")?;
+    let lock_result = match lock {
+        OperationLock::Shared => lock_file.try_lock_shared(),
+        _ => lock_file.try_lock(),
+    };
+    match lock_result {
+        Ok(()) => Ok(lock_file),
+        Err(TryLockError::WouldBlock) => Err(anyhow!(
+            "Another operation is in progress. Wait for it to finish, then try again"
+        )
+        .context(ErrorCode::OperationInProgress)),
+        Err(TryLockError::Error(err)) => Err(err).context("Locking operation lock file"),
     }
 }
 
@@ -73,22 +193,57 @@ fn get_app_version_only() -> Result<String> {
         .args(["package", crate::APK_ID])
         .output()
         .context("Invoking dumpsys")?;
-    let dumpsys_stdout =
-        String::from_utf8(dumpsys_output.stdout).context("Converting dumpsys output to UTF-8")?;
-
-    let version_offset = match dumpsys_stdout.find("versionName=") {
-        Some(offset) => offset,
-        None => return Err(anyhow!("Beat Saber was not installed")),
-    } + 12;
-
-    let newline_offset = version_offset
-        + dumpsys_stdout[version_offset..]
-            .find('\n')
-            .ok_or(anyhow!("No newline after version name"))?;
-
-    Ok(dumpsys_stdout[version_offset..newline_offset]
-        .trim()
-        .to_string())
+    let dumpsys_stdout = String::from_utf8_lossy(&dumpsys_output.stdout);
+
+    parse_dumpsys_version(&dumpsys_stdout)
+}
+
+/// Finds the version name of the app in the output of `dumpsys package`.
+///
+/// The format of this output varies between Android builds, so both `versionName=<version>` and `versionName: <version>`
+/// are accepted, and anything after the version on the same line is ignored.
+///
+/// # Returns
+/// The version, if one was found that looks like a Beat Saber version (e.g. `1.37.0_9064817954`).
+/// If the version name found doesn't look like a Beat Saber version, the `Err` contains the line it was found on.
+fn parse_dumpsys_version(dumpsys_output: &str) -> Result<String> {
+    let version_line = dumpsys_output
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("versionName"))
+        .ok_or(ErrorCode::NotInstalled)?;
+
+    let version = version_line["versionName".len()..]
+        .trim_start_matches(|c: char| c == '=' || c == ':' || c.is_whitespace())
+        .split_whitespace()
+        .next()
+        .unwrap_or("");
+
+    if is_bs_version(version) {
+        Ok(version.to_string())
+    } else {
+        Err(anyhow!(
+            "Could not parse app version from dumpsys output line `{version_line}`"
+        ))
+    }
+}
+
+/// Checks if `version` has the format of a Beat Saber version: a semver-like version number made of
+/// at least two dot-separated numbers, optionally followed by an `_` and a build number suffix.
+fn is_bs_version(version: &str) -> bool {
+    let (number, suffix) = match version.split_once('_') {
+        Some((number, suffix)) => (number, Some(suffix)),
+        None => (version, None),
+    };
+
+    let parts: Vec<&str> = number.split('.').collect();
+    parts.len() >= 2
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+        && suffix.is_none_or(|suffix| {
+            !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
 }
 
 /// Ensures that all core mods are installed and up to date.
@@ -102,45 +257,105 @@ fn get_app_version_only() -> Result<String> {
 /// * `app_info` - Details about the installed Beat Saber app.
 /// * `override_core_mod_url` - If this is a `Some` variant, the function will use this URL to download the core mod JSON, instead of using the default
 /// core mods URL. This can be useful for development purposes.
+/// * `core_mods_bundle` - If this is a `Some` variant, the path to a [CoreModBundle] whose index is used instead of fetching
+///   the core mods JSON, and whose QMODs are installed instead of downloading them.
 fn install_core_mods(
     res_cache: &ResCache,
     mod_manager: &mut ModManager,
     app_info: response::AppInfo,
     override_core_mod_url: Option<String>,
+    core_mods_bundle: Option<&str>,
 ) -> Result<()> {
     info!("Preparing core mods");
-    let core_mod_index =
-        mbf_res_man::external_res::fetch_core_mods(&res_cache, override_core_mod_url)?;
+    let mut bundle = core_mods_bundle
+        .map(CoreModBundle::open)
+        .transpose()
+        .context("Opening core mod bundle")?;
+    let core_mod_index = match &mut bundle {
+        Some(bundle) => bundle.read_index()?,
+        None => mbf_res_man::external_res::fetch_core_mods(&res_cache, override_core_mod_url)?,
+    };
 
     let core_mods = core_mod_index
         .get(&app_info.version)
-        .ok_or(anyhow!("No core mods existed for {}", app_info.version))?;
+        .ok_or(ErrorCode::NoCoreMods)
+        .with_context(|| format!("No core mods existed for {}", app_info.version))?;
 
-    for core_mod in &core_mods.mods {
-        // Check if there is already an existing mod.
-        match mod_manager.get_mod(&core_mod.id) {
-            Some(existing) => {
-                let existing_ref = existing.borrow();
-                if existing_ref.manifest().version >= core_mod.version {
-                    info!(
-                        "Core mod {} was already installed with new enough version: {}",
-                        core_mod.id,
-                        existing_ref.manifest().version
-                    );
-                    continue;
+    mod_manager.permit_core_mods(core_mods.mods.iter().map(|core_mod| core_mod.id.as_str()));
+    let to_download: Vec<_> = core_mods
+        .mods
+        .iter()
+        .filter(|core_mod| {
+            // Check if there is already an existing mod.
+            match mod_manager.get_mod(&core_mod.id) {
+                Some(existing) => {
+                    let existing_ref = existing.borrow();
+                    if existing_ref.manifest().version >= core_mod.version {
+                        info!(
+                            "Core mod {} was already installed with new enough version: {}",
+                            core_mod.id,
+                            existing_ref.manifest().version
+                        );
+                        return false;
+                    }
+                    true
                 }
+                None => true,
+            }
+        })
+        .collect();
+
+    for (idx, core_mod) in to_download.iter().enumerate() {
+        if let Some(bundle) = &mut bundle {
+            if let Some(mod_data) = bundle.read_mod(core_mod)? {
+                info!(
+                    "Installing {} v{} from bundle",
+                    core_mod.id, core_mod.version
+                );
+                mod_manager
+                    .try_load_new_mod(mod_data)
+                    .context("Loading core mod from bundle")?;
+                continue;
             }
-            None => {}
+            warn!(
+                "Core mod bundle did not contain {}, so downloading it",
+                core_mod.id
+            );
         }
 
         info!("Downloading {} v{}", core_mod.id, core_mod.version);
+        crate::report_item_progress(
+            "Downloading core mods",
+            &core_mod.id,
+            Some(idx as f32 / to_download.len() as f32),
+            0,
+            None,
+        );
 
-        let core_mod_vec =
-            downloads::download_to_vec_with_attempts(&crate::get_dl_cfg(), &core_mod.download_url)
-                .context("Downloading core mod")?;
-        let result = mod_manager.try_load_new_mod(Cursor::new(core_mod_vec));
-        // Delete the temporary file either way
-        result?;
+        let expected_sha256 = core_mod
+            .sha256
+            .as_deref()
+            .map(downloads::parse_sha256)
+            .transpose()
+            .context("Core mod index contained an invalid hash")?;
+        // The fraction of all the core mods downloaded includes the fraction of this mod downloaded so far.
+        let on_progress = |bytes_done: u64, bytes_total: u64| {
+            let mod_fraction = bytes_done as f32 / bytes_total.max(1) as f32;
+            crate::report_item_progress(
+                "Downloading core mods",
+                &core_mod.id,
+                Some((idx as f32 + mod_fraction) / to_download.len() as f32),
+                bytes_done,
+                Some(bytes_total),
+            );
+        };
+        mod_manager
+            .try_download_new_mod_with_progress(
+                &core_mod.download_url,
+                expected_sha256,
+                &on_progress,
+            )
+            .context("Downloading core mod")?;
     }
 
     info!("Installing core mods");
@@ -154,3 +369,39 @@ fn install_core_mods(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumpsys_version_with_equals() {
+        let output = "Packages:\n  Package [com.beatgames.beatsaber]:\n    versionCode=1130 minSdk=29\n    versionName=1.37.0_9064817954\n";
+        assert_eq!(parse_dumpsys_version(output).unwrap(), "1.37.0_9064817954");
+    }
+
+    #[test]
+    fn dumpsys_version_with_colon() {
+        assert_eq!(
+            parse_dumpsys_version("    versionName: 1.28.0_4124311467").unwrap(),
+            "1.28.0_4124311467"
+        );
+    }
+
+    #[test]
+    fn dumpsys_version_ignores_trailing_text() {
+        assert_eq!(
+            parse_dumpsys_version("versionName=1.37.0_9064817954 splits=[base]").unwrap(),
+            "1.37.0_9064817954"
+        );
+    }
+
+    #[test]
+    fn dumpsys_version_must_look_like_beat_saber_version() {
+        assert!(parse_dumpsys_version("versionName=null").is_err());
+        assert!(parse_dumpsys_version("versionName=").is_err());
+        // No version line means that the app is not installed.
+        let err = parse_dumpsys_version("Unable to find package").unwrap_err();
+        assert!(err.downcast_ref::<ErrorCode>().is_some());
+    }
+}
@@ -1,23 +1,32 @@
 //! This file contains the request handlers relating to mod management (i.e. toggling or removing mods).
 //! Adding new mods is considered to be an "importing" operation - check the [Import Handlers](crate::handlers::import)
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    mod_man::ModManager,
-    models::response::{ModModel, Response},
+    mod_man::{ModManager, ModPolicy},
+    models::response::{ModModel, Response, VersionModsDir},
+    paths,
 };
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use log::info;
 
 /// Handles `SetModsEnabled` [Requests](crate::requests::Request).
 ///
 /// # Returns
 /// The [Response] to the request (variant `ModSyncResult`)
-pub(super) fn handle_set_mods_enabled(statuses: HashMap<String, bool>) -> Result<Response> {
+pub(super) fn handle_set_mods_enabled(
+    statuses: HashMap<String, bool>,
+    override_mod_repo_url: Option<String>,
+) -> Result<Response> {
     let res_cache = crate::load_res_cache()?;
 
     let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.set_override_mod_repo_url(override_mod_repo_url);
     mod_manager.load_mods().context("Loading installed mods")?;
 
     let mut error = String::new();
@@ -59,6 +68,42 @@ pub(super) fn handle_set_mods_enabled(statuses: HashMap<String, bool>) -> Result
     })
 }
 
+/// Handles `SetModsDisabled` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModSyncResult`)
+pub(super) fn handle_set_mods_disabled(statuses: HashMap<String, bool>) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    let mut failures = Vec::new();
+    for (id, disabled) in statuses {
+        let result = if disabled {
+            mod_manager.disable_mod(&id)
+        } else {
+            mod_manager.enable_mod(&id)
+        };
+
+        if let Err(err) = result {
+            failures.push(format!(
+                "Failed to {} {id}: {err}",
+                if disabled { "disable" } else { "enable" }
+            ));
+        }
+    }
+
+    Ok(Response::ModSyncResult {
+        installed_mods: get_mod_models(mod_manager)?,
+        failures: if failures.is_empty() {
+            None
+        } else {
+            Some(failures.join("\n"))
+        },
+    })
+}
+
 /// Handles `RemoveMod` [Requests](crate::requests::Request).
 ///
 /// # Returns
@@ -74,6 +119,197 @@ pub(super) fn handle_remove_mod(id: String) -> Result<Response> {
     })
 }
 
+/// Handles `GetUninstallImpact` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `UninstallImpact`)
+pub(super) fn handle_get_uninstall_impact(id: String) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+    let uninstalled_ids = mod_manager.uninstall_impact(&id);
+
+    Ok(Response::UninstallImpact {
+        id,
+        uninstalled_ids,
+    })
+}
+
+/// Handles `ReinstallMod` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `Mods`)
+pub(super) fn handle_reinstall_mod(id: String) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+    mod_manager
+        .reinstall_mod(&id)
+        .with_context(|| format!("Reinstalling {id}"))?;
+
+    Ok(Response::Mods {
+        installed_mods: get_mod_models(mod_manager)?,
+    })
+}
+
+/// Handles `GetModFiles` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModFiles`)
+pub(super) fn handle_get_mod_files(id: String) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+    let paths = mod_manager
+        .get_mod(&id)
+        .ok_or(anyhow!("No mod with ID {id} exists"))?
+        .borrow()
+        .installed_paths()
+        .into_iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+
+    Ok(Response::ModFiles { id, paths })
+}
+
+/// Handles `GetModConfig` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModConfig`)
+pub(super) fn handle_get_mod_config(id: String) -> Result<Response> {
+    let config_path = get_mod_config_path(&id)?;
+    let config = if config_path.exists() {
+        let config_data = std::fs::read(&config_path).context("Reading mod config")?;
+        Some(serde_json::from_slice(&config_data).context("Mod config was invalid JSON")?)
+    } else {
+        None
+    };
+
+    Ok(Response::ModConfig { id, config })
+}
+
+/// Handles `SetModConfig` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModConfig`)
+pub(super) fn handle_set_mod_config(
+    id: String,
+    config: Option<serde_json::Value>,
+) -> Result<Response> {
+    let config_path = get_mod_config_path(&id)?;
+    match &config {
+        Some(config) => {
+            if !config.is_object() {
+                return Err(anyhow!("Config for {id} must be a JSON object"));
+            }
+
+            info!("Saving config for {id}");
+            std::fs::create_dir_all(paths::MOD_CONFIGS).context("Creating configs directory")?;
+            let config_data =
+                serde_json::to_vec_pretty(config).context("Serializing mod config")?;
+            std::fs::write(&config_path, config_data).context("Writing mod config")?;
+        }
+        None => {
+            if config_path.exists() {
+                info!("Removing config for {id}");
+                std::fs::remove_file(&config_path).context("Removing mod config")?;
+            }
+        }
+    }
+
+    Ok(Response::ModConfig { id, config })
+}
+
+// Gets the path of the config file for the mod with the given ID, giving an error if no such mod is loaded.
+fn get_mod_config_path(id: &str) -> Result<PathBuf> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+    if mod_manager.get_mod(id).is_none() {
+        return Err(anyhow!("No mod with ID {id} exists"));
+    }
+
+    // The ID is used as a file name, so must not contain a path.
+    if Path::new(id).file_name() != Some(OsStr::new(id)) {
+        return Err(anyhow!("Mod ID {id} cannot be used as a config file name"));
+    }
+
+    Ok(Path::new(paths::MOD_CONFIGS).join(format!("{id}.json")))
+}
+
+/// Handles `SetModPolicy` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModPolicy`)
+pub(super) fn handle_set_mod_policy(policy: Option<ModPolicy>) -> Result<Response> {
+    match &policy {
+        Some(policy) if policy.allowlist => {
+            info!("Only allowing mods {:?} to be installed", policy.mod_ids)
+        }
+        Some(policy) => info!("Blocking mods {:?} from being installed", policy.mod_ids),
+        None => info!("Removing mod policy"),
+    }
+    ModPolicy::save(policy.as_ref()).context("Saving mod policy")?;
+
+    Ok(Response::ModPolicy { policy })
+}
+
+/// Handles `RepairMods` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModsRepaired`)
+pub(super) fn handle_repair_mods() -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+    let (repaired, unrepairable) = mod_manager.repair_mods().context("Repairing mods")?;
+
+    Ok(Response::ModsRepaired {
+        installed_mods: get_mod_models(mod_manager)?,
+        repaired,
+        unrepairable,
+    })
+}
+
+/// Handles `GetVersionModDirs` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `VersionModDirs`)
+pub(super) fn handle_get_version_mod_dirs() -> Result<Response> {
+    let current_version = super::get_app_version_only()?;
+    let dirs = ModManager::get_version_mod_dirs()
+        .context("Listing QMOD directories")?
+        .into_iter()
+        .map(|dir| VersionModsDir {
+            is_current: dir.game_version == current_version,
+            game_version: dir.game_version,
+            size: dir.size,
+        })
+        .collect();
+
+    Ok(Response::VersionModDirs { dirs })
+}
+
+/// Handles `DeleteOtherVersionMods` [Requests](crate::requests::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `DeletedOtherVersionMods`)
+pub(super) fn handle_delete_other_version_mods() -> Result<Response> {
+    // The current version must be known, otherwise the QMODs of the installed mods could be deleted.
+    let res_cache = crate::load_res_cache()?;
+    let mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    let removed = mod_manager
+        .remove_other_version_mod_dirs()
+        .context("Deleting QMODs for other game versions")?;
+
+    let freed_bytes = removed.iter().map(|dir| dir.size).sum::<u64>();
+    info!("Freed {} MiB", freed_bytes / (1024 * 1024));
+    Ok(Response::DeletedOtherVersionMods {
+        deleted_versions: removed.into_iter().map(|dir| dir.game_version).collect(),
+        freed_bytes,
+    })
+}
+
 /// Consumes a [ModManager] and converts the loaded mods into [ModModels](ModModel) which can be serialized
 /// to JSON and sent back to the frontend.
 pub(super) fn get_mod_models(mut mod_manager: ModManager) -> Result<Vec<ModModel>> {
@@ -1,22 +1,23 @@
 //! Handler for the GetModStatus request.
 
-use std::{fs::File, io::Cursor};
+use std::{io::Cursor, path::Path};
 
 use log::{error, info, warn};
 use mbf_res_man::{
     models::{CoreMod, VersionDiffs},
     res_cache::{self, ResCache},
 };
-use mbf_zip::ZipFile;
+use mbf_zip::ReadOnlyZipFile;
 
 use crate::{
     axml::{self, AxmlReader},
     manifest::ManifestInfo,
     mod_man::ModManager,
-    models::response::{self, CoreModsInfo, Response},
-    patching,
+    models::response::{self, CoreModsInfo, InstallCheck, ModLoader, Response},
+    patching, paths,
 };
 use anyhow::{Context, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
 
 /// Handles `GetModStatus` [Requests](response::Request).
 ///
@@ -52,44 +53,355 @@ pub(super) fn handle_get_mod_status(override_core_mod_url: Option<String>) -> Re
         }
     };
 
+    let mut space_paths = paths::TEMP_DIRS.to_vec();
+    space_paths.extend([paths::MODLOADER_DIR, paths::OBB_DIR]);
+    if let Some(apk_dir) = app_info
+        .as_ref()
+        .and_then(|app_info| Path::new(&app_info.path).parent())
+        .and_then(Path::to_str)
+    {
+        space_paths.push(apk_dir);
+    }
+    let free_space = match patching::get_free_space_by_volume(&space_paths) {
+        Ok(free_space) => free_space,
+        Err(err) => {
+            warn!("Failed to get free space: {err:?}");
+            Vec::new()
+        }
+    };
+
+    // The APK is checked too, in case the libmain.so within it is out of date.
+    let mut apk = match &app_info {
+        Some(app_info) => {
+            Some(ReadOnlyZipFile::open_readonly(&app_info.path).context("Reading APK as ZIP")?)
+        }
+        None => None,
+    };
+    let modloader_install_status = patching::get_modloader_status(apk.as_mut())?;
+
+    // OBBs left behind by a previous install take up space for no reason.
+    let orphaned_obb_size = if app_info.is_none() {
+        let obb_size = get_obbs_size().context("Checking for OBBs left from an old install")?;
+        if obb_size > 0 {
+            warn!(
+                "Beat Saber is not installed, but {} MiB of OBBs remain from a previous install",
+                obb_size / (1024 * 1024)
+            );
+            Some(obb_size)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    let needs_loader_conversion = app_info.as_ref().is_some_and(needs_loader_conversion);
+    if needs_loader_conversion {
+        warn!("App is patched with an incompatible modloader, so vanilla Beat Saber must be reinstalled before modding");
+    }
+
     Ok(Response::ModStatus {
         app_info,
         core_mods,
-        modloader_install_status: patching::get_modloader_status()?,
+        modloader_install_status,
         installed_mods,
+        free_space,
+        needs_loader_conversion,
+        orphaned_obb_size,
+    })
+}
+
+/// Handles `VerifyInstall` [Requests](response::Request).
+///
+/// # Returns
+/// The [Response](response::Response) to the request (variant `InstallVerified`)
+pub(super) fn handle_verify_install(override_core_mod_url: Option<String>) -> Result<Response> {
+    Ok(Response::InstallVerified {
+        checks: verify_install(override_core_mod_url)?,
     })
 }
 
+/// Checks each part of the modded installation, as described for `VerifyInstall` [Requests](response::Request).
+///
+/// # Returns
+/// The checks made, in the order they were made.
+pub(super) fn verify_install(override_core_mod_url: Option<String>) -> Result<Vec<InstallCheck>> {
+    let mut checks = Vec::new();
+
+    info!("Searching for Beat Saber app");
+    let app_info = match get_app_info()? {
+        Some(app_info) => app_info,
+        None => {
+            checks.push(failed_check(
+                "Beat Saber installed",
+                "Beat Saber is not installed",
+                "Install Beat Saber from the store",
+            ));
+            return Ok(checks);
+        }
+    };
+    checks.push(passed_check("Beat Saber installed"));
+
+    let mut apk = ReadOnlyZipFile::open_readonly(&app_info.path).context("Reading APK as ZIP")?;
+    match app_info.loader_installed {
+        Some(ModLoader::Scotland2) => {
+            checks.push(passed_check("APK patched"));
+            if patching::is_lib_main_outdated(&mut apk)? {
+                checks.push(failed_check(
+                    "libmain.so up to date",
+                    "The APK was patched with an older libmain.so",
+                    "Repatch the game",
+                ));
+            } else {
+                checks.push(passed_check("libmain.so up to date"));
+            }
+        }
+        Some(_) => checks.push(failed_check(
+            "APK patched",
+            "The APK is patched with a modloader other than Scotland2",
+            "Uninstall Beat Saber, reinstall the vanilla game and then patch it",
+        )),
+        None => checks.push(failed_check(
+            "APK patched",
+            "The APK is not patched",
+            "Patch the game",
+        )),
+    }
+
+    match patching::get_modloader_status(None)? {
+        response::InstallStatus::Ready => checks.push(passed_check("Modloader installed")),
+        response::InstallStatus::NeedUpdate => checks.push(failed_check(
+            "Modloader installed",
+            "The installed modloader is outdated",
+            "Use the \"Fix issues\" button to update the modloader",
+        )),
+        // Only given when an APK is passed in, and libmain.so has already been checked above.
+        response::InstallStatus::NeedsRepatch => checks.push(failed_check(
+            "Modloader installed",
+            "The APK must be patched again to update the modloader",
+            "Repatch the game",
+        )),
+        response::InstallStatus::Missing => checks.push(failed_check(
+            "Modloader installed",
+            "The modloader is not installed",
+            "Use the \"Fix issues\" button to install the modloader",
+        )),
+    }
+
+    let res_cache = crate::load_res_cache()?;
+    info!("Loading installed mods");
+    let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    info!("Fetching core mod index");
+    match mbf_res_man::external_res::fetch_core_mods(&res_cache, override_core_mod_url) {
+        Ok(core_mods) => match core_mods.get(&app_info.version) {
+            Some(core_mods) => match get_core_mods_install_status(&core_mods.mods, &mod_manager) {
+                response::InstallStatus::Ready => checks.push(passed_check("Core mods installed")),
+                response::InstallStatus::NeedUpdate | response::InstallStatus::NeedsRepatch => {
+                    checks.push(failed_check(
+                        "Core mods installed",
+                        "Some core mods are outdated",
+                        "Use the \"Fix issues\" button to update the core mods",
+                    ))
+                }
+                response::InstallStatus::Missing => checks.push(failed_check(
+                    "Core mods installed",
+                    "Some core mods are not installed",
+                    "Use the \"Fix issues\" button to install the core mods",
+                )),
+            },
+            None => checks.push(failed_check(
+                "Core mods installed",
+                &format!("No core mods exist for Beat Saber {}", app_info.version),
+                "Downgrade to a version of Beat Saber that supports mods",
+            )),
+        },
+        Err(err) => {
+            error!("Failed to fetch core mod index: {err:?}");
+            checks.push(failed_check(
+                "Core mods installed",
+                &format!("Could not fetch the core mod index: {err}"),
+                "Check your internet connection and try again",
+            ));
+        }
+    }
+
+    let orphaned_libs = mod_manager
+        .get_orphaned_libs()
+        .context("Checking for orphaned libraries")?;
+    if orphaned_libs.is_empty() {
+        checks.push(passed_check("No orphaned libraries"));
+    } else {
+        checks.push(failed_check(
+            "No orphaned libraries",
+            &format!(
+                "Libraries not belonging to any mod were found: {}",
+                orphaned_libs.join(", ")
+            ),
+            "Remove these libraries, or reinstall the mods they belong to",
+        ));
+    }
+
+    if patching::check_obb_present()? {
+        checks.push(passed_check("OBB present"));
+    } else {
+        checks.push(failed_check(
+            "OBB present",
+            "The game's OBB file is missing, so the game will not load",
+            "Uninstall Beat Saber, reinstall it from the store and then patch it",
+        ));
+    }
+
+    Ok(checks)
+}
+
+fn passed_check(name: &str) -> InstallCheck {
+    InstallCheck {
+        name: name.to_string(),
+        passed: true,
+        details: None,
+        remediation: None,
+    }
+}
+
+fn failed_check(name: &str, details: &str, remediation: &str) -> InstallCheck {
+    InstallCheck {
+        name: name.to_string(),
+        passed: false,
+        details: Some(details.to_string()),
+        remediation: Some(remediation.to_string()),
+    }
+}
+
+// Gets the total size of the OBB files in the OBB directory, in bytes.
+fn get_obbs_size() -> Result<u64> {
+    let mut total = 0;
+    for obb_path in patching::get_obb_paths()? {
+        total += std::fs::metadata(obb_path)?.len();
+    }
+
+    Ok(total)
+}
+
+/// Handles `GetCoreModsForVersion` [Requests](response::Request).
+///
+/// # Returns
+/// The [Response](response::Response) to the request (variant `CoreModsForVersion`)
+pub(super) fn handle_get_core_mods_for_version(
+    version: String,
+    override_core_mod_url: Option<String>,
+) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    info!("Fetching core mod index");
+    let mut core_mod_index =
+        mbf_res_man::external_res::fetch_core_mods(&res_cache, override_core_mod_url)
+            .context("Fetching core mod index")?;
+
+    let (version_present, core_mods) = match core_mod_index.remove(&version) {
+        Some(core_mods) => (true, core_mods.mods),
+        None => {
+            warn!("No core mods exist for {version}");
+            (false, Vec::new())
+        }
+    };
+
+    Ok(Response::CoreModsForVersion {
+        version,
+        version_present,
+        core_mods,
+    })
+}
+
+/// Returns true if the app is patched with a modloader other than Scotland2, e.g. QuestLoader.
+/// Such an app is not vanilla, so cannot be downgraded, and cannot be modded by MBF either, so a vanilla version of
+/// the game must be reinstalled first.
+pub(super) fn needs_loader_conversion(app_info: &response::AppInfo) -> bool {
+    matches!(
+        app_info.loader_installed,
+        Some(ModLoader::QuestLoader | ModLoader::Unknown)
+    )
+}
+
 pub(super) fn get_app_info() -> Result<Option<response::AppInfo>> {
-    let apk_path = match crate::get_apk_path().context("Finding APK path")? {
-        Some(path) => path,
+    let (apk_path, split_paths) = match crate::get_installed_apks().context("Finding APK path")? {
+        Some(apks) => (apks.base, apks.splits),
         None => return Ok(None),
     };
 
-    let apk_reader = std::fs::File::open(&apk_path)?;
-    let mut apk = ZipFile::open(apk_reader).context("Reading APK as ZIP")?;
+    let mut apk = ReadOnlyZipFile::open_readonly(&apk_path).context("Reading APK as ZIP")?;
 
-    let modloader = patching::get_modloader_installed(&mut apk)?;
+    let modloader = patching::get_modloader_installed(&mut apk)?.map(|patched| patched.loader);
     let obb_present = patching::check_obb_present()?;
+    let signature_schemes = patching::get_signature_schemes(&mut apk)?;
+    if signature_schemes.is_empty() {
+        warn!("Installed APK has no detectable signature");
+    } else {
+        info!("Installed APK is signed with {signature_schemes:?}");
+    }
 
     let (manifest_info, manifest_xml) = get_manifest_info_and_xml(&mut apk)?;
+    let (label, icon_png) = get_label_and_icon(&mut apk, &manifest_info);
     Ok(Some(response::AppInfo {
         loader_installed: modloader,
         version: manifest_info.package_version,
         obb_present,
         path: apk_path,
         manifest_xml,
+        apk_size: apk.archive_size(),
+        permissions: manifest_info.permissions,
+        min_sdk_version: manifest_info.min_sdk_version,
+        target_sdk_version: manifest_info.target_sdk_version,
+        label,
+        icon_png,
+        signature_schemes,
+        split_paths,
     }))
 }
 
-fn get_manifest_info_and_xml(apk: &mut ZipFile<File>) -> Result<(ManifestInfo, String)> {
+// Gets the label of the app and its icon as a base64 encoded PNG, looking up any resources they refer to.
+// These are only for display, so a failure to find them is logged rather than failing the request.
+fn get_label_and_icon(
+    apk: &mut ReadOnlyZipFile,
+    manifest_info: &ManifestInfo,
+) -> (Option<String>, Option<String>) {
+    let resources = match patching::read_resource_table(apk) {
+        Ok(resources) => resources,
+        Err(err) => {
+            warn!("Failed to read resource table, so could not find app label or icon: {err:?}");
+            None
+        }
+    };
+
+    let label = manifest_info
+        .label
+        .as_ref()
+        .and_then(|label| patching::resolve_app_label(label, resources.as_ref()));
+    let icon_png = match (manifest_info.icon, &resources) {
+        (Some(icon_id), Some(resources)) => {
+            match patching::read_app_icon(apk, icon_id, resources) {
+                Ok(icon) => icon.map(|icon| BASE64_STANDARD.encode(icon)),
+                Err(err) => {
+                    warn!("Failed to read app icon: {err:?}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    (label, icon_png)
+}
+
+fn get_manifest_info_and_xml(apk: &mut ReadOnlyZipFile) -> Result<(ManifestInfo, String)> {
     let manifest = apk
         .read_file("AndroidManifest.xml")
         .context("Reading manifest file from APK")?;
 
     // Decode various important information from the manifest
     let mut manifest_reader = Cursor::new(&manifest);
-    let mut axml_reader = AxmlReader::new(&mut manifest_reader)?;
+    // Read leniently so that an invalid string in the manifest doesn't prevent the app's details from being shown.
+    let mut axml_reader = AxmlReader::new_lenient(&mut manifest_reader)?;
     let manifest_info =
         ManifestInfo::read(&mut axml_reader).context("Parsing manifest from AXML")?;
 
@@ -102,7 +414,7 @@ fn get_manifest_info_and_xml(apk: &mut ZipFile<File>) -> Result<(ManifestInfo, S
 
 pub(super) fn axml_bytes_to_xml_string(bytes: &[u8]) -> Result<String> {
     let mut cursor = Cursor::new(bytes);
-    let mut axml_reader = AxmlReader::new(&mut cursor)
+    let mut axml_reader = AxmlReader::new_lenient(&mut cursor)
         .context("File on manifests URI was invalid AXML. Report this!")?;
 
     let mut xml_output = Vec::new();
@@ -142,19 +454,7 @@ fn get_core_mods_info(
         None => response::InstallStatus::Missing,
     };
 
-    let supported_versions: Vec<String> = core_mods
-        .into_keys()
-        .filter(|version| {
-            let mut iter = version.split('.');
-            let _major = iter.next().unwrap();
-            let minor = iter.next().unwrap();
-
-            minor
-                .parse::<i64>()
-                .expect("Invalid version in core mod index")
-                >= 35
-        })
-        .collect();
+    let supported_versions = get_supported_versions(core_mods.into_keys());
     let is_version_supported = supported_versions.iter().any(|ver| ver == apk_version);
 
     // If the app is patched and not vanilla, then it's not possible to downgrade it even if a diff is available for the corresponding vanilla APK
@@ -225,6 +525,21 @@ fn try_parse_bs_ver_as_semver(version: &str) -> Option<semver::Version> {
     semver::Version::parse(version_segment).ok()
 }
 
+// Gets the versions in the core mod index that MBF supports modding, i.e. 1.35.0 and newer.
+fn get_supported_versions(index_versions: impl IntoIterator<Item = String>) -> Vec<String> {
+    index_versions
+        .into_iter()
+        // The index is fetched from the network, so a malformed version is skipped rather than failing the whole request.
+        .filter(|version| match try_parse_bs_ver_as_semver(version) {
+            Some(semver) => semver.minor >= 35,
+            None => {
+                warn!("Skipping invalid version {version} in core mod index");
+                false
+            }
+        })
+        .collect()
+}
+
 // Checks whether all the core mods in the provided slice are present within the mod manager given.
 // Will give InstallStatus::Ready if all core mods are installed and up to date,
 // InstallStatus::NeedUpdate if any core mods are out of date but all are installed, and InstallStatus::Missing if any
@@ -277,3 +592,27 @@ pub(super) fn mark_all_core_mods(mod_manager: &ModManager, core_mods: &[CoreMod]
         mod_manager.set_mod_core(&core_mod.id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_core_mod_index_versions_are_skipped() {
+        let versions = [
+            "1.37.0_9064817954",
+            "1.x.0",
+            "1",
+            "",
+            "not a version",
+            "1.28.0_4124311467",
+            "1.35.0_8016709773",
+        ]
+        .map(str::to_string);
+
+        assert_eq!(
+            get_supported_versions(versions),
+            ["1.37.0_9064817954", "1.35.0_8016709773"]
+        );
+    }
+}
@@ -1,17 +1,59 @@
 //! Handles requests related to the patching of the game.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use log::{info, warn};
 
-use crate::{mod_man::ModManager, models::response::Response, patching, paths};
+use crate::{
+    manifest::ManifestOptions,
+    mod_man::ModManager,
+    models::response::{ErrorCode, Response},
+    patching, paths,
+};
 use anyhow::{anyhow, Context, Result};
+use mbf_zip::ReadOnlyZipFile;
+
+/// Handles `GetCurrentManifest` [Requests](requests::Request).
+///
+/// # Returns
+/// The [Response](requests::Response) to the request (variant `CurrentManifest`)
+pub(super) fn handle_get_current_manifest() -> Result<Response> {
+    let apk_path = crate::get_installed_apks()
+        .context("Finding APK path")?
+        .ok_or(ErrorCode::NotInstalled)
+        .context("Cannot get manifest when app not installed")?
+        .base;
+
+    let mut apk = ReadOnlyZipFile::open_readonly(apk_path).context("Reading APK as ZIP")?;
+    let manifest_bytes = apk
+        .read_file("AndroidManifest.xml")
+        .context("Reading manifest file from APK")?;
+    let manifest_xml = super::mod_status::axml_bytes_to_xml_string(&manifest_bytes)?;
+
+    Ok(Response::CurrentManifest { manifest_xml })
+}
 
 /// Handles `GetDowngradedManifest` [Requests](requests::Request).
 ///
 /// # Returns
 /// The [Response](requests::Response) to the request (variant `DowngradedManifest`)
 pub(super) fn handle_get_downgraded_manifest(version: String) -> Result<Response> {
+    // The manifest is of little use if the installed version has no diff to downgrade to `version`.
+    let downgrade_available = match super::mod_status::get_app_info()? {
+        Some(app_info) => {
+            let res_cache = crate::load_res_cache()?;
+            let diff_index = mbf_res_man::external_res::get_diff_index(&res_cache)
+                .context("Getting diff index to check downgrade")?;
+            diff_index
+                .iter()
+                .any(|diff| diff.from_version == app_info.version && diff.to_version == version)
+        }
+        None => false,
+    };
+    if !downgrade_available {
+        warn!("No diff exists to downgrade the installed app to {version}");
+    }
+
     info!("Downloading manifest AXML file");
     let manifest_bytes = mbf_res_man::external_res::get_manifest_axml(
         mbf_res_man::default_agent::get_agent(),
@@ -21,26 +63,75 @@ pub(super) fn handle_get_downgraded_manifest(version: String) -> Result<Response
     info!("Converting into readable XML");
     let manifest_xml = super::mod_status::axml_bytes_to_xml_string(&manifest_bytes)?;
 
-    Ok(Response::DowngradedManifest { manifest_xml })
+    Ok(Response::DowngradedManifest {
+        manifest_xml,
+        downgrade_available,
+    })
 }
 
 /// Handles `Patch` [Requests](requests::Request).
 ///
 /// # Returns
 /// The [Response](requests::Response) to the request (variant `Mods`)
+#[allow(clippy::too_many_arguments)]
 pub(super) fn handle_patch(
     downgrade_to: Option<String>,
     repatch: bool,
     manifest_mod: String,
+    manifest_options: ManifestOptions,
     allow_no_core_mods: bool,
     override_core_mod_url: Option<String>,
+    override_mod_repo_url: Option<String>,
     vr_splash_path: Option<String>,
+    temp_dir: Option<String>,
+    skip_player_data: bool,
+    core_mods_bundle: Option<String>,
 ) -> Result<Response> {
-    let app_info =
-        super::mod_status::get_app_info()?.ok_or(anyhow!("Cannot patch when app not installed"))?;
+    // The temporary directory is deleted after patching, so a directory given by the frontend (which may contain the
+    // user's files) is never used directly.
+    let custom_temp_path = temp_dir.map(|dir| Path::new(&dir).join(paths::CUSTOM_TEMP_SUBDIR));
+
+    // If the last patch was interrupted, it must be dealt with first so that we don't patch on top of it.
+    // The last patch may have used any of the temporary directories.
+    let mut resumed = false;
+    for dir in paths::TEMP_DIRS
+        .iter()
+        .map(PathBuf::from)
+        .chain(custom_temp_path.clone())
+    {
+        resumed |= patching::recover_interrupted_patch(&dir)
+            .context("Recovering from interrupted patch")?;
+    }
+    if resumed {
+        // The installed app has changed since the frontend sent the request, so the request may no longer make sense,
+        // e.g. a downgrade from a version that is no longer installed.
+        return Err(anyhow!(
+            "An interrupted patch was completed instead of the requested patch. Reload MBF and patch again if needed"
+        ));
+    }
+
+    let temp_path = match custom_temp_path {
+        Some(path) => path,
+        None => patching::choose_temp_dir().context("Choosing temporary directory")?,
+    };
+
+    let app_info = super::mod_status::get_app_info()?
+        .ok_or(ErrorCode::NotInstalled)
+        .context("Cannot patch when app not installed")?;
+    if !app_info.split_paths.is_empty() {
+        return Err(anyhow!(
+            "Beat Saber is installed as split APKs ({}), which MBF cannot patch. Uninstall Beat Saber and reinstall it from the store",
+            app_info.split_paths.join(", ")
+        ));
+    }
+    if super::mod_status::needs_loader_conversion(&app_info) {
+        return Err(anyhow!(
+            "App is patched with an incompatible modloader. Uninstall Beat Saber and reinstall the vanilla game first"
+        ));
+    }
     let res_cache = crate::load_res_cache()?;
 
-    std::fs::create_dir_all(paths::TEMP)?;
+    std::fs::create_dir_all(&temp_path)?;
 
     // Either downgrade or just patch the current APK depending on the caller's choice.
     let patching_result = if let Some(to_version) = &downgrade_to {
@@ -57,38 +148,47 @@ pub(super) fn handle_patch(
             ))?;
 
         patching::downgrade_and_mod_apk(
-            Path::new(paths::TEMP),
+            &temp_path,
             &app_info,
             version_diffs,
             manifest_mod,
+            &manifest_options,
             vr_splash_path.as_deref(),
+            skip_player_data,
             &res_cache,
         )
         .context("Downgrading and patching APK")
     } else {
         patching::mod_current_apk(
-            Path::new(paths::TEMP),
+            &temp_path,
             &app_info,
             manifest_mod,
+            &manifest_options,
             repatch,
             vr_splash_path.as_deref(),
+            skip_player_data,
             &res_cache,
         )
         .context("Patching APK")
-        .map(|_| false) // Modding the currently installed APK will never remove DLC as they are restored automatically.
+        .map(|manifest_diff| (false, manifest_diff)) // Modding the currently installed APK will never remove DLC as they are restored automatically.
     };
 
-    // No matter what, make sure that all temporary files are gone.
-    std::fs::remove_dir_all(paths::TEMP)?;
+    // Make sure that all temporary files are gone, unless they are needed to recover from a failed patch next time.
+    if patching::is_patch_interrupted(&temp_path) {
+        warn!("Keeping temporary files so that patching can be resumed or rolled back. Try patching again");
+    } else {
+        std::fs::remove_dir_all(&temp_path)?;
+    }
     if let Some(splash_path) = vr_splash_path {
         std::fs::remove_file(splash_path)?;
     }
 
-    let removed_dlc = patching_result?;
+    let (removed_dlc, manifest_diff) = patching_result?;
     patching::install_modloader().context("Installing external modloader")?;
 
     let new_app_version = downgrade_to.unwrap_or(app_info.version);
     let mut mod_manager = ModManager::new(new_app_version, &res_cache);
+    mod_manager.set_override_mod_repo_url(override_mod_repo_url);
 
     if !repatch {
         info!("Wiping all existing mods");
@@ -103,6 +203,7 @@ pub(super) fn handle_patch(
             super::mod_status::get_app_info()?
                 .ok_or(anyhow!("Beat Saber should be installed after patching"))?,
             override_core_mod_url,
+            core_mods_bundle.as_deref(),
         ) {
             Ok(_) => info!("Successfully installed all core mods"),
             Err(err) => {
@@ -118,5 +219,6 @@ pub(super) fn handle_patch(
     Ok(Response::Patched {
         installed_mods: super::mod_management::get_mod_models(mod_manager)?,
         did_remove_dlc: removed_dlc,
+        manifest_diff,
     })
 }
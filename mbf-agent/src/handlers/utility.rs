@@ -1,10 +1,28 @@
 //! Handles requests relating to some buttons in the options page of MBF.
 
-use std::path::Path;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
-use crate::{data_fix, mod_man::ModManager, models::response::Response, patching, paths};
+use crate::{
+    data_fix,
+    mod_man::ModManager,
+    models::{
+        response::{ErrorCode, InstallCheck, LogFile, ModLoader, ModModel, Response},
+        PROTOCOL_VERSION,
+    },
+    patching, paths,
+};
 use anyhow::{anyhow, Context, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
 use log::{debug, info, warn};
+use mbf_zip::{FileCompression, ZipFile};
+use serde::Serialize;
+
+/// The maximum number of bytes of each log file to include in a diagnostics bundle, read from the end of the file.
+const MAX_DIAGNOSTIC_LOG_BYTES: usize = 4 * 1024 * 1024;
 
 /// Handles `QuickFix` [Requests](requests::Request).
 ///
@@ -12,13 +30,17 @@ use log::{debug, info, warn};
 /// The [Response](requests::Response) to the request (variant `Mods`)
 pub(super) fn handle_quick_fix(
     override_core_mod_url: Option<String>,
+    override_mod_repo_url: Option<String>,
     wipe_existing_mods: bool,
+    core_mods_bundle: Option<String>,
 ) -> Result<Response> {
     let app_info = super::mod_status::get_app_info()?
-        .ok_or(anyhow!("Cannot quick fix when app is not installed"))?;
+        .ok_or(ErrorCode::NotInstalled)
+        .context("Cannot quick fix when app is not installed")?;
     let res_cache = crate::load_res_cache()?;
 
     let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache);
+    mod_manager.set_override_mod_repo_url(override_mod_repo_url);
     if wipe_existing_mods {
         info!("Wiping all existing mods");
         mod_manager
@@ -33,6 +55,7 @@ pub(super) fn handle_quick_fix(
         &mut mod_manager,
         app_info,
         override_core_mod_url,
+        core_mods_bundle.as_deref(),
     )?;
     patching::install_modloader()?;
     Ok(Response::Mods {
@@ -71,3 +94,339 @@ pub(super) fn handle_fix_player_data() -> Result<Response> {
 
     Ok(Response::FixedPlayerData { existed: did_work })
 }
+
+/// Handles `DeleteOrphanedObbs` [Requests](requests::Request).
+///
+/// # Returns
+/// The [Response](requests::Response) to the request (variant `DeletedOrphanedObbs`)
+pub(super) fn handle_delete_orphaned_obbs() -> Result<Response> {
+    if crate::get_installed_apks()
+        .context("Finding APK path")?
+        .is_some()
+    {
+        return Err(anyhow!(
+            "Beat Saber is installed, so its OBBs are not orphaned and will not be deleted"
+        ));
+    }
+
+    let mut freed_bytes = 0;
+    for obb_path in patching::get_obb_paths()? {
+        info!("Deleting orphaned OBB {obb_path:?}");
+        freed_bytes += std::fs::metadata(&obb_path)?.len();
+        std::fs::remove_file(&obb_path).context("Deleting orphaned OBB")?;
+    }
+
+    info!("Freed {} MiB", freed_bytes / (1024 * 1024));
+    Ok(Response::DeletedOrphanedObbs { freed_bytes })
+}
+
+/// Handles `GetAgentVersion` [Requests](requests::Request).
+///
+/// # Returns
+/// The [Response](requests::Response) to the request (variant `AgentVersion`)
+pub(super) fn handle_get_agent_version() -> Result<Response> {
+    Ok(Response::AgentVersion {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
+
+/// Handles `CheckPrivileges` [Requests](requests::Request).
+///
+/// # Returns
+/// The [Response](requests::Response) to the request (variant `PrivilegesChecked`)
+pub(super) fn handle_check_privileges() -> Result<Response> {
+    // Commands that are harmless to run, but which need the same privileges as the commands MBF uses.
+    // The `android` package is always installed, unlike Beat Saber.
+    let probes: [(&str, &[&str]); 2] =
+        [("pm", &["path", "android"]), ("am", &["get-current-user"])];
+    for (program, args) in probes {
+        debug!("Checking that {program} can be run");
+        patching::run_command_checked(program, args)
+            .context(ErrorCode::MissingPrivilege)
+            .with_context(|| format!("Missing required privilege: {program}"))?;
+    }
+
+    for dir in [paths::MBF_DOWNLOADS, paths::MODLOADER_DIR] {
+        debug!("Checking that {dir} is writable");
+        check_dir_writable(Path::new(dir))
+            .context(ErrorCode::MissingPrivilege)
+            .with_context(|| format!("Missing required privilege: writing to {dir}"))?;
+    }
+
+    info!("Agent has all required privileges");
+    Ok(Response::PrivilegesChecked)
+}
+
+// Creates and then deletes a file in the given directory, creating the directory if it does not exist.
+fn check_dir_writable(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).context("Creating directory")?;
+    let probe_path = dir.join(".mbf_write_probe");
+    std::fs::write(&probe_path, []).context("Writing file")?;
+    std::fs::remove_file(&probe_path).context("Deleting file")?;
+    Ok(())
+}
+
+/// Handles `ReadLogs` [Requests](requests::Request).
+///
+/// # Returns
+/// The [Response](requests::Response) to the request (variant `Logs`)
+pub(super) fn handle_read_logs(max_bytes: usize) -> Result<Response> {
+    let mut log_path = None;
+    for log_dir in paths::MOD_LOG_DIRS {
+        if let Some(path) = find_latest_file(Path::new(log_dir)) {
+            log_path = Some(path);
+            break;
+        }
+    }
+
+    let mod_log = match log_path {
+        Some(path) => Some(read_file_tail(&path, max_bytes).context("Reading mod log")?),
+        None => {
+            info!("No mod logs found");
+            None
+        }
+    };
+
+    // Tombstones are typically not readable without root, so failing to read one is not an error.
+    let crash_dump = find_latest_file(Path::new(paths::TOMBSTONES)).and_then(|path| {
+        match read_file_tail(&path, max_bytes) {
+            Ok(crash_dump) => Some(crash_dump),
+            Err(err) => {
+                debug!("Could not read crash dump {path:?}: {err}");
+                None
+            }
+        }
+    });
+
+    Ok(Response::Logs {
+        mod_log,
+        crash_dump,
+    })
+}
+
+/// The information about the device and installation written to `report.json` in a diagnostics bundle.
+#[derive(Serialize)]
+struct DiagnosticsReport {
+    agent_version: String,
+    protocol_version: u32,
+    device_model: Option<String>,
+    android_version: Option<String>,
+    // None if Beat Saber is not installed.
+    game_version: Option<String>,
+    loader_installed: Option<ModLoader>,
+    installed_mods: Vec<ModModel>,
+    install_checks: Vec<InstallCheck>,
+    // Any parts of the diagnostics that could not be collected, so that the rest are still exported.
+    errors: Vec<String>,
+}
+
+/// Handles `ExportDiagnostics` [Requests](requests::Request).
+///
+/// # Returns
+/// The [Response](requests::Response) to the request (variant `Diagnostics`)
+pub(super) fn handle_export_diagnostics(override_core_mod_url: Option<String>) -> Result<Response> {
+    let mut errors = Vec::new();
+
+    info!("Collecting device and installation information");
+    let app_info = record_error(
+        &mut errors,
+        "Getting app info",
+        super::mod_status::get_app_info(),
+    )
+    .flatten();
+    let installed_mods = match &app_info {
+        Some(app_info) => record_error(
+            &mut errors,
+            "Loading installed mods",
+            load_mod_models(&app_info.version),
+        )
+        .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let install_checks = record_error(
+        &mut errors,
+        "Verifying installation",
+        super::mod_status::verify_install(override_core_mod_url),
+    )
+    .unwrap_or_default();
+
+    if let Some(parent) = Path::new(paths::DIAGNOSTICS_ZIP).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let zip_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(paths::DIAGNOSTICS_ZIP)
+        .context("Creating diagnostics ZIP")?;
+    let mut zip = ZipFile::create_new(zip_file);
+
+    info!("Collecting logs");
+    let log_path = paths::MOD_LOG_DIRS
+        .iter()
+        .find_map(|log_dir| find_latest_file(Path::new(log_dir)));
+    match log_path {
+        Some(path) => {
+            record_error(
+                &mut errors,
+                "Adding mod log",
+                add_log_file(&mut zip, "logs", &path),
+            );
+        }
+        None => info!("No mod logs found"),
+    }
+    // Tombstones are typically not readable without root, so failing to read one is not recorded as an error.
+    if let Some(path) = find_latest_file(Path::new(paths::TOMBSTONES)) {
+        if let Err(err) = add_log_file(&mut zip, "crashes", &path) {
+            debug!("Could not add crash dump {path:?}: {err}");
+        }
+    }
+
+    if let Some(app_info) = &app_info {
+        record_error(
+            &mut errors,
+            "Adding manifest",
+            add_file(
+                &mut zip,
+                "AndroidManifest.xml",
+                app_info.manifest_xml.as_bytes(),
+            ),
+        );
+    }
+    let cached_core_mods = ["core_mods.json", "core_mods_override.json"]
+        .map(|name| Path::new(paths::RES_CACHE).join(name));
+    for path in cached_core_mods
+        .iter()
+        .map(PathBuf::as_path)
+        .chain([Path::new(paths::MOD_POLICY)])
+    {
+        if path.exists() {
+            record_error(
+                &mut errors,
+                "Adding cached file",
+                std::fs::read(path)
+                    .context("Reading file")
+                    .and_then(|contents| add_file(&mut zip, &file_name_of(path), &contents)),
+            );
+        }
+    }
+
+    let report = DiagnosticsReport {
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        device_model: get_system_property("ro.product.model"),
+        android_version: get_system_property("ro.build.version.release"),
+        game_version: app_info.as_ref().map(|app_info| app_info.version.clone()),
+        loader_installed: app_info.and_then(|app_info| app_info.loader_installed),
+        installed_mods,
+        install_checks,
+        errors,
+    };
+    let report_json =
+        serde_json::to_vec_pretty(&report).context("Serializing diagnostics report")?;
+    add_file(&mut zip, "report.json", &report_json)?;
+
+    zip.save().context("Saving diagnostics ZIP")?;
+    let zip_bytes = std::fs::read(paths::DIAGNOSTICS_ZIP).context("Reading diagnostics ZIP")?;
+    std::fs::remove_file(paths::DIAGNOSTICS_ZIP).context("Deleting diagnostics ZIP")?;
+    info!("Exported {} KiB of diagnostics", zip_bytes.len() / 1024);
+
+    Ok(Response::Diagnostics {
+        zip_base64: BASE64_STANDARD.encode(zip_bytes),
+    })
+}
+
+// Gives the value of an `Ok` result, or otherwise logs the error and adds it to `errors`, prefixed with `action`.
+fn record_error<T>(errors: &mut Vec<String>, action: &str, result: Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("{action} failed: {err:?}");
+            errors.push(format!("{action}: {err:?}"));
+            None
+        }
+    }
+}
+
+// Loads the installed mods for the given game version, as they would be listed in a `Mods` response.
+fn load_mod_models(game_version: &str) -> Result<Vec<ModModel>> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(game_version.to_string(), &res_cache);
+    mod_manager.load_mods().context("Loading installed mods")?;
+    super::mod_management::get_mod_models(mod_manager)
+}
+
+// Gets the value of the Android system property with the given name, or None if it is unset or cannot be read.
+fn get_system_property(name: &str) -> Option<String> {
+    patching::run_command_checked("getprop", &[name])
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Adds the end of the log file at `path` to the diagnostics ZIP, within the directory `dir`.
+fn add_log_file(zip: &mut ZipFile<File>, dir: &str, path: &Path) -> Result<()> {
+    let log = read_file_tail(path, MAX_DIAGNOSTIC_LOG_BYTES).context("Reading log file")?;
+    add_file(
+        zip,
+        &format!("{dir}/{}", file_name_of(path)),
+        log.contents.as_bytes(),
+    )
+}
+
+fn add_file(zip: &mut ZipFile<File>, name: &str, contents: &[u8]) -> Result<()> {
+    zip.write_file(name, &mut Cursor::new(contents), FileCompression::Deflate)
+        .with_context(|| format!("Writing {name} to diagnostics ZIP"))
+}
+
+fn file_name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Finds the most recently modified file in `dir`.
+// Returns None if the directory does not exist, cannot be read, or contains no files.
+fn find_latest_file(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            metadata
+                .is_file()
+                .then(|| (metadata.modified().ok(), entry.path()))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+// Reads up to `max_bytes` from the end of the file at `path`.
+// If the file is longer than this, the contents begin at the first complete line within the last `max_bytes`.
+fn read_file_tail(path: &Path, max_bytes: usize) -> Result<LogFile> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let truncated = length > max_bytes as u64;
+    if truncated {
+        file.seek(SeekFrom::Start(length - max_bytes as u64))?;
+    }
+
+    let mut bytes = Vec::with_capacity(max_bytes.min(length as usize));
+    file.take(max_bytes as u64).read_to_end(&mut bytes)?;
+    let mut contents = String::from_utf8_lossy(&bytes).into_owned();
+
+    // Skip the partial line at the start.
+    if truncated {
+        if let Some(newline_idx) = contents.find('\n') {
+            contents.drain(..=newline_idx);
+        }
+    }
+
+    Ok(LogFile {
+        path: path.to_string_lossy().to_string(),
+        contents,
+        truncated,
+    })
+}
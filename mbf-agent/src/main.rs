@@ -1,4 +1,3 @@
-mod axml;
 mod data_fix;
 mod downloads;
 mod handlers;
@@ -8,25 +7,26 @@ mod models;
 mod patching;
 mod paths;
 
-use anyhow::{Context, Result};
-use downloads::DownloadConfig;
-use log::{debug, error, warn, Level};
+use anyhow::{anyhow, Context, Result};
+use downloads::{DownloadConfig, RateLimiter};
+use log::{debug, error, info, warn, Level};
+use mbf_axml as axml;
 use mbf_res_man::res_cache::ResCache;
 use models::{request, response};
-use serde::{Deserialize, Serialize};
 use std::{
     io::{BufRead, BufReader, Write},
     panic,
     path::Path,
     process::Command,
-    sync,
+    sync::{
+        self,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 /// The ID of the APK file that MBF manages.
 pub const APK_ID: &str = "com.beatgames.beatsaber";
 
-#[cfg(feature = "request_timing")]
-use log::info;
 #[cfg(feature = "request_timing")]
 use std::time::Instant;
 
@@ -43,7 +43,37 @@ pub fn try_delete_legacy_dirs() {
     }
 }
 
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Gets the token that is set to true when the frontend asks for the current request to be cancelled,
+/// by writing a line containing `Cancel` to stdin after the request.
+/// Operations that can be safely stopped part way through may check this to stop early.
+pub fn get_cancel_token() -> &'static AtomicBool {
+    &CANCELLED
+}
+
+#[cfg(feature = "request_timing")]
+static STAGE_TIMINGS: sync::Mutex<Vec<(&str, std::time::Duration)>> = sync::Mutex::new(Vec::new());
+
+/// Runs `stage`, recording how long it took under the given name if the `request_timing` feature is enabled.
+/// The recorded timings are logged once the request completes, to find which stages of a request are slowest.
+#[cfg_attr(not(feature = "request_timing"), allow(unused_variables))]
+pub fn time_stage<T>(name: &'static str, stage: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "request_timing")]
+    let start_time = Instant::now();
+    let result = stage();
+    #[cfg(feature = "request_timing")]
+    STAGE_TIMINGS
+        .lock()
+        .expect("Stage timings lock poisoned")
+        .push((name, Instant::now() - start_time));
+
+    result
+}
+
 static DOWNLOAD_CFG: sync::OnceLock<DownloadConfig> = sync::OnceLock::new();
+// The download speed limit given by the frontend, if any. Must be set before the download config is first used.
+static MAX_DOWNLOAD_BYTES_PER_SEC: sync::OnceLock<u64> = sync::OnceLock::new();
 
 /// Gets the default config used for downloads in MBF
 pub fn get_dl_cfg() -> &'static DownloadConfig<'static> {
@@ -54,7 +84,12 @@ pub fn get_dl_cfg() -> &'static DownloadConfig<'static> {
             disconnection_reset_time: Some(std::time::Duration::from_secs_f32(10.0)),
             disconnect_wait_time: std::time::Duration::from_secs_f32(5.0),
             progress_update_interval: Some(std::time::Duration::from_secs_f32(2.0)),
-            ureq_agent: mbf_res_man::default_agent::get_agent(),
+            parallel_connections: 4,
+            rate_limiter: MAX_DOWNLOAD_BYTES_PER_SEC.get().map(|&max| RateLimiter::new(max)),
+            mirrors: mbf_res_man::external_res::get_mirrors(),
+            max_redirects: 10,
+            ureq_agent: mbf_res_man::default_agent::get_no_redirect_agent(),
+            cancel: Some(get_cancel_token()),
         }
     })
 }
@@ -66,33 +101,90 @@ pub fn load_res_cache() -> Result<ResCache<'static>> {
     Ok(ResCache::new(
         paths::RES_CACHE.into(),
         mbf_res_man::default_agent::get_agent(),
-    ))
+    )
+    .with_mirrors(mbf_res_man::external_res::get_mirrors()))
+}
+
+/// The APK files that the installed app is made from.
+pub struct InstalledApks {
+    /// The path of the base APK, which contains the code and manifest of the app.
+    pub base: String,
+    /// The paths of any split APKs installed alongside the base APK, e.g. `split_config.arm64_v8a.apk`.
+    pub splits: Vec<String>,
 }
 
+/// Gets the path to the base APK of the installed app, or `None` if the app is not installed.
 pub fn get_apk_path() -> Result<Option<String>> {
+    Ok(get_installed_apks()?.map(|apks| apks.base))
+}
+
+/// Gets the paths of every APK of the installed app, or `None` if the app is not installed.
+/// `pm path` gives one path for each APK, of which there are several if the app was installed as split APKs.
+pub fn get_installed_apks() -> Result<Option<InstalledApks>> {
     let pm_output = Command::new("pm")
         .args(["path", APK_ID])
         .output()
         .context("Working out APK path")?;
-    if 8 > pm_output.stdout.len() {
-        // App not installed
-        Ok(None)
-    } else {
-        Ok(Some(
-            std::str::from_utf8(pm_output.stdout.split_at(8).1)?
-                .trim_end()
-                .to_owned(),
-        ))
+    let pm_stdout =
+        std::str::from_utf8(&pm_output.stdout).context("Output of pm path was not valid UTF-8")?;
+    let mut paths = parse_pm_path_output(pm_stdout);
+
+    if paths.len() <= 1 {
+        // App not installed if there are no paths
+        return Ok(paths.pop().map(|base| InstalledApks {
+            base,
+            splits: Vec::new(),
+        }));
+    }
+
+    // Split APKs name the split they contain in their manifest, unlike the base APK.
+    let mut base_index = None;
+    for (index, path) in paths.iter().enumerate() {
+        if !is_split_apk(path).with_context(|| format!("Checking if {path} is a split APK"))? {
+            if base_index.is_some() {
+                return Err(anyhow!("More than one base APK found: {paths:?}"));
+            }
+            base_index = Some(index);
+        }
+    }
+
+    match base_index {
+        Some(index) => {
+            let base = paths.remove(index);
+            warn!("App is installed as split APKs: {paths:?}");
+            Ok(Some(InstalledApks {
+                base,
+                splits: paths,
+            }))
+        }
+        None => Err(anyhow!("No base APK found in {paths:?}")),
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "camelCase")]
-struct ModTag {
-    patcher_name: String,
-    patcher_version: Option<String>,
-    modloader_name: String,
-    modloader_version: Option<String>,
+// Parses the APK paths from the output of `pm path <package>`, which gives one `package:<path>` line per APK.
+// Lines without the prefix (e.g. warnings) are ignored, and whitespace around each line and path is removed,
+// but any spaces within a path are kept.
+fn parse_pm_path_output(pm_stdout: &str) -> Vec<String> {
+    pm_stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+// Checks if the APK at the given path is a split APK rather than a base APK.
+fn is_split_apk(path: &str) -> Result<bool> {
+    let mut apk = mbf_zip::ReadOnlyZipFile::open_readonly(path).context("Reading APK as ZIP")?;
+    let manifest = apk
+        .read_file("AndroidManifest.xml")
+        .context("Reading manifest")?;
+    let mut manifest_cursor = std::io::Cursor::new(manifest);
+    let mut manifest_reader =
+        axml::AxmlReader::new(&mut manifest_cursor).context("Manifest was not valid AXML")?;
+
+    Ok(manifest::get_split_name(&mut manifest_reader)?.is_some())
 }
 
 struct ResponseLogger {}
@@ -132,6 +224,50 @@ impl log::Log for ResponseLogger {
     }
 }
 
+/// Sends a `Progress` response to the frontend, with the fraction of the current `stage` that has been completed, if known.
+/// As with log messages, failure to write the response is ignored.
+pub fn report_progress(stage: &str, fraction: Option<f32>) {
+    let _result = write_response(response::Response::Progress {
+        stage: stage.to_string(),
+        fraction,
+        bytes_done: None,
+        bytes_total: None,
+        item: None,
+    });
+}
+
+/// Sends a `Progress` response to the frontend for a stage that involves processing a number of bytes, e.g. a download.
+pub fn report_bytes_progress(stage: &str, bytes_done: u64, bytes_total: Option<u64>) {
+    let _result = write_response(response::Response::Progress {
+        stage: stage.to_string(),
+        fraction: bytes_total
+            .filter(|total| *total > 0)
+            .map(|total| bytes_done as f32 / total as f32),
+        bytes_done: Some(bytes_done),
+        bytes_total,
+        item: None,
+    });
+}
+
+/// Sends a `Progress` response to the frontend for one of several items processed in a stage, e.g. one of several mods
+/// being downloaded. `fraction` is the fraction of the whole stage completed, whereas `bytes_done` and `bytes_total`
+/// are for the item.
+pub fn report_item_progress(
+    stage: &str,
+    item: &str,
+    fraction: Option<f32>,
+    bytes_done: u64,
+    bytes_total: Option<u64>,
+) {
+    let _result = write_response(response::Response::Progress {
+        stage: stage.to_string(),
+        fraction,
+        bytes_done: Some(bytes_done),
+        bytes_total,
+        item: Some(item.to_string()),
+    });
+}
+
 fn write_response(response: response::Response) -> Result<()> {
     let mut lock = std::io::stdout().lock();
     serde_json::to_writer(&mut lock, &response).context("Serializing JSON response")?;
@@ -139,6 +275,25 @@ fn write_response(response: response::Response) -> Result<()> {
     Ok(())
 }
 
+// Finds the code of a failed request's error: either the code in the error chain, or one inferred from its causes.
+fn get_error_code(err: &anyhow::Error) -> response::ErrorCode {
+    if let Some(code) = err.downcast_ref::<response::ErrorCode>() {
+        *code
+    } else if err.downcast_ref::<mod_man::UpgradeConflict>().is_some() {
+        response::ErrorCode::DependencyConflict
+    } else if downloads::is_network_error(err) {
+        response::ErrorCode::NetworkFailure
+    } else if err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io_err| io_err.kind() == std::io::ErrorKind::StorageFull)
+    }) {
+        response::ErrorCode::InsufficientSpace
+    } else {
+        response::ErrorCode::Unknown
+    }
+}
+
 static LOGGER: ResponseLogger = ResponseLogger {};
 
 fn main() -> Result<()> {
@@ -151,7 +306,21 @@ fn main() -> Result<()> {
     let mut reader = BufReader::new(std::io::stdin());
     let mut line = String::new();
     reader.read_line(&mut line)?;
-    let req: request::Request = serde_json::from_str(&line)?;
+    let req: request::RequestEnvelope = serde_json::from_str(&line)?;
+    if let Some(max_bytes_per_sec) = req.max_download_bytes_per_sec {
+        info!("Limiting downloads to {max_bytes_per_sec} bytes per second");
+        MAX_DOWNLOAD_BYTES_PER_SEC.get_or_init(|| max_bytes_per_sec);
+    }
+
+    // Any further lines are used to cancel the request while it is running.
+    std::thread::spawn(move || {
+        for line in reader.lines().map_while(Result::ok) {
+            if line.trim() == "Cancel" {
+                warn!("Cancelling request");
+                CANCELLED.store(true, Ordering::Relaxed);
+            }
+        }
+    });
 
     // Set a panic hook that writes the panic as a JSON Log
     // (we don't do this in catch_unwind as we get an `Any` there, which doesn't implement Display)
@@ -166,14 +335,66 @@ fn main() -> Result<()> {
                 {
                     let req_time = Instant::now() - start_time;
                     info!("Request complete in {}ms", req_time.as_millis());
+                    for (stage, stage_time) in STAGE_TIMINGS.lock().unwrap().iter() {
+                        info!("- {stage}: {}ms", stage_time.as_millis());
+                    }
                 }
 
                 write_response(resp)?;
             }
-            Err(err) => error!("{err:?}"),
+            Err(err) => {
+                error!("{err:?}");
+                write_response(response::Response::Error {
+                    code: get_error_code(&err),
+                    message: format!("{err:?}"),
+                    dependency_conflicts: err
+                        .downcast_ref::<mod_man::UpgradeConflict>()
+                        .map(response::DependencyConflictInfo::from_upgrade_conflict)
+                        .unwrap_or_default(),
+                })?;
+            }
         },
         Err(_) => {} // Panic will be outputted above
     };
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pm_path_output_with_multiple_apks() {
+        assert_eq!(
+            parse_pm_path_output(
+                "package:/data/app/base.apk\npackage:/data/app/split_config.arm64_v8a.apk\n"
+            ),
+            ["/data/app/base.apk", "/data/app/split_config.arm64_v8a.apk"]
+        );
+    }
+
+    #[test]
+    fn pm_path_output_with_whitespace_and_warnings() {
+        assert_eq!(
+            parse_pm_path_output(
+                "WARNING: linker: unused DT entry\n  package:/data/app/base.apk  \r\n\npackage:\n"
+            ),
+            ["/data/app/base.apk"]
+        );
+    }
+
+    #[test]
+    fn pm_path_output_keeps_spaces_within_paths() {
+        assert_eq!(
+            parse_pm_path_output("package:/data/app/Beat Saber/base.apk"),
+            ["/data/app/Beat Saber/base.apk"]
+        );
+    }
+
+    #[test]
+    fn empty_pm_path_output_has_no_paths() {
+        assert!(parse_pm_path_output("").is_empty());
+        assert!(parse_pm_path_output("\n\n").is_empty());
+    }
+}
@@ -1,24 +1,67 @@
 //! Module containing convenience functions for modifying AndroidManifest.xml
 
-use std::io::{Read, Seek};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{Read, Seek},
+};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use log::warn;
 
-use crate::axml::{AttributeValue, AxmlReader, Event};
+use crate::{
+    axml::{self, Attribute, AttributeValue, AxmlReader, Event, ResourceIds, ANDROID_NS_URI},
+    models::response::{AttributeChange, ChangedElement, ManifestDiff, ManifestElement},
+};
 
 /// Useful struct to read key details from the APK manifest.
 pub struct ManifestInfo {
     pub package_version: String,
+    /// The names of the permissions declared with `uses-permission` elements.
+    pub permissions: Vec<String>,
+    /// The `minSdkVersion` of the `uses-sdk` element, or `None` if not given.
+    pub min_sdk_version: Option<u32>,
+    /// The `targetSdkVersion` of the `uses-sdk` element, or `None` if not given.
+    pub target_sdk_version: Option<u32>,
+    /// The `label` of the `application` element, which is either a string or a reference to a string resource.
+    pub label: Option<AttributeValue>,
+    /// The resource ID referred to by the `icon` of the `application` element.
+    pub icon: Option<u32>,
 }
 
 impl ManifestInfo {
     pub fn read<T: Read + Seek>(reader: &mut AxmlReader<T>) -> Result<Self> {
         let mut version: Option<String> = None;
-        while let Some(event) = reader.read_next_event()? {
-            match event {
+        let mut permissions = Vec::new();
+        let mut min_sdk_version = None;
+        let mut target_sdk_version = None;
+        let mut label = None;
+        let mut icon = None;
+        for event in reader.events() {
+            match event? {
                 Event::StartElement {
                     attributes, name, ..
                 } => {
+                    if &*name == "uses-permission" {
+                        if let Some(AttributeValue::String(permission)) =
+                            get_android_attr(&attributes, "name")
+                        {
+                            permissions.push(permission.clone());
+                        }
+                        continue;
+                    }
+                    if &*name == "uses-sdk" {
+                        min_sdk_version = get_sdk_version_attr(&attributes, "minSdkVersion");
+                        target_sdk_version = get_sdk_version_attr(&attributes, "targetSdkVersion");
+                        continue;
+                    }
+                    if &*name == "application" {
+                        label = get_android_attr(&attributes, "label").cloned();
+                        icon = match get_android_attr(&attributes, "icon") {
+                            Some(AttributeValue::Reference(id)) => Some(*id),
+                            _ => None,
+                        };
+                        continue;
+                    }
                     if &*name != "manifest" {
                         continue;
                     }
@@ -41,8 +84,412 @@ impl ManifestInfo {
         }
 
         match version {
-            Some(package_version) => Ok(Self { package_version }),
+            Some(package_version) => Ok(Self {
+                package_version,
+                permissions,
+                min_sdk_version,
+                target_sdk_version,
+                label,
+                icon,
+            }),
             None => Err(anyhow!("No useful information found in the manifest")),
         }
     }
 }
+
+/// Gets the name of the split contained in an APK, from the `split` attribute of the `manifest` element.
+/// Gives `None` for the base APK of an app, or an APK for an app that isn't split.
+pub fn get_split_name<T: Read + Seek>(reader: &mut AxmlReader<T>) -> Result<Option<String>> {
+    let root_element = reader.events().find_map(|event| match event {
+        Ok(Event::StartElement {
+            attributes, name, ..
+        }) => Some(Ok((name, attributes))),
+        Ok(_) => None,
+        Err(err) => Some(Err(err)),
+    });
+    let (name, attributes) = root_element.ok_or(anyhow!("No manifest element found"))??;
+    if name != "manifest" {
+        return Err(anyhow!("Root element was <{name}>, expected <manifest>"));
+    }
+
+    Ok(attributes.into_iter().find_map(|attr| {
+        match (attr.name.as_str(), attr.namespace, attr.value) {
+            ("split", None, AttributeValue::String(split)) => Some(split),
+            _ => None,
+        }
+    }))
+}
+
+/// Modifications made to the manifest during patching, in addition to those
+/// already present in the manifest XML supplied by the frontend.
+#[derive(Default)]
+pub struct ManifestOptions {
+    /// Permissions to add as `uses-permission` elements, if not already declared.
+    pub extra_permissions: Vec<String>,
+    /// If `Some`, the value to give `android:debuggable` on the `application` element.
+    pub debuggable: Option<bool>,
+    /// If `Some`, the value to give `android:extractNativeLibs` on the `application` element.
+    pub extract_native_libs: Option<bool>,
+}
+
+impl ManifestOptions {
+    /// Applies these options to the given manifest events.
+    pub fn apply(&self, events: &mut Vec<Event>, res_ids: &ResourceIds) -> Result<()> {
+        add_permissions(events, &self.extra_permissions, res_ids)
+            .context("Adding extra permissions")?;
+
+        if let Some(debuggable) = self.debuggable {
+            set_application_attr(
+                events,
+                "debuggable",
+                AttributeValue::Boolean(debuggable),
+                res_ids,
+            )
+            .context("Setting debuggable")?;
+        }
+        if let Some(extract_native_libs) = self.extract_native_libs {
+            set_application_attr(
+                events,
+                "extractNativeLibs",
+                AttributeValue::Boolean(extract_native_libs),
+                res_ids,
+            )
+            .context("Setting extractNativeLibs")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the given manifest events are structurally valid, i.e.
+/// - Every opening tag has a matching closing tag.
+/// - There is exactly one root element, which is `manifest`.
+/// - The `manifest` element directly contains an `application` element.
+pub fn validate_manifest(events: &[Event]) -> Result<()> {
+    let mut open_elements: Vec<&str> = Vec::new();
+    let mut root_count = 0;
+    let mut has_application = false;
+
+    for event in events {
+        match event {
+            Event::StartElement { name, .. } => {
+                if open_elements.is_empty() {
+                    if name != "manifest" {
+                        return Err(anyhow!("Root element was <{name}>, expected <manifest>"));
+                    }
+                    root_count += 1;
+                } else if open_elements.len() == 1 && name == "application" {
+                    has_application = true;
+                }
+
+                open_elements.push(name);
+            }
+            Event::EndElement { name, .. } => match open_elements.pop() {
+                Some(open) if open == name => {}
+                Some(open) => return Err(anyhow!("Expected </{open}> but found </{name}>")),
+                None => return Err(anyhow!("Found </{name}> with no matching opening tag")),
+            },
+            _ => {}
+        }
+    }
+
+    if let Some(unclosed) = open_elements.last() {
+        return Err(anyhow!("Element <{unclosed}> was never closed"));
+    }
+    if root_count != 1 {
+        return Err(anyhow!(
+            "Manifest must have exactly one root element, found {root_count}"
+        ));
+    }
+    if !has_application {
+        return Err(anyhow!("Manifest had no <application> element"));
+    }
+
+    Ok(())
+}
+
+/// Adds a `uses-permission` element to the manifest for each of the given permissions.
+/// Permissions that the manifest already declares are skipped, as are duplicates within `permissions`.
+///
+/// # Arguments
+/// * `events` - The events of the manifest, which must contain a root `manifest` element.
+/// * `permissions` - The names of the permissions to add, e.g. `android.permission.RECORD_AUDIO`
+/// * `res_ids` - Used to look up the resource ID of the `android:name` attribute.
+fn add_permissions(
+    events: &mut Vec<Event>,
+    permissions: &[String],
+    res_ids: &ResourceIds,
+) -> Result<()> {
+    let manifest_idx = events
+        .iter()
+        .position(|event| is_element_start(event, "manifest"))
+        .ok_or(anyhow!("Manifest had no <manifest> element"))?;
+
+    let mut declared: Vec<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::StartElement {
+                attributes, name, ..
+            } if name == "uses-permission" => get_android_attr(attributes, "name"),
+            _ => None,
+        })
+        .filter_map(|value| match value {
+            AttributeValue::String(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect();
+
+    // Inserted directly after the opening <manifest> tag so the permissions are direct children of it.
+    let mut insert_idx = manifest_idx + 1;
+    for permission in permissions {
+        if declared.contains(permission) {
+            continue;
+        }
+
+        events.insert(
+            insert_idx,
+            Event::StartElement {
+                attributes: vec![Attribute {
+                    name: "name".to_string(),
+                    namespace: Some(ANDROID_NS_URI.to_string()),
+                    resource_id: res_ids.get_res_id_or_none("name"),
+                    value: AttributeValue::String(permission.clone()),
+                }],
+                name: "uses-permission".to_string(),
+                namespace: None,
+                line_num: 0,
+            },
+        );
+        events.insert(
+            insert_idx + 1,
+            Event::EndElement {
+                line_num: 0,
+                namespace: None,
+                name: "uses-permission".to_string(),
+            },
+        );
+        insert_idx += 2;
+
+        declared.push(permission.clone());
+    }
+
+    Ok(())
+}
+
+// Sets the attribute with the given name (in the `android` namespace) on the `application` element.
+// Any existing attribute with this name is replaced.
+fn set_application_attr(
+    events: &mut [Event],
+    attr_name: &str,
+    value: AttributeValue,
+    res_ids: &ResourceIds,
+) -> Result<()> {
+    let attributes = events
+        .iter_mut()
+        .find_map(|event| match event {
+            Event::StartElement {
+                attributes, name, ..
+            } if name == "application" => Some(attributes),
+            _ => None,
+        })
+        .ok_or(anyhow!("Manifest had no <application> element"))?;
+
+    attributes.retain(|attr| {
+        !(attr.name == attr_name && attr.namespace.as_deref() == Some(ANDROID_NS_URI))
+    });
+    attributes.push(Attribute {
+        name: attr_name.to_string(),
+        namespace: Some(ANDROID_NS_URI.to_string()),
+        resource_id: Some(
+            res_ids
+                .get_res_id_or_none(attr_name)
+                .ok_or(anyhow!("No resource ID found for {attr_name}"))?,
+        ),
+        value,
+    });
+
+    Ok(())
+}
+
+/// Finds the elements and attributes that were added, removed or changed between the `old` and `new` manifest events.
+/// Elements are listed in the order they appear in the manifest.
+pub fn diff_manifests(old: &[Event], new: &[Event]) -> ManifestDiff {
+    let old_elements = list_elements(old);
+    let new_elements = list_elements(new);
+    let old_by_path: HashMap<&str, &ManifestElement> = old_elements
+        .iter()
+        .map(|element| (element.path.as_str(), element))
+        .collect();
+    let new_paths: HashSet<&str> = new_elements
+        .iter()
+        .map(|element| element.path.as_str())
+        .collect();
+
+    let mut diff = ManifestDiff::default();
+    for new_element in &new_elements {
+        let old_element = match old_by_path.get(new_element.path.as_str()) {
+            Some(old_element) => old_element,
+            None => {
+                diff.added_elements.push(ManifestElement {
+                    path: new_element.path.clone(),
+                    attributes: new_element.attributes.clone(),
+                });
+                continue;
+            }
+        };
+
+        let mut changed = ChangedElement {
+            path: new_element.path.clone(),
+            added_attributes: BTreeMap::new(),
+            removed_attributes: BTreeMap::new(),
+            changed_attributes: BTreeMap::new(),
+        };
+        for (name, new_value) in &new_element.attributes {
+            match old_element.attributes.get(name) {
+                None => {
+                    changed
+                        .added_attributes
+                        .insert(name.clone(), new_value.clone());
+                }
+                Some(old_value) if old_value != new_value => {
+                    changed.changed_attributes.insert(
+                        name.clone(),
+                        AttributeChange {
+                            old: old_value.clone(),
+                            new: new_value.clone(),
+                        },
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, old_value) in &old_element.attributes {
+            if !new_element.attributes.contains_key(name) {
+                changed
+                    .removed_attributes
+                    .insert(name.clone(), old_value.clone());
+            }
+        }
+
+        if !changed.added_attributes.is_empty()
+            || !changed.removed_attributes.is_empty()
+            || !changed.changed_attributes.is_empty()
+        {
+            diff.changed_elements.push(changed);
+        }
+    }
+
+    diff.removed_elements = old_elements
+        .into_iter()
+        .filter(|element| !new_paths.contains(element.path.as_str()))
+        .collect();
+
+    diff
+}
+
+// Lists every element in the manifest with its path, as described in [ManifestDiff], and its attributes.
+fn list_elements(events: &[Event]) -> Vec<ManifestElement> {
+    // Attribute names are given the prefix of their namespace, as in the manifest XML.
+    let ns_prefixes: HashMap<&str, &str> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::StartNamespace(namespace) => namespace
+                .prefix
+                .as_deref()
+                .map(|prefix| (namespace.uri.as_str(), prefix)),
+            _ => None,
+        })
+        .collect();
+    let qualified_name = |name: &str, namespace: &Option<String>| match namespace {
+        Some(uri) => format!(
+            "{}:{name}",
+            ns_prefixes.get(uri.as_str()).copied().unwrap_or(uri)
+        ),
+        None => name.to_string(),
+    };
+
+    let mut elements = Vec::new();
+    let mut open_paths: Vec<String> = Vec::new();
+    let mut used_paths = HashSet::new();
+    for event in events {
+        match event {
+            Event::StartElement {
+                attributes,
+                name,
+                namespace,
+                ..
+            } => {
+                let mut segment = qualified_name(name, namespace);
+                if let Some(AttributeValue::String(android_name)) =
+                    get_android_attr(attributes, "name")
+                {
+                    segment.push_str(&format!("[{android_name}]"));
+                }
+
+                let base_path = match open_paths.last() {
+                    Some(parent) => format!("{parent}/{segment}"),
+                    None => segment,
+                };
+                let mut path = base_path.clone();
+                let mut occurrence = 1;
+                while used_paths.contains(&path) {
+                    occurrence += 1;
+                    path = format!("{base_path}#{occurrence}");
+                }
+                used_paths.insert(path.clone());
+
+                elements.push(ManifestElement {
+                    path: path.clone(),
+                    attributes: attributes
+                        .iter()
+                        .map(|attr| {
+                            (
+                                qualified_name(&attr.name, &attr.namespace),
+                                axml::stringify_attr_value(attr.value.clone()),
+                            )
+                        })
+                        .collect(),
+                });
+                open_paths.push(path);
+            }
+            Event::EndElement { .. } => {
+                open_paths.pop();
+            }
+            _ => {}
+        }
+    }
+
+    elements
+}
+
+fn is_element_start(event: &Event, element_name: &str) -> bool {
+    match event {
+        Event::StartElement { name, .. } => name == element_name,
+        _ => false,
+    }
+}
+
+// Finds the value of the attribute with the given name in the `android` namespace.
+fn get_android_attr<'a>(attributes: &'a [Attribute], name: &str) -> Option<&'a AttributeValue> {
+    attributes
+        .iter()
+        .find(|attr| attr.name == name && attr.namespace.as_deref() == Some(ANDROID_NS_URI))
+        .map(|attr| &attr.value)
+}
+
+// Gets the SDK version in the `android:` attribute with the given name, which may be an integer or a string containing one.
+// Gives `None` if the attribute is absent or not a valid SDK version, as the version is only informational.
+fn get_sdk_version_attr(attributes: &[Attribute], name: &str) -> Option<u32> {
+    let version = match get_android_attr(attributes, name)? {
+        AttributeValue::Integer(version) | AttributeValue::Hex(version) => {
+            u32::try_from(*version).ok()
+        }
+        AttributeValue::String(version) => version.parse().ok(),
+        _ => None,
+    };
+    if version.is_none() {
+        warn!("{name} in manifest was not a valid SDK version");
+    }
+
+    version
+}
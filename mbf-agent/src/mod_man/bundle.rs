@@ -0,0 +1,121 @@
+//! A bundle of core mods obtained by the user ahead of time, so that core mods can be installed without network access.
+
+use std::{
+    fs::File,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use mbf_res_man::{external_res::CoreModIndex, models::CoreMod};
+use mbf_zip::ZipFile;
+use sha2::{Digest, Sha256};
+
+use crate::downloads;
+
+/// The name of the core mod index within a bundle, which has the same format as the `core_mods.json` in the core mods repository.
+const INDEX_NAME: &str = "core_mods.json";
+
+/// The location that the files of a bundle are read from.
+enum BundleSource {
+    Directory(PathBuf),
+    Zip(ZipFile<File>),
+}
+
+/// A core mod index alongside the QMODs of (some of) the core mods within it.
+/// A bundle is either a directory or a ZIP file containing `core_mods.json` and the QMODs.
+/// Each QMOD is named after the last segment of its download URL, or otherwise `<mod ID>.qmod`.
+pub struct CoreModBundle {
+    source: BundleSource,
+}
+
+impl CoreModBundle {
+    /// Opens the bundle at the given path, which may be a directory or a ZIP file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = if path.is_dir() {
+            BundleSource::Directory(path.to_owned())
+        } else {
+            let bundle_file = File::open(path).context("Opening core mod bundle")?;
+            BundleSource::Zip(
+                ZipFile::open(bundle_file)
+                    .context("Core mod bundle was not a directory or a valid ZIP")?,
+            )
+        };
+
+        info!("Using core mod bundle at {path:?}");
+        Ok(Self { source })
+    }
+
+    /// Reads the core mod index within the bundle, which is used instead of fetching the core mod index.
+    pub fn read_index(&mut self) -> Result<CoreModIndex> {
+        let index_data = read_file(&mut self.source, INDEX_NAME)?
+            .ok_or(anyhow!("Core mod bundle did not contain {INDEX_NAME}"))?;
+        serde_json::from_slice(&index_data).context("Core mod bundle's index was invalid JSON")
+    }
+
+    /// Reads the QMOD for the given core mod from the bundle.
+    /// If the core mod has a SHA-256 hash, the QMOD in the bundle must match it.
+    /// # Returns
+    /// The contents of the QMOD, or `None` if the bundle does not contain it, in which case the mod must be downloaded.
+    pub fn read_mod(&mut self, core_mod: &CoreMod) -> Result<Option<Cursor<Vec<u8>>>> {
+        let url_name = core_mod
+            .download_url
+            .split(['?', '#'])
+            .next()
+            .and_then(|url| url.rsplit('/').next())
+            .filter(|name| !name.is_empty());
+
+        let candidates = url_name
+            .into_iter()
+            .map(str::to_owned)
+            .chain(std::iter::once(format!("{}.qmod", core_mod.id)));
+        for name in candidates {
+            let mod_data = match read_file(&mut self.source, &name)? {
+                Some(mod_data) => mod_data,
+                None => continue,
+            };
+
+            if let Some(expected_sha256) = core_mod.sha256.as_deref() {
+                let expected = downloads::parse_sha256(expected_sha256)
+                    .context("Core mod index contained an invalid hash")?;
+                let hash: [u8; 32] = Sha256::digest(&mod_data).into();
+                if hash != expected {
+                    return Err(anyhow!(
+                        "{name} in core mod bundle was corrupt: SHA-256 {} did not match expected {}",
+                        downloads::to_hex(&hash),
+                        downloads::to_hex(&expected)
+                    ));
+                }
+            }
+
+            return Ok(Some(Cursor::new(mod_data)));
+        }
+
+        Ok(None)
+    }
+}
+
+// Reads the file with the given name from the bundle, giving `None` if it does not exist.
+fn read_file(source: &mut BundleSource, name: &str) -> Result<Option<Vec<u8>>> {
+    match source {
+        BundleSource::Directory(dir) => {
+            let path = dir.join(name);
+            if !path.is_file() {
+                return Ok(None);
+            }
+            std::fs::read(&path)
+                .map(Some)
+                .with_context(|| format!("Reading {name} from core mod bundle"))
+        }
+        BundleSource::Zip(zip) => {
+            if !zip.contains_file(name) {
+                return Ok(None);
+            }
+            zip.read_file(name)
+                .map(Some)
+                .with_context(|| format!("Reading {name} from core mod bundle"))
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Errors given when a mod cannot be upgraded as installed mods depend on a different version of it.
+
+use semver::{Version, VersionReq};
+
+/// An installed mod which depends on a range of versions of a mod that excludes the version being installed.
+#[derive(Clone, Debug)]
+pub struct DependencyConflict {
+    /// The ID of the installed mod that depends on the mod being upgraded.
+    pub dependent_id: String,
+    /// The installed version of the dependent mod.
+    pub dependent_version: Version,
+    /// The range of versions of the mod being upgraded that the dependent mod requires.
+    pub version_range: VersionReq,
+}
+
+/// Error given when a mod cannot be upgraded (or downgraded) to a new version, as some installed mods
+/// depend on versions of it that exclude the new version.
+/// This can be downcast from the `anyhow::Error` of a failed import so that the conflicts can be shown to the user.
+#[derive(Clone, Debug)]
+pub struct UpgradeConflict {
+    /// The ID of the mod being upgraded.
+    pub mod_id: String,
+    /// The version of the mod that was rejected.
+    pub new_version: Version,
+    /// The installed mods that do not accept `new_version`. Never empty.
+    pub conflicts: Vec<DependencyConflict>,
+}
+
+impl std::fmt::Display for UpgradeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Could not upgrade {} to v{}:",
+            self.mod_id, self.new_version
+        )?;
+        for conflict in &self.conflicts {
+            write!(
+                f,
+                "\nMod {} v{} depends on range {}",
+                conflict.dependent_id, conflict.dependent_version, conflict.version_range
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for UpgradeConflict {}
@@ -2,14 +2,17 @@
 //! This module is responsible for installing/removing the mod files and representing the loaded mods
 //! but does not handle mod dependencies and other responsibilities - this is the job of the [ModManager](super::ModManager)
 
-use std::{collections::HashSet, ffi::{OsStr, OsString}, path::{Path, PathBuf}};
+use std::{cell::OnceCell, collections::HashSet, ffi::{OsStr, OsString}, path::{Path, PathBuf}};
 
 use crate::paths;
 
 use super::{util, ModInfo};
-use anyhow::{Result, Context};
-use log::{debug, warn};
+use anyhow::{anyhow, Result, Context};
+use log::{debug, info, warn};
 
+/// The name of the folder within the extracted mod that holds the mod's binary files while the mod is disabled.
+/// This folder existing marks the mod as disabled.
+const DISABLED_DIR_NAME: &str = ".mbf_disabled";
 
 /// Represents a mod (in QMOD format).
 #[derive(Debug)]
@@ -26,6 +29,11 @@ pub struct Mod {
     loaded_from: PathBuf,
     /// Whether the mod is core or any (transitive) required dependency of a core mod.
     pub(super) is_core: bool,
+    /// Whether the mod has been disabled, i.e. its binary files moved out of the modloader folders so that they can be moved back quickly.
+    disabled: bool,
+    /// The total size of the files of the mod in bytes, calculated when first needed.
+    /// Reset whenever the files of the mod are changed.
+    disk_size: OnceCell<u64>,
 }
 
 impl Mod {
@@ -57,15 +65,58 @@ impl Mod {
         self.is_core
     }
 
+    /// Gets whether the mod is disabled. A disabled mod is never installed.
+    /// See [Mod::disable_unchecked].
+    /// # Returns
+    /// Whether the mod is disabled.
+    pub fn disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Gets the space used by the mod on disk, which is the size of the extracted mod plus the size of its installed
+    /// mod files, library files, late mod files and file copies.
+    /// Libraries shared with other mods are counted for each mod that uses them.
+    /// # Returns
+    /// The size of the mod in bytes.
+    pub fn disk_size(&self) -> u64 {
+        *self.disk_size.get_or_init(|| {
+            let extracted_size = util::get_dir_size(&self.loaded_from).unwrap_or_else(|err| {
+                warn!("Failed to get size of extracted mod {}: {err}", self.manifest.id);
+                0
+            });
+            let installed_size: u64 = self.installed_paths()
+                .iter()
+                .filter_map(|dest| std::fs::metadata(dest).ok())
+                .map(|metadata| metadata.len())
+                .sum();
+
+            extracted_size + installed_size
+        })
+    }
+
+    /// Gets the absolute paths that the mod's files are copied to when it is installed: its mod files, library files
+    /// and late mod files in the modloader folders, followed by the destinations of its file copies.
+    /// The paths are computed from the manifest, so are given whether or not the files currently exist.
+    /// # Returns
+    /// The paths of the files that the mod installs.
+    pub fn installed_paths(&self) -> Vec<PathBuf> {
+        self.get_file_paths()
+            .into_iter()
+            .map(|(_, dest)| dest)
+            .collect()
+    }
+
     /// Creates a new [Mod] based on the loaded mod manifest and the directory containing the
     /// extracted QMOD file.
     pub(super) fn new(manifest: ModInfo, loaded_from: PathBuf) -> Result<Self> {
         Ok(Self {
+            disabled: loaded_from.join(DISABLED_DIR_NAME).exists(),
             loaded_from,
             files_exist: Self::check_if_files_copied(&manifest).context("Checking if mod installed")?,
             manifest,
             installed: None,
-            is_core: false
+            is_core: false,
+            disk_size: OnceCell::new(),
         })
     }
 
@@ -80,6 +131,10 @@ impl Mod {
     /// 
     /// Does not install dependencies, hence the "unchecked".
     pub(super) fn install_unchecked(&mut self) -> Result<()> {
+        // The mod may have been extracted by an older version of MBF that did not check this.
+        util::check_copy_destinations(&self.manifest)
+            .with_context(|| format!("Mod {} copies files to a disallowed location", self.manifest.id))?;
+
         // Copy early mods, late mods and library binaries.
         util::copy_files_from_mod_folder(
             &self.loaded_from,
@@ -98,10 +153,12 @@ impl Mod {
         )?;
 
         self.copy_file_copies().context("Copying auxillary files")?;
+        self.remove_disabled_files()?;
 
         // Update the install status of the mod.
         self.installed = Some(true);
         self.files_exist = true;
+        self.disk_size.take();
 
         Ok(())
     }
@@ -138,14 +195,117 @@ impl Mod {
                 std::fs::remove_file(dest_path).context("Deleting copied file")?;
             }
         }
+        self.remove_disabled_files()?;
 
         // Mark as uninstalled.
         self.installed = Some(false);
         self.files_exist = false;
+        self.disk_size.take();
+
+        Ok(())
+    }
+
+    /// Disables this mod by moving its mod files, late mod files and library files out of the modloader folders into
+    /// a folder within the extracted mod, so that the mod is no longer installed but can be enabled again quickly.
+    /// File copies are left in place.
+    ///
+    /// Does not uninstall dependant mods, hence the "unchecked". Dependant mods will simply no longer be considered installed.
+    /// # Arguments
+    /// * `retained_libs` - The file names of libraries in use by other installed mods, which are left in place.
+    pub(super) fn disable_unchecked(&mut self, retained_libs: HashSet<OsString>) -> Result<()> {
+        let disabled_path = self.loaded_from.join(DISABLED_DIR_NAME);
+        std::fs::create_dir_all(&disabled_path).context("Creating disabled files folder")?;
+
+        for (file_name, active_path, disabled_file_path) in self.get_disabled_file_paths() {
+            if active_path.starts_with(paths::LIBS) && retained_libs.contains(&file_name) {
+                continue;
+            }
+
+            if active_path.exists() {
+                debug!("Disabling {active_path:?}");
+                if let Some(parent) = disabled_file_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                util::move_file(&active_path, &disabled_file_path).context("Moving mod file to disabled files folder")?;
+            }
+        }
+
+        self.disabled = true;
+        self.installed = Some(false);
+        self.files_exist = false;
+        self.disk_size.take();
+        Ok(())
+    }
+
+    /// Enables this mod after it was disabled with [Mod::disable_unchecked], by moving its files back to the modloader folders.
+    /// Any files that are still missing afterwards are copied from the extracted mod.
+    ///
+    /// Does not install dependencies, hence the "unchecked".
+    pub(super) fn enable_unchecked(&mut self) -> Result<()> {
+        for (_, active_path, disabled_file_path) in self.get_disabled_file_paths() {
+            if disabled_file_path.exists() && !active_path.exists() {
+                debug!("Enabling {active_path:?}");
+                util::move_file(&disabled_file_path, &active_path).context("Moving mod file back from disabled files folder")?;
+            }
+        }
+
+        self.remove_disabled_files()?;
+        self.files_exist = Self::check_if_files_copied(&self.manifest).context("Checking if mod installed")?;
+        self.disk_size.take();
+        // e.g. a library used by another mod that has since been uninstalled
+        if let Err(err) = self.repair_unchecked() {
+            warn!("Failed to restore missing files of enabled mod: {err}");
+        }
 
         Ok(())
     }
 
+    /// Restores any files of a partially installed mod that are missing from their destinations, by copying them from the extracted mod.
+    /// A mod is partially installed if some, but not all, of its files exist in their destinations.
+    /// A mod with none of its files in their destinations is not installed, so is left alone.
+    ///
+    /// Does not repair dependencies, hence the "unchecked".
+    /// # Returns
+    /// `Ok(true)` if files were restored, `Ok(false)` if the mod did not need repairing, or an `Err` if
+    /// the mod could not be repaired, e.g. because a missing file is also missing from the extracted mod.
+    /// No files are copied if the mod cannot be repaired.
+    pub(super) fn repair_unchecked(&mut self) -> Result<bool> {
+        // A disabled mod is missing its files on purpose.
+        if self.files_exist || self.disabled {
+            return Ok(false);
+        }
+
+        let file_paths = self.get_file_paths();
+        let missing: Vec<&(PathBuf, PathBuf)> = file_paths.iter()
+            .filter(|(_, dest)| !dest.exists())
+            .collect();
+        if missing.len() == file_paths.len() {
+            return Ok(false);
+        }
+        util::check_copy_destinations(&self.manifest)
+            .with_context(|| format!("Mod {} copies files to a disallowed location", self.manifest.id))?;
+
+        let missing_sources: Vec<String> = missing.iter()
+            .filter(|(source, _)| !source.exists())
+            .map(|(source, _)| source.to_string_lossy().to_string())
+            .collect();
+        if !missing_sources.is_empty() {
+            return Err(anyhow!("Missing files do not exist in the extracted mod either: {}", missing_sources.join(", ")));
+        }
+
+        for (source, dest) in missing {
+            info!("Restoring missing file {dest:?}");
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent).context("Creating destination directory for mod file")?;
+            }
+            std::fs::copy(source, dest).context("Copying missing mod file")?;
+        }
+
+        self.files_exist = true;
+        self.disk_size.take();
+        Ok(true)
+    }
+
     /// Deletes the mod, and will not check first whether it needs to be uninstalled.
     pub(super) fn delete_unchecked(self) -> Result<()> {
         std::fs::remove_dir_all(self.loaded_from).context("Deleting mod extract directory")?;
@@ -154,8 +314,6 @@ impl Mod {
 
     /// Copies all auxillary file copies in the manifest from the extracted mod to the required destination.
     fn copy_file_copies(&self) -> Result<()> {
-        // TODO: Deny certain file copy destinations.
-
         for file_copy in &self.manifest().file_copies {
             let file_path_in_mod = self.loaded_from.join(&file_copy.name);
             if !file_path_in_mod.exists() {
@@ -189,6 +347,66 @@ impl Mod {
         Ok(())
     }
 
+    /// Removes the folder holding the mod's files while it is disabled, if it exists, marking the mod as no longer disabled.
+    fn remove_disabled_files(&mut self) -> Result<()> {
+        let disabled_path = self.loaded_from.join(DISABLED_DIR_NAME);
+        if disabled_path.exists() {
+            std::fs::remove_dir_all(disabled_path).context("Removing disabled files folder")?;
+        }
+
+        self.disabled = false;
+        Ok(())
+    }
+
+    /// Gets the file name of each mod binary file, paired with its path in the modloader folders and its path while the mod is disabled.
+    fn get_disabled_file_paths(&self) -> Vec<(OsString, PathBuf, PathBuf)> {
+        let disabled_path = self.loaded_from.join(DISABLED_DIR_NAME);
+        let mut file_paths = Vec::new();
+        for (files, modloader_folder) in self.get_binary_files() {
+            // Each modloader folder has a corresponding folder in the disabled files folder, since the folders may contain files with the same name.
+            let folder_name = Path::new(modloader_folder).file_name().expect("Modloader folders should have a name");
+            for file in files {
+                if let Some(file_name) = Path::new(file).file_name() {
+                    file_paths.push((
+                        file_name.to_owned(),
+                        Path::new(modloader_folder).join(file_name),
+                        disabled_path.join(folder_name).join(file_name),
+                    ));
+                }
+            }
+        }
+
+        file_paths
+    }
+
+    /// Gets the mod files, library files and late mod files of the mod, each paired with the modloader folder they are copied to.
+    fn get_binary_files(&self) -> [(&Vec<String>, &'static str); 3] {
+        let manifest = self.manifest();
+        [
+            (&manifest.mod_files, paths::EARLY_MODS),
+            (&manifest.library_files, paths::LIBS),
+            (&manifest.late_mod_files, paths::LATE_MODS),
+        ]
+    }
+
+    /// Gets the path within the extracted mod of each mod binary file and file copy, paired with the destination it is copied to when installed.
+    fn get_file_paths(&self) -> Vec<(PathBuf, PathBuf)> {
+        let mut file_paths = Vec::new();
+        for (files, modloader_folder) in self.get_binary_files() {
+            for file in files {
+                // As in `util::copy_files_from_mod_folder`, only the file name is kept in the destination.
+                if let Some(file_name) = Path::new(file).file_name() {
+                    file_paths.push((self.loaded_from.join(file), Path::new(modloader_folder).join(file_name)));
+                }
+            }
+        }
+        for file_copy in &self.manifest().file_copies {
+            file_paths.push((self.loaded_from.join(&file_copy.name), PathBuf::from(&file_copy.destination)));
+        }
+
+        file_paths
+    }
+
     /// Checks if the mod is installed, in the sense that all its mod binary files and file copies
     /// exist in their required destinations.
     /// # Arguments
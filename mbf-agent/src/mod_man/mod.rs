@@ -1,23 +1,31 @@
 //! Module for mod management within MBF.
 
+mod bundle;
+mod conflict;
 mod manifest;
 mod util;
 mod loaded_mod;
+mod policy;
 
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
     ffi::OsString,
     fs::OpenOptions,
-    io::{Cursor, Read, Seek},
+    io::{Read, Seek},
     path::{Path, PathBuf},
     rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use jsonschema::JSONSchema;
 use log::{debug, error, info, warn};
+pub use bundle::CoreModBundle;
+pub use conflict::{DependencyConflict, UpgradeConflict};
 pub use manifest::*;
 pub use loaded_mod::Mod;
+pub use policy::ModPolicy;
+pub use util::check_copy_destination;
 
 use anyhow::{anyhow, Context, Result};
 use mbf_res_man::{
@@ -25,10 +33,13 @@ use mbf_res_man::{
     models::{ModRepo, ModRepoMod},
     res_cache::ResCache,
 };
-use mbf_zip::ZipFile;
+use mbf_zip::{ExtractProgress, ZipFile};
 use semver::Version;
 
-use crate::{downloads, paths};
+use crate::{downloads, models::response::ErrorCode, paths};
+
+/// Suffix of the temporary folder that a new mod is extracted to before the existing version of the mod is replaced.
+const TEMP_EXTRACT_SUFFIX: &str = ".extracting";
 
 /// The JSON schema for the `mod.json` file within a qmod.
 /// This is the same schema used by QuestPatcher.
@@ -40,6 +51,15 @@ const QMOD_SCHEMA: &str = include_str!("qmod_schema.json");
 /// than "schema validation failed."
 const MAX_SCHEMA_VERSION: Version = Version::new(1, 2, 0);
 
+/// A directory within [paths::PACKAGES] containing the extracted QMODs for a particular game version.
+pub struct VersionModsDir {
+    /// The game version the QMODs within the directory are for.
+    pub game_version: String,
+    pub path: PathBuf,
+    /// The total size of all files within the directory, in bytes.
+    pub size: u64,
+}
+
 /// A structure to manage QMODs installed on Beat Saber.
 pub struct ModManager<'cache> {
     /// A map of mod IDs to mods.
@@ -54,6 +74,12 @@ pub struct ModManager<'cache> {
     res_cache: &'cache ResCache<'cache>,
     /// The mod repository used by MBF, if it has been loaded already.
     mod_repo: Option<ModRepo>,
+    /// If `Some`, the URL to load the mod repository from instead of the default.
+    override_mod_repo_url: Option<String>,
+    /// The policy restricting which mods may be installed, loaded with the mods.
+    policy: Option<ModPolicy>,
+    /// IDs of core mods (and their dependencies) that may be installed even if the policy does not permit them.
+    permitted_core_mods: HashSet<String>,
 }
 
 impl<'cache> ModManager<'cache> {
@@ -80,9 +106,45 @@ impl<'cache> ModManager<'cache> {
             game_version,
             res_cache,
             mod_repo: None,
+            override_mod_repo_url: None,
+            policy: None,
+            permitted_core_mods: HashSet::new(),
         }
     }
 
+    /// Allows the mods with the given IDs, and their dependencies, to be installed regardless of the mod policy.
+    /// This should be called with the IDs of the core mods before they are installed, so that the game remains playable.
+    pub fn permit_core_mods<'a>(&mut self, ids: impl IntoIterator<Item = &'a str>) {
+        self.permitted_core_mods.extend(ids.into_iter().map(str::to_owned));
+    }
+
+    // Gives an error if the mod policy does not permit the mod with the given ID to be installed.
+    fn check_policy_permits(&self, id: &str) -> Result<()> {
+        let policy = match &self.policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+
+        let is_core = self.permitted_core_mods.contains(id)
+            || self.mods.get(id).is_some_and(|m| m.borrow().is_core());
+        if is_core || policy.permits(id) {
+            Ok(())
+        } else if policy.allowlist {
+            Err(anyhow!("Mod {id} is not in the list of mods allowed on this device"))
+        } else {
+            Err(anyhow!("Mod {id} is blocked from being installed on this device"))
+        }
+    }
+
+    /// Sets a URL to load the mod repository from instead of the default, or `None` to use the default.
+    /// This is useful for developers testing dependency resolution against a staging repository.
+    pub fn set_override_mod_repo_url(&mut self, override_mod_repo_url: Option<String>) {
+        if self.override_mod_repo_url != override_mod_repo_url {
+            self.mod_repo = None;
+        }
+        self.override_mod_repo_url = override_mod_repo_url;
+    }
+
     /// Removes ALL mod/early-mod and library files, ensuring that all installed mods are removed from the game.
     pub fn wipe_all_mods(&mut self) -> Result<()> {
         self.mods.clear();
@@ -130,6 +192,7 @@ impl<'cache> ModManager<'cache> {
     pub fn load_mods(&mut self) -> Result<()> {
         self.create_mods_dir()?;
         self.mods.clear();
+        self.policy = ModPolicy::load().context("Loading mod policy")?;
 
         for stat in std::fs::read_dir(&self.qmods_dir)? {
             let entry = match stat {
@@ -141,6 +204,12 @@ impl<'cache> ModManager<'cache> {
             if !entry.file_type()?.is_dir() {
                 continue;
             }
+            // A new mod was being extracted here when the agent was interrupted, so the existing version was kept.
+            if entry.file_name().to_string_lossy().ends_with(TEMP_EXTRACT_SUFFIX) {
+                info!("Removing partially extracted mod at {mod_path:?}");
+                remove_temp_extract_dir(&mod_path);
+                continue;
+            }
 
             match self.load_mod_from_directory(mod_path.clone()) {
                 Ok(loaded_mod) => {
@@ -236,6 +305,14 @@ impl<'cache> ModManager<'cache> {
         if to_install.installed() {
             return Ok(());
         }
+        self.check_policy_permits(id)?;
+        // Core mods can't work without their dependencies, so these are permitted too.
+        if to_install.is_core() || self.permitted_core_mods.contains(id) {
+            let dep_ids: Vec<String> = to_install.manifest().dependencies.iter()
+                .map(|dep| dep.id.clone())
+                .collect();
+            self.permitted_core_mods.extend(dep_ids);
+        }
 
         info!(
             "Installing {} v{}",
@@ -327,6 +404,263 @@ impl<'cache> ModManager<'cache> {
         Ok(())
     }
 
+    /// Finds the mods that [ModManager::uninstall_mod] would uninstall, without uninstalling anything.
+    /// # Arguments
+    /// * `id` - the ID of the mod that would be uninstalled.
+    /// # Returns
+    /// The IDs of the mod and of every installed mod that requires it, transitively, with `id` first.
+    /// Empty if the mod does not exist or is not installed, since uninstalling it would do nothing.
+    pub fn uninstall_impact(&self, id: &str) -> Vec<String> {
+        let mut impacted = Vec::new();
+        self.add_uninstall_impact(id, &mut impacted);
+        impacted
+    }
+
+    // Adds the given mod and its depending mods to `impacted`, following the same recursion as `uninstall_mod`.
+    // Mods already in `impacted` are skipped, as `uninstall_mod` would have uninstalled them already.
+    fn add_uninstall_impact(&self, id: &str, impacted: &mut Vec<String>) {
+        let installed = self.mods.get(id)
+            .is_some_and(|m| (**m).borrow().installed());
+        if !installed || impacted.iter().any(|impacted_id| impacted_id == id) {
+            return;
+        }
+        impacted.push(id.to_string());
+
+        for (other_id, m) in self.mods.iter() {
+            if other_id == id {
+                continue;
+            }
+
+            let m_ref = (**m).borrow();
+            let requires_mod = m_ref.installed()
+                && m_ref
+                    .manifest()
+                    .dependencies
+                    .iter()
+                    .any(|dep| dep.id == id && dep.required);
+            drop(m_ref);
+
+            if requires_mod {
+                self.add_uninstall_impact(other_id, impacted);
+            }
+        }
+    }
+
+    /// Disables the mod with the given ID: see [Mod::disable_unchecked].
+    /// Unlike [ModManager::uninstall_mod], mods that depend on this mod are not uninstalled, although they will no
+    /// longer be considered installed. Does nothing if the mod is already disabled.
+    /// # Arguments
+    /// * `id` - the ID of the mod to disable.
+    pub fn disable_mod(&self, id: &str) -> Result<()> {
+        let mod_rc = self
+            .mods
+            .get(id)
+            .ok_or(anyhow!(
+                "Could not disable mod with ID {id} as it did not exist"
+            ))?;
+        if mod_rc.borrow().disabled() {
+            return Ok(());
+        }
+
+        info!("Disabling {id}");
+        let retained_libs = self.get_retained_lib_files(id);
+        mod_rc.borrow_mut().disable_unchecked(retained_libs)
+            .context("Disabling unchecked")?;
+        Ok(())
+    }
+
+    /// Enables the mod with the given ID after it was disabled with [ModManager::disable_mod].
+    /// This does not download or install any dependencies, so the mod will only be installed if its dependencies still are.
+    /// Does nothing if the mod is not disabled.
+    /// # Arguments
+    /// * `id` - the ID of the mod to enable.
+    pub fn enable_mod(&self, id: &str) -> Result<()> {
+        let mod_rc = self
+            .mods
+            .get(id)
+            .ok_or(anyhow!(
+                "Could not enable mod with ID {id} as it did not exist"
+            ))?;
+        if !mod_rc.borrow().disabled() {
+            return Ok(());
+        }
+
+        info!("Enabling {id}");
+        mod_rc.borrow_mut().enable_unchecked()
+            .context("Enabling unchecked")?;
+        Ok(())
+    }
+
+    /// Gets the file names of any libraries in the modloader's libs folder that do not belong to any loaded mod,
+    /// e.g. as they were left behind by a mod that was deleted without being uninstalled.
+    /// # Returns
+    /// The file names of the orphaned libraries, sorted.
+    pub fn get_orphaned_libs(&self) -> Result<Vec<String>> {
+        if !Path::new(paths::LIBS).exists() {
+            return Ok(Vec::new());
+        }
+
+        let owned_libs: HashSet<OsString> = self
+            .mods
+            .values()
+            .flat_map(|m| m.borrow().manifest().library_files.clone())
+            .filter_map(|lib_path| Path::new(&lib_path).file_name().map(|name| name.to_owned()))
+            .collect();
+
+        let mut orphaned = Vec::new();
+        for entry in std::fs::read_dir(paths::LIBS).context("Listing modloader libs")? {
+            let file_name = entry?.file_name();
+            if !owned_libs.contains(&file_name) {
+                orphaned.push(file_name.to_string_lossy().into_owned());
+            }
+        }
+
+        orphaned.sort();
+        Ok(orphaned)
+    }
+
+    /// Lists the directories of extracted QMODs for every game version that has been modded, including the current version.
+    /// # Returns
+    /// The directories found, sorted by game version.
+    pub fn get_version_mod_dirs() -> Result<Vec<VersionModsDir>> {
+        if !Path::new(paths::PACKAGES).exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut dirs = Vec::new();
+        for entry in std::fs::read_dir(paths::PACKAGES).context("Listing Packages directory")? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let path = entry.path();
+            dirs.push(VersionModsDir {
+                game_version: entry.file_name().to_string_lossy().into_owned(),
+                size: util::get_dir_size(&path)
+                    .with_context(|| format!("Getting size of {path:?}"))?,
+                path,
+            });
+        }
+
+        dirs.sort_by(|a, b| a.game_version.cmp(&b.game_version));
+        Ok(dirs)
+    }
+
+    /// Deletes the directories of extracted QMODs for all game versions other than the current version,
+    /// which are left behind after downgrading or upgrading the game.
+    /// Only the QMODs are deleted: the mod, early mod and library files in the modloader folders are
+    /// shared by all versions and belong to the current version's installed mods, so are never touched.
+    /// # Returns
+    /// The directories that were deleted.
+    pub fn remove_other_version_mod_dirs(&self) -> Result<Vec<VersionModsDir>> {
+        let mut removed = Vec::new();
+        for dir in Self::get_version_mod_dirs()? {
+            if dir.game_version == self.game_version {
+                continue;
+            }
+
+            info!("Deleting QMODs for game version {}", dir.game_version);
+            std::fs::remove_dir_all(&dir.path)
+                .with_context(|| format!("Deleting QMODs for game version {}", dir.game_version))?;
+            removed.push(dir);
+        }
+
+        Ok(removed)
+    }
+
+    /// Reinstalls the mod with the given ID by removing its files and copying them again from the extracted mod,
+    /// replacing any files that have been corrupted.
+    /// Unlike [ModManager::remove_mod], the mod is not deleted and mods that depend on it are not uninstalled.
+    /// Dependencies are not installed, so the mod will only be installed afterwards if its dependencies are.
+    /// # Arguments
+    /// * `id` - the ID of the mod to reinstall.
+    pub fn reinstall_mod(&mut self, id: &str) -> Result<()> {
+        let mod_rc = self
+            .mods
+            .get(id)
+            .ok_or(anyhow!(
+                "Could not reinstall mod with ID {id} as it did not exist"
+            ))?;
+        if mod_rc.borrow().disabled() {
+            return Err(anyhow!("Could not reinstall mod with ID {id} as it is disabled. Enable it first"));
+        }
+
+        info!("Reinstalling {id}");
+        // Libraries used by other mods are kept, so these mods still work if copying the files again fails.
+        // Any that are corrupted are still replaced when the mod is installed again.
+        let retained_libs = self.get_retained_lib_files(id);
+        let mut mod_ref = mod_rc.borrow_mut();
+        mod_ref.uninstall_unchecked(retained_libs)
+            .context("Uninstalling unchecked")?;
+        mod_ref.install_unchecked()
+            .context("Installing unchecked")?;
+        drop(mod_ref);
+
+        self.check_mods_installed().context("Checking if mods installed after reinstall")?;
+        Ok(())
+    }
+
+    /// Restores the missing files of any partially installed mods. See [Mod::repair_unchecked].
+    /// The install status of all mods is checked again afterwards.
+    /// # Returns
+    /// The IDs of the mods that were repaired, and a map of the IDs of mods that could not be repaired to the reason why.
+    pub fn repair_mods(&mut self) -> Result<(Vec<String>, HashMap<String, String>)> {
+        let mut repaired = Vec::new();
+        let mut unrepairable = HashMap::new();
+        for (id, mod_rc) in &self.mods {
+            match mod_rc.borrow_mut().repair_unchecked() {
+                Ok(true) => {
+                    info!("Repaired {id}");
+                    repaired.push(id.clone());
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    warn!("Could not repair {id}: {err}");
+                    unrepairable.insert(id.clone(), err.to_string());
+                }
+            }
+        }
+
+        self.check_mods_installed().context("Checking if mods installed after repair")?;
+        Ok((repaired, unrepairable))
+    }
+
+    /// Downloads a QMOD from the given URL and loads it with [ModManager::try_load_new_mod].
+    /// The QMOD is downloaded to a temporary file rather than into memory, since QMODs can be large.
+    /// The temporary file is removed whether or not loading succeeds.
+    /// # Arguments
+    /// * `url` - The URL to download the mod from.
+    /// * `expected_sha256` - If given, the downloaded mod is checked to have this SHA-256 hash.
+    /// # Returns
+    /// If successful, the ID of the loaded mod.
+    pub fn try_download_new_mod(&mut self, url: &str, expected_sha256: Option<[u8; 32]>) -> Result<String> {
+        self.try_download_new_mod_with_progress(url, expected_sha256, &downloads::report_download_progress)
+    }
+
+    /// Downloads and loads a QMOD in the same way as [ModManager::try_download_new_mod], but reports the progress
+    /// of the download through `on_progress`.
+    pub fn try_download_new_mod_with_progress(
+        &mut self,
+        url: &str,
+        expected_sha256: Option<[u8; 32]>,
+        on_progress: downloads::ProgressCallback,
+    ) -> Result<String> {
+        std::fs::create_dir_all(paths::MBF_DOWNLOADS)?;
+        let download_path = Path::new(paths::MBF_DOWNLOADS).join("mod_download.qmod");
+
+        downloads::download_file_with_progress(&crate::get_dl_cfg(), &download_path, url, expected_sha256, on_progress)
+            .context("Downloading mod")?;
+        let result = std::fs::File::open(&download_path)
+            .context("Opening downloaded mod")
+            .and_then(|mod_file| self.try_load_new_mod(mod_file));
+
+        if let Err(err) = std::fs::remove_file(&download_path) {
+            warn!("Failed to remove downloaded mod: {err}");
+        }
+        result
+    }
+
     /// Attempts to load a new QMOD from a stream.
     /// This will load the mod as a ZIP and validate its manifest.
     ///
@@ -341,6 +675,24 @@ impl<'cache> ModManager<'cache> {
     /// # Returns
     /// If successful, the ID of the loaded mod.
     pub fn try_load_new_mod(&mut self, mod_stream: impl Read + Seek) -> Result<String> {
+        self.try_load_new_mod_with_progress(mod_stream, |_| {}, None)
+    }
+
+    /// Loads a new QMOD from a stream in the same way as [ModManager::try_load_new_mod], but reports progress
+    /// through extracting the mod and allows extraction to be cancelled.
+    /// If extraction fails or is cancelled, the partially extracted mod is deleted.
+    /// # Arguments
+    /// * `mod_stream` - A readable and seekable stream to load the mod from.
+    /// * `on_progress` - Called after each file in the mod is extracted.
+    /// * `cancel` - If given, extraction stops with an error (with [ErrorCode::Cancelled]) once this is set to true.
+    /// # Returns
+    /// If successful, the ID of the loaded mod.
+    pub fn try_load_new_mod_with_progress(
+        &mut self,
+        mod_stream: impl Read + Seek,
+        on_progress: impl FnMut(ExtractProgress),
+        cancel: Option<&AtomicBool>,
+    ) -> Result<String> {
         let mut zip = ZipFile::open(mod_stream).context("Mod was invalid ZIP archive")?;
 
         let json_data = zip
@@ -355,37 +707,53 @@ impl<'cache> ModManager<'cache> {
             loaded_mod_manifest.id, loaded_mod_manifest.version, loaded_mod_manifest.author
         );
 
+        util::check_copy_destinations(&loaded_mod_manifest)
+            .with_context(|| format!("Mod {} copies files to a disallowed location", loaded_mod_manifest.id))?;
+        self.check_policy_permits(&loaded_mod_manifest.id)?;
+
         // Check that upgrading the mod to the new version is actually safe...
         let id = loaded_mod_manifest.id.clone();
-        if let Err(msg) = self.check_dependency_compatibility(&id, &loaded_mod_manifest.version) {
-            return Err(anyhow!(
-                "Could not upgrade {} to v{}: {}",
-                id,
-                loaded_mod_manifest.version,
-                msg
-            ));
+        self.check_dependency_compatibility(&id, &loaded_mod_manifest.version)?;
+
+        // Extract the mod to a temporary folder first, so that the existing version of the mod is kept
+        // if extraction fails or is cancelled.
+        info!(
+            "Extracting {} v{}",
+            loaded_mod_manifest.id, loaded_mod_manifest.version
+        );
+        let temp_extract_path = Path::new(&self.qmods_dir).join(format!(".{id}{TEMP_EXTRACT_SUFFIX}"));
+        debug!("Temporary extract path: {temp_extract_path:?}");
+        if temp_extract_path.exists() {
+            std::fs::remove_dir_all(&temp_extract_path).context("Removing leftover extract directory")?;
+        }
+        std::fs::create_dir_all(&temp_extract_path).context("Creating extract directory")?;
+        if let Err(err) = zip.extract_to_directory_with_progress(&temp_extract_path, on_progress, cancel) {
+            // Don't leave a partially extracted mod behind, as it would be loaded next time
+            remove_temp_extract_dir(&temp_extract_path);
+
+            let err = err.context("Extracting QMOD file");
+            return Err(if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                err.context(ErrorCode::Cancelled)
+            } else {
+                err
+            });
         }
 
         // Remove the existing version of the mod,
         // unchecked as we don't want to nuke any dependant mods or any of its dependencies; we have established that the upgrade is safe.
         // by allowing remove_mod to run a regular uninstall
-        if let Some(existing_mod) = self.mods.get(&id) {
-            info!("Removing existing version of mod");
-            existing_mod.borrow_mut().uninstall_unchecked(self.get_retained_lib_files(&id))
-                .context("Uninstalling existing mod")?;
+        if let Err(err) = self.remove_existing_version(&id) {
+            remove_temp_extract_dir(&temp_extract_path);
+            return Err(err);
         }
-        self.remove_mod(&id)?;
 
-        // Extract the mod to the mods folder
-        info!(
-            "Extracting {} v{}",
-            loaded_mod_manifest.id, loaded_mod_manifest.version
-        );
+        // Move the extracted mod into place in the mods folder
         let extract_path = self.get_mod_extract_path(&loaded_mod_manifest);
         debug!("Extract path: {extract_path:?}");
-        std::fs::create_dir_all(&extract_path).context("Creating extract directory")?;
-        zip.extract_to_directory(&extract_path)
-            .context("Extracting QMOD file")?;
+        if let Err(err) = std::fs::rename(&temp_extract_path, &extract_path) {
+            remove_temp_extract_dir(&temp_extract_path);
+            return Err(err).context("Moving extracted mod into place");
+        }
 
         // Insert the mod into the HashMap of loaded mods, and now it is ready to be manipulated by the mod manager!
         let loaded_mod = Mod::new(loaded_mod_manifest, extract_path).context("Creating Mod")?;
@@ -398,6 +766,16 @@ impl<'cache> ModManager<'cache> {
         Ok(id)
     }
 
+    // Uninstalls and deletes the existing version of the mod with the given ID before it is replaced with a new version.
+    fn remove_existing_version(&mut self, id: &str) -> Result<()> {
+        if let Some(existing_mod) = self.mods.get(id) {
+            info!("Removing existing version of mod");
+            existing_mod.borrow_mut().uninstall_unchecked(self.get_retained_lib_files(id))
+                .context("Uninstalling existing mod")?;
+        }
+        self.remove_mod(id)
+    }
+
     /// Uninstalls (if installed) and deletes the mod with the specified ID.
     /// If no mod exists with this ID, then the operation does nothing.
     ///
@@ -576,11 +954,8 @@ impl<'cache> ModManager<'cache> {
         };
 
         info!("Downloading dependency from {}", link);
-        let dependency_bytes =
-            downloads::download_to_vec_with_attempts(&crate::get_dl_cfg(), &link)
-                .context("Downloading dependency")?;
-
-        self.try_load_new_mod(Cursor::new(dependency_bytes))?;
+        self.try_download_new_mod(&link, None)
+            .context("Downloading dependency")?;
         self.install_mod(&dep.id)?;
         Ok(())
     }
@@ -680,7 +1055,8 @@ impl<'cache> ModManager<'cache> {
         checked_in_path: &mut HashSet<String>,
     ) -> Result<bool> {
         // If the mod does not have its files in the necessary destinations, then this mod definitely is not installed, so no need to check dependencies.
-        if !mod_ref.files_exist() {
+        // A disabled mod may still have libraries in place that are used by other mods, but is never installed.
+        if !mod_ref.files_exist() || mod_ref.disabled() {
             return Ok(false);
         }
 
@@ -754,16 +1130,15 @@ impl<'cache> ModManager<'cache> {
     }
 
     // Checks that upgrading the dependency with ID dep_id to new_version will not result in an incompatibility with an existing installed mod.
-    // Gives Err with a string containing the list of incompatibilities found, if any.
+    // Gives Err with an UpgradeConflict listing the incompatibilities found, if any.
     // Gives Ok if no incompatibilities are found.
     // Also logs any issues discovered.
     fn check_dependency_compatibility(
         &self,
         dep_id: &str,
         new_version: &Version,
-    ) -> Result<(), String> {
-        let mut incompatibilities = String::new();
-        let mut all_compatible = true;
+    ) -> Result<(), UpgradeConflict> {
+        let mut conflicts = Vec::new();
         for (_, existing_mod) in &self.mods {
             let mod_ref = (**existing_mod).borrow();
             // We don't care about uninstalled mods, since they have no invariants to preserve.
@@ -780,27 +1155,30 @@ impl<'cache> ModManager<'cache> {
             {
                 Some(existing_dep) => {
                     if !existing_dep.version_range.matches(new_version) {
-                        all_compatible = false;
-                        let incompat_msg = format!(
-                            "Mod {} depends on range {}",
+                        error!(
+                            "Cannot upgrade {dep_id} to {new_version}: Mod {} depends on range {}",
                             mod_ref.manifest().id, existing_dep.version_range
                         );
 
-                        error!("Cannot upgrade {dep_id} to {new_version}: {incompat_msg}");
-                        // Append each message to the overall error.
-                        incompatibilities.push_str(&incompat_msg);
-                        incompatibilities.push('\n');
+                        conflicts.push(DependencyConflict {
+                            dependent_id: mod_ref.manifest().id.clone(),
+                            dependent_version: mod_ref.manifest().version.clone(),
+                            version_range: existing_dep.version_range.clone(),
+                        });
                     }
                 }
                 None => {}
             }
         }
 
-        if all_compatible {
+        if conflicts.is_empty() {
             Ok(())
         } else {
-            incompatibilities.pop();
-            Err(incompatibilities)
+            Err(UpgradeConflict {
+                mod_id: dep_id.to_string(),
+                new_version: new_version.clone(),
+                conflicts,
+            })
         }
     }
 
@@ -809,10 +1187,19 @@ impl<'cache> ModManager<'cache> {
     /// A reference to the loaded mod repo, if successful.
     fn get_or_load_mod_repo(&mut self) -> Result<&ModRepo> {
         if self.mod_repo.is_none() {
-            self.mod_repo =
-                Some(external_res::get_mod_repo(&self.res_cache).context("Downloading mod repo")?)
+            self.mod_repo = Some(
+                external_res::get_mod_repo(&self.res_cache, self.override_mod_repo_url.as_deref())
+                    .context("Downloading mod repo")?,
+            )
         }
 
         Ok(self.mod_repo.as_ref().expect("Just loaded mod repo"))
     }
 }
+
+// Removes the temporary folder a new mod was being extracted to, after extracting or installing it failed.
+fn remove_temp_extract_dir(temp_extract_path: &Path) {
+    if let Err(err) = std::fs::remove_dir_all(temp_extract_path) {
+        warn!("Failed to remove partially extracted mod: {err}");
+    }
+}
@@ -0,0 +1,55 @@
+//! Policy restricting which mods may be installed, for devices that are shared or managed by someone else.
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+/// A list of mod IDs that are either the only mods allowed to be installed, or mods that are blocked from being installed.
+/// Core mods are always permitted regardless of the policy, so that the game can still be played.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ModPolicy {
+    /// If true, only the mods in `mod_ids` may be installed.
+    /// If false, any mod may be installed except those in `mod_ids`.
+    pub allowlist: bool,
+    pub mod_ids: HashSet<String>,
+}
+
+impl ModPolicy {
+    /// Loads the policy saved at [paths::MOD_POLICY], or gives `None` if no policy has been saved.
+    pub fn load() -> Result<Option<Self>> {
+        if !Path::new(paths::MOD_POLICY).exists() {
+            return Ok(None);
+        }
+
+        let policy_data = std::fs::read(paths::MOD_POLICY).context("Reading mod policy")?;
+        Ok(Some(
+            serde_json::from_slice(&policy_data).context("Mod policy was invalid JSON")?,
+        ))
+    }
+
+    /// Saves the given policy to [paths::MOD_POLICY], so that it is used by later requests.
+    /// If `policy` is `None`, any saved policy is removed.
+    pub fn save(policy: Option<&Self>) -> Result<()> {
+        match policy {
+            Some(policy) => {
+                if let Some(parent) = Path::new(paths::MOD_POLICY).parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(paths::MOD_POLICY, serde_json::to_vec_pretty(policy)?)
+                    .context("Writing mod policy")
+            }
+            None if Path::new(paths::MOD_POLICY).exists() => {
+                std::fs::remove_file(paths::MOD_POLICY).context("Removing mod policy")
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns true if the policy permits the mod with the given ID to be installed, ignoring whether it is a core mod.
+    pub fn permits(&self, id: &str) -> bool {
+        self.mod_ids.contains(id) == self.allowlist
+    }
+}
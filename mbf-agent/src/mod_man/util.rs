@@ -1,8 +1,11 @@
 //! Utilities for mod management.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use log::{debug, warn};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+
+use super::ModInfo;
+use crate::paths;
 
 /// Checks if all files with the specified file names exist within a directory.
 ///
@@ -90,3 +93,147 @@ pub(super) fn remove_file_names_from_folder(
 
     Ok(())
 }
+
+/// Moves a file to a new path, replacing any existing file.
+/// This will copy the file and then delete the original if the destination is on a different volume.
+pub(super) fn move_file(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    if std::fs::rename(&from, &to).is_err() {
+        std::fs::copy(&from, &to).context("Copying file to new location")?;
+        std::fs::remove_file(&from).context("Removing original file")?;
+    }
+
+    Ok(())
+}
+
+/// Gets the total size of all files within a directory, including those in subdirectories.
+/// Symbolic links are not followed.
+/// # Returns
+/// The size in bytes, or an `Err` if the directory or any of its subdirectories could not be read.
+pub(super) fn get_dir_size(dir_path: impl AsRef<Path>) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir_path).context("Reading directory")? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += get_dir_size(entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Checks that the destination of a file copy or copy extension is within one of the [paths::FILE_COPY_ROOTS],
+/// so that a mod cannot write files elsewhere on the device.
+/// The destination does not need to exist.
+///
+/// # Returns
+/// `Ok` if the destination is allowed, or an `Err` if it is not absolute, contains `..`, or is outside the allowed folders.
+pub fn check_copy_destination(destination: &str) -> Result<()> {
+    let path = Path::new(destination);
+    if !path.is_absolute() {
+        return Err(anyhow!("Destination {destination} is not an absolute path"));
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                return Err(anyhow!("Destination {destination} must not contain `..`"))
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+
+    if paths::FILE_COPY_ROOTS
+        .iter()
+        .any(|root| normalized.starts_with(root) && normalized != Path::new(root))
+    {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Destination {destination} is not within an allowed folder: {}",
+            paths::FILE_COPY_ROOTS.join(", ")
+        ))
+    }
+}
+
+/// Checks that the destinations of all file copies and copy extensions of a mod are allowed.
+/// See [check_copy_destination].
+pub(super) fn check_copy_destinations(manifest: &ModInfo) -> Result<()> {
+    for file_copy in &manifest.file_copies {
+        check_copy_destination(&file_copy.destination)
+            .with_context(|| format!("File copy {} is not allowed", file_copy.name))?;
+    }
+    for copy_ext in &manifest.copy_extensions {
+        check_copy_destination(&copy_ext.destination)
+            .with_context(|| format!("Copy extension .{} is not allowed", copy_ext.extension))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destinations_within_mod_data_are_allowed() {
+        for destination in [
+            "/sdcard/ModData/com.beatgames.beatsaber/Mods/Qosmetics/Sabers/saber.qsaber",
+            "/sdcard/ModData/com.beatgames.beatsaber/./Configs/config.json",
+            "/storage/emulated/0/Android/data/com.beatgames.beatsaber/files/file.txt",
+        ] {
+            assert!(
+                check_copy_destination(destination).is_ok(),
+                "{destination} was rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn destinations_with_parent_dirs_are_rejected() {
+        for destination in [
+            "/sdcard/ModData/com.beatgames.beatsaber/../../DCIM/photo.jpg",
+            "/sdcard/ModData/com.beatgames.beatsaber/Mods/../../com.other.app/file",
+            "/sdcard/ModData/com.beatgames.beatsaber/..",
+        ] {
+            assert!(
+                check_copy_destination(destination).is_err(),
+                "{destination} was allowed"
+            );
+        }
+    }
+
+    #[test]
+    fn relative_destinations_are_rejected() {
+        for destination in [
+            "sdcard/ModData/com.beatgames.beatsaber/file.txt",
+            "file.txt",
+            "./file.txt",
+            "",
+        ] {
+            assert!(
+                check_copy_destination(destination).is_err(),
+                "{destination} was allowed"
+            );
+        }
+    }
+
+    #[test]
+    fn destinations_outside_allowed_folders_are_rejected() {
+        for destination in [
+            "/sdcard/ModData/com.beatgames.beatsaber",
+            "/sdcard/ModData/com.beatgames.beatsaber2/file.txt",
+            "/sdcard/DCIM/photo.jpg",
+            "/data/local/tmp/file",
+        ] {
+            assert!(
+                check_copy_destination(destination).is_err(),
+                "{destination} was allowed"
+            );
+        }
+    }
+}
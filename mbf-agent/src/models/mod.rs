@@ -3,3 +3,7 @@
 
 pub mod request;
 pub mod response;
+
+/// The version of the protocol used for communication between the frontend and backend.
+/// This must be incremented whenever the models change in a way that a frontend or agent built before the change cannot handle.
+pub const PROTOCOL_VERSION: u32 = 1;
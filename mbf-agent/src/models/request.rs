@@ -4,6 +4,21 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 
+use crate::mod_man::ModPolicy;
+
+/// A request, along with the version of the protocol used by the frontend that sent it.
+#[derive(Deserialize)]
+pub struct RequestEnvelope {
+    /// `None` if the request was sent by a frontend from before the protocol was versioned.
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    /// If given, limits the total speed of the downloads made while handling the request.
+    #[serde(default)]
+    pub max_download_bytes_per_sec: Option<u64>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum Request {
@@ -18,6 +33,20 @@ pub enum Request {
         // This is useful for developers testing a core mod update.
         override_core_mod_url: Option<String>,
     },
+    /// Gets the version of the agent and the version of the protocol that it uses.
+    /// Gives an `AgentVersion` response.
+    GetAgentVersion,
+    /// Runs harmless commands with each of the tools that MBF needs, such as `pm` and `am`, and checks that the directories
+    /// MBF writes to are writable, so that an agent lacking the privileges it needs fails up front rather than part way through a request.
+    /// Gives a `PrivilegesChecked` response, or an error with code `MissingPrivilege` naming the command that failed.
+    CheckPrivileges,
+    /// Checks each part of the modded installation, i.e. the patched APK, the modloader, the core mods, the mod
+    /// libraries and the OBB, and reports which of these are broken and how to fix them.
+    /// Gives an `InstallVerified` response.
+    VerifyInstall {
+        #[serde(default)]
+        override_core_mod_url: Option<String>,
+    },
     /// Installs or uninstalls any number of mods.
     /// This will also attempt to download and install dependencies, upgrade dependencies and will uninstall any
     /// depending mods of mods that have been disabled.
@@ -25,6 +54,19 @@ pub enum Request {
     /// Returns a `ModSyncResult` response.
     SetModsEnabled {
         statuses: HashMap<String, bool>,
+        // If not null, this specifies a mod repo JSON to use instead of the default when finding dependencies to download.
+        // This is useful for developers testing against a staging mod repo.
+        #[serde(default)]
+        override_mod_repo_url: Option<String>,
+    },
+    /// Disables or enables any number of mods, with each mod ID mapped to `true` to disable it and `false` to enable it.
+    /// Disabling a mod moves its files out of the modloader folders rather than deleting them, so that it can be
+    /// enabled again quickly. Unlike `SetModsEnabled`, this does not uninstall depending mods when a mod is disabled,
+    /// or install dependencies when a mod is enabled.
+    ///
+    /// Returns a `ModSyncResult` response.
+    SetModsDisabled {
+        statuses: HashMap<String, bool>,
     },
 
     // TODO: Make these lists to allow importing multiple mods at once?
@@ -33,17 +75,86 @@ pub enum Request {
     RemoveMod {
         id: String,
     },
+    /// Finds the mods that would be uninstalled by uninstalling the mod with the given ID, without changing anything.
+    /// This is the mod itself and any installed mods that depend on it.
+    /// Returns an `UninstallImpact` response.
+    GetUninstallImpact {
+        id: String,
+    },
+    /// Removes the files of the mod with the given ID and copies them again from the extracted mod, to replace
+    /// any corrupted files. Dependant mods are left installed.
+    /// Returns a Mods message containing the mods now installed.
+    ReinstallMod {
+        id: String,
+    },
+    /// Gets the paths of the files that the mod with the given ID copies to when installed.
+    /// Returns a `ModFiles` response.
+    GetModFiles {
+        id: String,
+    },
+    /// Reads the config file of the installed mod with the given ID, which the mod reads its settings from.
+    /// Returns a `ModConfig` response.
+    GetModConfig {
+        id: String,
+    },
+    /// Saves the config file of the installed mod with the given ID, or removes it if `config` is null so that the
+    /// mod uses its default settings. The config must be a JSON object.
+    /// Returns a `ModConfig` response.
+    SetModConfig {
+        id: String,
+        #[serde(default)]
+        config: Option<serde_json::Value>,
+    },
+    /// Saves the policy restricting which mods may be installed, or removes the saved policy if `policy` is null.
+    /// Core mods are always permitted, whatever the policy.
+    /// Returns a `ModPolicy` response.
+    SetModPolicy {
+        #[serde(default)]
+        policy: Option<ModPolicy>,
+    },
+    /// Restores the files of any mods that are partially installed, i.e. have some of their files missing
+    /// from the modloader folders, by copying them again from the extracted mod.
+    /// Returns a `ModsRepaired` response.
+    RepairMods,
+    /// Lists the directories of extracted QMODs kept for each game version that has been modded, and their sizes.
+    /// Returns a `VersionModDirs` response.
+    GetVersionModDirs,
+    /// Deletes the extracted QMODs kept for game versions other than the installed version, to free up space after
+    /// downgrading or upgrading the game. The installed mods of the current version are not affected.
+    /// Returns a `DeletedOtherVersionMods` response.
+    DeleteOtherVersionMods,
     /// Imports a mod or file copy from the given path on the quest.
     /// Returns an ImportedMod message containing the mods now installed, and the ID of the one that was imported, if importing a mod.
     /// Returns an ImportedFileCopy message if the file type was copied by a mod copy extension.
     /// Returns an ImportedSong message if the file type was copied to the songs folder.
     Import {
         from_path: String,
+        // If not null, this specifies a mod repo JSON to use instead of the default when finding dependencies to download.
+        // This is useful for developers testing against a staging mod repo.
+        #[serde(default)]
+        override_mod_repo_url: Option<String>,
+        // If not null, the size in bytes that the file at `from_path` should be.
+        // A file of any other size is deleted without being imported, as the upload was likely interrupted.
+        #[serde(default)]
+        expected_size: Option<u64>,
+    },
+    /// Imports every QMOD file in the directory at the given path on the quest, continuing past any that fail to import.
+    /// The QMOD files are left in place.
+    /// Returns an ImportedDirectory message.
+    ImportDirectory {
+        path: String,
+        // If not null, this specifies a mod repo JSON to use instead of the default when finding dependencies to download.
+        #[serde(default)]
+        override_mod_repo_url: Option<String>,
     },
     /// Downloads the file from the given URL and then attempts to import it.
     /// Returns an ImportResult message.
     ImportUrl {
         from_url: String,
+        // If not null, this specifies a mod repo JSON to use instead of the default when finding dependencies to download.
+        // This is useful for developers testing against a staging mod repo.
+        #[serde(default)]
+        override_mod_repo_url: Option<String>,
     },
 
     /// - Patches Beat Saber to add support for modloaders.
@@ -57,6 +168,16 @@ pub enum Request {
         // The contents of the manifest of the patched app, as XML
         // The frontend is reponsible for adding the necessary permissions and features here.
         manifest_mod: String,
+        // Names of permissions to add to the manifest as `uses-permission` elements, in addition to those in `manifest_mod`.
+        // Permissions already declared in `manifest_mod` are not duplicated.
+        #[serde(default)]
+        extra_permissions: Vec<String>,
+        // If not null, `android:debuggable` is set to this value on the `application` element, replacing any existing value.
+        #[serde(default)]
+        debuggable: Option<bool>,
+        // If not null, `android:extractNativeLibs` is set to this value on the `application` element, replacing any existing value.
+        #[serde(default)]
+        extract_native_libs: Option<bool>,
         // The complete path to a PNG file to be used as the vr_splash.png file within the APK
         // This is the splash screen that appears when starting the game in headset.
         // This file will always be automatically deleted after patching, whether it succeeded or failed.
@@ -72,6 +193,24 @@ pub enum Request {
         // If not null, this specifies a core mod JSON to use instead of the default core mods source.
         // This is useful for developers testing a core mod update.
         override_core_mod_url: Option<String>,
+        // If not null, this specifies a mod repo JSON to use instead of the default when finding dependencies to download.
+        // This is useful for developers testing against a staging mod repo.
+        #[serde(default)]
+        override_mod_repo_url: Option<String>,
+        // If not null, the directory to store temporary files in while patching, e.g. the copied APK and OBB backups.
+        // The files are stored in a subdirectory named `mbf-patch`, which is deleted after patching. The rest of the directory is left as is.
+        // If null, the known temporary directory on the volume with the most free space is used.
+        #[serde(default)]
+        temp_dir: Option<String>,
+        // If this is true, the player data (and the datakeeper copy of it) is not backed up before reinstalling the app,
+        // so any existing player data, e.g. the user's scores and settings, is lost.
+        #[serde(default)]
+        skip_player_data: bool,
+        // If not null, the path to a directory or ZIP file on the device containing a `core_mods.json` and core mod QMODs.
+        // The core mods are installed from this bundle instead of being downloaded, so that core mods can be installed without network access.
+        // Any core mods listed in the bundle's index but missing from the bundle are still downloaded.
+        #[serde(default)]
+        core_mods_bundle: Option<String>,
     },
 
     // Attempts to fix a blackscreen issue by removing PlayerData.dat from `/sdcard/...../files/`.
@@ -80,10 +219,38 @@ pub enum Request {
     // unfortunately chmod 777 doesn't seem to fix the issue.)
     // Gives a `FixedPlayerData` response.
     FixPlayerData,
+    /// Deletes the OBB files left behind in the OBB directory by a previous install of Beat Saber.
+    /// Fails if Beat Saber is installed, as the OBBs are then needed by the game.
+    /// Gives a `DeletedOrphanedObbs` response.
+    DeleteOrphanedObbs,
+    /// Gets the AndroidManifest.xml of the installed APK (which may have been patched), converted from AXML into an XML string.
+    /// Gives a `CurrentManifest` response.
+    GetCurrentManifest,
     /// Gets a copy of the AndroidManifest.xml for the given Beat Saber version, converted from AXML into an XML string.
     GetDowngradedManifest {
         version: String,
     },
+    /// Gets the core mods that would be installed for the given Beat Saber version, from the core mod index.
+    /// Returns a `CoreModsForVersion` response.
+    GetCoreModsForVersion {
+        version: String,
+        // If not null, this specifies a core mod JSON to use instead of the default core mods source.
+        #[serde(default)]
+        override_core_mod_url: Option<String>,
+    },
+    /// Reads the end of the most recent mod log and the most recent crash dump, if they exist and can be accessed.
+    /// Returns a `Logs` response.
+    ReadLogs {
+        // The maximum number of bytes to read from the end of each file.
+        max_bytes: usize,
+    },
+    /// Collects the mod log, most recent crash dump, cached core mod index, device information, installed mods and
+    /// the checks made by `VerifyInstall` into a ZIP file, which the user can attach to a bug report.
+    /// Returns a `Diagnostics` response.
+    ExportDiagnostics {
+        #[serde(default)]
+        override_core_mod_url: Option<String>,
+    },
     /// Reinstalls any core mods that are misssing/out of date and overwrites the modloader in case it is corrupt.
     /// Should fix most issues with any installation.
     /// Returns a `Mods` response containing the newly installed mods.
@@ -91,7 +258,65 @@ pub enum Request {
         // If not null, this specifies a core mod JSON to use instead of the default core mods source.
         // This is useful for developers testing a core mod update.
         override_core_mod_url: Option<String>,
+        // If not null, this specifies a mod repo JSON to use instead of the default when finding dependencies to download.
+        // This is useful for developers testing against a staging mod repo.
+        #[serde(default)]
+        override_mod_repo_url: Option<String>,
         // If true, this request will delete ALL mods before reinstalling only the core mods.
         wipe_existing_mods: bool,
+        // If not null, the path to a directory or ZIP file on the device to install core mods from instead of downloading them.
+        // See `Patch` for the format of the bundle.
+        #[serde(default)]
+        core_mods_bundle: Option<String>,
     },
 }
+
+/// The lock on [paths::OPERATION_LOCK](crate::paths::OPERATION_LOCK) that a request takes while it is handled.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OperationLock {
+    /// The request does not read or modify the installed mods, so can run at any time.
+    None,
+    /// The request reads the installed mods, so cannot run while they are being modified.
+    /// Loading the mods can still delete mods that fail to load, so this must not be held alongside an `Exclusive` lock.
+    Shared,
+    /// The request modifies the installation (e.g. the APK, mods or player data) or shared temporary files,
+    /// so no other request using the lock can run at the same time.
+    Exclusive,
+}
+
+impl Request {
+    /// Gets the lock that must be held while handling the request.
+    pub fn operation_lock(&self) -> OperationLock {
+        match self {
+            Self::GetAgentVersion
+            | Self::CheckPrivileges
+            | Self::GetCurrentManifest
+            | Self::GetDowngradedManifest { .. }
+            | Self::GetCoreModsForVersion { .. }
+            | Self::ReadLogs { .. } => OperationLock::None,
+            Self::GetModStatus { .. }
+            | Self::VerifyInstall { .. }
+            | Self::GetUninstallImpact { .. }
+            | Self::GetModFiles { .. }
+            | Self::GetModConfig { .. }
+            | Self::GetVersionModDirs => OperationLock::Shared,
+            // Diagnostics are written to a fixed path, so only one request can export them at a time.
+            Self::ExportDiagnostics { .. }
+            | Self::SetModsEnabled { .. }
+            | Self::SetModsDisabled { .. }
+            | Self::RemoveMod { .. }
+            | Self::ReinstallMod { .. }
+            | Self::SetModConfig { .. }
+            | Self::SetModPolicy { .. }
+            | Self::RepairMods
+            | Self::DeleteOtherVersionMods
+            | Self::Import { .. }
+            | Self::ImportDirectory { .. }
+            | Self::ImportUrl { .. }
+            | Self::Patch { .. }
+            | Self::FixPlayerData
+            | Self::DeleteOrphanedObbs
+            | Self::QuickFix { .. } => OperationLock::Exclusive,
+        }
+    }
+}
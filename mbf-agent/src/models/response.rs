@@ -1,7 +1,11 @@
 //! Models used for communication *from the backend back to the frontend*
 
+use std::collections::{BTreeMap, HashMap};
+
 use serde::{Deserialize, Serialize};
 
+use mbf_res_man::models::CoreMod;
+
 use crate::mod_man;
 
 #[derive(Serialize)]
@@ -12,6 +16,44 @@ pub struct AppInfo {
     pub path: String,
     pub version: String,
     pub manifest_xml: String,
+    /// The size of the installed APK, in bytes.
+    pub apk_size: u64,
+    /// The permissions declared in the manifest of the installed APK.
+    pub permissions: Vec<String>,
+    /// The `minSdkVersion` and `targetSdkVersion` in the manifest of the installed APK, if given.
+    pub min_sdk_version: Option<u32>,
+    pub target_sdk_version: Option<u32>,
+    /// The label of the app, as shown in the app library, if it could be found.
+    pub label: Option<String>,
+    /// The icon of the app as a base64 encoded PNG, if it has a PNG icon.
+    pub icon_png: Option<String>,
+    /// The APK signature schemes that the installed APK is signed with. Empty if it is not signed with any scheme MBF can detect.
+    pub signature_schemes: Vec<SignatureScheme>,
+    /// The paths of any split APKs installed alongside the APK at `path`.
+    /// MBF can only patch apps installed as a single APK.
+    #[serde(skip_serializing)]
+    pub split_paths: Vec<String>,
+}
+
+/// The result of one check made by a `VerifyInstall` request.
+#[derive(Serialize)]
+pub struct InstallCheck {
+    pub name: String,
+    pub passed: bool,
+    /// More information about why the check failed, if it did.
+    pub details: Option<String>,
+    /// What the user should do to fix the installation if the check failed.
+    pub remediation: Option<String>,
+}
+
+/// A directory of extracted QMODs for a particular game version.
+#[derive(Serialize)]
+pub struct VersionModsDir {
+    pub game_version: String,
+    /// The total size of the QMODs within the directory, in bytes.
+    pub size: u64,
+    /// True if the directory is for the installed game version, so contains the currently loaded mods.
+    pub is_current: bool,
 }
 
 #[derive(Serialize)]
@@ -34,10 +76,130 @@ pub enum InstallStatus {
     Ready,
     /// Installed but not up to date
     NeedUpdate,
+    /// Installed, but the APK must be patched again to bring it up to date, so a quick fix will not help
+    NeedsRepatch,
     /// Not installed
     Missing,
 }
 
+/// A category of failure, sent to the frontend with a failed request so that it can show steps to recover.
+/// An `anyhow::Error` is given a code by including the code in its chain, typically as the root cause
+/// e.g. `.ok_or(ErrorCode::NotInstalled).context("Patching")`
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum ErrorCode {
+    /// Beat Saber is not installed
+    NotInstalled,
+    /// There are no core mods for the installed version of Beat Saber
+    NoCoreMods,
+    /// A file could not be downloaded, e.g. due to there being no internet connection
+    NetworkFailure,
+    /// There is not enough free space on the Quest
+    InsufficientSpace,
+    /// The patched APK could not be signed, or Android rejected its signature
+    SignatureFailure,
+    /// The request was cancelled by the user
+    Cancelled,
+    /// The agent is not running with the privileges it needs, e.g. as it was not started from an ADB shell
+    MissingPrivilege,
+    /// The request was sent by a frontend using a different protocol version, so the agent needs to be updated
+    IncompatibleProtocol,
+    /// Another request that modifies the installation is already running
+    OperationInProgress,
+    /// A mod could not be upgraded as installed mods depend on a different version of it
+    DependencyConflict,
+    /// Any other failure
+    Unknown,
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotInstalled => "Beat Saber is not installed",
+            Self::NoCoreMods => "No core mods are available for this version of Beat Saber",
+            Self::NetworkFailure => "Failed to download a file, check your internet connection",
+            Self::InsufficientSpace => "Not enough free space on the Quest",
+            Self::SignatureFailure => "APK signature was invalid",
+            Self::Cancelled => "Cancelled",
+            Self::MissingPrivilege => "The agent is missing a privilege it needs",
+            Self::IncompatibleProtocol => {
+                "The agent is outdated or too new for this version of MBF"
+            }
+            Self::OperationInProgress => "Another operation is in progress",
+            Self::DependencyConflict => "Installed mods depend on a different version of this mod",
+            Self::Unknown => "Unknown error",
+        })
+    }
+}
+
+impl std::error::Error for ErrorCode {}
+
+/// A scheme that an APK can be signed with.
+#[derive(Serialize, Debug)]
+pub enum SignatureScheme {
+    /// JAR signing, with the signature files in `META-INF`.
+    V1,
+    /// A V2 signature within the APK Signing Block.
+    V2,
+    /// A V3 signature within the APK Signing Block, which supports key rotation.
+    V3,
+}
+
+/// The space available on a volume (partition) of the Quest.
+#[derive(Serialize)]
+pub struct VolumeSpace {
+    /// The paths used by MBF that are on this volume.
+    pub paths: Vec<String>,
+    /// The number of bytes available to MBF.
+    pub free_bytes: u64,
+}
+
+/// The differences between the manifest of the APK before and after patching.
+/// Elements are identified by their path from the root element, e.g. `manifest/application`. If an element has an
+/// `android:name` attribute, its value is included in the path, e.g. `manifest/uses-permission[android.permission.INTERNET]`.
+/// If several elements have the same path, all but the first have `#2`, `#3`, etc. added to the end.
+#[derive(Serialize, Default)]
+pub struct ManifestDiff {
+    /// Elements only present in the patched manifest.
+    pub added_elements: Vec<ManifestElement>,
+    /// Elements only present in the original manifest.
+    pub removed_elements: Vec<ManifestElement>,
+    /// Elements present in both manifests, but with different attributes.
+    pub changed_elements: Vec<ChangedElement>,
+}
+
+#[derive(Serialize)]
+pub struct ManifestElement {
+    pub path: String,
+    /// The attributes of the element, as they would be written in the manifest XML, e.g. `android:name`,
+    /// mapped to their values.
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+pub struct ChangedElement {
+    pub path: String,
+    pub added_attributes: BTreeMap<String, String>,
+    pub removed_attributes: BTreeMap<String, String>,
+    /// Attributes present in both manifests with a different value.
+    pub changed_attributes: BTreeMap<String, AttributeChange>,
+}
+
+#[derive(Serialize)]
+pub struct AttributeChange {
+    pub old: String,
+    pub new: String,
+}
+
+/// The end of a log or crash dump file on the Quest.
+#[derive(Serialize)]
+pub struct LogFile {
+    pub path: String,
+    // The last bytes of the file, converted to UTF-8 lossily.
+    pub contents: String,
+    // True if the start of the file was cut off to fit within the requested size.
+    pub truncated: bool,
+}
+
 #[derive(Serialize)]
 pub enum LogLevel {
     Error,
@@ -68,7 +230,10 @@ pub enum ImportResultType {
         // The mod ID that the file copy belonged to
         mod_id: String,
     },
-    ImportedSong,
+    ImportedSong {
+        // The number of songs imported, which is more than one if the ZIP was a song pack.
+        imported_count: usize,
+    },
     // A non-quest mod was detected (i.e. `.DLL`) and so the import failed.
     NonQuestModDetected,
 }
@@ -82,8 +247,44 @@ pub struct ModModel {
     pub game_version: Option<String>,
     pub description: Option<String>,
     pub is_enabled: bool,
+    // True if the mod has been disabled, which moves its files aside so it can be enabled again without reinstalling.
+    pub is_disabled: bool,
     // True if the mod is core or if it is a required dependency of another core mod (potentially indirectly.)
     pub is_core: bool,
+    // The space used by the mod on disk in bytes, including both the extracted mod and its installed files.
+    pub disk_size: u64,
+}
+
+/// An installed mod that prevented a mod from being upgraded, as it depends on a range of versions
+/// that excludes the new version.
+#[derive(Serialize)]
+pub struct DependencyConflictInfo {
+    /// The ID of the mod that could not be upgraded.
+    pub mod_id: String,
+    /// The version of the mod that was rejected.
+    pub rejected_version: semver::Version,
+    /// The ID of the installed mod that depends on the mod being upgraded.
+    /// Uninstalling this mod would allow the upgrade to proceed.
+    pub dependent_id: String,
+    pub dependent_version: semver::Version,
+    /// The range of versions of the mod that the dependent mod requires.
+    pub version_range: semver::VersionReq,
+}
+
+impl DependencyConflictInfo {
+    /// Lists the conflicts within an [UpgradeConflict](mod_man::UpgradeConflict) error.
+    pub fn from_upgrade_conflict(err: &mod_man::UpgradeConflict) -> Vec<Self> {
+        err.conflicts
+            .iter()
+            .map(|conflict| Self {
+                mod_id: err.mod_id.clone(),
+                rejected_version: err.new_version.clone(),
+                dependent_id: conflict.dependent_id.clone(),
+                dependent_version: conflict.dependent_version.clone(),
+                version_range: conflict.version_range.clone(),
+            })
+            .collect()
+    }
 }
 
 impl From<&mod_man::Mod> for ModModel {
@@ -95,13 +296,16 @@ impl From<&mod_man::Mod> for ModModel {
             game_version: value.manifest().package_version.clone(),
             description: value.manifest().description.clone(),
             is_enabled: value.installed(),
+            is_disabled: value.disabled(),
             is_core: value.is_core(),
+            disk_size: value.disk_size(),
         }
     }
 }
 
 #[derive(Serialize)]
 #[serde(tag = "type")]
+#[allow(clippy::large_enum_variant)] // Only one response is sent per request, so the size doesn't matter.
 pub enum Response {
     ModStatus {
         // None if Beat Saber is not installed.
@@ -115,6 +319,19 @@ pub enum Response {
         core_mods: Option<CoreModsInfo>,
 
         modloader_install_status: InstallStatus,
+
+        // The free space on each volume used while patching or downgrading, including the volumes containing
+        // the APK, the temporary files, `ModData` and the OBB directory.
+        // Empty if the free space could not be determined.
+        free_space: Vec<VolumeSpace>,
+
+        // True if the app is patched with a modloader other than Scotland2, such as QuestLoader.
+        // The app must be uninstalled and vanilla Beat Saber reinstalled before it can be modded or downgraded.
+        needs_loader_conversion: bool,
+
+        // If Beat Saber is not installed but OBB files from a previous install remain, the total size of these files in bytes.
+        // These can be removed with a `DeleteOrphanedObbs` request.
+        orphaned_obb_size: Option<u64>,
     },
     Mods {
         installed_mods: Vec<ModModel>,
@@ -126,9 +343,56 @@ pub enum Response {
         // containing a list of the errors generated.
         failures: Option<String>,
     },
+    ModsRepaired {
+        // The new state of the installed mods after the repair
+        installed_mods: Vec<ModModel>,
+        // The IDs of the mods that had missing files restored.
+        repaired: Vec<String>,
+        // The IDs of mods that were partially installed but could not be repaired, and the reason why.
+        unrepairable: HashMap<String, String>,
+    },
+    VersionModDirs {
+        // The QMOD directories for each game version that has been modded, sorted by game version.
+        dirs: Vec<VersionModsDir>,
+    },
+    DeletedOtherVersionMods {
+        // The game versions whose QMODs were deleted.
+        deleted_versions: Vec<String>,
+        // The total size of the deleted QMODs, in bytes.
+        freed_bytes: u64,
+    },
+    UninstallImpact {
+        id: String,
+        // The IDs of the mods that uninstalling the mod would uninstall, starting with the mod itself.
+        // Empty if the mod does not exist or is not installed.
+        uninstalled_ids: Vec<String>,
+    },
+    ModFiles {
+        id: String,
+        // The absolute paths that the mod installs files to, whether or not these files currently exist.
+        paths: Vec<String>,
+    },
+    ModConfig {
+        id: String,
+        // The config of the mod, or None if it has no config file so uses its default settings.
+        config: Option<serde_json::Value>,
+    },
+    ModPolicy {
+        // The policy now in use, or None if any mod may be installed.
+        policy: Option<mod_man::ModPolicy>,
+    },
     Patched {
         installed_mods: Vec<ModModel>,
         did_remove_dlc: bool,
+        // The changes made to the manifest while patching.
+        manifest_diff: ManifestDiff,
+    },
+    ImportedDirectory {
+        installed_mods: Vec<ModModel>,
+        // The file name of each QMOD that was imported, mapped to the ID of the imported mod.
+        imported: BTreeMap<String, String>,
+        // The file name of each QMOD that could not be imported, mapped to the reason why.
+        failures: BTreeMap<String, String>,
     },
     ImportResult {
         result: ImportResultType, // The result of importing the file.
@@ -140,11 +404,72 @@ pub enum Response {
         message: String,
         level: LogLevel,
     },
+    // Sent to update the progress of the current stage of a request, e.g. a download.
+    // Like `LogMsg`, this will NOT be the final message sent.
+    Progress {
+        // Human readable name of the current stage, e.g. "Downloading" or "Patching APK"
+        stage: String,
+        // Between 0 and 1, if the fraction of the stage that has been completed is known.
+        fraction: Option<f32>,
+        // If the stage involves processing a number of bytes, the number processed thus far.
+        bytes_done: Option<u64>,
+        // The total number of bytes to process, if known.
+        bytes_total: Option<u64>,
+        // If the stage processes several items, e.g. downloading several mods, the ID of the item currently being processed.
+        // In this case, `bytes_done` and `bytes_total` are for the current item, while `fraction` is for the whole stage.
+        item: Option<String>,
+    },
+    AgentVersion {
+        // The version of the agent executable.
+        version: String,
+        // See [PROTOCOL_VERSION](crate::models::PROTOCOL_VERSION)
+        protocol_version: u32,
+    },
+    // Sent if the agent has all the privileges that it needs.
+    PrivilegesChecked,
+    InstallVerified {
+        // The checks made, in the order they were made.
+        // If Beat Saber is not installed, only the check that it is installed is made.
+        checks: Vec<InstallCheck>,
+    },
+    // Sent instead of any other response if the request failed.
+    Error {
+        code: ErrorCode,
+        // The full error, including its causes.
+        message: String,
+        // If code is DependencyConflict, the installed mods that prevented the upgrade. Otherwise empty.
+        dependency_conflicts: Vec<DependencyConflictInfo>,
+    },
+    DeletedOrphanedObbs {
+        // The total size of the deleted OBB files, in bytes.
+        freed_bytes: u64,
+    },
     FixedPlayerData {
         // True if a PlayerData.dat existed to fix, false if the request did nothing.
         existed: bool,
     },
+    CurrentManifest {
+        manifest_xml: String,
+    },
     DowngradedManifest {
         manifest_xml: String,
+        // True if a diff exists to downgrade the installed version of the app to the version of the manifest.
+        downgrade_available: bool,
+    },
+    CoreModsForVersion {
+        version: String,
+        // False if the core mod index has no entry for the version, in which case `core_mods` is empty.
+        version_present: bool,
+        core_mods: Vec<CoreMod>,
+    },
+    Logs {
+        // The most recent mod log, if any mod logs exist.
+        mod_log: Option<LogFile>,
+        // The most recent crash dump, if any exist and are readable.
+        crash_dump: Option<LogFile>,
+    },
+    Diagnostics {
+        // The diagnostics bundle, as a base64 encoded ZIP file.
+        zip_base64: String,
     },
 }
@@ -3,148 +3,383 @@ use std::{
     io::{BufReader, BufWriter, Cursor, Read, Write},
     path::{Path, PathBuf},
     process::Command,
+    time::Duration,
 };
 
 use crate::{
-    axml::{self, AxmlWriter},
+    axml::{
+        self,
+        arsc::{ResConfig, ResValue, ResourceTable},
+        AttributeValue, AxmlReader, AxmlWriter, ResourceIds,
+    },
     data_fix::fix_colour_schemes,
     downloads,
-    models::response::{AppInfo, InstallStatus, ModLoader},
-    paths, ModTag, APK_ID,
+    manifest::{self, ManifestOptions},
+    models::response::{
+        AppInfo, ErrorCode, InstallStatus, ManifestDiff, ModLoader, SignatureScheme, VolumeSpace,
+    },
+    paths, APK_ID,
 };
 use anyhow::{anyhow, Context, Result};
-use log::{info, warn};
+use log::{debug, info, warn};
 use mbf_res_man::{
+    apk_check::{self, ModTag, MOD_TAG_PATH},
     external_res,
-    models::{Diff, VersionDiffs},
+    models::{get_obb_id, Diff, VersionDiffs},
     res_cache::ResCache,
 };
-use mbf_zip::{signing, FileCompression, ZipFile, ZIP_CRC};
+use mbf_zip::{signing, FileCompression, ReadOnlyZipFile, ZipFile, ZIP_CRC};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 const DEBUG_CERT_PEM: &[u8] = include_bytes!("debug_cert.pem");
 const LIB_MAIN: &[u8] = include_bytes!("../libs/libmain.so");
 const MODLOADER: &[u8] = include_bytes!("../libs/libsl2.so");
 
 const MODLOADER_NAME: &str = "libsl2.so";
-const MOD_TAG_PATH: &str = "modded.json";
+const RESOURCE_TABLE_PATH: &str = "resources.arsc";
+// The maximum number of references followed when resolving a resource to a value.
+const MAX_REFERENCE_DEPTH: u32 = 8;
 
 const LIB_MAIN_PATH: &str = "lib/arm64-v8a/libmain.so";
 const LIB_UNITY_PATH: &str = "lib/arm64-v8a/libunity.so";
+// Libraries added to the APK by modloaders, which are removed before adding the current libmain.so so that
+// no libraries from a previous patch remain.
+const MODLOADER_LIB_NAMES: &[&str] = &["libmain.so", "libmodloader.so"];
+
+// Generous estimate of the size of an unstripped libunity.so, used when checking there is enough free space to patch.
+const LIBUNITY_SIZE_ESTIMATE: u64 = 200 * 1024 * 1024;
+
+// Name of the file within the temporary directory that the progress of patching is saved to.
+const PATCH_STATE_NAME: &str = "patch_state.json";
 
 // Aligment to use for ZIP entries with the STORE compression method, in bytes.
 // 4 is the standard value.
 const STORE_ALIGNMENT: u16 = 4;
 
+// Number of times to attempt a `pm` command that fails for a reason that may be transient.
+const PM_ATTEMPTS: u32 = 4;
+// Time to wait before retrying a failed `pm` command, which doubles after each attempt.
+const PM_RETRY_DELAY: Duration = Duration::from_secs(2);
+// Parts of `pm` failure messages given for failures that may succeed if retried, e.g. as the package manager
+// is still recovering after the app was killed.
+const TRANSIENT_PM_FAILURES: &[&str] = &[
+    "Package manager has died",
+    "DeadObjectException",
+    "Can't find service: package",
+    "Device or resource busy",
+    "INSTALL_FAILED_INSUFFICIENT_STORAGE",
+];
+
 // Mods the currently installed version of the given app and reinstalls it, without doing any downgrading.
 // If `manifest_only` is true, patching will only overwrite the manifest and will not add a modloader.
+// If `skip_player_data` is true, the player data is not backed up, so will be lost when the app is reinstalled.
+// Returns the changes made to the manifest.
+#[allow(clippy::too_many_arguments)]
 pub fn mod_current_apk(
     temp_path: &Path,
     app_info: &AppInfo,
     manifest_mod: String,
+    manifest_options: &ManifestOptions,
     manifest_only: bool,
     vr_splash_path: Option<&str>,
+    skip_player_data: bool,
     res_cache: &ResCache,
-) -> Result<()> {
+) -> Result<ManifestDiff> {
+    // Space for the temporary copy of the APK, the APK once installed, and the OBB backups.
+    let mut required_space =
+        app_info.apk_size * 2 + get_dir_size(paths::OBB_DIR).context("Getting OBB size")?;
+    if !manifest_only {
+        required_space += LIBUNITY_SIZE_ESTIMATE;
+    }
+    check_free_space(temp_path, required_space)?;
+
     let libunity_path = if manifest_only {
         None
     } else {
-        info!("Downloading unstripped libunity.so (this could take a minute)");
-        save_libunity(res_cache, temp_path, &app_info.version).context("Preparing libunity.so")?
+        crate::time_stage("Downloading libunity.so", || {
+            save_libunity(res_cache, &app_info.version)
+        })
+        .context("Preparing libunity.so")?
     };
 
     kill_app().context("Killing Beat Saber")?;
 
     info!("Copying APK to temporary location");
     let temp_apk_path = temp_path.join("mbf-tmp.apk");
-    std::fs::copy(&app_info.path, &temp_apk_path).context("Copying APK to temp")?;
+    crate::time_stage("Copying APK", || {
+        std::fs::copy(&app_info.path, &temp_apk_path)
+    })
+    .context("Copying APK to temp")?;
 
     info!("Saving OBB files");
     let obb_backup = temp_path.join("obbs");
     std::fs::create_dir_all(&obb_backup)?;
-    let obb_backups =
-        save_obbs(Path::new(paths::OBB_DIR), &obb_backup).context("Saving OBB files")?;
-
+    PatchState::BackingUpObbs {
+        backup_dir: obb_backup.clone(),
+    }
+    .save(temp_path)?;
+    let obb_backups = crate::time_stage("Backing up OBBs", || {
+        save_obbs(Path::new(paths::OBB_DIR), &obb_backup)
+    })
+    .context("Saving OBB files")?;
+
+    let install = InstallState {
+        temp_apk_path,
+        obb_backups,
+        obbs_are_original: true,
+        has_player_data: false,
+    };
     patch_and_reinstall(
+        temp_path,
         libunity_path,
-        &temp_apk_path,
-        obb_backups,
+        install,
         manifest_mod,
+        manifest_options,
         manifest_only,
         vr_splash_path,
+        skip_player_data,
     )
-    .context("Patching and reinstalling APK")?;
-    Ok(())
+    .context("Patching and reinstalling APK")
 }
 
 // Downgrades the APK/OBB files for the given app using the diffs provided, then reinstalls the app.
-// Returns true if any DLC were found while modding the APK, false otherwise.
+// If `skip_player_data` is true, the player data is not backed up, so will be lost when the app is reinstalled.
+// Returns true if any DLC were found while modding the APK, false otherwise, and the changes made to the manifest.
+#[allow(clippy::too_many_arguments)]
 pub fn downgrade_and_mod_apk(
     temp_path: &Path,
     app_info: &AppInfo,
     diffs: VersionDiffs,
     manifest_mod: String,
+    manifest_options: &ManifestOptions,
     vr_splash_path: Option<&str>,
+    skip_player_data: bool,
     res_cache: &ResCache,
-) -> Result<bool> {
+) -> Result<(bool, ManifestDiff)> {
+    // The diffs are generated from vanilla APKs, so applying them to a modded APK could only fail after a lengthy download.
+    if app_info.loader_installed.is_some() {
+        return Err(anyhow!(
+            "Cannot downgrade a modded APK. Uninstall Beat Saber and reinstall the latest version from the store first"
+        ));
+    }
+
+    // Space for the downgraded APK (before and after installing), the downgraded OBBs and libunity.so
+    // The diffs themselves are typically small compared to these so are not included.
+    let required_space = diffs.apk_diff.output_size as u64 * 2
+        + diffs
+            .obb_diffs
+            .iter()
+            .map(|diff| diff.output_size as u64)
+            .sum::<u64>()
+        + LIBUNITY_SIZE_ESTIMATE;
+    check_free_space(temp_path, required_space)?;
+
     // Download libunity.so *for the downgraded version*
-    info!("Downloading unstripped libunity.so (this could take a minute)");
-    let libunity_path =
-        save_libunity(res_cache, temp_path, &diffs.to_version).context("Saving libunity.so")?;
+    let libunity_path = crate::time_stage("Downloading libunity.so", || {
+        save_libunity(res_cache, &diffs.to_version)
+    })
+    .context("Saving libunity.so")?;
 
     // Download the diff files
     let diffs_path = temp_path.join("diffs");
     std::fs::create_dir_all(&diffs_path).context("Creating diffs directory")?;
     info!("Downloading diffs needed to downgrade Beat Saber (this could take a LONG time, make a cup of tea)");
-    download_diffs(&diffs_path, &diffs).context("Downloading diffs")?;
+    crate::time_stage("Downloading diffs", || download_diffs(&diffs_path, &diffs))
+        .context("Downloading diffs")?;
 
     kill_app().context("Killing Beat Saber")?;
 
+    // Progress through applying the diffs is reported as the fraction of the diffs applied so far.
+    let diff_count = (diffs.obb_diffs.len() + 1) as f32;
+
     // Copy the APK to temp, downgrading it in the process.
     info!("Downgrading APK");
+    crate::report_progress("Downgrading", Some(0.0));
     let temp_apk_path = temp_path.join("mbf-downgraded.apk");
-    apply_diff(
-        Path::new(&app_info.path),
-        &temp_apk_path,
-        &diffs.apk_diff,
-        &diffs_path,
-    )
+    crate::time_stage("Downgrading APK", || {
+        apply_diff(
+            Path::new(&app_info.path),
+            &temp_apk_path,
+            &diffs.apk_diff,
+            &diffs_path,
+        )
+    })
     .context("Applying diff to APK")?;
 
     // Downgrade the obb files, copying them to a temporary directory in the process.
     let obb_backup_dir = temp_path.join("obbs");
     std::fs::create_dir_all(&obb_backup_dir).context("Creating OBB backup directory")?;
     let mut obb_backup_paths = Vec::new();
-    for obb_diff in &diffs.obb_diffs {
-        let obb_path = Path::new(paths::OBB_DIR).join(&obb_diff.file_name);
-        if !obb_path.exists() {
-            return Err(anyhow!(
+    let mut obb_paths = list_obbs(Path::new(paths::OBB_DIR)).context("Listing OBB files")?;
+    for (idx, obb_diff) in diffs.obb_diffs.iter().enumerate() {
+        crate::report_progress("Downgrading", Some((idx + 1) as f32 / diff_count));
+        let obb_path = take_obb_for_diff(&mut obb_paths, obb_diff).ok_or_else(|| {
+            anyhow!(
                 "Obb file {} did not exist, is the Beat Saber installation corrupt",
                 obb_diff.file_name
-            ));
-        }
+            )
+        })?;
 
         let obb_backup_path = obb_backup_dir.join(&obb_diff.output_file_name);
 
         info!("Downgrading obb {}", obb_diff.file_name);
-        apply_diff(&obb_path, &obb_backup_path, obb_diff, &diffs_path)
-            .context("Applying diff to OBB")?;
+        crate::time_stage("Downgrading OBB", || {
+            apply_diff(&obb_path, &obb_backup_path, obb_diff, &diffs_path)
+        })
+        .context("Applying diff to OBB")?;
         obb_backup_paths.push(obb_backup_path);
     }
+    for unused_obb in obb_paths {
+        warn!(
+            "No diff exists for {unused_obb:?}, so it will not be present in the downgraded game"
+        );
+    }
 
     // Beat Saber DLC asset files do not have the .obb suffix.
     // If there are any DLC, then these have been deleted by the patching process so we return true so that the user can later be informed of this.
     let contains_dlc = has_file_with_no_extension(paths::OBB_DIR).context("Checking for DLC")?;
 
-    patch_and_reinstall(
+    let install = InstallState {
+        temp_apk_path,
+        obb_backups: obb_backup_paths,
+        obbs_are_original: false,
+        has_player_data: false,
+    };
+    let manifest_diff = patch_and_reinstall(
+        temp_path,
         libunity_path,
-        &temp_apk_path,
-        obb_backup_paths,
+        install,
         manifest_mod,
+        manifest_options,
         false,
         vr_splash_path,
+        skip_player_data,
     )
     .context("Patching and reinstall APK")?;
-    Ok(contains_dlc)
+    Ok((contains_dlc, manifest_diff))
+}
+
+/// Gets the number of bytes available to MBF on the filesystem containing `path`.
+pub fn get_free_space(path: impl AsRef<Path>) -> Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_ref().as_os_str().as_bytes())
+        .context("Path contained a null byte")?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is a valid null-terminated string and `stats` is a valid statvfs struct to write to.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Getting filesystem stats");
+    }
+
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Gets the free space on each of the volumes containing the given paths, with paths on the same volume grouped together.
+/// Paths that don't exist yet are measured using their closest existing parent directory.
+pub fn get_free_space_by_volume(paths: &[&str]) -> Result<Vec<VolumeSpace>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut volumes: Vec<(u64, VolumeSpace)> = Vec::new();
+    for path in paths {
+        let existing_path = get_existing_ancestor(Path::new(path))?;
+        let device_id = std::fs::metadata(existing_path)
+            .with_context(|| format!("Getting volume of {path}"))?
+            .dev();
+        match volumes.iter_mut().find(|(id, _)| *id == device_id) {
+            Some((_, volume)) => volume.paths.push(path.to_string()),
+            None => volumes.push((
+                device_id,
+                VolumeSpace {
+                    paths: vec![path.to_string()],
+                    free_bytes: get_free_space(existing_path)
+                        .with_context(|| format!("Getting free space for {path}"))?,
+                },
+            )),
+        }
+    }
+
+    Ok(volumes.into_iter().map(|(_, volume)| volume).collect())
+}
+
+// Gets the closest ancestor of `path` that exists, which is `path` itself if it exists.
+fn get_existing_ancestor(path: &Path) -> Result<&Path> {
+    let mut existing_path = path;
+    while !existing_path.exists() {
+        existing_path = existing_path
+            .parent()
+            .ok_or_else(|| anyhow!("No parent of {path:?} exists"))?;
+    }
+
+    Ok(existing_path)
+}
+
+/// Chooses the directory to use for temporary files while patching.
+/// This is whichever of [paths::TEMP_DIRS] is writable and on the volume with the most free space, so that patching
+/// is less likely to run out of space when the volume of [paths::TEMP] is nearly full.
+/// The chosen directory is created if it does not already exist.
+pub fn choose_temp_dir() -> Result<PathBuf> {
+    let mut candidates = Vec::new();
+    for dir in paths::TEMP_DIRS {
+        match get_existing_ancestor(Path::new(dir)).and_then(get_free_space) {
+            Ok(free) => candidates.push((free, *dir)),
+            Err(err) => warn!("Failed to get free space for {dir}: {err:?}"),
+        }
+    }
+    // Stable, so directories with equal free space stay in order of preference.
+    candidates.sort_by_key(|(free, _)| std::cmp::Reverse(*free));
+
+    for (free, dir) in candidates {
+        match std::fs::create_dir_all(dir) {
+            Ok(_) => {
+                info!(
+                    "Using {dir} for temporary files ({} MiB free)",
+                    free / (1024 * 1024)
+                );
+                return Ok(PathBuf::from(dir));
+            }
+            Err(err) => warn!("Temporary directory {dir} is not writable: {err}"),
+        }
+    }
+
+    Err(anyhow!("None of the temporary directories were writable"))
+}
+
+// Gives an error if there are fewer than `required` bytes free on the filesystem containing `path`.
+fn check_free_space(path: &Path, required: u64) -> Result<()> {
+    let free = get_free_space(path).context("Checking free space")?;
+    if free < required {
+        return Err(anyhow::Error::new(ErrorCode::InsufficientSpace).context(format!(
+            "Not enough free space to patch: {} MiB free but {} MiB needed. Free up at least {} MiB and try again",
+            free / (1024 * 1024),
+            required / (1024 * 1024),
+            (required - free).div_ceil(1024 * 1024)
+        )));
+    }
+
+    info!(
+        "{} MiB free, {} MiB needed",
+        free / (1024 * 1024),
+        required / (1024 * 1024)
+    );
+    Ok(())
+}
+
+// Gets the total size of the files directly within the given directory. Returns 0 if the directory does not exist.
+fn get_dir_size(dir: impl AsRef<Path>) -> Result<u64> {
+    if !dir.as_ref().exists() {
+        return Ok(0);
+    }
+
+    let mut total = 0;
+    for stat in std::fs::read_dir(dir)? {
+        let metadata = stat?.metadata()?;
+        if metadata.is_file() {
+            total += metadata.len();
+        }
+    }
+
+    Ok(total)
 }
 
 // Returns true if the given folder contains any files with no file extension.
@@ -168,49 +403,393 @@ pub fn kill_app() -> Result<()> {
     Ok(())
 }
 
+// `install` gives the APK to patch and the OBBs to restore once it is installed. Whether there is player data to restore is
+// filled in by this function.
+#[allow(clippy::too_many_arguments)]
 fn patch_and_reinstall(
+    temp_path: &Path,
     libunity_path: Option<PathBuf>,
-    temp_apk_path: &Path,
-    obb_paths: Vec<PathBuf>,
+    mut install: InstallState,
     manifest_mod: String,
+    manifest_options: &ManifestOptions,
     manifest_only: bool,
     vr_splash_path: Option<&str>,
-) -> Result<()> {
+    skip_player_data: bool,
+) -> Result<ManifestDiff> {
     info!("Patching APK");
-    patch_apk_in_place(
-        &temp_apk_path,
+    crate::report_progress("Patching APK", None);
+    let manifest_diff = patch_apk_in_place(
+        &install.temp_apk_path,
         libunity_path,
         manifest_mod,
+        manifest_options,
         manifest_only,
         vr_splash_path,
     )
     .context("Patching APK")?;
 
-    if Path::new(paths::PLAYER_DATA).exists() {
-        info!("Backing up player data");
-        backup_player_data().context("Backing up player data")?;
+    // Check the APK before uninstalling the existing app, so the user isn't left without Beat Saber if it is broken.
+    info!("Verifying patched APK");
+    crate::report_progress("Verifying patched APK", None);
+    crate::time_stage("Verifying patched APK", || {
+        verify_patched_apk(&install.temp_apk_path)
+    })
+    .context("Verifying patched APK")?;
+
+    if skip_player_data {
+        info!("Skipping player data backup, so player data will be lost");
     } else {
-        info!("No player data to backup");
-    }
+        install.has_player_data = Path::new(paths::PLAYER_DATA).exists();
+        if install.has_player_data {
+            info!("Backing up player data");
+            crate::time_stage("Backing up player data", backup_player_data)
+                .context("Backing up player data")?;
+        } else {
+            info!("No player data to backup");
+        }
 
-    if Path::new(paths::DATAKEEPER_PLAYER_DATA).exists() {
-        info!("Fixing colour schemes in backed up PlayerData.dat");
-        match fix_colour_schemes(paths::DATAKEEPER_PLAYER_DATA) {
-            Ok(_) => {}
-            Err(err) => warn!("Failed to fix colour schemes: {err}"),
+        if Path::new(paths::DATAKEEPER_PLAYER_DATA).exists() {
+            info!("Fixing colour schemes in backed up PlayerData.dat");
+            match fix_colour_schemes(paths::DATAKEEPER_PLAYER_DATA) {
+                Ok(_) => {}
+                Err(err) => warn!("Failed to fix colour schemes: {err}"),
+            }
         }
     }
 
-    reinstall_modded_app(&temp_apk_path).context("Reinstalling modded APK")?;
-    std::fs::remove_file(temp_apk_path)?;
+    PatchState::ReadyToInstall(install.clone()).save(temp_path)?;
+    install_patched_apk(temp_path, install, false)?;
+    Ok(manifest_diff)
+}
+
+// Installs the patched APK, then restores the OBBs and player data.
+// If `already_installed` is true, the patched APK is not reinstalled, and only the OBBs and player data are restored.
+// Once complete, the saved patch state is removed since the patch can no longer be interrupted.
+fn install_patched_apk(
+    temp_path: &Path,
+    install: InstallState,
+    already_installed: bool,
+) -> Result<()> {
+    if !already_installed {
+        crate::report_progress("Reinstalling", None);
+        crate::time_stage("Reinstalling", || {
+            reinstall_modded_app(&install.temp_apk_path)
+        })
+        .context("Reinstalling modded APK")?;
+        PatchState::Installed(install.clone()).save(temp_path)?;
+        std::fs::remove_file(&install.temp_apk_path)?;
+    }
 
     info!("Restoring OBB files");
-    restore_obb_files(Path::new(paths::OBB_DIR), obb_paths).context("Restoring OBB files")?;
+    crate::time_stage("Restoring OBBs", || {
+        restore_obb_files(Path::new(paths::OBB_DIR), install.obb_backups)
+    })
+    .context("Restoring OBB files")?;
+
+    if install.has_player_data {
+        info!("Restoring player data");
+        // If the permissions of PlayerData.dat are wrong, BS can't access it, which causes a black screen
+        // that can only be fixed by deleting the file. In this case it is safer to leave the file out, as the backups will
+        // still be present for the user (or datakeeper) to restore.
+        if let Err(err) = restore_player_data() {
+            warn!("Could not restore player data, it will need to be restored from the backup: {err:#}");
+            if Path::new(paths::PLAYER_DATA).exists() {
+                std::fs::remove_file(paths::PLAYER_DATA)
+                    .context("Removing player data with incorrect permissions")?;
+            }
+        }
+    }
 
-    // Player data is not restored back to the `files` directory as we cannot correctly set its permissions so that BS can access it.
-    // (which causes a black screen that can only be fixed by manually deleting the file)
+    PatchState::clear(temp_path)
+}
 
-    Ok(())
+/// Progress through patching, saved at the points where interrupting patching (e.g. by unplugging the Quest)
+/// would leave the game in a broken state.
+/// This allows the next patch to resume or roll back the interrupted one rather than starting again on top of it.
+#[derive(Serialize, Deserialize)]
+enum PatchState {
+    // The OBBs of the installed app are being moved to `backup_dir`.
+    BackingUpObbs { backup_dir: PathBuf },
+    // The APK has been patched and is about to replace the installed app.
+    ReadyToInstall(InstallState),
+    // The patched APK has been installed, but the OBBs and player data may not have been restored.
+    Installed(InstallState),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct InstallState {
+    temp_apk_path: PathBuf,
+    obb_backups: Vec<PathBuf>,
+    // True if `obb_backups` are the OBBs of the installed app, false if they are downgraded OBBs.
+    obbs_are_original: bool,
+    // True if `PlayerData.dat` was backed up and needs restoring.
+    has_player_data: bool,
+}
+
+impl PatchState {
+    fn load(temp_path: &Path) -> Result<Option<Self>> {
+        let state_path = temp_path.join(PATCH_STATE_NAME);
+        if !state_path.exists() {
+            return Ok(None);
+        }
+
+        let state_json = std::fs::read(state_path).context("Reading patch state")?;
+        Ok(Some(
+            serde_json::from_slice(&state_json).context("Parsing patch state")?,
+        ))
+    }
+
+    fn save(&self, temp_path: &Path) -> Result<()> {
+        std::fs::write(temp_path.join(PATCH_STATE_NAME), serde_json::to_vec(self)?)
+            .context("Saving patch state")
+    }
+
+    fn clear(temp_path: &Path) -> Result<()> {
+        let state_path = temp_path.join(PATCH_STATE_NAME);
+        if state_path.exists() {
+            std::fs::remove_file(state_path).context("Removing patch state")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns true if a patch using `temp_path` as its temporary directory was interrupted (or failed)
+/// at a point where the files in `temp_path` are needed to recover from it.
+pub fn is_patch_interrupted(temp_path: &Path) -> bool {
+    temp_path.join(PATCH_STATE_NAME).exists()
+}
+
+/// Recovers from a patch that was interrupted before it completed, e.g. because the Quest was disconnected.
+/// If the original app is still installed, the interrupted patch is rolled back by restoring its OBBs.
+/// If the original app has been uninstalled, the interrupted patch is completed instead, as this is the only way to restore the game.
+/// Any other files left in `temp_path` by an interrupted patch are removed.
+///
+/// Returns true if an interrupted patch was completed, in which case the installed app is now modded.
+pub fn recover_interrupted_patch(temp_path: &Path) -> Result<bool> {
+    let state = match PatchState::load(temp_path) {
+        Ok(Some(state)) => state,
+        Ok(None) => {
+            // Nothing irreversible happened before the state was first saved, so the files can be safely removed.
+            if temp_path.exists() {
+                warn!("Removing temporary files left over from an interrupted patch");
+                std::fs::remove_dir_all(temp_path).context("Removing leftover temporary files")?;
+            }
+            return Ok(false);
+        }
+        Err(err) => {
+            return Err(err).context(
+                "A previous patch was interrupted, but its progress could not be read. Reinstall Beat Saber to fix this",
+            )
+        }
+    };
+
+    let completed = match state {
+        PatchState::BackingUpObbs { backup_dir } => {
+            warn!("Previous patch was interrupted while backing up OBBs, restoring them");
+            restore_interrupted_obb_backup(&backup_dir).context("Restoring OBB backups")?;
+            false
+        }
+        PatchState::ReadyToInstall(install) => {
+            if is_apk_installed(&install.temp_apk_path)? {
+                warn!(
+                    "Previous patch was interrupted after installing the patched APK, finishing it"
+                );
+                install_patched_apk(temp_path, install, true)?;
+                true
+            } else if crate::get_apk_path()?.is_some() {
+                warn!("Previous patch was interrupted before replacing the installed app, rolling it back");
+                if install.obbs_are_original {
+                    restore_obb_files(Path::new(paths::OBB_DIR), install.obb_backups)
+                        .context("Restoring OBB files")?;
+                }
+                false
+            } else {
+                warn!("Previous patch was interrupted while reinstalling the app, finishing it");
+                install_patched_apk(temp_path, install, false)?;
+                true
+            }
+        }
+        PatchState::Installed(install) => {
+            warn!("Previous patch was interrupted after installing the patched APK, finishing it");
+            install_patched_apk(temp_path, install, true)?;
+            true
+        }
+    };
+
+    std::fs::remove_dir_all(temp_path).context("Removing leftover temporary files")?;
+    Ok(completed)
+}
+
+// Moves the OBBs in `backup_dir` that are no longer in the OBB directory back to it.
+// OBBs are only deleted once completely copied by [save_obbs], so these backups are complete.
+fn restore_interrupted_obb_backup(backup_dir: &Path) -> Result<()> {
+    if !backup_dir.exists() {
+        return Ok(());
+    }
+
+    let mut to_restore = Vec::new();
+    for entry in std::fs::read_dir(backup_dir)? {
+        let backup_path = entry?.path();
+        let obb_path = Path::new(paths::OBB_DIR).join(backup_path.file_name().unwrap());
+        if !obb_path.exists() {
+            to_restore.push(backup_path);
+        }
+    }
+
+    restore_obb_files(Path::new(paths::OBB_DIR), to_restore)
+}
+
+// Returns true if the currently installed APK is identical to the APK at `apk_path`.
+fn is_apk_installed(apk_path: &Path) -> Result<bool> {
+    let installed_path = match crate::get_apk_path()? {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    if !apk_path.exists()
+        || std::fs::metadata(apk_path)?.len() != std::fs::metadata(&installed_path)?.len()
+    {
+        return Ok(false);
+    }
+
+    let crc_of_file =
+        |path: &Path| -> Result<u32> { mbf_zip::crc_of_stream(BufReader::new(File::open(path)?)) };
+    Ok(crc_of_file(apk_path)? == crc_of_file(Path::new(&installed_path))?)
+}
+
+/// Gets the signature schemes that the given APK is signed with.
+/// The signatures are not verified, only detected.
+pub fn get_signature_schemes(apk: &mut ReadOnlyZipFile) -> Result<Vec<SignatureScheme>> {
+    let mut schemes = Vec::new();
+    // A V1 signature is a signature file alongside a manifest of the digests of each entry.
+    if apk.contains_file("META-INF/MANIFEST.MF")
+        && apk
+            .iter_entry_names()
+            .any(|name| name.starts_with("META-INF/") && name.ends_with(".SF"))
+    {
+        schemes.push(SignatureScheme::V1);
+    }
+
+    if let Some(signing_block) = apk
+        .read_signing_block()
+        .context("Reading APK Signing Block")?
+    {
+        if signing_block.get(signing::V2_SIGNATURE_ID).is_some() {
+            schemes.push(SignatureScheme::V2);
+        }
+        if signing_block.get(signing::V3_SIGNATURE_ID).is_some() {
+            schemes.push(SignatureScheme::V3);
+        }
+    }
+
+    Ok(schemes)
+}
+
+/// Reads the resource table (`resources.arsc`) of the given APK, or gives `None` if the APK has no resource table.
+pub fn read_resource_table(apk: &mut ReadOnlyZipFile) -> Result<Option<ResourceTable>> {
+    if !apk.contains_file(RESOURCE_TABLE_PATH) {
+        return Ok(None);
+    }
+
+    let table_data = apk
+        .read_file(RESOURCE_TABLE_PATH)
+        .context("Reading resource table from APK")?;
+    Ok(Some(
+        ResourceTable::read(&mut Cursor::new(table_data)).context("Parsing resource table")?,
+    ))
+}
+
+/// Gets the label of the app from the `label` attribute in its manifest, looking up the label in `resources`
+/// if the attribute refers to a string resource. The label for the default language is preferred.
+pub fn resolve_app_label(
+    label: &AttributeValue,
+    resources: Option<&ResourceTable>,
+) -> Option<String> {
+    match (label, resources) {
+        (AttributeValue::String(label), _) => Some(label.clone()),
+        (AttributeValue::Reference(id), Some(resources)) => {
+            let mut labels = resolve_resource_strings(resources, *id);
+            let default_index = labels
+                .iter()
+                .position(|(config, _)| config.language == [0, 0])
+                .unwrap_or(0);
+            (default_index < labels.len()).then(|| labels.swap_remove(default_index).1)
+        }
+        _ => None,
+    }
+}
+
+/// Reads the PNG icon of the app, given the resource ID of the icon in its manifest.
+/// If there is a version of the icon for several densities, the highest density is chosen.
+/// Gives `None` if the icon has no PNG versions, e.g. if it is an adaptive icon.
+pub fn read_app_icon(
+    apk: &mut ReadOnlyZipFile,
+    icon_id: u32,
+    resources: &ResourceTable,
+) -> Result<Option<Vec<u8>>> {
+    let icon_path = resolve_resource_strings(resources, icon_id)
+        .into_iter()
+        .filter(|(_, path)| path.ends_with(".png") && apk.contains_file(path))
+        .max_by_key(|(config, _)| match config.density {
+            ResConfig::DENSITY_ANY | ResConfig::DENSITY_NONE => 0,
+            density => density,
+        })
+        .map(|(_, path)| path);
+
+    match icon_path {
+        Some(path) => Ok(Some(
+            apk.read_file(&path)
+                .with_context(|| format!("Reading icon {path}"))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+// Gets the string values of the resource with the given ID for each configuration, following any references to other resources.
+fn resolve_resource_strings(resources: &ResourceTable, id: u32) -> Vec<(ResConfig, String)> {
+    let mut strings = Vec::new();
+    let mut to_resolve = vec![(id, 0)];
+    while let Some((id, depth)) = to_resolve.pop() {
+        for (config, value) in resources.get_values(id) {
+            match value {
+                ResValue::String(string) => strings.push((*config, string.clone())),
+                // Prevents looping forever on resources that refer to each other.
+                ResValue::Reference(next_id) if depth < MAX_REFERENCE_DEPTH => {
+                    to_resolve.push((*next_id, depth + 1))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    strings
+}
+
+// Checks that the APK at the given path has a V2 signature and a valid manifest.
+fn verify_patched_apk(apk_path: &Path) -> Result<()> {
+    let mut zip = ZipFile::open(File::open(apk_path).context("Opening patched APK")?)
+        .context("Patched APK was not a valid ZIP")?;
+
+    let signing_block = zip
+        .read_signing_block()
+        .context("Reading APK Signing Block")?
+        .ok_or(ErrorCode::SignatureFailure)
+        .context("Patched APK had no APK Signing Block")?;
+    if signing_block.get(signing::V2_SIGNATURE_ID).is_none() {
+        return Err(anyhow::Error::new(ErrorCode::SignatureFailure)
+            .context("Patched APK had no V2 signature"));
+    }
+
+    let manifest = zip
+        .read_file("AndroidManifest.xml")
+        .context("Reading patched manifest")?;
+    let mut cursor = Cursor::new(manifest);
+    let mut reader = AxmlReader::new(&mut cursor).context("Patched manifest was not valid AXML")?;
+    let events = reader
+        .events()
+        .collect::<Result<Vec<_>>>()
+        .context("Patched manifest was not valid AXML")?;
+    manifest::validate_manifest(&events)
 }
 
 pub fn backup_player_data() -> Result<()> {
@@ -231,26 +810,194 @@ pub fn backup_player_data() -> Result<()> {
     Ok(())
 }
 
+// Copies the player data backed up in [paths::AUX_DATA_BACKUP] back to the `files` directory of the app,
+// making it owned by the app's user with mode 0600 so that BS can read it.
+fn restore_player_data() -> Result<()> {
+    use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+
+    let uid = get_app_uid().context("Finding UID of reinstalled app")?;
+
+    let files_dir = Path::new(paths::ANDROID_APP_FILES);
+    if !files_dir.exists() {
+        std::fs::create_dir_all(files_dir).context("Creating app files directory")?;
+        chown(files_dir, Some(uid), Some(uid)).context("Setting owner of files directory")?;
+    }
+
+    std::fs::copy(paths::AUX_DATA_BACKUP, paths::PLAYER_DATA).context("Copying player data")?;
+    chown(paths::PLAYER_DATA, Some(uid), Some(uid)).context("Setting owner of player data")?;
+    std::fs::set_permissions(paths::PLAYER_DATA, std::fs::Permissions::from_mode(0o600))
+        .context("Setting mode of player data")?;
+
+    // Some filesystems silently ignore `chown`, so check that the owner actually changed.
+    let owner = std::fs::metadata(paths::PLAYER_DATA)?.uid();
+    if owner != uid {
+        return Err(anyhow!(
+            "Player data was owned by {owner} rather than the app's UID {uid}"
+        ));
+    }
+
+    Ok(())
+}
+
+// Gets the Linux user ID assigned to the installed app.
+// This is found from `dumpsys` if possible, otherwise from the owner of the app's private data directory.
+fn get_app_uid() -> Result<u32> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dumpsys_output = Command::new("dumpsys")
+        .args(["package", APK_ID])
+        .output()
+        .context("Invoking dumpsys")?;
+    if let Some(uid) = parse_app_uid(&String::from_utf8_lossy(&dumpsys_output.stdout)) {
+        return Ok(uid);
+    }
+
+    let data_dir_stat = std::fs::metadata(format!("/data/data/{APK_ID}"))
+        .context("No UID in dumpsys output and could not stat app data directory")?;
+    Ok(data_dir_stat.uid())
+}
+
+// Parses the app's UID from the output of `dumpsys package <APK_ID>`, e.g. `userId=10123`
+fn parse_app_uid(dumpsys_output: &str) -> Option<u32> {
+    dumpsys_output.lines().map(str::trim).find_map(|line| {
+        let uid = line
+            .strip_prefix("userId=")
+            .or_else(|| line.strip_prefix("appId="))?;
+        uid.split_whitespace().next()?.parse().ok()
+    })
+}
+
 fn reinstall_modded_app(temp_apk_path: &Path) -> Result<()> {
     info!("Reinstalling modded app");
-    Command::new("pm")
-        .args(["uninstall", APK_ID])
-        .output()
-        .context("Uninstalling vanilla APK")?;
+    // The app may already be uninstalled if resuming an interrupted patch.
+    if crate::get_apk_path()?.is_some() {
+        run_pm_with_retry(&["uninstall", APK_ID]).context("Uninstalling vanilla APK")?;
+    }
 
-    Command::new("pm")
-        .args(["install", &temp_apk_path.to_string_lossy()])
-        .output()
-        .context("Installing modded APK")?;
+    // A lossily converted path would refer to a different file, so non-UTF-8 paths are rejected.
+    let temp_apk_path = temp_apk_path.to_str().ok_or(anyhow!(
+        "Modded APK path {temp_apk_path:?} was not valid UTF-8"
+    ))?;
+    run_pm_with_retry(&["install", temp_apk_path]).context("Installing modded APK")?;
 
     info!("Granting external storage permission");
-    Command::new("appops")
-        .args(["set", "--uid", APK_ID, "MANAGE_EXTERNAL_STORAGE", "allow"])
-        .output()?;
+    run_command_checked(
+        "appops",
+        &["set", "--uid", APK_ID, "MANAGE_EXTERNAL_STORAGE", "allow"],
+    )
+    .context("Granting external storage permission")?;
 
     Ok(())
 }
 
+// Runs the given command, returning its stdout if it was successful.
+// An `Err` is returned if the command exits with a non-zero status, or if it reports a failure in its output,
+// since `pm` does not reliably give a non-zero exit code on failure.
+pub fn run_command_checked(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Invoking {program}"))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = get_failure_reason(&stdout).or_else(|| get_failure_reason(&stderr));
+
+    match (output.status.success(), reason) {
+        (true, None) => Ok(stdout.into_owned()),
+        (_, Some(reason)) => {
+            let err = anyhow!("{program} failed: {reason}");
+            Err(match get_failure_code(&reason) {
+                Some(code) => err.context(code),
+                None => err,
+            })
+        }
+        (false, None) => Err(anyhow!(
+            "{program} exited with {}: {}",
+            output.status,
+            if stderr.trim().is_empty() {
+                stdout.trim()
+            } else {
+                stderr.trim()
+            }
+        )),
+    }
+}
+
+// Runs `pm` with the given arguments as in `run_command_checked`, retrying with an increasing delay if it fails
+// for one of the TRANSIENT_PM_FAILURES. Other failures, e.g. a signature mismatch, are returned immediately.
+fn run_pm_with_retry(args: &[&str]) -> Result<String> {
+    let mut delay = PM_RETRY_DELAY;
+    for attempt in 1.. {
+        match run_command_checked("pm", args) {
+            Err(err) if attempt < PM_ATTEMPTS && is_transient_pm_failure(&err) => {
+                warn!(
+                    "pm {} failed (attempt {attempt}/{PM_ATTEMPTS}), retrying in {}s: {err:#}",
+                    args[0],
+                    delay.as_secs()
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+
+    unreachable!("Final attempt always returns")
+}
+
+fn is_transient_pm_failure(err: &anyhow::Error) -> bool {
+    let message = format!("{err:#}");
+    TRANSIENT_PM_FAILURES
+        .iter()
+        .any(|failure| message.contains(failure))
+}
+
+// Finds the reason for failure in the output of a `pm`/`appops` command, if the output indicates a failure.
+// e.g. `Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE]` gives `INSTALL_FAILED_INSUFFICIENT_STORAGE`
+fn get_failure_reason(output: &str) -> Option<String> {
+    for line in output.lines().map(str::trim) {
+        if let Some(after_failure) = line.strip_prefix("Failure") {
+            let reason = after_failure
+                .trim()
+                .trim_start_matches('[')
+                .trim_end_matches(']');
+            return Some(if reason.is_empty() {
+                line.to_string()
+            } else {
+                reason.to_string()
+            });
+        }
+
+        if let Some(offset) = line
+            .find("INSTALL_FAILED_")
+            .or_else(|| line.find("DELETE_FAILED_"))
+        {
+            return Some(line[offset..].to_string());
+        }
+
+        if line.starts_with("Error") || line.starts_with("Security exception") {
+            return Some(line.to_string());
+        }
+    }
+
+    None
+}
+
+// Gets the code for a failure reason given by `pm`, if it is one that the frontend can help the user to fix.
+fn get_failure_code(reason: &str) -> Option<ErrorCode> {
+    if reason.contains("INSUFFICIENT_STORAGE") {
+        Some(ErrorCode::InsufficientSpace)
+    } else if reason.contains("UPDATE_INCOMPATIBLE")
+        || reason.contains("NO_CERTIFICATES")
+        || reason.contains("INCONSISTENT_CERTIFICATES")
+    {
+        Some(ErrorCode::SignatureFailure)
+    } else {
+        None
+    }
+}
+
 // Reads the content of the given file path as a Vec
 fn read_file_vec(path: impl AsRef<Path>) -> Result<Vec<u8>> {
     let handle = std::fs::File::open(path)?;
@@ -317,28 +1064,141 @@ fn download_diff_retry(diff: &Diff, to_dir: impl AsRef<Path>) -> Result<()> {
     let url = external_res::get_diff_url(diff);
     let output_path = to_dir.as_ref().join(&diff.diff_name);
 
-    downloads::download_file_with_attempts(&crate::get_dl_cfg(), &output_path, &url)
-        .context("Downloading diff file")?;
+    let expected_sha256 = diff
+        .diff_sha256
+        .as_deref()
+        .map(downloads::parse_sha256)
+        .transpose()
+        .context("Diff index contained an invalid hash")?;
+
+    downloads::download_file_with_attempts(
+        &crate::get_dl_cfg(),
+        &output_path,
+        &url,
+        expected_sha256,
+    )
+    .context("Downloading diff file")?;
     Ok(())
 }
 
-fn save_libunity(
-    res_cache: &ResCache,
-    temp_path: impl AsRef<Path>,
-    version: &str,
-) -> Result<Option<PathBuf>> {
+/// Details of a `libunity.so` saved in [paths::LIBUNITY_CACHE], used to check the cached copy can be reused.
+#[derive(Serialize, Deserialize)]
+struct CachedLibUnity {
+    // The URL that the libunity.so was downloaded from.
+    url: String,
+    // CRC32 of the downloaded libunity.so
+    crc: u32,
+}
+
+// Gets the path to the unstripped libunity.so for the given version, downloading it if
+// there is no valid copy in the cache already.
+// Returns None if there is no unstripped libunity available for this version.
+fn save_libunity(res_cache: &ResCache, version: &str) -> Result<Option<PathBuf>> {
     let url = match external_res::get_libunity_url(res_cache, APK_ID, version)? {
         Some(url) => url,
         None => return Ok(None), // No libunity for this version
     };
 
-    let libunity_path = temp_path.as_ref().join("libunity.so");
-    downloads::download_file_with_attempts(&crate::get_dl_cfg(), &libunity_path, &url)
+    let cache_dir = Path::new(paths::LIBUNITY_CACHE);
+    std::fs::create_dir_all(cache_dir).context("Creating libunity cache directory")?;
+    let libunity_path = cache_dir.join(format!("{APK_ID}-{version}.so"));
+    let info_path = cache_dir.join(format!("{APK_ID}-{version}.json"));
+
+    match check_cached_libunity(&libunity_path, &info_path, &url) {
+        Ok(true) => {
+            info!("Using cached libunity.so for {version}");
+            return Ok(Some(libunity_path));
+        }
+        Ok(false) => {}
+        Err(err) => warn!("Failed to check cached libunity.so, downloading again: {err}"),
+    }
+
+    info!("Downloading unstripped libunity.so (this could take a minute)");
+    // Remove the info first so that an interrupted download is never mistaken for a valid cached copy.
+    if info_path.exists() {
+        std::fs::remove_file(&info_path).context("Removing outdated libunity cache info")?;
+    }
+    downloads::download_file_with_attempts(&crate::get_dl_cfg(), &libunity_path, &url, None)
         .context("Downloading unstripped libunity.so")?;
 
+    let crc = mbf_zip::crc_of_stream(BufReader::new(
+        File::open(&libunity_path).context("Opening downloaded libunity.so")?,
+    ))
+    .context("Calculating CRC of libunity.so")?;
+    std::fs::write(
+        &info_path,
+        serde_json::to_vec(&CachedLibUnity { url, crc })?,
+    )
+    .context("Saving libunity cache info")?;
+
     Ok(Some(libunity_path))
 }
 
+// Returns true if the cached libunity.so exists, was downloaded from `url` and still matches its recorded CRC.
+fn check_cached_libunity(libunity_path: &Path, info_path: &Path, url: &str) -> Result<bool> {
+    if !libunity_path.exists() || !info_path.exists() {
+        return Ok(false);
+    }
+
+    let info: CachedLibUnity = serde_json::from_slice(&std::fs::read(info_path)?)
+        .context("Parsing libunity cache info")?;
+    if info.url != url {
+        info!("libunity.so URL has changed since it was cached");
+        return Ok(false);
+    }
+
+    let crc = mbf_zip::crc_of_stream(BufReader::new(File::open(libunity_path)?))?;
+    if crc != info.crc {
+        warn!(
+            "Cached libunity.so was corrupt (CRC {crc} did not match {})",
+            info.crc
+        );
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+// Gets the paths of the files with extension .obb in `obb_dir`.
+fn list_obbs(obb_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut obb_paths = Vec::new();
+    for entry in std::fs::read_dir(obb_dir).context("Reading OBB directory")? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("obb"))
+        {
+            obb_paths.push(path);
+        }
+    }
+
+    Ok(obb_paths)
+}
+
+// Removes the OBB that `diff` applies to from `obb_paths` and returns it, or returns None if there is no such OBB.
+// An OBB with the exact filename in the diff is preferred, otherwise an OBB with the same ID (see [get_obb_id]) is used,
+// since the filenames of OBBs can change between versions.
+fn take_obb_for_diff(obb_paths: &mut Vec<PathBuf>, diff: &Diff) -> Option<PathBuf> {
+    let file_name_of = |path: &PathBuf| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    let diff_id = get_obb_id(&diff.file_name);
+    let idx = obb_paths
+        .iter()
+        .position(|path| file_name_of(path) == diff.file_name)
+        .or_else(|| {
+            obb_paths
+                .iter()
+                .position(|path| get_obb_id(&file_name_of(path)) == diff_id)
+        })?;
+
+    Some(obb_paths.remove(idx))
+}
+
 // Moves the OBB file to a backup location and returns the path that the OBB needs to be restored to
 fn save_obbs(obb_dir: &Path, obb_backups_path: &Path) -> Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
@@ -359,16 +1219,73 @@ fn save_obbs(obb_dir: &Path, obb_backups_path: &Path) -> Result<Vec<PathBuf>> {
 }
 
 // Copies the contents of `obb_backups` back to `restore_dir`, creating it if it doesn't already exist.
+// Each backup is only deleted once it has been fully restored, so if restoring fails (e.g. due to a lack of space),
+// the backups that have not yet been restored are kept and restoring can be tried again.
 fn restore_obb_files(restore_dir: &Path, obb_backups: Vec<PathBuf>) -> Result<()> {
     std::fs::create_dir_all(restore_dir)?;
-    for backup_path in obb_backups {
-        // Cannot use a `rename` since the mount points are different
+    let obb_count = obb_backups.len();
+    for (idx, backup_path) in obb_backups.into_iter().enumerate() {
+        // Backups that no longer exist were already restored before patching was interrupted.
+        if !backup_path.exists() {
+            continue;
+        }
+
         info!("Restoring {:?}", backup_path);
-        std::fs::copy(
-            &backup_path,
-            restore_dir.join(backup_path.file_name().unwrap()),
-        )?;
-        std::fs::remove_file(backup_path)?;
+        crate::report_progress("Restoring OBB files", Some(idx as f32 / obb_count as f32));
+        restore_obb_file(&backup_path, restore_dir)
+            .with_context(|| format!("Restoring OBB backup {backup_path:?}"))?;
+        std::fs::remove_file(backup_path).context("Deleting restored OBB backup")?;
+    }
+
+    Ok(())
+}
+
+// Copies the OBB backup at `backup_path` into `restore_dir`, with the same file name.
+// The OBB is copied to a temporary name and only renamed into place once it has been verified to match the backup, so a
+// partially restored OBB is never left in place of the real OBB.
+fn restore_obb_file(backup_path: &Path, restore_dir: &Path) -> Result<()> {
+    let file_name = backup_path
+        .file_name()
+        .ok_or(anyhow!("OBB backup path had no file name"))?;
+    let restore_path = restore_dir.join(file_name);
+    let mut temp_name = file_name.to_owned();
+    temp_name.push(".restoring");
+    let temp_path = restore_dir.join(temp_name);
+
+    // Cannot use a `rename` since the mount points are different
+    let copy_result = std::fs::copy(backup_path, &temp_path)
+        .context("Copying OBB backup")
+        .and_then(|_| verify_restored_obb(backup_path, &temp_path))
+        .and_then(|_| {
+            std::fs::rename(&temp_path, &restore_path).context("Moving restored OBB into place")
+        });
+
+    if copy_result.is_err() && temp_path.exists() {
+        if let Err(err) = std::fs::remove_file(&temp_path) {
+            warn!("Failed to remove partially restored OBB: {err}");
+        }
+    }
+    copy_result
+}
+
+// Checks that the OBB restored to `restored_path` has the same length and CRC-32 as the backup at `backup_path`.
+fn verify_restored_obb(backup_path: &Path, restored_path: &Path) -> Result<()> {
+    let backup_len = std::fs::metadata(backup_path)?.len();
+    let restored_len = std::fs::metadata(restored_path)
+        .context("Reading restored OBB metadata")?
+        .len();
+    if restored_len != backup_len {
+        return Err(anyhow!(
+            "Restored OBB was {restored_len} bytes, but the backup is {backup_len} bytes"
+        ));
+    }
+
+    let backup_crc = mbf_zip::crc_of_stream(File::open(backup_path)?)
+        .context("Calculating CRC-32 of OBB backup")?;
+    let restored_crc = mbf_zip::crc_of_stream(File::open(restored_path)?)
+        .context("Calculating CRC-32 of restored OBB")?;
+    if restored_crc != backup_crc {
+        return Err(anyhow!("Restored OBB has different contents to the backup"));
     }
 
     Ok(())
@@ -395,10 +1312,42 @@ pub fn install_modloader() -> Result<()> {
     Ok(())
 }
 
+/// Returns true if the given APK has been patched with Scotland2, but with a different libmain.so to the one
+/// that this version of MBF adds.
+pub fn is_lib_main_outdated(apk: &mut ReadOnlyZipFile) -> Result<bool> {
+    Ok(match get_modloader_installed(apk)? {
+        Some(PatchedLoader {
+            loader: ModLoader::Scotland2,
+            lib_main_outdated,
+        }) => match lib_main_outdated {
+            Some(outdated) => outdated,
+            // The mod tag doesn't say which libmain.so was used, so it must be compared directly.
+            None => {
+                apk.contains_file(LIB_MAIN_PATH)
+                    && apk
+                        .read_file(LIB_MAIN_PATH)
+                        .context("Reading libmain.so from APK")?
+                        != LIB_MAIN
+            }
+        },
+        _ => false,
+    })
+}
+
 /// Checks the installed libsl2.so to see if it is present and up to date.
-pub fn get_modloader_status() -> Result<InstallStatus> {
+/// If `apk` is given and has been patched with Scotland2, the libmain.so inside it is also checked to be up to date,
+/// since an APK patched by an old version of MBF may have an old libmain.so even if libsl2.so has since been updated.
+/// An outdated libmain.so gives [InstallStatus::NeedsRepatch], since only patching the APK again can replace it.
+pub fn get_modloader_status(apk: Option<&mut ReadOnlyZipFile>) -> Result<InstallStatus> {
     let loader_path = get_modloader_path()?;
 
+    if let Some(apk) = apk {
+        if is_lib_main_outdated(apk)? {
+            warn!("libmain.so in the APK is out of date, so the APK must be patched again");
+            return Ok(InstallStatus::NeedsRepatch);
+        }
+    }
+
     info!("Checking if modloader is up to date");
     if loader_path.exists() {
         // Load the existing modloader into memory
@@ -423,9 +1372,10 @@ fn patch_apk_in_place(
     path: impl AsRef<Path>,
     libunity_path: Option<PathBuf>,
     manifest_mod: String,
+    manifest_options: &ManifestOptions,
     manifest_only: bool,
     vr_splash_path: Option<&str>,
-) -> Result<()> {
+) -> Result<ManifestDiff> {
     let file = OpenOptions::new()
         .read(true)
         .write(true)
@@ -436,13 +1386,20 @@ fn patch_apk_in_place(
     zip.set_store_alignment(STORE_ALIGNMENT);
 
     info!("Applying manifest mods");
-    patch_manifest(&mut zip, manifest_mod).context("Patching manifest")?;
+    let manifest_diff = crate::time_stage("Patching manifest", || {
+        patch_manifest(&mut zip, manifest_mod, manifest_options)
+    })
+    .context("Patching manifest")?;
 
     let (priv_key, cert) = signing::load_cert_and_priv_key(DEBUG_CERT_PEM);
 
     if !manifest_only {
         info!("Adding libmainloader");
-        zip.delete_file(LIB_MAIN_PATH);
+        let removed_libs = zip.delete_files_matching(|name| {
+            name.strip_prefix("lib/arm64-v8a/")
+                .is_some_and(|lib_name| MODLOADER_LIB_NAMES.contains(&lib_name))
+        });
+        debug!("Removed {removed_libs} existing modloader libraries");
         zip.write_file(
             LIB_MAIN_PATH,
             &mut Cursor::new(LIB_MAIN),
@@ -455,6 +1412,7 @@ fn patch_apk_in_place(
                 patcher_version: Some("0.1.0".to_string()), // TODO: Get this from the frontend maybe?
                 modloader_name: "Scotland2".to_string(), // TODO: This should really be Libmainloader because SL2 isn't inside the APK
                 modloader_version: None, // Temporary, but this field is universally considered to be optional so this should be OK.
+                lib_main_sha256: Some(lib_main_sha256()),
             },
         )?;
 
@@ -463,7 +1421,9 @@ fn patch_apk_in_place(
             Some(unity_path) => {
                 let mut unity_stream =
                     File::open(unity_path).context("Opening unstripped libunity.so")?;
-                zip.write_file(LIB_UNITY_PATH, &mut unity_stream, FileCompression::Deflate)?;
+                crate::time_stage("Adding libunity.so", || {
+                    zip.write_file(LIB_UNITY_PATH, &mut unity_stream, FileCompression::Deflate)
+                })?;
             }
             None => warn!("No unstripped unity added to the APK! This might cause issues later"),
         }
@@ -482,10 +1442,10 @@ fn patch_apk_in_place(
     }
 
     info!("Signing");
-    zip.save_and_sign_v2(&cert, &priv_key)
+    crate::time_stage("Signing", || zip.save_and_sign_v2(&cert, &priv_key))
         .context("Saving/signing APK")?;
 
-    Ok(())
+    Ok(manifest_diff)
 }
 
 fn add_modded_tag(to: &mut ZipFile<File>, tag: ModTag) -> Result<()> {
@@ -498,19 +1458,43 @@ fn add_modded_tag(to: &mut ZipFile<File>, tag: ModTag) -> Result<()> {
     Ok(())
 }
 
-pub fn get_modloader_installed(apk: &mut ZipFile<File>) -> Result<Option<ModLoader>> {
+/// The SHA-256 hash of the libmain.so that MBF adds to the APK, as lowercase hex.
+fn lib_main_sha256() -> String {
+    downloads::to_hex(&Sha256::digest(LIB_MAIN))
+}
+
+/// The modloader that an APK has been patched with, according to its mod tag.
+pub struct PatchedLoader {
+    pub loader: ModLoader,
+    /// Whether the APK was patched with a different libmain.so to the one that this version of MBF adds, meaning
+    /// that the patch is stale and should be redone.
+    /// `None` if the mod tag does not record which libmain.so was used, e.g. if patched by an older version of MBF.
+    pub lib_main_outdated: Option<bool>,
+}
+
+pub fn get_modloader_installed(apk: &mut ReadOnlyZipFile) -> Result<Option<PatchedLoader>> {
     if apk.contains_file(MOD_TAG_PATH) {
         let tag_data = apk.read_file(MOD_TAG_PATH).context("Reading mod tag")?;
         let mod_tag: ModTag = match serde_json::from_slice(&tag_data) {
             Ok(tag) => tag,
             Err(err) => {
                 warn!("Mod tag was invalid JSON: {err}... Assuming unknown modloader");
-                return Ok(Some(ModLoader::Unknown));
+                return Ok(Some(PatchedLoader {
+                    loader: ModLoader::Unknown,
+                    lib_main_outdated: None,
+                }));
             }
         };
 
-        Ok(Some(
-            if mod_tag.modloader_name.eq_ignore_ascii_case("QuestLoader") {
+        let lib_main_outdated = mod_tag
+            .lib_main_sha256
+            .map(|hash| !hash.eq_ignore_ascii_case(&lib_main_sha256()));
+        if lib_main_outdated == Some(true) {
+            warn!("APK was patched with a different libmain.so to this version of MBF");
+        }
+
+        Ok(Some(PatchedLoader {
+            loader: if mod_tag.modloader_name.eq_ignore_ascii_case("QuestLoader") {
                 ModLoader::QuestLoader
             } else if mod_tag.modloader_name.eq_ignore_ascii_case("Scotland2") {
                 // TODO: It's a bit problematic that "Scotland2" is the standard for the contents of modded.json
@@ -519,9 +1503,13 @@ pub fn get_modloader_installed(apk: &mut ZipFile<File>) -> Result<Option<ModLoad
             } else {
                 ModLoader::Unknown
             },
-        ))
-    } else if apk.iter_entry_names().any(|entry| entry.contains("modded")) {
-        Ok(Some(ModLoader::Unknown))
+            lib_main_outdated,
+        }))
+    } else if apk_check::is_modded(apk) {
+        Ok(Some(PatchedLoader {
+            loader: ModLoader::Unknown,
+            lib_main_outdated: None,
+        }))
     } else {
         Ok(None)
     }
@@ -547,14 +1535,60 @@ pub fn check_obb_present() -> Result<bool> {
     }))
 }
 
-fn patch_manifest(zip: &mut ZipFile<File>, additional_properties: String) -> Result<()> {
+/// Gets the paths of the files with extension .obb in the `/sdcard/Android/obb/com.beatgames.beatsaber` folder.
+/// Empty if the folder does not exist.
+pub fn get_obb_paths() -> Result<Vec<PathBuf>> {
+    let obb_dir = Path::new(paths::OBB_DIR);
+    if !obb_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    list_obbs(obb_dir)
+}
+
+fn patch_manifest(
+    zip: &mut ZipFile<File>,
+    additional_properties: String,
+    manifest_options: &ManifestOptions,
+) -> Result<ManifestDiff> {
     let mut xml_reader = xml::EventReader::new(Cursor::new(additional_properties.as_bytes()));
 
+    let mut events = axml::xml_to_axml_events(&mut xml_reader)
+        .context("Converting XML back to (binary) AXML")?;
+
+    let res_ids = ResourceIds::load().context("Loading resource IDs")?;
+    manifest_options
+        .apply(&mut events, &res_ids)
+        .context("Applying manifest options")?;
+    manifest::validate_manifest(&events).context("Validating patched manifest")?;
+
+    // Save the manifest with the same string encoding as the original, rather than always re-encoding it as UTF-8.
+    let original_manifest = zip
+        .read_file("AndroidManifest.xml")
+        .context("Reading original manifest")?;
+    let mut original_cursor = Cursor::new(original_manifest);
+    let mut original_reader =
+        AxmlReader::new(&mut original_cursor).context("Original manifest was not valid AXML")?;
+    let utf8 = original_reader.is_utf8();
+    let original_events = original_reader
+        .events()
+        .collect::<Result<Vec<_>>>()
+        .context("Reading original manifest")?;
+
+    let manifest_diff = manifest::diff_manifests(&original_events, &events);
+    info!(
+        "Manifest changes: {} elements added, {} removed, {} changed",
+        manifest_diff.added_elements.len(),
+        manifest_diff.removed_elements.len(),
+        manifest_diff.changed_elements.len()
+    );
+
     let mut data_output = Cursor::new(Vec::new());
     let mut axml_writer = AxmlWriter::new(&mut data_output);
-
-    axml::xml_to_axml(&mut axml_writer, &mut xml_reader)
-        .context("Converting XML back to (binary) AXML")?;
+    axml_writer.set_utf8(utf8);
+    for event in events {
+        axml_writer.write_event(event);
+    }
     axml_writer
         .finish()
         .context("Saving AXML (binary) manifest")?;
@@ -567,5 +1601,57 @@ fn patch_manifest(zip: &mut ZipFile<File>, additional_properties: String) -> Res
     )
     .context("Writing modified manifest")?;
 
-    Ok(())
+    Ok(manifest_diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Creates an empty directory for a test to use, inside the temporary directory.
+    fn create_test_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mbf-test-{}-{name}", std::process::id()));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn restored_obb_is_moved_into_place() {
+        let dir = create_test_dir("restore-obb");
+        let backup_path = dir.join("main.obb");
+        std::fs::write(&backup_path, b"OBB contents").unwrap();
+        let restore_dir = dir.join("restored");
+        std::fs::create_dir(&restore_dir).unwrap();
+
+        restore_obb_file(&backup_path, &restore_dir).unwrap();
+        assert_eq!(
+            std::fs::read(restore_dir.join("main.obb")).unwrap(),
+            b"OBB contents"
+        );
+        assert!(!restore_dir.join("main.obb.restoring").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn short_or_corrupt_obb_copy_is_rejected() {
+        let dir = create_test_dir("verify-obb");
+        let backup_path = dir.join("main.obb");
+        std::fs::write(&backup_path, b"OBB contents").unwrap();
+
+        let restored_path = dir.join("main.obb.restoring");
+        // Simulates a copy that stopped early.
+        std::fs::write(&restored_path, b"OBB cont").unwrap();
+        assert!(verify_restored_obb(&backup_path, &restored_path).is_err());
+        // Same length, but different contents.
+        std::fs::write(&restored_path, b"OBB CONTENTS").unwrap();
+        assert!(verify_restored_obb(&backup_path, &restored_path).is_err());
+        std::fs::write(&restored_path, b"OBB contents").unwrap();
+        assert!(verify_restored_obb(&backup_path, &restored_path).is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
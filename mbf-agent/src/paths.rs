@@ -3,9 +3,11 @@
 use crate::APK_ID;
 use const_format::formatcp;
 
+/// Directory containing a directory of QMOD files for each game version that has been modded.
+pub const PACKAGES: &str = formatcp!("/sdcard/ModData/{APK_ID}/Packages");
 /// Directory that QMOD files are stored in.
 /// `$` is replaced with the game version
-pub const QMODS: &str = formatcp!("/sdcard/ModData/{APK_ID}/Packages/$");
+pub const QMODS: &str = formatcp!("{PACKAGES}/$");
 /// The legacy directory used to contain QMOD files in older builds of MBF.
 pub const OLD_QMODS: &str = "/sdcard/ModsBeforeFriday/Mods";
 /// The path of the `.nomedia` file added to ModData.
@@ -18,6 +20,8 @@ pub const LATE_MODS: &str = formatcp!("{MODLOADER_DIR}/mods");
 pub const EARLY_MODS: &str = formatcp!("{MODLOADER_DIR}/early_mods");
 /// Directory containing installed library files.
 pub const LIBS: &str = formatcp!("{MODLOADER_DIR}/libs");
+/// Directory containing the config file of each mod, named `<mod ID>.json`, which mods read their settings from.
+pub const MOD_CONFIGS: &str = formatcp!("/sdcard/ModData/{APK_ID}/Configs");
 /// The Android `files` directory for the app being modded.
 pub const ANDROID_APP_FILES: &str = formatcp!("/sdcard/Android/data/{APK_ID}/files");
 /// Path of the `PlayerData.dat` in the vanilla game.
@@ -27,20 +31,53 @@ pub const PLAYER_DATA_BAK: &str = formatcp!("{ANDROID_APP_FILES}/PlayerData.dat.
 /// Directory containing OBBs for the app.
 pub const OBB_DIR: &str = formatcp!("/sdcard/Android/obb/{APK_ID}/");
 
+/// The folders that mods may copy files into with file copies and copy extensions.
+/// Both the `/sdcard` path and the `/storage/emulated/0` path that it links to are permitted.
+pub const FILE_COPY_ROOTS: &[&str] = &[
+    formatcp!("/sdcard/ModData/{APK_ID}"),
+    formatcp!("/sdcard/Android/data/{APK_ID}"),
+    formatcp!("/storage/emulated/0/ModData/{APK_ID}"),
+    formatcp!("/storage/emulated/0/Android/data/{APK_ID}"),
+];
+
 /// Path to the `PlayerData.dat` of the `datakeeper` mod.
 pub const DATAKEEPER_PLAYER_DATA: &str =
     "/sdcard/ModData/com.beatgames.beatsaber/Mods/datakeeper/PlayerData.dat";
 /// An auxillary path that `PlayerData.dat` is copied to when modding in case it is corrupted/lost for any other reason.
 pub const AUX_DATA_BACKUP: &str = "/sdcard/ModsBeforeFriday/PlayerData.backup.dat";
 
+/// Directories that mod and modloader logs may be written to, depending on the version of the logging library used.
+pub const MOD_LOG_DIRS: &[&str] = &[
+    formatcp!("/sdcard/ModData/{APK_ID}/logs2"),
+    formatcp!("/sdcard/ModData/{APK_ID}/logs"),
+    formatcp!("{ANDROID_APP_FILES}/logs"),
+];
+/// Directory containing native crash dumps. This is usually only readable on rooted or debug devices.
+pub const TOMBSTONES: &str = "/data/tombstones";
+
 /// The folder that SongCore loads custom levels from.
 pub const CUSTOM_LEVELS: &str = formatcp!("/sdcard/ModData/{APK_ID}/Mods/SongCore/CustomLevels");
 /// A folder that MBF uses to download temporary files.
 pub const MBF_DOWNLOADS: &str = "/data/local/tmp/mbf/downloads";
 /// Temporary folder used by MBF during patching.
 pub const TEMP: &str = "/data/local/tmp/mbf/tmp";
+/// Temporary folder on shared storage, used during patching instead of [TEMP] if its volume has more free space.
+pub const SHARED_TEMP: &str = "/sdcard/ModsBeforeFriday/tmp";
+/// Subdirectory created within a temporary directory chosen by the frontend, so that MBF only ever deletes a directory it created.
+pub const CUSTOM_TEMP_SUBDIR: &str = "mbf-patch";
+/// The folders that may be used for temporary files during patching, in order of preference if they have the same free space.
+pub const TEMP_DIRS: &[&str] = &[TEMP, SHARED_TEMP];
+/// Path of the policy file restricting which mods may be installed.
+/// This is outside of `/sdcard` so that it can only be changed over ADB, and not by apps on the Quest.
+pub const MOD_POLICY: &str = "/data/local/tmp/mbf/mod_policy.json";
+/// Temporary location of the ZIP file created by an `ExportDiagnostics` request.
+pub const DIAGNOSTICS_ZIP: &str = "/data/local/tmp/mbf/diagnostics.zip";
+/// Lock file held by requests that modify the installation, so that only one can run at a time.
+pub const OPERATION_LOCK: &str = "/data/local/tmp/mbf/operation.lock";
 /// Path to the MBF resource cache.
 pub const RES_CACHE: &str = "/data/local/tmp/mbf/res-cache";
+/// Directory within the resource cache where downloaded unstripped `libunity.so` files are kept, one per game version.
+pub const LIBUNITY_CACHE: &str = formatcp!("{RES_CACHE}/libunity");
 /// Directories no longer used by MBF that should be deleted on startup if detected.
 pub const LEGACY_DIRS: &[&str] = &[
     "/data/local/tmp/mbf-downloads",
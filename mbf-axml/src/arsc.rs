@@ -0,0 +1,245 @@
+//! A minimal reader for `resources.arsc`, the binary resource table of an APK.
+//! This is used to resolve resource references within the manifest, e.g. the label and icon of the app.
+//! Only simple values are read: styles, arrays and other complex resources are skipped.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+};
+
+use anyhow::{anyhow, Context, Result};
+use byteorder::{ReadBytesExt, LE};
+
+use super::reader::load_string_pool;
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_TABLE_TYPE: u16 = 0x0002;
+const RES_TABLE_PACKAGE_TYPE: u16 = 0x0200;
+const RES_TABLE_TYPE_TYPE: u16 = 0x0201;
+
+// The entry offsets of a type chunk are pairs of entry index and offset, only for entries that exist.
+const TYPE_FLAG_SPARSE: u8 = 0x01;
+// The entry offsets of a type chunk are 16 bit, and must be multiplied by 4.
+const TYPE_FLAG_OFFSET16: u8 = 0x02;
+const NO_ENTRY: u32 = 0xFFFFFFFF;
+const NO_ENTRY_16: u16 = 0xFFFF;
+
+// The entry is a map of values, e.g. a style, rather than a single value.
+const ENTRY_FLAG_COMPLEX: u16 = 0x0001;
+// The entry stores its key and value within 8 bytes, with the data type of the value in the upper byte of the flags.
+const ENTRY_FLAG_COMPACT: u16 = 0x0008;
+
+const VALUE_TYPE_REFERENCE: u8 = 0x01;
+const VALUE_TYPE_STRING: u8 = 0x03;
+
+/// The device configuration that a resource value is used for.
+/// Only the parts of the configuration needed by MBF are read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResConfig {
+    /// The language code, as two ASCII characters, or `[0, 0]` if the value is used for any language.
+    pub language: [u8; 2],
+    /// The screen density in DPI, 0 if the value is for the default density, `0xFFFE` if it is for any density
+    /// or `0xFFFF` if the value doesn't depend on the density.
+    pub density: u16,
+}
+
+impl ResConfig {
+    pub const DENSITY_ANY: u16 = 0xFFFE;
+    pub const DENSITY_NONE: u16 = 0xFFFF;
+}
+
+/// A simple resource value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResValue {
+    String(String),
+    /// A reference to another resource, by resource ID.
+    Reference(u32),
+    /// A value of any other type, with its raw data type and data.
+    Other {
+        data_type: u8,
+        data: u32,
+    },
+}
+
+/// The simple values within a resource table, for each resource ID and configuration.
+pub struct ResourceTable {
+    values: HashMap<u32, Vec<(ResConfig, ResValue)>>,
+}
+
+impl ResourceTable {
+    /// Reads a resource table from a `resources.arsc` file.
+    /// Invalid sequences within strings are replaced with U+FFFD, as the values are only read for display.
+    pub fn read(data: &mut (impl Read + Seek)) -> Result<Self> {
+        let table_start = data.stream_position()?;
+        let (chunk_type, header_size, size) = read_chunk_header(data)?;
+        if chunk_type != RES_TABLE_TYPE {
+            return Err(anyhow!("Initial chunk was not a resource table"));
+        }
+        let table_end = table_start + size as u64;
+
+        let mut table = Self {
+            values: HashMap::new(),
+        };
+        let mut strings = Vec::new();
+
+        let mut chunk_start = table_start + header_size as u64;
+        while chunk_start < table_end {
+            data.seek(SeekFrom::Start(chunk_start))?;
+            let (chunk_type, header_size, size) = read_chunk_header(data)?;
+            match chunk_type {
+                RES_STRING_POOL_TYPE => {
+                    (strings, _) =
+                        load_string_pool(data, true).context("Loading global string pool")?
+                }
+                RES_TABLE_PACKAGE_TYPE => table
+                    .read_package(data, chunk_start, header_size, size, &strings)
+                    .context("Reading package")?,
+                _ => {}
+            }
+
+            if size == 0 {
+                return Err(anyhow!("Chunk at {chunk_start} had zero length"));
+            }
+            chunk_start += size as u64;
+        }
+
+        Ok(table)
+    }
+
+    /// Gets the values of the resource with the given ID, along with the configuration that each value is for.
+    /// Gives an empty slice if the resource doesn't exist or is not a simple value.
+    pub fn get_values(&self, id: u32) -> &[(ResConfig, ResValue)] {
+        self.values.get(&id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn read_package(
+        &mut self,
+        data: &mut (impl Read + Seek),
+        package_start: u64,
+        header_size: u16,
+        size: u32,
+        strings: &[String],
+    ) -> Result<()> {
+        let package_id = data.read_u32::<LE>()?;
+        let package_end = package_start + size as u64;
+
+        let mut chunk_start = package_start + header_size as u64;
+        while chunk_start < package_end {
+            data.seek(SeekFrom::Start(chunk_start))?;
+            let (chunk_type, header_size, size) = read_chunk_header(data)?;
+            // The type and key string pools are not needed, since entries are looked up by ID.
+            if chunk_type == RES_TABLE_TYPE_TYPE {
+                self.read_type(data, chunk_start, header_size, package_id, strings)
+                    .context("Reading type chunk")?;
+            }
+
+            if size == 0 {
+                return Err(anyhow!("Chunk at {chunk_start} had zero length"));
+            }
+            chunk_start += size as u64;
+        }
+
+        Ok(())
+    }
+
+    fn read_type(
+        &mut self,
+        data: &mut (impl Read + Seek),
+        type_start: u64,
+        header_size: u16,
+        package_id: u32,
+        strings: &[String],
+    ) -> Result<()> {
+        let type_id = data.read_u8()?;
+        let flags = data.read_u8()?;
+        let _reserved = data.read_u16::<LE>()?;
+        let entry_count = data.read_u32::<LE>()?;
+        let entries_start = data.read_u32::<LE>()?;
+
+        // The size of the configuration includes the size field itself.
+        let config_size = data.read_u32::<LE>()?;
+        let mut config_data = vec![0u8; (config_size as usize).saturating_sub(4)];
+        data.read_exact(&mut config_data)?;
+        let config = ResConfig {
+            language: match config_data.get(4..6) {
+                Some(language) => [language[0], language[1]],
+                None => [0, 0],
+            },
+            density: match config_data.get(10..12) {
+                Some(density) => u16::from_le_bytes([density[0], density[1]]),
+                None => 0,
+            },
+        };
+
+        // Find the index of each entry present, and its offset from the start of the entries.
+        data.seek(SeekFrom::Start(type_start + header_size as u64))?;
+        let mut entry_offsets = Vec::with_capacity(entry_count as usize);
+        for index in 0..entry_count {
+            if flags & TYPE_FLAG_SPARSE != 0 {
+                let index = data.read_u16::<LE>()?;
+                let offset = data.read_u16::<LE>()? as u32 * 4;
+                entry_offsets.push((index as u32, offset));
+            } else if flags & TYPE_FLAG_OFFSET16 != 0 {
+                let offset = data.read_u16::<LE>()?;
+                if offset != NO_ENTRY_16 {
+                    entry_offsets.push((index, offset as u32 * 4));
+                }
+            } else {
+                let offset = data.read_u32::<LE>()?;
+                if offset != NO_ENTRY {
+                    entry_offsets.push((index, offset));
+                }
+            }
+        }
+
+        for (index, offset) in entry_offsets {
+            data.seek(SeekFrom::Start(
+                type_start + entries_start as u64 + offset as u64,
+            ))?;
+
+            // The size of the entry, or the key of a compact entry, neither of which are needed.
+            let _size_or_key = data.read_u16::<LE>()?;
+            let entry_flags = data.read_u16::<LE>()?;
+            let (data_type, value_data) = if entry_flags & ENTRY_FLAG_COMPACT != 0 {
+                ((entry_flags >> 8) as u8, data.read_u32::<LE>()?)
+            } else {
+                let _key = data.read_u32::<LE>()?;
+                if entry_flags & ENTRY_FLAG_COMPLEX != 0 {
+                    continue;
+                }
+
+                let _value_size = data.read_u16::<LE>()?;
+                let _res0 = data.read_u8()?;
+                (data.read_u8()?, data.read_u32::<LE>()?)
+            };
+
+            let value = match data_type {
+                VALUE_TYPE_STRING => ResValue::String(
+                    strings
+                        .get(value_data as usize)
+                        .ok_or(anyhow!("String index {value_data} out of range"))?
+                        .clone(),
+                ),
+                VALUE_TYPE_REFERENCE => ResValue::Reference(value_data),
+                _ => ResValue::Other {
+                    data_type,
+                    data: value_data,
+                },
+            };
+
+            let id = (package_id << 24) | ((type_id as u32) << 16) | index;
+            self.values.entry(id).or_default().push((config, value));
+        }
+
+        Ok(())
+    }
+}
+
+// Reads the type, header size and total size of a chunk.
+fn read_chunk_header(data: &mut impl Read) -> Result<(u16, u16, u32)> {
+    Ok((
+        data.read_u16::<LE>()?,
+        data.read_u16::<LE>()?,
+        data.read_u32::<LE>()?,
+    ))
+}
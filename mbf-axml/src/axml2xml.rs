@@ -15,28 +15,43 @@
 //! Certain AXML attributes contain "references" to other resources within the APK. Each reference is a 4 byte unsigned integer,
 //! and they are written in the XML with the format `[REF ID_HERE]`. This is necessary as the attribute value type must be set to `reference` for Android to parse the value
 //! correctly.
+//! References to resources in shared libraries, whose package ID is only assigned at runtime, are "dynamic references" and are
+//! written with the format `[DREF ID_HERE]` so that they keep their attribute type.
 //!
 //! TYPED ATTRIBUTE VALUES:
 //! In regular XML, attribute values are always strings, with stringified booleans and integers etc, used for other data types.
-//! In AXML attribute values can be strings, booleans, integers, references or styles (styles are not implemented currently.)
+//! In AXML attribute values can be strings, booleans, integers, references, fractions or styles (styles are not implemented currently.)
 //! This allows integers/booleans to be stored more efficiently. (no string needed) but also means that, in AXML, there is a difference between
 //! the string "true" and the boolean value `true`.
 //!
 //! When AXML attributes are converted to strings in this implementation, the values "true" "false" and any integers represent their AXML data types.
+//! Integers stored as hexadecimal are written with the format `[HEX 0x7f0a0001]`, so that they keep their data type.
+//! (Plain strings that happen to look like hexadecimal, e.g. "0x1f", are kept as strings.)
+//! Fractions are written as percentages with the format `[FRACTION 50%]`, or `[FRACTION 50%p]` for a fraction of the parent
+//! container. (Plain strings that happen to look like percentages, e.g. "50%", are kept as strings.)
+//!
+//! UNKNOWN CHUNKS:
+//! AXML chunks that this implementation does not understand are written as processing instructions in the position of the chunk,
+//! with the format `<?mbf-unknown-chunk res_type=RES_TYPE contents=BASE64_CONTENTS?>`, and are read back into the same chunk.
+//! These must not be modified, as their contents may e.g. refer to indices in the string pool.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use std::collections::HashMap;
 use xml::common::Position;
 
-use crate::axml::{ResourceIds, ANDROID_NS_URI};
+use crate::{ResourceIds, ANDROID_NS_URI};
 
-use super::{AxmlReader, AxmlWriter};
+use super::AxmlReader;
 type AxmlAttrValue = super::AttributeValue;
 type AxmlEvent = super::Event;
 type AxmlNamespace = super::Namespace;
 type AxmlAttribute = super::Attribute;
 type XmlName<'a> = xml::name::Name<'a>;
 
+/// The name of the processing instruction used to store AXML chunks that are not understood.
+const UNKNOWN_CHUNK_PI_NAME: &str = "mbf-unknown-chunk";
+
 /// Converts an AXML document into readable XML format.
 pub fn axml_to_xml<W: std::io::Write, R: std::io::Read + std::io::Seek>(
     writer: &mut xml::EventWriter<W>,
@@ -54,8 +69,8 @@ pub fn axml_to_xml<W: std::io::Write, R: std::io::Read + std::io::Seek>(
     // Needed to correctly write the namespace prefix with each XML attribute.
     let mut current_ns_prefixes: HashMap<String, String> = HashMap::new();
 
-    while let Some(ev) = reader.read_next_event()? {
-        match ev {
+    for ev in reader.events() {
+        match ev? {
             AxmlEvent::StartElement {
                 attributes,
                 name,
@@ -130,25 +145,31 @@ pub fn axml_to_xml<W: std::io::Write, R: std::io::Read + std::io::Seek>(
                 current_ns_prefixes.remove(&namespace.uri);
                 Ok(())
             }
-            AxmlEvent::Unknown {
-                contents: _,
-                res_type,
-            } => writer.write(XmlEvent::comment(&format!(
-                "WARNING: UNKNOWN AXML EVENT. RES_TYPE: {res_type}"
-            ))),
+            AxmlEvent::Unknown { contents, res_type } => {
+                writer.write(XmlEvent::processing_instruction(
+                    UNKNOWN_CHUNK_PI_NAME,
+                    Some(&format!(
+                        "res_type={res_type} contents={}",
+                        BASE64.encode(contents)
+                    )),
+                ))
+            }
         }?
     }
 
     Ok(())
 }
 
-pub fn xml_to_axml<W: std::io::Write, R: std::io::Read>(
-    writer: &mut AxmlWriter<W>,
+/// Converts an XML document into a list of AXML events.
+/// The events can be modified programmatically before being saved with an [AxmlWriter](super::AxmlWriter).
+pub fn xml_to_axml_events<R: std::io::Read>(
     reader: &mut xml::EventReader<R>,
-) -> Result<()> {
+) -> Result<Vec<AxmlEvent>> {
     use xml::reader::XmlEvent;
     let res_ids = ResourceIds::load().context("Loading resource IDs")?;
 
+    let mut events = Vec::new();
+
     // A list of the namespaces declared by each element in the tree.
     // Will be empty if an element declares no namespaces
     // Used to determine when to write EndNamespace AXML chunks
@@ -194,7 +215,7 @@ pub fn xml_to_axml<W: std::io::Write, R: std::io::Read>(
                         ns.uri.clone(),
                     );
 
-                    writer.write_event(AxmlEvent::StartNamespace(ns.clone()));
+                    events.push(AxmlEvent::StartNamespace(ns.clone()));
                 }
                 // Note which namespaces have been declared with each element so we can remove them later.
                 declared_nses.push(newly_declared_nses);
@@ -215,7 +236,7 @@ pub fn xml_to_axml<W: std::io::Write, R: std::io::Read>(
                     })
                 }
 
-                writer.write_event(AxmlEvent::StartElement {
+                events.push(AxmlEvent::StartElement {
                     attributes: axml_attributes,
                     name: name.local_name,
                     namespace: name.namespace,
@@ -224,7 +245,7 @@ pub fn xml_to_axml<W: std::io::Write, R: std::io::Read>(
             }
             XmlEvent::EndElement { name } => {
                 // Firstly end the element.
-                writer.write_event(AxmlEvent::EndElement {
+                events.push(AxmlEvent::EndElement {
                     line_num: reader.position().row as u32,
                     namespace: name.namespace.map(|value| value.to_string()),
                     name: name.local_name.to_string(),
@@ -237,19 +258,53 @@ pub fn xml_to_axml<W: std::io::Write, R: std::io::Read>(
 
                 let no_prefix = xml::namespace::NS_NO_PREFIX.to_string();
                 for ns in nses.iter().rev() {
-                    writer.write_event(AxmlEvent::EndNamespace(ns.clone()));
+                    events.push(AxmlEvent::EndNamespace(ns.clone()));
                     current_namespace_map.remove(match &ns.prefix {
                         Some(prefix) => prefix,
                         None => &no_prefix,
                     });
                 }
             }
+            XmlEvent::ProcessingInstruction { name, data } if name == UNKNOWN_CHUNK_PI_NAME => {
+                events.push(
+                    parse_unknown_chunk(data.as_deref().unwrap_or_default()).with_context(
+                        || {
+                            format!(
+                                "Invalid unknown chunk at line {}",
+                                reader.position().row + 1
+                            )
+                        },
+                    )?,
+                )
+            }
             XmlEvent::EndDocument => break,
             _ => {} // No need for any other events
         }
     }
 
-    Ok(())
+    Ok(events)
+}
+
+// Parses the data of a processing instruction written for an unknown AXML chunk back into the chunk.
+fn parse_unknown_chunk(data: &str) -> Result<AxmlEvent> {
+    let mut res_type = None;
+    let mut contents = None;
+    for field in data.split_whitespace() {
+        match field.split_once('=') {
+            Some(("res_type", value)) => {
+                res_type = Some(value.parse::<u32>().context("Invalid res_type")?)
+            }
+            Some(("contents", value)) => {
+                contents = Some(BASE64.decode(value).context("Invalid base64 contents")?)
+            }
+            _ => return Err(anyhow!("Unexpected field `{field}`")),
+        }
+    }
+
+    Ok(AxmlEvent::Unknown {
+        contents: contents.ok_or(anyhow!("No contents given"))?,
+        res_type: res_type.ok_or(anyhow!("No res_type given"))?,
+    })
 }
 
 // Converts an axml name and namespace into an XmlName struct, which wraps the name and namespace slightly differently.
@@ -271,7 +326,7 @@ fn get_xml_name_from_axml<'a>(
 }
 
 /// Converts an attribute value into a string to be stored as an XML value.
-fn stringify_attr_value(value: AxmlAttrValue) -> String {
+pub fn stringify_attr_value(value: AxmlAttrValue) -> String {
     match value {
         AxmlAttrValue::Boolean(b) => {
             if b {
@@ -281,13 +336,49 @@ fn stringify_attr_value(value: AxmlAttrValue) -> String {
             }
         }
         AxmlAttrValue::Integer(i) => i.to_string(),
+        AxmlAttrValue::Hex(i) => format!("[HEX {:#x}]", i as u32),
         AxmlAttrValue::String(s) => s,
         AxmlAttrValue::Reference(reference) => format!("[REF {reference}]"),
+        AxmlAttrValue::DynamicReference(reference) => format!("[DREF {reference}]"),
+        AxmlAttrValue::Null => "[NULL]".to_string(),
+        AxmlAttrValue::Empty => "[EMPTY]".to_string(),
+        AxmlAttrValue::Fraction { value, of_parent } => {
+            // Rounding removes the error from storing the fraction in binary, so that e.g. 33.3% is not written as 33.299995%
+            let percentage = format!("{:.4}", value * 100.0);
+            let percentage = percentage.trim_end_matches('0').trim_end_matches('.');
+            format!(
+                "[FRACTION {percentage}%{}]",
+                if of_parent { "p" } else { "" }
+            )
+        }
     }
 }
 
+// Parses a fraction in the format `50%`, or `50%p` for a fraction of the parent container.
+fn parse_fraction(string: &str) -> Option<AxmlAttrValue> {
+    let (percentage, of_parent) = match string.strip_suffix("%p") {
+        Some(percentage) => (percentage, true),
+        None => (string.strip_suffix('%')?, false),
+    };
+
+    // Only accept plain numbers, as Rust also parses values like `inf` which AXML cannot store.
+    if percentage.is_empty()
+        || !percentage
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        return None;
+    }
+
+    Some(AxmlAttrValue::Fraction {
+        value: percentage.parse::<f32>().ok()? / 100.0,
+        of_parent,
+    })
+}
+
 // Converts an attribute value back from a string to the value of an AXML attribute.
-// If the value is a valid integer, boolean or reference, it will be stored using the appropriate AXML attribute type.
+// If the value is a valid integer, hexadecimal integer, boolean, reference, dynamic reference, fraction or null value,
+// it will be stored using the appropriate AXML attribute type.
 fn attr_value_from_string(string: String) -> Result<AxmlAttrValue> {
     Ok(if string == "true" {
         AxmlAttrValue::Boolean(true)
@@ -295,13 +386,121 @@ fn attr_value_from_string(string: String) -> Result<AxmlAttrValue> {
         AxmlAttrValue::Boolean(false)
     } else if let Ok(i) = string.parse::<i32>() {
         AxmlAttrValue::Integer(i)
-    } else if string.starts_with("[REF ") {
+    } else if let Some(hex) = string.strip_prefix("[HEX ") {
+        let hex = hex
+            .strip_suffix(']')
+            .and_then(|hex| hex.strip_prefix("0x"))
+            .ok_or(anyhow!("Invalid axml hexadecimal integer {string}"))?;
+        AxmlAttrValue::Hex(
+            u32::from_str_radix(hex, 16).context("Invalid axml hexadecimal integer")? as i32,
+        )
+    } else if let Some(fraction) = string.strip_prefix("[FRACTION ") {
+        fraction
+            .strip_suffix(']')
+            .and_then(parse_fraction)
+            .ok_or(anyhow!("Invalid axml fraction {string}"))?
+    } else if string == "[NULL]" {
+        AxmlAttrValue::Null
+    } else if string == "[EMPTY]" {
+        AxmlAttrValue::Empty
+    } else if let Some(reference) = string.strip_prefix("[REF ") {
         AxmlAttrValue::Reference(
-            string[5..string.len() - 1]
+            reference
+                .strip_suffix(']')
+                .ok_or(anyhow!("Unterminated axml reference {string}"))?
                 .parse::<u32>()
                 .context("Invalid axml reference")?,
         )
+    } else if let Some(reference) = string.strip_prefix("[DREF ") {
+        AxmlAttrValue::DynamicReference(
+            reference
+                .strip_suffix(']')
+                .ok_or(anyhow!("Unterminated axml dynamic reference {string}"))?
+                .parse::<u32>()
+                .context("Invalid axml dynamic reference")?,
+        )
     } else {
         AxmlAttrValue::String(string)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_round_trip() {
+        for value in [
+            AxmlAttrValue::Reference(0x7f0a0001),
+            AxmlAttrValue::DynamicReference(0x000a0001),
+        ] {
+            let string = stringify_attr_value(value.clone());
+            assert_eq!(attr_value_from_string(string).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn hex_integers_round_trip() {
+        for value in [
+            AxmlAttrValue::Hex(0x7f0a0001),
+            AxmlAttrValue::Hex(0),
+            AxmlAttrValue::Hex(-1),
+        ] {
+            let string = stringify_attr_value(value.clone());
+            assert_eq!(attr_value_from_string(string).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn hex_looking_strings_stay_strings() {
+        for string in ["0x1f", "0xdeadbeef", "0x"] {
+            assert_eq!(
+                attr_value_from_string(string.to_string()).unwrap(),
+                AxmlAttrValue::String(string.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn fractions_round_trip() {
+        for string in [
+            "[FRACTION 50%]",
+            "[FRACTION 33.3%]",
+            "[FRACTION 0.5%p]",
+            "[FRACTION -25%]",
+            "[FRACTION 150%]",
+        ] {
+            let value = attr_value_from_string(string.to_string()).unwrap();
+            assert!(matches!(value, AxmlAttrValue::Fraction { .. }));
+            assert_eq!(stringify_attr_value(value), string);
+        }
+    }
+
+    #[test]
+    fn percentage_strings_stay_strings() {
+        for string in ["50%", "5%p", "100%"] {
+            assert_eq!(
+                attr_value_from_string(string.to_string()).unwrap(),
+                AxmlAttrValue::String(string.to_string())
+            );
+        }
+        for string in ["[FRACTION 50]", "[FRACTION inf%]", "[FRACTION 50%"] {
+            assert!(
+                attr_value_from_string(string.to_string()).is_err(),
+                "{string} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_references_are_rejected() {
+        for string in [
+            "[REF ", "[REF 12", "[REF x]", "[DREF ", "[DREF ]", "[DREF 12",
+        ] {
+            assert!(
+                attr_value_from_string(string.to_string()).is_err(),
+                "{string} was accepted"
+            );
+        }
+    }
+}
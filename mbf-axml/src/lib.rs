@@ -0,0 +1,282 @@
+//! Library to parse and save the Android binary XML format
+//! Used for modifying the APK manifest
+
+pub mod arsc;
+mod axml2xml;
+mod reader;
+mod res_ids;
+mod writer;
+
+pub use reader::{AxmlReader, Events};
+pub use res_ids::ResourceIds;
+pub use writer::AxmlWriter;
+const UTF8_FLAG: u32 = 0x00000100;
+pub const ANDROID_NS_URI: &str = "http://schemas.android.com/apk/res/android";
+pub use axml2xml::{axml_to_xml, stringify_attr_value, xml_to_axml_events};
+
+/// An XML event within the main body of an AXML file.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// An event that this implementation does not parse/understand, typically CData
+    Unknown {
+        /// The contents of the chunk, after its res type and length.
+        contents: Vec<u8>,
+        res_type: u32,
+    },
+    StartNamespace(Namespace),
+    EndNamespace(Namespace),
+    StartElement {
+        attributes: Vec<Attribute>,
+        name: String,
+        namespace: Option<String>,
+        line_num: u32,
+    },
+    EndElement {
+        line_num: u32,
+        namespace: Option<String>,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    pub prefix: Option<String>,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub namespace: Option<String>,
+    pub resource_id: Option<u32>,
+    pub value: AttributeValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Boolean(bool),
+    Integer(i32),
+    /// An integer that was stored as hexadecimal, which is kept separate from [AttributeValue::Integer]
+    /// so that it is written back with the same type.
+    Hex(i32),
+    Reference(u32), // Reference ID
+    Null,           // No value, i.e. undefined
+    Empty,          // Explicitly empty value
+    /// A reference to a resource in a shared library, whose package ID is assigned at runtime.
+    /// This is kept separate from [AttributeValue::Reference] so that it is written back with the same type.
+    DynamicReference(u32),
+    /// A fraction, e.g. `50%` (a `value` of 0.5), or `50%p` if the fraction is `of_parent`, i.e. relative to the parent container.
+    Fraction {
+        value: f32,
+        of_parent: bool,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum ChunkType {
+    StringPool,
+    Xml,
+    XmlResourceMap,
+    XmlStartNamespace,
+    XmlEndNamespace,
+    XmlStartElement,
+    XmlEndElement,
+}
+
+impl ChunkType {
+    fn parse(from: u32) -> Option<Self> {
+        match from & 0xFFFF {
+            0x0001 => Some(Self::StringPool),
+            0x0003 => Some(Self::Xml),
+            0x0180 => Some(Self::XmlResourceMap),
+            0x0103 => Some(Self::XmlEndElement),
+            0x0100 => Some(Self::XmlStartNamespace),
+            0x0101 => Some(Self::XmlEndNamespace),
+            0x0102 => Some(Self::XmlStartElement),
+            _ => None,
+        }
+    }
+
+    fn save(&self) -> u32 {
+        let prefix = match self {
+            ChunkType::StringPool => 0x001C,
+            ChunkType::XmlResourceMap | ChunkType::Xml => 0x0008,
+            _ => 0x0010,
+        };
+
+        let id = match self {
+            ChunkType::StringPool => 0x0001,
+            ChunkType::Xml => 0x0003,
+            ChunkType::XmlResourceMap => 0x0180,
+            ChunkType::XmlStartNamespace => 0x0100,
+            ChunkType::XmlEndNamespace => 0x0101,
+            ChunkType::XmlStartElement => 0x0102,
+            ChunkType::XmlEndElement => 0x0103,
+        };
+
+        id | prefix << 16
+    }
+}
+
+#[derive(Copy, Clone)]
+enum AttributeTypeId {
+    Int,
+    Boolean,
+    Hex,
+    Reference,
+    DynamicReference,
+    String,
+    Null,
+    Fraction,
+}
+
+impl AttributeTypeId {
+    fn parse(from: u32) -> Option<AttributeTypeId> {
+        match from >> 24 {
+            0x10 => Some(Self::Int),
+            0x12 => Some(Self::Boolean),
+            0x11 => Some(Self::Hex),
+            0x01 => Some(Self::Reference),
+            0x07 => Some(Self::DynamicReference),
+            0x03 => Some(Self::String),
+            0x00 => Some(Self::Null),
+            0x06 => Some(Self::Fraction),
+            _ => None,
+        }
+    }
+
+    fn save(&self) -> u32 {
+        let basic_type = match self {
+            Self::Int => 0x10,
+            Self::Boolean => 0x12,
+            Self::Hex => 0x11,
+            Self::Reference => 0x01,
+            Self::DynamicReference => 0x07,
+            Self::String => 0x03,
+            Self::Null => 0x00,
+            Self::Fraction => 0x06,
+        };
+
+        (basic_type << 24) | 0x000008
+    }
+}
+
+// Complex values (fractions and dimensions) store their unit in the lowest 4 bits, then the position of the
+// binary point within the mantissa in the next 2 bits (the radix), then a signed 24 bit mantissa in the upper 24 bits.
+const COMPLEX_UNIT_MASK: u32 = 0xF;
+const COMPLEX_RADIX_SHIFT: u32 = 4;
+const COMPLEX_MANTISSA_SHIFT: u32 = 8;
+const COMPLEX_MANTISSA_MASK: u32 = 0xFFFFFF;
+// Number of bits of the mantissa that come after the binary point, for each radix.
+const COMPLEX_RADIX_FRACTION_BITS: [u32; 4] = [0, 7, 15, 23];
+
+const COMPLEX_UNIT_FRACTION: u32 = 0;
+const COMPLEX_UNIT_FRACTION_PARENT: u32 = 1;
+
+// Gets the numeric value of a complex value, ignoring its unit.
+fn complex_to_float(complex: u32) -> f32 {
+    // Keeping the mantissa in the upper 24 bits makes it signed without needing to sign extend it.
+    let mantissa = (complex & (COMPLEX_MANTISSA_MASK << COMPLEX_MANTISSA_SHIFT)) as i32;
+    let fraction_bits =
+        COMPLEX_RADIX_FRACTION_BITS[((complex >> COMPLEX_RADIX_SHIFT) & 0b11) as usize];
+
+    (mantissa as f64 / (1u64 << (fraction_bits + COMPLEX_MANTISSA_SHIFT)) as f64) as f32
+}
+
+// Encodes a number as a complex value with the given unit, choosing the radix that keeps the most precision.
+// Gives `None` if the number is too large to be stored.
+fn float_to_complex(value: f32, unit: u32) -> Option<u32> {
+    let negative = value < 0.0;
+    let bits = (value.abs() as f64 * (1u64 << 23) as f64).round();
+    if !bits.is_finite() || bits >= (1u64 << 46) as f64 {
+        return None;
+    }
+    let bits = bits as u64;
+
+    // Use the radix with the most bits after the binary point that still fits the integer part of the value.
+    let (radix, shift) = if bits & 0x7FFFFF == 0 {
+        (0, 23) // No fractional part
+    } else if bits >> 23 == 0 {
+        (3, 0)
+    } else if bits >> 31 == 0 {
+        (2, 8)
+    } else if bits >> 39 == 0 {
+        (1, 16)
+    } else {
+        (0, 23)
+    };
+
+    let mut mantissa = (bits >> shift) as u32 & COMPLEX_MANTISSA_MASK;
+    if negative {
+        mantissa = mantissa.wrapping_neg() & COMPLEX_MANTISSA_MASK;
+    }
+
+    Some((mantissa << COMPLEX_MANTISSA_SHIFT) | (radix << COMPLEX_RADIX_SHIFT) | unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encodes `value` as a complex value and decodes it again, checking that the unit is kept.
+    // Returns the decoded value and the radix used to store it.
+    fn round_trip_complex(value: f32, unit: u32) -> (f32, u32) {
+        let complex = float_to_complex(value, unit).unwrap();
+        assert_eq!(complex & COMPLEX_UNIT_MASK, unit);
+        (
+            complex_to_float(complex),
+            (complex >> COMPLEX_RADIX_SHIFT) & 0b11,
+        )
+    }
+
+    #[test]
+    fn fractions_round_trip() {
+        for (value, unit) in [
+            (0.5, COMPLEX_UNIT_FRACTION),          // 50%
+            (0.005, COMPLEX_UNIT_FRACTION_PARENT), // 0.5%p
+            (-0.25, COMPLEX_UNIT_FRACTION),        // -25%
+            (1.5, COMPLEX_UNIT_FRACTION),          // 150%
+        ] {
+            let (decoded, _) = round_trip_complex(value, unit);
+            assert!(
+                (decoded - value).abs() < 1e-6,
+                "{value} was decoded as {decoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn inexact_fraction_round_trips_closely() {
+        // 33.3% cannot be stored exactly in binary, but should be within the precision of the mantissa.
+        let (decoded, radix) = round_trip_complex(0.333, COMPLEX_UNIT_FRACTION);
+        assert_eq!(radix, 3);
+        assert!(
+            (decoded - 0.333).abs() < 1e-6,
+            "0.333 was decoded as {decoded}"
+        );
+    }
+
+    #[test]
+    fn large_fractions_use_a_different_radix() {
+        // Too large for the 23 fractional bits of radix 3, so fewer fractional bits must be used.
+        let (decoded, radix) = round_trip_complex(1000.25, COMPLEX_UNIT_FRACTION);
+        assert_eq!(radix, 1);
+        assert_eq!(decoded, 1000.25);
+
+        let (decoded, radix) = round_trip_complex(-3.75, COMPLEX_UNIT_FRACTION);
+        assert_eq!(radix, 2);
+        assert_eq!(decoded, -3.75);
+
+        // Whole numbers don't need any fractional bits.
+        let (decoded, radix) = round_trip_complex(2.0, COMPLEX_UNIT_FRACTION);
+        assert_eq!(radix, 0);
+        assert_eq!(decoded, 2.0);
+    }
+
+    #[test]
+    fn too_large_fraction_is_rejected() {
+        assert!(float_to_complex(1e8, COMPLEX_UNIT_FRACTION).is_none());
+        assert!(float_to_complex(f32::INFINITY, COMPLEX_UNIT_FRACTION).is_none());
+    }
+}
@@ -21,13 +21,44 @@ use std::io::{Read, Seek, SeekFrom};
 
 use anyhow::{anyhow, Context, Result};
 use byteorder::{ReadBytesExt, LE};
+use log::warn;
+
+use super::{
+    complex_to_float, Attribute, AttributeTypeId, AttributeValue, ChunkType, Event, Namespace,
+    COMPLEX_UNIT_FRACTION, COMPLEX_UNIT_FRACTION_PARENT, COMPLEX_UNIT_MASK, UTF8_FLAG,
+};
+
+/// Iterator over the events of an AXML file, created with [AxmlReader::events].
+pub struct Events<'a, 'r, R: Read + Seek> {
+    reader: &'a mut AxmlReader<'r, R>,
+    // Reading stops after an error, since the reader may no longer be at the start of an event.
+    failed: bool,
+}
+
+impl<R: Read + Seek> Iterator for Events<'_, '_, R> {
+    type Item = Result<Event>;
 
-use super::{Attribute, AttributeTypeId, AttributeValue, ChunkType, Event, Namespace, UTF8_FLAG};
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        match self.reader.read_next_event() {
+            Ok(event) => event.map(Ok),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
 
 pub struct AxmlReader<'r, R: Read + Seek> {
     data: &'r mut R,
 
     string_pool: Vec<String>,
+    // Whether the string pool was encoded as UTF-8 rather than UTF-16
+    utf8: bool,
 
     // Map of resource IDs to resource map indices
     res_map: Vec<u32>,
@@ -36,7 +67,20 @@ pub struct AxmlReader<'r, R: Read + Seek> {
 }
 
 impl<'r, R: Read + Seek> AxmlReader<'r, R> {
+    /// Creates a reader for the AXML document in `data`, failing if any string in the string pool is not validly encoded.
     pub fn new(data: &'r mut R) -> Result<Self> {
+        Self::new_internal(data, false)
+    }
+
+    /// Creates a reader in the same way as [AxmlReader::new], but replaces invalid sequences within the strings of the
+    /// string pool with U+FFFD instead of failing, logging a warning for each invalid string.
+    /// This is useful when only some information needs to be read from the document, e.g. to display the app's details,
+    /// but should not be used when the document will be written back, as the invalid strings will be changed.
+    pub fn new_lenient(data: &'r mut R) -> Result<Self> {
+        Self::new_internal(data, true)
+    }
+
+    fn new_internal(data: &'r mut R, lenient: bool) -> Result<Self> {
         // The initial structure of the AXML document is an XML tag, which contains, in order:
         // The StringPool, then the XmlResourceMap, then all of the tags within a file
 
@@ -49,7 +93,7 @@ impl<'r, R: Read + Seek> AxmlReader<'r, R> {
             return Err(anyhow!("Expected string pool after first XML tag"));
         }
         let post_string_pool = data.read_u32::<LE>()? as u64 + data.stream_position()? - 8;
-        let (string_pool, _was_utf8) = load_string_pool(data).context("Loading string pool")?;
+        let (string_pool, utf8) = load_string_pool(data, lenient).context("Loading string pool")?;
         data.seek(SeekFrom::Start(post_string_pool))?;
 
         let c_type = ChunkType::parse(data.read_u32::<LE>()?);
@@ -71,11 +115,27 @@ impl<'r, R: Read + Seek> AxmlReader<'r, R> {
         Ok(Self {
             data,
             string_pool,
+            utf8,
             res_map,
             end_file_offset: file_size as u64,
         })
     }
 
+    /// Returns true if the string pool of the document is encoded as UTF-8, or false if it is encoded as UTF-16.
+    /// Pass this to [AxmlWriter::set_utf8](super::AxmlWriter::set_utf8) to save the document with the same encoding.
+    pub fn is_utf8(&self) -> bool {
+        self.utf8
+    }
+
+    /// Returns an iterator that reads the remaining events from the file, as with [AxmlReader::read_next_event].
+    /// If an event can't be read, the error is given as an `Err` item, and the iterator then ends.
+    pub fn events(&mut self) -> Events<'_, 'r, R> {
+        Events {
+            reader: self,
+            failed: false,
+        }
+    }
+
     /// Reads the next event from the file.
     pub fn read_next_event(&mut self) -> Result<Option<Event>> {
         if self.data.stream_position()? == self.end_file_offset {
@@ -107,7 +167,11 @@ impl<'r, R: Read + Seek> AxmlReader<'r, R> {
                 Ok(Some(result))
             }
             None => {
-                let mut contents = vec![0u8; length as usize];
+                // The length of the chunk includes the res type and length.
+                let contents_len = length
+                    .checked_sub(8)
+                    .ok_or(anyhow!("Chunk length {length} too small to include header"))?;
+                let mut contents = vec![0u8; contents_len as usize];
                 self.data.read_exact(&mut contents)?;
 
                 Ok(Some(Event::Unknown {
@@ -164,19 +228,31 @@ impl<'r, R: Read + Seek> AxmlReader<'r, R> {
         let attr_ns = self.string_pool.get(self.data.read_u32::<LE>()? as usize);
         let name_and_res_id = self.data.read_u32::<LE>()?;
 
-        let _raw_string_idx = self.data.read_u32::<LE>()?; // Only used for id, class and style attributes
+        // Only used for id, class and style attributes, which are not yet supported, so this isn't kept.
+        let _raw_string_idx = self.data.read_u32::<LE>()?;
         let type_id = self.data.read_u32::<LE>()?;
         let raw_value = self.data.read_u32::<LE>()?;
 
         let value = match AttributeTypeId::parse(type_id) {
             Some(AttributeTypeId::Boolean) => AttributeValue::Boolean(raw_value > 0),
-            Some(AttributeTypeId::Int) | Some(AttributeTypeId::Hex) => {
-                AttributeValue::Integer(raw_value as i32)
-            }
+            Some(AttributeTypeId::Int) => AttributeValue::Integer(raw_value as i32),
+            Some(AttributeTypeId::Hex) => AttributeValue::Hex(raw_value as i32),
             Some(AttributeTypeId::String) => {
                 AttributeValue::String(self.get_pooled_string(raw_value)?.to_string())
             }
             Some(AttributeTypeId::Reference) => AttributeValue::Reference(raw_value),
+            Some(AttributeTypeId::DynamicReference) => AttributeValue::DynamicReference(raw_value),
+            // The value of a null attribute is 1 if it is empty, or 0 if it is undefined
+            Some(AttributeTypeId::Null) if raw_value == 1 => AttributeValue::Empty,
+            Some(AttributeTypeId::Null) => AttributeValue::Null,
+            Some(AttributeTypeId::Fraction) => AttributeValue::Fraction {
+                value: complex_to_float(raw_value),
+                of_parent: match raw_value & COMPLEX_UNIT_MASK {
+                    COMPLEX_UNIT_FRACTION => false,
+                    COMPLEX_UNIT_FRACTION_PARENT => true,
+                    unit => return Err(anyhow!("Fraction unit {unit} was not recognised")),
+                },
+            },
             None => return Err(anyhow!("Attribute type ID {type_id} was not recognised")),
         };
 
@@ -238,7 +314,12 @@ impl<'r, R: Read + Seek> AxmlReader<'r, R> {
     }
 }
 
-fn load_string_pool(data: &mut (impl Read + Seek)) -> Result<(Vec<String>, bool)> {
+// Loads the strings within a string pool, and whether the pool is UTF-8 encoded.
+// If `lenient` is true, invalid sequences in strings are replaced with U+FFFD, rather than failing.
+pub(crate) fn load_string_pool(
+    data: &mut (impl Read + Seek),
+    lenient: bool,
+) -> Result<(Vec<String>, bool)> {
     let begin_chunk = data.stream_position()? - 8; // -8 because of the chunk type/chunk length
     let num_strings = data.read_u32::<LE>()?;
     let _styles_offset = data.read_u32::<LE>()?; // Styles currently implemented
@@ -258,7 +339,7 @@ fn load_string_pool(data: &mut (impl Read + Seek)) -> Result<(Vec<String>, bool)
     }
 
     let mut result: Vec<String> = Vec::with_capacity(num_strings as usize);
-    for offset in string_offsets.into_iter() {
+    for (idx, offset) in string_offsets.into_iter().enumerate() {
         data.seek(SeekFrom::Start(
             begin_chunk + string_data_offset as u64 + offset as u64,
         ))?;
@@ -272,7 +353,14 @@ fn load_string_pool(data: &mut (impl Read + Seek)) -> Result<(Vec<String>, bool)
             let mut buffer = vec![0u8; length];
             data.read_exact(&mut buffer)?;
 
-            result.push(std::str::from_utf8(&buffer)?.into());
+            result.push(match String::from_utf8(buffer) {
+                Ok(string) => string,
+                Err(err) if lenient => {
+                    warn!("String {idx} in string pool was invalid UTF-8 ({err}), replacing invalid sequences");
+                    String::from_utf8_lossy(err.as_bytes()).into_owned()
+                }
+                Err(err) => return Err(err).with_context(|| format!("String {idx} was invalid UTF-8")),
+            });
         } else {
             // Length is in UTF-16 codepoints
             let length = read_utf16_len(data)? as usize;
@@ -281,7 +369,14 @@ fn load_string_pool(data: &mut (impl Read + Seek)) -> Result<(Vec<String>, bool)
                 buffer.push(data.read_u16::<LE>()?);
             }
 
-            result.push(String::from_utf16(&buffer)?.into());
+            result.push(match String::from_utf16(&buffer) {
+                Ok(string) => string,
+                Err(err) if lenient => {
+                    warn!("String {idx} in string pool was invalid UTF-16 ({err}), replacing invalid sequences");
+                    String::from_utf16_lossy(&buffer)
+                }
+                Err(err) => return Err(err).with_context(|| format!("String {idx} was invalid UTF-16")),
+            });
         }
     }
 
@@ -305,8 +400,78 @@ fn read_utf8_len(data: &mut impl Read) -> Result<u16> {
 fn read_utf16_len(data: &mut impl Read) -> Result<u32> {
     let mut length = data.read_u16::<LE>()? as u32;
     if length & 0x8000 != 0 {
-        length = (length & 0x7FFF << 16) | data.read_u16::<LE>()? as u32;
+        length = ((length & 0x7FFF) << 16) | data.read_u16::<LE>()? as u32;
     }
 
     Ok(length)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+    use std::io::Cursor;
+
+    // Builds a string pool chunk containing a single string with the given (possibly invalid) encoded contents.
+    // Returns a cursor positioned just after the chunk type and length, where `load_string_pool` expects to start.
+    fn build_string_pool(contents: &[u8], char_len: u8, utf8: bool) -> Cursor<Vec<u8>> {
+        const HEADER_LEN: u32 = 28;
+
+        let mut chunk = Vec::new();
+        chunk.write_u16::<LE>(0x0001).unwrap(); // Chunk type
+        chunk.write_u16::<LE>(HEADER_LEN as u16).unwrap();
+        chunk.write_u32::<LE>(0).unwrap(); // Chunk length, unused by `load_string_pool`
+        chunk.write_u32::<LE>(1).unwrap(); // Number of strings
+        chunk.write_u32::<LE>(0).unwrap(); // Number of styles
+        chunk
+            .write_u32::<LE>(if utf8 { UTF8_FLAG } else { 0 })
+            .unwrap();
+        chunk.write_u32::<LE>(HEADER_LEN + 4).unwrap(); // String data offset
+        chunk.write_u32::<LE>(0).unwrap(); // Style data offset
+        chunk.write_u32::<LE>(0).unwrap(); // Offset of the string
+
+        if utf8 {
+            chunk.push(char_len);
+            chunk.push(contents.len() as u8);
+            chunk.extend_from_slice(contents);
+            chunk.push(0);
+        } else {
+            chunk.write_u16::<LE>(char_len as u16).unwrap();
+            chunk.extend_from_slice(contents);
+            chunk.write_u16::<LE>(0).unwrap();
+        }
+
+        let mut cursor = Cursor::new(chunk);
+        cursor.set_position(8);
+        cursor
+    }
+
+    #[test]
+    fn valid_string_pool_is_loaded() {
+        let (strings, utf8) =
+            load_string_pool(&mut build_string_pool(b"valid", 5, true), false).unwrap();
+        assert_eq!(strings, ["valid"]);
+        assert!(utf8);
+    }
+
+    #[test]
+    fn invalid_utf8_fails_unless_lenient() {
+        assert!(load_string_pool(&mut build_string_pool(b"a\xFFb", 3, true), false).is_err());
+
+        let (strings, _) =
+            load_string_pool(&mut build_string_pool(b"a\xFFb", 3, true), true).unwrap();
+        assert_eq!(strings, ["a\u{FFFD}b"]);
+    }
+
+    #[test]
+    fn invalid_utf16_fails_unless_lenient() {
+        // `a`, followed by an unpaired surrogate.
+        let contents = [0x61, 0x00, 0x00, 0xD8];
+        assert!(load_string_pool(&mut build_string_pool(&contents, 2, false), false).is_err());
+
+        let (strings, utf8) =
+            load_string_pool(&mut build_string_pool(&contents, 2, false), true).unwrap();
+        assert_eq!(strings, ["a\u{FFFD}"]);
+        assert!(!utf8);
+    }
+}
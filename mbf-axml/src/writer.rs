@@ -25,11 +25,16 @@ use std::{
 
 use byteorder::{WriteBytesExt, BE, LE};
 
-use super::{Attribute, AttributeTypeId, AttributeValue, ChunkType, Event, Namespace, UTF8_FLAG};
+use super::{
+    float_to_complex, Attribute, AttributeTypeId, AttributeValue, ChunkType, Event, Namespace,
+    COMPLEX_UNIT_FRACTION, COMPLEX_UNIT_FRACTION_PARENT, UTF8_FLAG,
+};
 
 pub struct AxmlWriter<'w, W: Write> {
     data: &'w mut W,
 
+    // The string pool is written in the order of `linear_string_pool`, which is the order strings are first used in,
+    // so the output does not depend on `HashMap` iteration order.
     string_pool: HashMap<String, u32>,
     linear_string_pool: Vec<String>,
 
@@ -38,6 +43,9 @@ pub struct AxmlWriter<'w, W: Write> {
 
     events: Vec<Event>,
     main_contents: Cursor<Vec<u8>>,
+
+    // Whether the string pool is saved as UTF-8 rather than UTF-16
+    utf8: bool,
 }
 
 impl<'w, W: Write> AxmlWriter<'w, W> {
@@ -50,9 +58,15 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
             linear_res_map: Vec::new(),
             main_contents: Cursor::new(Vec::new()),
             events: Vec::new(),
+            utf8: true,
         }
     }
 
+    /// Sets whether the string pool is saved as UTF-8 (the default) or UTF-16.
+    pub fn set_utf8(&mut self, utf8: bool) {
+        self.utf8 = utf8;
+    }
+
     pub fn write_event(&mut self, event: Event) {
         match &event {
             Event::StartElement { attributes, .. } => self.prepare_res_map(&attributes),
@@ -79,7 +93,7 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
 
         // The string pool must be padded to a multiple of 4 bytes
         let str_pool_len = self.get_total_str_pool_len() as u32;
-        let str_pool_padding = 4 - str_pool_len % 4;
+        let str_pool_padding = (4 - str_pool_len % 4) % 4;
         let res_pool_len = self.linear_res_map.len() as u32 * 4;
 
         // The "XML" chunk is the parent chunk of the entire file
@@ -133,7 +147,8 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
         match event {
             Event::Unknown { contents, res_type } => {
                 self.main_contents.write_u32::<LE>(res_type)?;
-                self.main_contents.write_u32::<LE>(contents.len() as u32)?;
+                self.main_contents
+                    .write_u32::<LE>(contents.len() as u32 + 8)?;
                 self.main_contents.write_all(&contents)?;
             }
             Event::StartNamespace(ns) => self.write_start_namespace(ns)?,
@@ -210,7 +225,13 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
 
         // Attributes must be sorted in the order of increasing resource ID
         // Otherwise, certain attributes are not properly detected by android
-        attributes.sort_by_key(|attr| attr.resource_id);
+        // Ties (i.e. attributes without a resource ID) are broken by name so that the output is reproducible.
+        attributes.sort_by(|a, b| {
+            a.resource_id
+                .cmp(&b.resource_id)
+                .then_with(|| a.namespace.cmp(&b.namespace))
+                .then_with(|| a.name.cmp(&b.name))
+        });
 
         for attribute in attributes {
             self.write_attribute(attribute)?;
@@ -280,7 +301,23 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
             AttributeValue::Boolean(true) => (-1, -1, AttributeTypeId::Boolean),
             AttributeValue::Boolean(false) => (0, -1, AttributeTypeId::Boolean),
             AttributeValue::Integer(i) => (i, -1, AttributeTypeId::Int),
+            AttributeValue::Hex(i) => (i, -1, AttributeTypeId::Hex),
             AttributeValue::Reference(link) => (link as i32, -1, AttributeTypeId::Reference),
+            AttributeValue::DynamicReference(link) => {
+                (link as i32, -1, AttributeTypeId::DynamicReference)
+            }
+            AttributeValue::Null => (0, -1, AttributeTypeId::Null),
+            AttributeValue::Empty => (1, -1, AttributeTypeId::Null),
+            AttributeValue::Fraction { value, of_parent } => {
+                let unit = if of_parent {
+                    COMPLEX_UNIT_FRACTION_PARENT
+                } else {
+                    COMPLEX_UNIT_FRACTION
+                };
+                let complex = float_to_complex(value, unit)
+                    .ok_or_else(|| anyhow!("Fraction {value} is too large to be stored"))?;
+                (complex as i32, -1, AttributeTypeId::Fraction)
+            }
             AttributeValue::String(str_value) => {
                 let str_idx = self.get_string_idx(str_value)?;
                 (str_idx as i32, str_idx as i32, AttributeTypeId::String)
@@ -346,7 +383,7 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
     // Gets the total length of the string pool chunk, not including the chunk header/chunk length bytes
     fn get_total_str_pool_len(&self) -> usize {
         let mut strings_len = 0;
-        for str in self.string_pool.keys() {
+        for str in self.linear_string_pool.iter() {
             strings_len += self.get_pooled_str_len(str);
         }
 
@@ -355,12 +392,18 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
 
     // Calculates the length of the given string within the string pool
     fn get_pooled_str_len(&self, str: &str) -> usize {
-        // Each string is prefixed with an extra 0 byte.
-        // The purpose of this byte is unknown, I have not found an implementation that uses it
-        1 + if str.len() > 0x7F { 2 } else { 1 } + str.len()
+        if self.utf8 {
+            // Each string is prefixed with an extra 0 byte.
+            // The purpose of this byte is unknown, I have not found an implementation that uses it
+            1 + if str.len() > 0x7F { 2 } else { 1 } + str.len()
+        } else {
+            // Length varint, then the UTF-16 code units, then a null terminator
+            let units = str.encode_utf16().count();
+            (if units > 0x7FFF { 4 } else { 2 }) + units * 2 + 2
+        }
     }
 
-    // Saves the AXML string pool, as UTF-8
+    // Saves the AXML string pool, as either UTF-8 or UTF-16
     fn write_string_pool(&mut self) -> Result<()> {
         self.data.write_u32::<LE>(
             self.string_pool
@@ -369,7 +412,8 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
                 .context("String pool length too large")?,
         )?;
         self.data.write_u32::<LE>(0)?; // Style count, not implemented
-        self.data.write_u32::<LE>(UTF8_FLAG)?; // Strings are saved as UTF-8
+        self.data
+            .write_u32::<LE>(if self.utf8 { UTF8_FLAG } else { 0 })?; // UTF-16 is used if the flag is not set
 
         // Offset from the start of the chunk to the first byte of the first string
         let strings_offset = 7 * 4 + self.string_pool.len() * 4;
@@ -390,9 +434,17 @@ impl<'w, W: Write> AxmlWriter<'w, W> {
 
         // Now write each string within the pool
         for str in self.linear_string_pool.iter() {
-            self.data.write_u8(0)?; // TODO: Figure out what this byte is for
-            write_utf8_len(self.data, str.len())?;
-            self.data.write_all(str.as_bytes())?;
+            if self.utf8 {
+                self.data.write_u8(0)?; // TODO: Figure out what this byte is for
+                write_utf8_len(self.data, str.len())?;
+                self.data.write_all(str.as_bytes())?;
+            } else {
+                write_utf16_len(self.data, str.encode_utf16().count())?;
+                for unit in str.encode_utf16() {
+                    self.data.write_u16::<LE>(unit)?;
+                }
+                self.data.write_u16::<LE>(0)?; // Null terminator
+            }
         }
 
         Ok(())
@@ -414,3 +466,88 @@ fn write_utf8_len(data: &mut impl Write, len: usize) -> Result<()> {
 
     Ok(())
 }
+
+// Writes the given length as the varint used to represent the length of a UTF16 string in AXML
+fn write_utf16_len(data: &mut impl Write, len: usize) -> Result<()> {
+    if len > 0x7FFFFFFF {
+        return Err(anyhow!(
+            "String length is too long to save as UTF-16 {}",
+            len
+        ));
+    } else if len > 0x7FFF {
+        data.write_u16::<LE>(((len >> 16) | 0x8000) as u16)?;
+        data.write_u16::<LE>((len & 0xFFFF) as u16)?;
+    } else {
+        data.write_u16::<LE>(len as u16)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AxmlReader;
+    use byteorder::{ReadBytesExt, LE};
+
+    fn element_events(name: &str) -> Vec<Event> {
+        vec![
+            Event::StartElement {
+                attributes: Vec::new(),
+                name: name.to_string(),
+                namespace: None,
+                line_num: 1,
+            },
+            Event::EndElement {
+                line_num: 1,
+                namespace: None,
+                name: name.to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn string_pool_is_padded_to_4_bytes_only_when_needed() {
+        let mut tested_aligned = false;
+        for name_len in 1..=8 {
+            let name = "a".repeat(name_len);
+
+            // Find the length of the string pool before padding.
+            let mut unused = Vec::new();
+            let mut unpadded_writer = AxmlWriter::new(&mut unused);
+            for event in element_events(&name) {
+                unpadded_writer.write_event_internal(event).unwrap();
+            }
+            let unpadded_len = unpadded_writer.get_total_str_pool_len() as u32;
+            tested_aligned |= unpadded_len.is_multiple_of(4);
+
+            let mut output = Vec::new();
+            let mut writer = AxmlWriter::new(&mut output);
+            for event in element_events(&name) {
+                writer.write_event(event);
+            }
+            writer.finish().unwrap();
+
+            // The string pool chunk comes after the 8 byte XML chunk header.
+            let mut cursor = Cursor::new(&output);
+            cursor.set_position(8);
+            assert_eq!(
+                cursor.read_u32::<LE>().unwrap(),
+                ChunkType::StringPool.save()
+            );
+            let pool_chunk_len = cursor.read_u32::<LE>().unwrap();
+            assert_eq!(pool_chunk_len - 8, unpadded_len.next_multiple_of(4));
+
+            let mut cursor = Cursor::new(output);
+            let mut reader = AxmlReader::new(&mut cursor).unwrap();
+            match reader.read_next_event().unwrap() {
+                Some(Event::StartElement {
+                    name: read_name, ..
+                }) => assert_eq!(read_name, name),
+                _ => panic!("Expected the element to be read back"),
+            }
+        }
+
+        assert!(tested_aligned, "No string pool was already aligned");
+    }
+}
@@ -0,0 +1,47 @@
+//! Checks that an APK is vanilla, i.e. has not been modded, before it is used as the source for a diff or merged APK.
+
+use std::{
+    io::{Read, Seek},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use mbf_zip::ZipFile;
+use serde::{Deserialize, Serialize};
+
+/// The path of the tag added to the APK when it is patched, containing details of the modloader.
+pub const MOD_TAG_PATH: &str = "modded.json";
+
+/// The contents of the mod tag, in the format shared by the various patchers for Beat Saber.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ModTag {
+    pub patcher_name: String,
+    pub patcher_version: Option<String>,
+    pub modloader_name: String,
+    pub modloader_version: Option<String>,
+    /// The SHA-256 hash of the libmain.so added to the APK, as lowercase hex.
+    /// This is specific to MBF, so is absent from APKs patched by other patchers or older versions of MBF.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lib_main_sha256: Option<String>,
+}
+
+/// Returns true if the given APK has been modded.
+/// This is the case if it contains a mod tag, or any other file with `modded` in its name, as used by older patchers.
+pub fn is_modded<T: Read + Seek>(apk: &ZipFile<T>) -> bool {
+    apk.contains_file(MOD_TAG_PATH) || apk.iter_entry_names().any(|entry| entry.contains("modded"))
+}
+
+/// Gives an error if the APK at the given path has been modded.
+pub fn verify_not_modded(apk_path: impl AsRef<Path>) -> Result<()> {
+    let apk_path = apk_path.as_ref();
+    let apk = ZipFile::open_readonly(apk_path).context("APK was not valid ZIP archive")?;
+
+    if is_modded(&apk) {
+        Err(anyhow!(
+            "APK {apk_path:?} has been modded. A vanilla APK is required; uninstall and reinstall the game to get one"
+        ))
+    } else {
+        Ok(())
+    }
+}
@@ -8,15 +8,23 @@ const REQUEST_TIMEOUT_WRITE_SECS: u64 = 20;
 
 /// The ureq agent used by MBF for downloads
 static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+/// The same as [AGENT], but does not follow redirects.
+static NO_REDIRECT_AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+
+fn build_agent() -> ureq::AgentBuilder {
+    ureq::AgentBuilder::new()
+        .timeout_read(Duration::from_secs(REQUEST_TIMEOUT_READ_SECS))
+        .timeout_write(Duration::from_secs(REQUEST_TIMEOUT_WRITE_SECS))
+        .https_only(true)
+        .try_proxy_from_env(true)
+        .user_agent(format!("mbf-agent/{}", env!("CARGO_PKG_VERSION")).as_str())
+}
 
 pub fn get_agent() -> &'static ureq::Agent {
-    AGENT.get_or_init(|| {
-        ureq::AgentBuilder::new()
-            .timeout_read(Duration::from_secs(REQUEST_TIMEOUT_READ_SECS))
-            .timeout_write(Duration::from_secs(REQUEST_TIMEOUT_WRITE_SECS))
-            .https_only(true)
-            .try_proxy_from_env(true)
-            .user_agent(format!("mbf-agent/{}", env!("CARGO_PKG_VERSION")).as_str())
-            .build()
-    })
+    AGENT.get_or_init(|| build_agent().build())
+}
+
+/// Gets an agent that returns redirect responses rather than following them, for callers that follow redirects themselves.
+pub fn get_no_redirect_agent() -> &'static ureq::Agent {
+    NO_REDIRECT_AGENT.get_or_init(|| build_agent().redirects(0).build())
 }
@@ -1,10 +1,11 @@
 use crate::models::Diff;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use crc::{Algorithm, Crc};
 use log::info;
+use sha2::{Digest, Sha256};
 use std::{
     fs::OpenOptions,
-    io::{BufReader, BufWriter, Read},
+    io::{BufReader, BufWriter, Read, Write},
     path::Path,
 };
 
@@ -52,13 +53,121 @@ pub fn generate_diff(
     let _diff_bytes = qbsdiff::Bsdiff::new(&from_bytes, &to_bytes)
         .compression_level(6)
         .compare(&mut output)?;
+    output.flush()?;
+    drop(output);
 
-    Ok(Diff {
-        diff_name: get_file_name(output_path),
+    let diff_bytes = read_to_vec(&output_path)?;
+    info!("Verifying diff");
+    if let Err(err) = verify_diff(&from_bytes, &to_bytes, &diff_bytes) {
+        // Make sure the broken diff can't be uploaded by accident.
+        std::fs::remove_file(&output_path)?;
+        return Err(err);
+    }
+
+    Ok(describe_diff(
+        from_file,
+        to_file,
+        output_path,
+        from_crc,
+        to_crc,
+        &to_bytes,
+        &diff_bytes,
+    ))
+}
+
+// Creates the index entry for an existing diff file at `diff_path` that patches `from_file` into `to_file`.
+// The diff is checked to reproduce `to_file` exactly, so that a diff can't be added to the index with the wrong files.
+pub fn describe_existing_diff(
+    from_file: impl AsRef<Path>,
+    to_file: impl AsRef<Path>,
+    diff_path: impl AsRef<Path>,
+) -> Result<Diff> {
+    let from_bytes = read_to_vec(&from_file)?;
+    let to_bytes = read_to_vec(&to_file)?;
+    let diff_bytes = read_to_vec(&diff_path)?;
+
+    info!("Verifying diff");
+    verify_diff(&from_bytes, &to_bytes, &diff_bytes)?;
+
+    info!("Generating checksums");
+    let from_crc = mbf_zip::crc_bytes(&from_bytes);
+    let to_crc = mbf_zip::crc_bytes(&to_bytes);
+
+    Ok(describe_diff(
+        from_file,
+        to_file,
+        diff_path,
+        from_crc,
+        to_crc,
+        &to_bytes,
+        &diff_bytes,
+    ))
+}
+
+// Creates the index entry for a verified diff.
+fn describe_diff(
+    from_file: impl AsRef<Path>,
+    to_file: impl AsRef<Path>,
+    diff_path: impl AsRef<Path>,
+    from_crc: u32,
+    to_crc: u32,
+    to_bytes: &[u8],
+    diff_bytes: &[u8],
+) -> Diff {
+    // Allows the agent to detect if the diff was corrupted while downloading.
+    info!("Hashing diff");
+    let diff_sha256 = Sha256::digest(diff_bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    Diff {
+        diff_name: get_file_name(diff_path),
+        diff_sha256: Some(diff_sha256),
         file_name: get_file_name(from_file),
         file_crc: from_crc,
         output_file_name: get_file_name(to_file),
         output_crc: to_crc,
         output_size: to_bytes.len(),
-    })
+    }
+}
+
+// Applies the diff at `diff_path` to `input_file`.
+// Outputs the patched file to `output_path`
+pub fn apply_diff(
+    input_file: impl AsRef<Path>,
+    diff_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+) -> Result<()> {
+    let input_bytes = read_to_vec(input_file)?;
+    let diff_bytes = read_to_vec(diff_path)?;
+
+    info!("Applying diff");
+    let output_bytes = patch_bytes(&input_bytes, &diff_bytes)?;
+    std::fs::write(output_path, output_bytes)?;
+
+    Ok(())
+}
+
+// Applies the diff in `diff_bytes` to `from_bytes`, returning the patched file.
+fn patch_bytes(from_bytes: &[u8], diff_bytes: &[u8]) -> Result<Vec<u8>> {
+    let patch = qbsdiff::Bspatch::new(diff_bytes).context("Diff was invalid")?;
+    let mut patched = Vec::with_capacity(patch.hint_target_size() as usize);
+    patch
+        .apply(from_bytes, &mut patched)
+        .context("Applying diff")?;
+
+    Ok(patched)
+}
+
+// Applies `diff_bytes` to `from_bytes` and checks that the result is identical to `to_bytes`.
+fn verify_diff(from_bytes: &[u8], to_bytes: &[u8], diff_bytes: &[u8]) -> Result<()> {
+    let patched = patch_bytes(from_bytes, diff_bytes).context("Applying generated diff")?;
+    if Sha256::digest(&patched) != Sha256::digest(to_bytes) {
+        return Err(anyhow!(
+            "Applying the generated diff did not reproduce the target file"
+        ));
+    }
+
+    Ok(())
 }
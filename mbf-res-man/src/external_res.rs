@@ -1,5 +1,6 @@
 //! Collection of types used to read the BMBF resources repository to fetch core mod information.
 use crate::{
+    mirrors::Mirrors,
     models::{Diff, DiffIndex, ModRepo, VersionedCoreMods},
     res_cache::{JsonPullError, ResCache},
 };
@@ -9,6 +10,29 @@ use std::collections::HashMap;
 
 pub type CoreModIndex = HashMap<String, VersionedCoreMods>;
 
+/// Mirrors for the locations resources are fetched from, as pairs of the primary base URL and mirror base URL.
+/// Mirrors are tried in order if the primary location refuses the request or cannot be reached.
+const MIRRORS: &[(&str, &str)] = &[
+    (
+        "https://raw.githubusercontent.com/QuestPackageManager/bs-coremods/main/",
+        "https://cdn.jsdelivr.net/gh/QuestPackageManager/bs-coremods@main/",
+    ),
+    (
+        "https://raw.githubusercontent.com/Lauriethefish/QuestUnstrippedUnity/main/",
+        "https://cdn.jsdelivr.net/gh/Lauriethefish/QuestUnstrippedUnity@main/",
+    ),
+];
+
+/// Gets the default mirrors for the resources used by MBF.
+pub fn get_mirrors() -> Mirrors {
+    let mut mirrors = Mirrors::default();
+    for (primary, mirror) in MIRRORS {
+        mirrors.add(primary, mirror);
+    }
+
+    mirrors
+}
+
 const CORE_MODS_URL: &str =
     "https://raw.githubusercontent.com/QuestPackageManager/bs-coremods/main/core_mods.json";
 
@@ -93,6 +117,13 @@ pub fn get_manifest_axml(agent: &ureq::Agent, version: String) -> Result<Vec<u8>
 const MOD_REPO_URL: &str = "https://mods.bsquest.xyz/mods.json";
 
 /// Gets the mods repository used to search for dependency download URLs.
-pub fn get_mod_repo(res_cache: &ResCache) -> Result<ModRepo> {
-    Ok(res_cache.get_json_cached(MOD_REPO_URL, "mod_repo.json")?)
+/// If `override_mod_repo_url` is `Some`, the repository is fetched from this URL instead of the default.
+pub fn get_mod_repo(res_cache: &ResCache, override_mod_repo_url: Option<&str>) -> Result<ModRepo> {
+    Ok(match override_mod_repo_url {
+        Some(url) => {
+            info!("Using overridden mod repo URL");
+            res_cache.get_json_cached(url, "mod_repo_override.json")?
+        }
+        None => res_cache.get_json_cached(MOD_REPO_URL, "mod_repo.json")?,
+    })
 }
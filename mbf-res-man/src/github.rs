@@ -0,0 +1,102 @@
+//! Access to the GitHub REST API, used to find the files attached to GitHub releases.
+
+use anyhow::{anyhow, Context, Result};
+
+pub const API_ROOT: &str = "https://api.github.com";
+
+#[derive(Clone, Debug)]
+pub struct Repo {
+    pub owner: String,
+    pub repo: String,
+}
+
+/// A release to find the files of, identified from the URL of its page on GitHub.
+#[derive(Clone, Debug)]
+pub struct ReleasePage {
+    pub repo: Repo,
+    /// The tag of the release, or `None` for the latest release.
+    pub tag: Option<String>,
+}
+
+/// A file attached to a release, which can be downloaded without authentication.
+#[derive(Clone, Debug)]
+pub struct ReleaseFile {
+    pub name: String,
+    pub download_url: String,
+}
+
+/// Sets the headers needed for a request to the GitHub REST API.
+pub fn set_api_headers(req: ureq::Request) -> ureq::Request {
+    req.set("Accept", "application/vnd.github+json")
+        .set("X-GitHub-Api-Version", "2022-11-28")
+}
+
+/// Parses the URL of a release page on GitHub, i.e. `https://github.com/<owner>/<repo>/releases` or
+/// `.../releases/latest` for the latest release, or `.../releases/tag/<tag>` for a particular release.
+/// Gives `None` if the URL is not a release page, e.g. if it is a direct link to a file attached to a release.
+pub fn parse_release_url(url: &str) -> Option<ReleasePage> {
+    let url = url.split(['?', '#']).next()?;
+    let path = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let path = path.strip_prefix("www.").unwrap_or(path);
+
+    let segments: Vec<&str> = path
+        .strip_prefix("github.com/")?
+        .trim_end_matches('/')
+        .split('/')
+        .collect();
+    let tag = match segments.as_slice() {
+        [_, _, "releases"] | [_, _, "releases", "latest"] => None,
+        [_, _, "releases", "tag", tag] if !tag.is_empty() => Some(tag.to_string()),
+        _ => return None,
+    };
+    if segments[0].is_empty() || segments[1].is_empty() {
+        return None;
+    }
+
+    Some(ReleasePage {
+        repo: Repo {
+            owner: segments[0].to_string(),
+            repo: segments[1].to_string(),
+        },
+        tag,
+    })
+}
+
+/// Gets the files attached to the given release, using the GitHub REST API.
+pub fn get_release_files(agent: &ureq::Agent, release: &ReleasePage) -> Result<Vec<ReleaseFile>> {
+    let Repo { owner, repo } = &release.repo;
+    let req_path = match &release.tag {
+        Some(tag) => format!("{API_ROOT}/repos/{owner}/{repo}/releases/tags/{tag}"),
+        None => format!("{API_ROOT}/repos/{owner}/{repo}/releases/latest"),
+    };
+
+    let resp = set_api_headers(agent.get(&req_path))
+        .call()
+        .with_context(|| format!("Getting release of {owner}/{repo} from GitHub"))?;
+    let document: serde_json::Value =
+        serde_json::from_reader(resp.into_reader()).context("Release was invalid JSON")?;
+
+    let assets = document
+        .get("assets")
+        .and_then(|assets| assets.as_array())
+        .ok_or(anyhow!("Release had no list of assets"))?;
+    assets
+        .iter()
+        .map(|asset| {
+            Ok(ReleaseFile {
+                name: asset
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .ok_or(anyhow!("Release asset had no name"))?
+                    .to_string(),
+                download_url: asset
+                    .get("browser_download_url")
+                    .and_then(|url| url.as_str())
+                    .ok_or(anyhow!("Release asset had no download URL"))?
+                    .to_string(),
+            })
+        })
+        .collect()
+}
@@ -1,4 +1,7 @@
+pub mod apk_check;
 pub mod default_agent;
 pub mod external_res;
+pub mod github;
+pub mod mirrors;
 pub mod models;
 pub mod res_cache;
@@ -7,8 +7,9 @@ use clap::{arg, command, Parser, Subcommand};
 use const_format::formatcp;
 use hash_cache::HashCache;
 use log::{info, warn};
-use mbf_zip::ZipFile;
-use models::{DiffIndex, VersionDiffs};
+use mbf_axml::{self as axml, AttributeValue, AxmlReader};
+use mbf_zip::{signing, ZipFile};
+use models::{get_obb_id, DiffIndex, VersionDiffs};
 use oculus_db::{get_obb_binary, AndroidBinary};
 use release_editor::Repo;
 use res_cache::ResCache;
@@ -18,17 +19,20 @@ use std::{
     collections::HashMap,
     ffi::OsStr,
     fs::{FileType, OpenOptions},
-    io::Write,
+    io::{Read, Seek, Write},
     path::{Path, PathBuf},
     str::FromStr,
 };
 use version_grabber::SemiSemVer;
 
 mod adb;
+mod apk_check;
 mod default_agent;
 mod diff_builder;
 mod external_res;
+mod github;
 mod hash_cache;
+mod mirrors;
 mod models;
 mod oculus_db;
 mod release_editor;
@@ -57,7 +61,6 @@ fn get_res_cache() -> Result<ResCache<'static>> {
 // Downloads the installed version of Beat Saber to BS_VERSIONS_PATH
 fn download_installed_bs() -> Result<String> {
     info!("Downloading the currently installed copy of Beat Saber");
-    warn!("Check that your APK is not modded before running this command");
 
     let bs_version = match adb::get_package_version(APK_ID)? {
         Some(ver) => ver,
@@ -71,6 +74,11 @@ fn download_installed_bs() -> Result<String> {
     let apk_output_path = version_path.join(format!("{APK_ID}.apk"));
 
     adb::download_apk(APK_ID, &apk_output_path.to_string_lossy()).context("Downloading APK")?;
+    // A modded APK would be useless for generating diffs, so don't keep it.
+    if let Err(err) = apk_check::verify_not_modded(&apk_output_path) {
+        std::fs::remove_dir_all(&version_path).context("Removing downloaded modded APK")?;
+        return Err(err);
+    }
 
     info!("Downloading OBB file(s)");
 
@@ -128,24 +136,21 @@ fn install_bs_version(bs_version: &str, fuzzy_lookup: bool) -> Result<()> {
     info!("Installing BS {bs_version}");
     let version_path = get_bs_ver_path(bs_version, fuzzy_lookup)?;
 
-    let (apk_path, maybe_obb_path) =
+    let (apk_path, obb_paths) =
         get_obb_and_apk_path(bs_version, fuzzy_lookup).context("Getting APK and OBB path")?;
 
     info!("Installing APK");
     adb::install_apk(&apk_path.to_string_lossy())?;
 
-    if let Some(obb_path) = maybe_obb_path {
-        let obb_file_name = obb_path
-            .file_name()
-            .ok_or(anyhow!("Obb had no filename"))?
-            .to_string_lossy()
-            .to_string();
+    if obb_paths.is_empty() {
+        info!("No OBB found to copy");
+    }
+    for obb_path in obb_paths {
+        let obb_file_name = get_file_name(&obb_path)?;
         info!("Copying {obb_file_name}");
 
         let obb_dest = format!("/sdcard/Android/obb/{APK_ID}/{obb_file_name}");
         adb::push_file(&obb_path.to_string_lossy(), &obb_dest)?;
-    } else {
-        info!("No OBB found to copy");
     }
 
     Ok(())
@@ -179,9 +184,9 @@ fn save_diff_index(index: DiffIndex) -> Result<()> {
     Ok(())
 }
 
-// Gets the path to the OBB and APK for the given Beat Saber version.
-// Will error if multiple OBBs exist, or if the version is not stored locally.
-fn get_obb_and_apk_path(version: &str, fuzzy_lookup: bool) -> Result<(PathBuf, Option<PathBuf>)> {
+// Gets the path to the APK and OBBs for the given Beat Saber version, with the OBBs sorted by filename.
+// Will error if the version is not stored locally.
+fn get_obb_and_apk_path(version: &str, fuzzy_lookup: bool) -> Result<(PathBuf, Vec<PathBuf>)> {
     let version_path = get_bs_ver_path(version, fuzzy_lookup)?;
 
     // APK is always in the same place
@@ -190,26 +195,27 @@ fn get_obb_and_apk_path(version: &str, fuzzy_lookup: bool) -> Result<(PathBuf, O
         return Err(anyhow!("APK did not exist"));
     }
 
-    // Iterate the folder and find the .OBB file.
-    // NB: Multiple OBBs are not yet supported. If Beat Saber begins using multiple OBBs,
-    // then we will have to make a decision about how to do the downgrading: e.g. concatenating the OBBs and downgrading this to the previous version.
-
-    // We will also have to figure out how to associate the OBBs containing the same content with each other
-    // (we cannot JUST do this by filename as the filename of each OBB varies with version, so it'll take some figuring out)
-    let mut obb_path: Option<PathBuf> = None;
+    // Iterate the folder and find the .OBB files.
+    let mut obb_paths = Vec::new();
     for file_result in std::fs::read_dir(version_path)? {
         let file_path = file_result?.path();
 
-        // Find the file with OBB extension
         if file_path.extension() == Some(OsStr::new("obb")) {
-            match obb_path {
-                Some(_) => return Err(anyhow!("Multiple obb files existed for {version}. A decision will need to be made about how diffs are handled")),
-                None => obb_path = Some(file_path)
-            }
+            obb_paths.push(file_path);
         }
     }
+    obb_paths.sort();
+
+    Ok((apk_path, obb_paths))
+}
 
-    Ok((apk_path, obb_path))
+// Gets the filename of the given path as a string.
+fn get_file_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .ok_or(anyhow!("{path:?} had no filename"))?
+        .to_string_lossy()
+        .to_string())
 }
 
 // Removes a file if it already exists.
@@ -279,35 +285,78 @@ fn add_diff_to_index(
     )
     .context("Verifying removal of existing diff")?;
 
-    let (from_apk, from_obb) = get_obb_and_apk_path(&from_version, false)
+    let (from_apk, from_obbs) = get_obb_and_apk_path(&from_version, false)
         .context("Getting APK/OBB path for original version")?;
-    let (to_apk, to_obb) = get_obb_and_apk_path(&to_version, false)
+    let (to_apk, to_obbs) = get_obb_and_apk_path(&to_version, false)
         .context("Getting APK/OBB path for downgraded version")?;
 
-    if from_obb == None || to_obb == None {
+    if from_obbs.is_empty() || to_obbs.is_empty() {
         return Err(anyhow!("One of the Beat Saber versions had no OBB! Obb-less diffs aren't supported by mbf-res-man"));
     }
 
+    apk_check::verify_not_modded(&from_apk).context("Checking original APK")?;
+    apk_check::verify_not_modded(&to_apk).context("Checking downgraded APK")?;
+
+    let obb_pairs = pair_obbs(&from_obbs, &to_obbs)?;
+
     let apk_diff_name = format!("bs-apk-{from_version}-to-{to_version}.apk.diff");
-    let obb_diff_name = format!("bs-obb-{from_version}-to-{to_version}.obb.diff");
-
-    info!("Generating diff for APK");
-    let apk_diff =
-        diff_builder::generate_diff(from_apk, to_apk, Path::new(DIFFS_PATH).join(apk_diff_name))
-            .context("Generating diff for APK")?;
-
-    info!("Generating diff for OBB");
-    let obb_diff = diff_builder::generate_diff(
-        from_obb.unwrap(),
-        to_obb.unwrap(),
-        Path::new(DIFFS_PATH).join(obb_diff_name),
-    )
-    .context("Generating diff for OBB")?;
+
+    let mut obb_diff_names = Vec::new();
+    for (_, to_obb) in &obb_pairs {
+        // Diffs of the only OBB keep the name used before multiple OBBs were supported.
+        obb_diff_names.push(if obb_pairs.len() == 1 {
+            format!("bs-obb-{from_version}-to-{to_version}.obb.diff")
+        } else {
+            let obb_id = get_obb_id(&get_file_name(to_obb)?);
+            format!("bs-obb-{from_version}-to-{to_version}-{obb_id}.diff")
+        });
+    }
+
+    // The diffs are independent of each other, so are generated on separate threads to save time.
+    info!("Generating diffs for APK and OBB(s)");
+    let (apk_diff, obb_diffs) = std::thread::scope(|scope| {
+        let apk_thread = scope.spawn(|| {
+            diff_builder::generate_diff(
+                &from_apk,
+                &to_apk,
+                Path::new(DIFFS_PATH).join(&apk_diff_name),
+            )
+            .context("Generating diff for APK")
+        });
+
+        let obb_threads: Vec<_> = obb_pairs
+            .iter()
+            .zip(&obb_diff_names)
+            .map(|((from_obb, to_obb), obb_diff_name)| {
+                scope.spawn(move || {
+                    diff_builder::generate_diff(
+                        from_obb,
+                        to_obb,
+                        Path::new(DIFFS_PATH).join(obb_diff_name),
+                    )
+                    .with_context(|| format!("Generating diff for OBB {from_obb:?}"))
+                })
+            })
+            .collect();
+
+        let apk_diff = apk_thread
+            .join()
+            .map_err(|_| anyhow!("APK diff thread panicked"))?;
+        let obb_diffs: Result<Vec<_>> = obb_threads
+            .into_iter()
+            .map(|thread| {
+                thread
+                    .join()
+                    .map_err(|_| anyhow!("OBB diff thread panicked"))?
+            })
+            .collect();
+        Ok::<_, anyhow::Error>((apk_diff?, obb_diffs?))
+    })?;
 
     info!("Adding to diff index");
     current_diff_idx.push(VersionDiffs {
         apk_diff,
-        obb_diffs: vec![obb_diff],
+        obb_diffs,
         from_version,
         to_version,
     });
@@ -316,6 +365,255 @@ fn add_diff_to_index(
     Ok(())
 }
 
+// The diff files found for one pair of versions when rebuilding the diff index.
+#[derive(Default)]
+struct DiffFiles {
+    apk_diff: Option<String>,
+    // The name of each OBB diff, with the ID of the OBB it produces, or None for the diff of the only OBB.
+    obb_diffs: Vec<(String, Option<String>)>,
+}
+
+// The kind of diff a file in DIFFS_PATH contains, based on the names given by [add_diff_to_index].
+enum DiffFileKind {
+    Apk,
+    Obb(Option<String>),
+}
+
+// Parses the name of a diff file into the version it downgrades from, the version it downgrades to and its kind.
+// Returns None if the name is not in the format given by [add_diff_to_index].
+fn parse_diff_file_name(name: &str) -> Option<(String, String, DiffFileKind)> {
+    if let Some(versions) = name
+        .strip_prefix("bs-apk-")
+        .and_then(|rest| rest.strip_suffix(".apk.diff"))
+    {
+        let (from_version, to_version) = versions.split_once("-to-")?;
+        return Some((
+            from_version.to_string(),
+            to_version.to_string(),
+            DiffFileKind::Apk,
+        ));
+    }
+
+    let rest = name.strip_prefix("bs-obb-")?.strip_suffix(".diff")?;
+    let (from_version, rest) = rest.split_once("-to-")?;
+    // Versions never contain a `-`, so any `-` after the version separates it from the OBB ID.
+    let (to_version, obb_id) = match rest.split_once('-') {
+        Some((to_version, obb_id)) => (to_version, Some(obb_id.to_string())),
+        None => (rest.strip_suffix(".obb")?, None),
+    };
+
+    Some((
+        from_version.to_string(),
+        to_version.to_string(),
+        DiffFileKind::Obb(obb_id),
+    ))
+}
+
+// Creates a new diff index from the diff files in DIFFS_PATH, replacing the existing index.
+// Entries of the existing index are kept if all of their diff files still exist. The details of any other diffs
+// are worked out from the APK and OBB files of both versions, so these versions must be stored locally.
+fn rebuild_diff_index() -> Result<()> {
+    info!("Rebuilding diff index from {DIFFS_PATH}");
+    let existing_index = match load_current_diffs() {
+        Ok(index) => index,
+        Err(err) => {
+            warn!(
+                "Existing diff index could not be loaded, so all diffs will be re-checked: {err:?}"
+            );
+            Vec::new()
+        }
+    };
+
+    let mut found: HashMap<(String, String), DiffFiles> = HashMap::new();
+    for entry_result in std::fs::read_dir(DIFFS_PATH)? {
+        let entry = entry_result?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        if entry.path() == Path::new(DIFF_INDEX_PATH) {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        match parse_diff_file_name(&name) {
+            Some((from_version, to_version, kind)) => {
+                let files = found.entry((from_version, to_version)).or_default();
+                match kind {
+                    DiffFileKind::Apk => files.apk_diff = Some(name),
+                    DiffFileKind::Obb(obb_id) => files.obb_diffs.push((name, obb_id)),
+                }
+            }
+            None => {
+                warn!("{name} is not named like a diff file, so will not be added to the index")
+            }
+        }
+    }
+
+    let mut new_index = Vec::new();
+    for ((from_version, to_version), files) in found {
+        let apk_diff = match files.apk_diff {
+            Some(apk_diff) => apk_diff,
+            None => {
+                warn!("OBB diffs from {from_version} to {to_version} have no APK diff, so will not be added to the index");
+                continue;
+            }
+        };
+        if files.obb_diffs.is_empty() {
+            warn!("APK diff from {from_version} to {to_version} has no OBB diffs, so will not be added to the index");
+            continue;
+        }
+
+        let mut obb_diff_names: Vec<&str> = files
+            .obb_diffs
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        obb_diff_names.sort();
+        let existing = existing_index.iter().find(|diff| {
+            let mut existing_obb_names: Vec<&str> = diff
+                .obb_diffs
+                .iter()
+                .map(|obb_diff| obb_diff.diff_name.as_str())
+                .collect();
+            existing_obb_names.sort();
+
+            diff.from_version == from_version
+                && diff.to_version == to_version
+                && diff.apk_diff.diff_name == apk_diff
+                && existing_obb_names == obb_diff_names
+        });
+        if let Some(existing) = existing {
+            info!("Keeping existing index entry for {from_version} to {to_version}");
+            new_index.push(existing.clone());
+            continue;
+        }
+
+        info!("Adding diff from {from_version} to {to_version}");
+        match describe_diff_files(&from_version, &to_version, &apk_diff, &files.obb_diffs) {
+            Ok(diffs) => new_index.push(diffs),
+            Err(err) => warn!(
+                "Diff from {from_version} to {to_version} will not be added to the index: {err:?}"
+            ),
+        }
+    }
+
+    for diff in &existing_index {
+        if !new_index
+            .iter()
+            .any(|new| new.from_version == diff.from_version && new.to_version == diff.to_version)
+        {
+            warn!(
+                "Removing diff from {} to {} from the index as its files are missing",
+                diff.from_version, diff.to_version
+            );
+        }
+    }
+
+    new_index
+        .sort_by(|a, b| (&a.from_version, &a.to_version).cmp(&(&b.from_version, &b.to_version)));
+    save_diff_index(new_index).context("Saving diff index")?;
+    Ok(())
+}
+
+// Works out the index entry for the diffs from `from_version` to `to_version`, using the locally stored APKs and OBBs.
+fn describe_diff_files(
+    from_version: &str,
+    to_version: &str,
+    apk_diff: &str,
+    obb_diffs: &[(String, Option<String>)],
+) -> Result<VersionDiffs> {
+    let (from_apk, from_obbs) = get_obb_and_apk_path(from_version, false)
+        .context("Getting APK/OBB path for original version")?;
+    let (to_apk, to_obbs) = get_obb_and_apk_path(to_version, false)
+        .context("Getting APK/OBB path for downgraded version")?;
+    let obb_pairs = pair_obbs(&from_obbs, &to_obbs)?;
+    if obb_pairs.len() != obb_diffs.len() {
+        return Err(anyhow!(
+            "Found {} OBB diffs, but the downgraded version has {} OBBs",
+            obb_diffs.len(),
+            obb_pairs.len()
+        ));
+    }
+
+    let apk_diff = diff_builder::describe_existing_diff(
+        &from_apk,
+        &to_apk,
+        Path::new(DIFFS_PATH).join(apk_diff),
+    )
+    .context("Checking APK diff")?;
+
+    // The OBB diffs are kept in the same order as when generated by [add_diff_to_index].
+    let mut obb_diff_infos = Vec::new();
+    for (from_obb, to_obb) in &obb_pairs {
+        let to_obb_id = get_obb_id(&get_file_name(to_obb)?);
+        let diff_name = obb_diffs
+            .iter()
+            .find(|(_, obb_id)| match obb_id {
+                // Only the diff of the only OBB has no ID.
+                None => obb_pairs.len() == 1,
+                Some(obb_id) => obb_id == &to_obb_id,
+            })
+            .map(|(diff_name, _)| diff_name)
+            .ok_or(anyhow!("No OBB diff found to produce {to_obb:?}"))?;
+
+        obb_diff_infos.push(
+            diff_builder::describe_existing_diff(
+                from_obb,
+                to_obb,
+                Path::new(DIFFS_PATH).join(diff_name),
+            )
+            .with_context(|| format!("Checking OBB diff {diff_name}"))?,
+        );
+    }
+
+    Ok(VersionDiffs {
+        from_version: from_version.to_string(),
+        to_version: to_version.to_string(),
+        apk_diff,
+        obb_diffs: obb_diff_infos,
+    })
+}
+
+// Matches each OBB of the downgraded version with the OBB of the original version that contains the same content,
+// using the ID of each OBB (see [get_obb_id]).
+// Gives an error if an OBB of the downgraded version has no counterpart, as it could not be produced by a diff.
+// OBBs of the original version with no counterpart are not needed by the downgraded version so are skipped.
+fn pair_obbs(from_obbs: &[PathBuf], to_obbs: &[PathBuf]) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut pairs = Vec::new();
+    for to_obb in to_obbs {
+        let to_id = get_obb_id(&get_file_name(to_obb)?);
+
+        let mut from_obb = None;
+        for candidate in from_obbs {
+            if get_obb_id(&get_file_name(candidate)?) == to_id {
+                from_obb = Some(candidate);
+                break;
+            }
+        }
+
+        match from_obb {
+            Some(from_obb) => pairs.push((from_obb.clone(), to_obb.clone())),
+            None => {
+                return Err(anyhow!(
+                    "OBB {to_obb:?} of the downgraded version had no matching OBB in the original version"
+                ))
+            }
+        }
+    }
+
+    for from_obb in from_obbs {
+        if !pairs.iter().any(|(paired, _)| paired == from_obb) {
+            warn!(
+                "OBB {from_obb:?} is not present in the downgraded version, so will not be diffed"
+            );
+        }
+    }
+
+    Ok(pairs)
+}
+
 // Converts a Beat Saber version string to semver.
 fn bs_ver_to_semver(bs_ver: &str) -> semver::Version {
     let semver_portion = bs_ver
@@ -469,7 +767,8 @@ fn update_all_repositories(latest_bs_version: String) -> Result<()> {
 
 fn merge_obb(version: String, out_path: impl AsRef<Path>) -> Result<()> {
     info!("Merging APK and OBB for version {version}");
-    let (apk_path, maybe_obb_path) = get_obb_and_apk_path(&version, true)?;
+    let (apk_path, obb_paths) = get_obb_and_apk_path(&version, true)?;
+    apk_check::verify_not_modded(&apk_path)?;
 
     info!("Copying APK to destination");
     std::fs::copy(&apk_path, out_path.as_ref()).context("Copying APK to destination path")?;
@@ -477,15 +776,23 @@ fn merge_obb(version: String, out_path: impl AsRef<Path>) -> Result<()> {
     let apk_file = OpenOptions::new().read(true).write(true).open(out_path)?;
     let mut apk_zip = ZipFile::open(apk_file).context("APK was not valid ZIP archive")?;
 
-    let obb_path = maybe_obb_path.ok_or(anyhow!("No OBB found for v{version} to merge"))?;
-
-    let mut obb_zip =
-        ZipFile::open(std::fs::File::open(obb_path)?).context("OBB was not valid ZIP archive")?;
+    if obb_paths.is_empty() {
+        return Err(anyhow!("No OBB found for v{version} to merge"));
+    }
 
-    info!("Copying entries from OBB into APK");
-    obb_zip
-        .copy_all_entries_to(&mut apk_zip)
-        .context("Copying over over OBB entries")?;
+    for obb_path in obb_paths {
+        let mut obb_zip = ZipFile::open(std::fs::File::open(&obb_path)?)
+            .with_context(|| format!("OBB {obb_path:?} was not valid ZIP archive"))?;
+
+        info!("Copying entries from {obb_path:?} into APK");
+        let counts = obb_zip
+            .copy_all_entries_to(&mut apk_zip, true)
+            .context("Copying over over OBB entries")?;
+        info!(
+            "Copied {} entries, skipped {} identical entries",
+            counts.copied, counts.skipped
+        );
+    }
 
     const CERT_PEM: &[u8] = include_bytes!("../../mbf-agent/src/debug_cert.pem");
     let (cert, priv_key) = mbf_zip::signing::load_cert_and_priv_key(CERT_PEM);
@@ -512,6 +819,7 @@ enum Commands {
     /// Downloads the currently installed Beat Saber APK and OBB(s) into the local version list
     PullVersion,
     /// Generates a diff file to downgrade between the two given versions, and adds it to the diff index.
+    /// The diff is checked to reproduce the downgraded version exactly before it is added.
     GenerateDiff {
         #[arg(short, long)]
         from_version: String,
@@ -541,6 +849,13 @@ enum Commands {
     InstallLatestModdable,
     /// Uploads any changes made to the mbf diffs index.
     UpdateDiffIndex,
+    /// Rebuilds the diff index from the diff files in the diffs folder, e.g. after diff files were deleted manually.
+    /// Diffs not already in the index are checked against the locally stored versions they downgrade between.
+    /// Diff files that have no APK or OBB counterpart are left out of the index.
+    RebuildDiffIndex,
+    /// Prints details of the APK at the given path, e.g. its version and whether it is modded.
+    /// Useful for checking APKs supplied by users without needing to install them.
+    AnalyzeApk { path: String },
     /// Extracts all AndroidManifest.xml files from APKs and uploads them to the MBF manifests repo.
     UpdateManifestsRepo,
     /// Convenience command for use when a Beat Saber update releases.
@@ -567,6 +882,15 @@ enum Commands {
         #[arg(short, long)]
         out_path: String,
     },
+    /// Applies a diff file to the given input file, for manually testing diffs.
+    ApplyDiff {
+        #[arg(short, long)]
+        input: String,
+        #[arg(short, long)]
+        diff: String,
+        #[arg(short, long)]
+        output: String,
+    },
     /// Fetches Beat Saber versions from the oculus database, then:
     /// - Ensures all manifests are available on the manifests repo.
     /// - Ensures the latest version has a diff to the latest moddable version (if not the same)
@@ -577,6 +901,93 @@ enum Commands {
     },
 }
 
+/// The directory within an APK containing the native libraries for 64 bit ARM, as used by the Quest.
+const ARM64_LIBS_DIR: &str = "lib/arm64-v8a/";
+/// The Unity asset bundle containing the game data, which is moved into an OBB when a Unity game is built with a split binary.
+const UNITY_DATA_PATH: &str = "assets/bin/Data/data.unity3d";
+
+/// Logs the signature, mod tag, native libraries and manifest details of the APK at `path`.
+fn analyze_apk(path: &Path) -> Result<()> {
+    let mut apk = ZipFile::open_readonly(path).context("APK was not valid ZIP archive")?;
+
+    let v2_signed = apk
+        .read_signing_block()
+        .context("Reading APK Signing Block")?
+        .is_some_and(|block| block.get(signing::V2_SIGNATURE_ID).is_some());
+    info!("V2 signed: {v2_signed}");
+
+    if apk.contains_file(apk_check::MOD_TAG_PATH) {
+        let tag_data = apk
+            .read_file(apk_check::MOD_TAG_PATH)
+            .context("Reading mod tag")?;
+        match serde_json::from_slice::<apk_check::ModTag>(&tag_data) {
+            Ok(tag) => info!("Modded: {tag:?}"),
+            Err(err) => warn!("Modded, but the mod tag was invalid JSON: {err}"),
+        }
+    } else if apk_check::is_modded(&apk) {
+        info!("Modded, but with no mod tag (likely by an older patcher)");
+    } else {
+        info!("Not modded");
+    }
+
+    let mut libs: Vec<_> = apk
+        .iter_entry_names()
+        .filter(|name| name.starts_with(ARM64_LIBS_DIR))
+        .map(|name| (name.to_string(), apk.uncompressed_size(name).unwrap_or(0)))
+        .collect();
+    libs.sort();
+    info!("{} native libraries", libs.len());
+    for (name, size) in libs {
+        info!("- {name}: {size} bytes");
+    }
+
+    let manifest = apk
+        .read_file("AndroidManifest.xml")
+        .context("Reading manifest")?;
+    let mut manifest_cursor = std::io::Cursor::new(manifest);
+    let mut manifest_reader =
+        AxmlReader::new(&mut manifest_cursor).context("Manifest was not valid AXML")?;
+    let (package_id, version) =
+        read_package_and_version(&mut manifest_reader).context("Parsing manifest")?;
+    info!("Package ID: {package_id}");
+    info!("Version: {version}");
+
+    // Unity moves its game data into an OBB when the game is built with a split binary, so without the data the APK is not playable alone.
+    info!("Needs OBBs: {}", !apk.contains_file(UNITY_DATA_PATH));
+    Ok(())
+}
+
+/// Reads the package ID and version name from the `manifest` element of an AXML manifest.
+fn read_package_and_version<R: Read + Seek>(
+    reader: &mut AxmlReader<R>,
+) -> Result<(String, String)> {
+    while let Some(event) = reader.read_next_event()? {
+        if let axml::Event::StartElement {
+            attributes, name, ..
+        } = event
+        {
+            if name != "manifest" {
+                return Err(anyhow!("Root element was <{name}>, expected <manifest>"));
+            }
+
+            let get_string_attr = |attr_name: &str| {
+                attributes
+                    .iter()
+                    .find(|attr| attr.name == attr_name)
+                    .and_then(|attr| match &attr.value {
+                        AttributeValue::String(value) => Some(value.clone()),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("No {attr_name} string attribute on <manifest>"))
+            };
+
+            return Ok((get_string_attr("package")?, get_string_attr("versionName")?));
+        }
+    }
+
+    Err(anyhow!("Manifest contained no elements"))
+}
+
 /// If `argument` is Some, this unwraps `argument` and returns the contained access token.
 /// Otherwise, this function tries to load the meta access token from META_TOKEN_PATH
 /// Gives an error if reading the file fails. (or the file doesn't exist)
@@ -656,6 +1067,8 @@ fn main() -> Result<()> {
             add_diff_to_index(from_version, latest_moddable, overwrite)?;
         }
         Commands::UpdateDiffIndex => upload_diff_index()?,
+        Commands::RebuildDiffIndex => rebuild_diff_index()?,
+        Commands::AnalyzeApk { path } => analyze_apk(Path::new(&path))?,
         // Enable fuzzy lookup so that if e.g. 1.28.0 is selected, the command will find the full version string with the build suffix and install that
         Commands::InstallVersion { version } => install_bs_version(&version, true)?,
         Commands::InstallLatestModdable => install_bs_version(&get_latest_moddable_bs()?, false)?,
@@ -707,6 +1120,11 @@ fn main() -> Result<()> {
             update_all_repositories(latest_bs_version)?;
         }
         Commands::MergeObb { version, out_path } => merge_obb(version, out_path)?,
+        Commands::ApplyDiff {
+            input,
+            diff,
+            output,
+        } => diff_builder::apply_diff(input, diff, output)?,
     }
 
     Ok(())
@@ -0,0 +1,45 @@
+//! Support for downloading resources from mirrors when their primary location is unavailable,
+//! e.g. when GitHub is rate limiting or blocked on the user's network.
+
+/// A list of mirrors for resources, each of which maps the base URL of a primary location to
+/// one or more alternative base URLs that serve the same files at the same paths.
+#[derive(Clone, Default)]
+pub struct Mirrors {
+    // Each primary base URL and its mirrors, in the order they should be tried.
+    bases: Vec<(String, Vec<String>)>,
+}
+
+impl Mirrors {
+    /// Adds `mirror_base` as a mirror for all URLs starting with `primary_base`.
+    /// Mirrors are tried in the order they are added.
+    pub fn add(&mut self, primary_base: &str, mirror_base: &str) {
+        match self
+            .bases
+            .iter_mut()
+            .find(|(primary, _)| primary == primary_base)
+        {
+            Some((_, mirrors)) => mirrors.push(mirror_base.to_string()),
+            None => self
+                .bases
+                .push((primary_base.to_string(), vec![mirror_base.to_string()])),
+        }
+    }
+
+    /// Gets the URLs to try when downloading from `url`, in order. The first URL is always `url` itself.
+    pub fn get_urls(&self, url: &str) -> Vec<String> {
+        let mut urls = vec![url.to_string()];
+        for (primary, mirrors) in &self.bases {
+            if let Some(path) = url.strip_prefix(primary.as_str()) {
+                urls.extend(mirrors.iter().map(|mirror| format!("{mirror}{path}")));
+            }
+        }
+
+        urls
+    }
+}
+
+/// Returns true if a response with the given status code means the next mirror should be tried,
+/// as the server is refusing to serve us (rather than the file not existing).
+pub fn should_try_mirror(status: u16) -> bool {
+    status == 403 || status == 429
+}
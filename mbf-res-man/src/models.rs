@@ -13,6 +13,10 @@ pub struct CoreMod {
     pub version: Version,
     #[serde(rename = "downloadLink")]
     pub download_url: String,
+    /// SHA-256 hash of the QMOD at `download_url`, as a hex string.
+    /// Optional since older core mod indices do not contain hashes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -35,6 +39,9 @@ pub struct VersionDiffs {
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Diff {
     pub diff_name: String,
+    /// SHA-256 of the diff file, as a hex string. Not present for diffs generated by older versions of mbf-res-man.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_sha256: Option<String>,
 
     pub file_name: String,
     pub file_crc: u32,
@@ -43,6 +50,19 @@ pub struct Diff {
     pub output_size: usize,
 }
 
+/// Gets an identifier for an OBB file that stays the same between Beat Saber versions, so that
+/// the OBBs of one version can be matched up with the OBBs containing the same content in another.
+/// OBBs are named `<main|patch>.<version code>.<package ID>.obb`, so the identifier is the filename without the version code.
+/// Filenames not in this format are used as the identifier unchanged.
+pub fn get_obb_id(file_name: &str) -> String {
+    let mut parts: Vec<&str> = file_name.split('.').collect();
+    if parts.len() > 2 && !parts[1].is_empty() && parts[1].bytes().all(|b| b.is_ascii_digit()) {
+        parts.remove(1);
+    }
+
+    parts.join(".")
+}
+
 /// The mod repo served on mods.bsquest.xyz
 /// Key is full Beat Saber version incl. non-semver portion, value is a list of mods for the version.
 pub type ModRepo = HashMap<String, Vec<ModRepoMod>>;
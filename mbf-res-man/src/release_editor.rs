@@ -10,18 +10,16 @@ use anyhow::{anyhow, Context, Result};
 use byteorder::{ReadBytesExt, BE};
 use log::{error, info, warn};
 
-use crate::hash_cache::HashCache;
+use crate::{
+    github::{self, API_ROOT},
+    hash_cache::HashCache,
+};
+
+pub use crate::github::Repo;
 
-const API_ROOT: &str = "https://api.github.com";
 const UPLOAD_API_ROOT: &str = "https://uploads.github.com";
 const ASSET_CRC_FILENAME: &str = "assets.crc32.json";
 
-#[derive(Clone, Debug)]
-pub struct Repo {
-    pub owner: String,
-    pub repo: String,
-}
-
 #[derive(Clone, Debug)]
 pub struct Release {
     pub repo: Repo,
@@ -37,9 +35,7 @@ pub struct ReleaseAsset {
 }
 
 fn set_headers(req: ureq::Request, auth_token: &str) -> ureq::Request {
-    req.set("Accept", "application/vnd.github+json")
-        .set("Authorization", &format!("Bearer {auth_token}"))
-        .set("X-GitHub-Api-Version", "2022-11-28")
+    github::set_api_headers(req).set("Authorization", &format!("Bearer {auth_token}"))
 }
 
 // Reads the details about an asset, other than its CRC32, from the github JSON response
@@ -7,10 +7,12 @@ use std::{
     path::PathBuf,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use log::{debug, warn};
 use serde::de::DeserializeOwned;
 
+use crate::mirrors::{should_try_mirror, Mirrors};
+
 /// We separate this out into an enum as if a file can't be fetched,
 /// then it is useful to know that the *fetching* was the problem and not the *parsing*
 /// so that the user can be warned of their failing internet connection.
@@ -31,6 +33,24 @@ impl Display for JsonPullError {
     }
 }
 
+// Error from fetching a file from a particular URL, indicating whether the file should be fetched from a mirror instead.
+enum CachedGetError {
+    TryMirror(anyhow::Error),
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for CachedGetError {
+    fn from(value: anyhow::Error) -> Self {
+        Self::Other(value)
+    }
+}
+
+impl From<std::io::Error> for CachedGetError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Other(value.into())
+    }
+}
+
 /// The file used to store a cache of the ETags of the other files, if given in the response
 /// This filename cannot be used as the name of a cache file.
 pub const ETAG_CACHE_FILENAME: &str = "etag_cache.json";
@@ -44,6 +64,7 @@ pub struct ResCache<'agent> {
     // If this is none, then the ETag cache is yet to be loaded.
     etag_cache: RefCell<Option<HashMap<String, String>>>,
     etag_cache_path: PathBuf,
+    mirrors: Mirrors,
 }
 
 impl<'agent> ResCache<'agent> {
@@ -56,9 +77,16 @@ impl<'agent> ResCache<'agent> {
             cache_root,
             agent,
             etag_cache: RefCell::new(None),
+            mirrors: Mirrors::default(),
         }
     }
 
+    /// Sets the mirrors used if a file cannot be fetched from its primary URL.
+    pub fn with_mirrors(mut self, mirrors: Mirrors) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
     fn load_etag_cache(&self) -> Result<()> {
         let mut etag_ref = self.etag_cache.borrow_mut();
         if etag_ref.is_none() {
@@ -114,7 +142,25 @@ impl<'agent> ResCache<'agent> {
     /// if there is no cached copy already or the cached copy is out of date.
     ///
     /// If the cached copy is found to be in date, this copy will be returned instead.
+    /// If the request is refused or cannot be made, each mirror of `url` is tried in turn.
     pub fn get_cached(&self, url: &str, cached_file_name: &str) -> Result<File> {
+        let urls = self.mirrors.get_urls(url);
+        for (idx, mirror_url) in urls.iter().enumerate() {
+            match self.get_cached_from(mirror_url, cached_file_name) {
+                Ok(file) => return Ok(file),
+                Err(CachedGetError::TryMirror(err)) if idx + 1 < urls.len() => {
+                    warn!("Failed to fetch {mirror_url} ({err:#}), trying next mirror")
+                }
+                Err(CachedGetError::TryMirror(err) | CachedGetError::Other(err)) => {
+                    return Err(err)
+                }
+            }
+        }
+
+        unreachable!("There is always at least one URL to try")
+    }
+
+    fn get_cached_from(&self, url: &str, cached_file_name: &str) -> Result<File, CachedGetError> {
         let mut request = self.agent.get(url);
         self.load_etag_cache()?;
 
@@ -138,7 +184,19 @@ impl<'agent> ResCache<'agent> {
             request = request.set("If-None-Match", &cached_etag);
         }
 
-        let resp = request.call().context("HTTP GET to get file to cache")?;
+        let resp = match request.call() {
+            Ok(resp) => resp,
+            Err(ureq::Error::Status(code, _)) if !should_try_mirror(code) => {
+                return Err(CachedGetError::Other(anyhow!(
+                    "HTTP GET to get file to cache: got status {code}"
+                )))
+            }
+            Err(err) => {
+                return Err(CachedGetError::TryMirror(
+                    anyhow::Error::new(err).context("HTTP GET to get file to cache"),
+                ))
+            }
+        };
         if resp.status() != 304 {
             // If cached file out of date. (or no cache)
             if let Some(etag) = resp.header("ETag") {
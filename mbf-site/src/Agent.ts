@@ -1,12 +1,15 @@
 import { AdbSync, AdbSyncWriteOptions, Adb, encodeUtf8 } from '@yume-chan/adb';
 import { Consumable, ConcatStringStream, TextDecoderStream, MaybeConsumable, ReadableStream } from '@yume-chan/stream-extra';
-import { Request, Response, LogMsg, ModStatus, Mods, FixedPlayerData, ImportResult, DowngradedManifest, Patched, ModSyncResult } from "./Messages";
+import { Request, Response, LogMsg, Progress, ModStatus, Mods, FixedPlayerData, DeletedOrphanedObbs, AgentVersion, InstallCheck, InstallVerified, ImportResult, ImportedDirectory, CurrentManifest, DowngradedManifest, CoreModsForVersion, Patched, ModSyncResult, ModsRepaired, VersionModsDir, VersionModDirs, DeletedOtherVersionMods, UninstallImpact, ModFiles, ModConfig, ModPolicy, ModPolicyResult, Logs, Diagnostics, AgentError, ErrorCode, DependencyConflictInfo } from "./Messages";
 import { AGENT_SHA1 } from './agent_manifest';
 import { toast } from 'react-toastify';
 import { Log } from './Logging';
+import { AndroidManifest } from './AndroidManifest';
 
 const AgentPath: string = "/data/local/tmp/mbf-agent";
 const UploadsPath: string = "/data/local/tmp/mbf/uploads/";
+// The version of the protocol used to communicate with the agent. Must match `PROTOCOL_VERSION` in the agent.
+const PROTOCOL_VERSION: number = 1;
 
 // Converts the provided byte array into a ReadableStream that can be fed into ADB.
 function readableStreamFromByteArray(array: Uint8Array): ReadableStream<Uint8Array> {
@@ -137,8 +140,26 @@ async function downloadAgent(): Promise<Uint8Array> {
   throw new Error("Failed to fetch agent after multiple attempts.\nDid you lose internet connection just after you loaded the site?\n\nIf not, then please report this issue, including a screenshot of the browser console window!");
 }
 
-async function sendRequest(adb: Adb, request: Request): Promise<Response> {
-  let command_buffer = encodeUtf8(JSON.stringify(request) + "\n");
+// Thrown when a request fails, with the code sent by the agent if it gave one.
+export class RequestError extends Error {
+  code: ErrorCode;
+  // The installed mods that prevented a mod from being upgraded, if the code is DependencyConflict.
+  dependencyConflicts: DependencyConflictInfo[];
+
+  constructor(message: string, code: ErrorCode, dependencyConflicts: DependencyConflictInfo[] = []) {
+    super(message);
+    this.code = code;
+    this.dependencyConflicts = dependencyConflicts;
+  }
+}
+
+// If `signal` is given, aborting it asks the agent to cancel the request, if the request supports cancellation.
+async function sendRequest(adb: Adb, request: Request, signal?: AbortSignal): Promise<Response> {
+  let command_buffer = encodeUtf8(JSON.stringify({
+    ...request,
+    protocol_version: PROTOCOL_VERSION,
+    max_download_bytes_per_sec: MAX_DOWNLOAD_BYTES_PER_SEC
+  }) + "\n");
 
   let agentProcess = await adb.subprocess.spawn(AgentPath);
 
@@ -149,6 +170,15 @@ async function sendRequest(adb: Adb, request: Request): Promise<Response> {
     stdin.releaseLock();
   }
 
+  signal?.addEventListener("abort", () => {
+    const stdin = agentProcess.stdin.getWriter();
+    try {
+      stdin.write(new Consumable(encodeUtf8("Cancel\n")));
+    } finally {
+      stdin.releaseLock();
+    }
+  }, { once: true });
+
   let exited = false;
   agentProcess.exit.then(() => exited = true);
   adb.disconnected.then(() => exited = true);
@@ -191,6 +221,8 @@ async function sendRequest(adb: Adb, request: Request): Promise<Response> {
         if(msg_obj.level === 'Error') {
           response = msg_obj;
         }
+      } else if(msg_obj.type === "Progress") {
+        Log.emitProgress(msg_obj as Progress);
       } else  {
         // The final message is the only one that isn't of type `log`.
         // This contains the actual response data
@@ -205,7 +237,10 @@ async function sendRequest(adb: Adb, request: Request): Promise<Response> {
       throw new Error("Received error response from agent");
     } else if(response.type === 'LogMsg') {
       const log = response as LogMsg;
-      throw new Error("`" + log.message + "`");
+      throw new RequestError("`" + log.message + "`", "Unknown");
+    } else if(response.type === 'Error') {
+      const error = response as AgentError;
+      throw new RequestError("`" + error.message + "`", error.code, error.dependency_conflicts);
     } else  {
       return response;
     }
@@ -228,6 +263,17 @@ export function setCoreModOverrideUrl(core_mod_override_url: string | null) {
   CORE_MOD_OVERRIDE_URL = core_mod_override_url;
 }
 
+let MOD_REPO_OVERRIDE_URL: string | null = null;
+export function setModRepoOverrideUrl(mod_repo_override_url: string | null) {
+  MOD_REPO_OVERRIDE_URL = mod_repo_override_url;
+}
+
+// Limits the total speed of downloads made by the agent, or null for no limit.
+let MAX_DOWNLOAD_BYTES_PER_SEC: number | null = null;
+export function setMaxDownloadSpeed(max_bytes_per_sec: number | null) {
+  MAX_DOWNLOAD_BYTES_PER_SEC = max_bytes_per_sec;
+}
+
 // Gets the status of mods from the quest, i.e. whether the app is patched, and what mods are currently installed.
 export async function loadModStatus(device: Adb): Promise<ModStatus> {
   await prepareAgent(device);
@@ -243,24 +289,77 @@ export async function setModStatuses(device: Adb,
   changesRequested: { [id: string]: boolean }): Promise<ModSyncResult> {
   let response = await sendRequest(device, {
       type: 'SetModsEnabled',
+      statuses: changesRequested,
+      override_mod_repo_url: MOD_REPO_OVERRIDE_URL
+  });
+
+  return response as ModSyncResult;
+}
+
+// Tells the backend to disable or enable the given mods, which moves their files aside rather than uninstalling them.
+// Dependencies are not installed or uninstalled.
+export async function setModsDisabled(device: Adb,
+  changesRequested: { [id: string]: boolean }): Promise<ModSyncResult> {
+  let response = await sendRequest(device, {
+      type: 'SetModsDisabled',
       statuses: changesRequested
   });
 
   return response as ModSyncResult;
 }
 
-// Gets the AndroidManifest.xml file for the given Beat Saber APK version, converted from AXML to XML.
-export async function getDowngradedManifest(device: Adb, gameVersion: string): Promise<string> {
+// Gets the AndroidManifest.xml file of the installed APK, converted from AXML to XML.
+export async function getCurrentManifest(device: Adb): Promise<string> {
+  let response = await sendRequest(device, {
+    type: 'GetCurrentManifest'
+  });
+
+  return (response as CurrentManifest).manifest_xml;
+}
+
+// Gets the AndroidManifest.xml file for the given Beat Saber APK version, converted from AXML to XML,
+// and whether the installed version can be downgraded to the given version.
+export async function getDowngradedManifest(device: Adb, gameVersion: string): Promise<DowngradedManifest> {
   let response = await sendRequest(device, {
     type: 'GetDowngradedManifest',
     version: gameVersion
   });
 
-  return (response as DowngradedManifest).manifest_xml;
+  return response as DowngradedManifest;
 }
 
+// Gets the core mods that would be installed for the given Beat Saber version.
+export async function getCoreModsForVersion(device: Adb, gameVersion: string): Promise<CoreModsForVersion> {
+  let response = await sendRequest(device, {
+    type: 'GetCoreModsForVersion',
+    version: gameVersion,
+    override_core_mod_url: CORE_MOD_OVERRIDE_URL
+  });
+
+  return response as CoreModsForVersion;
+}
+
+// Pushes a file to the uploads directory on the device, giving the path it was pushed to.
+async function uploadFile(device: Adb, file: File): Promise<string> {
+  const sync = await device.sync();
+  const path = UploadsPath + file.name;
+  try {
+    Log.debug(`Pushing ${file.name} to ${path}`);
+    await sync.write({
+      filename: path,
+      file: readableStreamFromByteArray(new Uint8Array(await file.arrayBuffer()))
+    });
+  } finally {
+    await sync.dispose();
+  }
+
+  return path;
+}
+
+// If `signal` is aborted while a mod is being extracted, the import is cancelled.
 export async function importFile(device: Adb,
-    file: File): Promise<ImportResult> {
+    file: File,
+    signal?: AbortSignal): Promise<ImportResult> {
   const sync = await device.sync();
   const tempPath = UploadsPath + file.name;
   try {
@@ -274,8 +373,10 @@ export async function importFile(device: Adb,
 
     const response = await sendRequest(device, {
       'type': 'Import',
-      from_path: tempPath
-    });
+      from_path: tempPath,
+      override_mod_repo_url: MOD_REPO_OVERRIDE_URL,
+      expected_size: file.size
+    }, signal);
 
     return response as ImportResult;
   } finally {
@@ -283,16 +384,31 @@ export async function importFile(device: Adb,
   }
 }
 
+// If `signal` is aborted while the file is being downloaded or extracted, the import is cancelled.
 export async function importUrl(device: Adb,
-url: string) {
+url: string,
+signal?: AbortSignal) {
   const response = await sendRequest(device, {
     type: 'ImportUrl',
-    from_url: url
-  });
+    from_url: url,
+    override_mod_repo_url: MOD_REPO_OVERRIDE_URL
+  }, signal);
 
   return response as ImportResult;
 }
 
+// Imports every QMOD in the given directory on the Quest, continuing past any that fail to import.
+export async function importDirectory(device: Adb,
+  path: string): Promise<ImportedDirectory> {
+  const response = await sendRequest(device, {
+    type: 'ImportDirectory',
+    path,
+    override_mod_repo_url: MOD_REPO_OVERRIDE_URL
+  });
+
+  return response as ImportedDirectory;
+}
+
 export async function removeMod(device: Adb,
   mod_id: string) {
   let response = await sendRequest(device, {
@@ -303,32 +419,113 @@ export async function removeMod(device: Adb,
   return (response as Mods).installed_mods;
 }
 
+// Gets the IDs of the mods that removing the given mod would uninstall, including the mod itself.
+export async function getUninstallImpact(device: Adb,
+  mod_id: string): Promise<string[]> {
+  let response = await sendRequest(device, {
+      type: 'GetUninstallImpact',
+      id: mod_id
+  });
+
+  return (response as UninstallImpact).uninstalled_ids;
+}
+
+// Copies the files of the given mod again from the extracted mod, replacing any corrupted files.
+export async function reinstallMod(device: Adb,
+  mod_id: string) {
+  let response = await sendRequest(device, {
+      type: 'ReinstallMod',
+      id: mod_id
+  });
+
+  return (response as Mods).installed_mods;
+}
+
+// Gets the paths on the Quest that the given mod installs files to.
+export async function getModFiles(device: Adb,
+  mod_id: string): Promise<string[]> {
+  let response = await sendRequest(device, {
+      type: 'GetModFiles',
+      id: mod_id
+  });
+
+  return (response as ModFiles).paths;
+}
+
+// Reads the config of the mod with the given ID, or gives null if it has no config file.
+export async function getModConfig(device: Adb,
+  mod_id: string): Promise<object | null> {
+  let response = await sendRequest(device, {
+      type: 'GetModConfig',
+      id: mod_id
+  });
+
+  return (response as ModConfig).config;
+}
+
+// Saves the config of the mod with the given ID, or removes it if config is null.
+export async function setModConfig(device: Adb,
+  mod_id: string,
+  config: object | null) {
+  await sendRequest(device, {
+      type: 'SetModConfig',
+      id: mod_id,
+      config
+  });
+}
+
+// Saves the policy restricting which mods may be installed, or removes it if policy is null.
+export async function setModPolicy(device: Adb,
+  policy: ModPolicy | null): Promise<ModPolicy | null> {
+  let response = await sendRequest(device, {
+      type: 'SetModPolicy',
+      policy
+  });
+
+  return (response as ModPolicyResult).policy;
+}
+
+// Restores the missing files of any partially installed mods.
+export async function repairMods(device: Adb): Promise<ModsRepaired> {
+  let response = await sendRequest(device, {
+      type: 'RepairMods'
+  });
+
+  return response as ModsRepaired;
+}
+
+// Lists the QMODs kept for each game version that has been modded, and their sizes.
+export async function getVersionModDirs(device: Adb): Promise<VersionModsDir[]> {
+  let response = await sendRequest(device, { type: 'GetVersionModDirs' });
+
+  return (response as VersionModDirs).dirs;
+}
+
+// Deletes the QMODs kept for game versions other than the installed version, giving the number of bytes freed.
+export async function deleteOtherVersionMods(device: Adb): Promise<number> {
+  let response = await sendRequest(device, { type: 'DeleteOtherVersionMods' });
+
+  return (response as DeletedOtherVersionMods).freed_bytes;
+}
+
 // Instructs the agent to patch the app, adding the modloader and installing the core mods.
 // Updates the ModStatus `beforePatch` to reflect the state of the installation after patching.
 // (will not patch if the APK is already modded - will just extract the modloader and install core mods.)
+// If `signal` is aborted while files are being downloaded, the downloads are stopped and patching fails as cancelled.
 export async function patchApp(device: Adb,
   beforePatch: ModStatus,
   downgradeToVersion: string | null,
   manifestMod: string,
   remodding: boolean,
   allow_no_core_mods: boolean,
-  splashScreen: File | null): Promise<ModStatus> {
+  splashScreen: File | null,
+  skipPlayerData: boolean = false,
+  coreModsBundle: File | null = null,
+  signal?: AbortSignal): Promise<ModStatus> {
   Log.debug("Patching with manifest: " + manifestMod);
 
-  let splashPath: string | null = null;
-  if(splashScreen !== null) {
-    const sync = await device.sync();
-    splashPath = UploadsPath + splashScreen.name;
-    try {
-      Log.debug(`Pushing splash to ${splashPath}`)
-      await sync.write({
-        filename: splashPath,
-        file: readableStreamFromByteArray(new Uint8Array(await splashScreen.arrayBuffer()))
-      })
-    } finally {
-      await sync.dispose();
-    }
-  }
+  const splashPath = splashScreen === null ? null : await uploadFile(device, splashScreen);
+  const bundlePath = coreModsBundle === null ? null : await uploadFile(device, coreModsBundle);
 
   let response = await sendRequest(device, {
       type: 'Patch',
@@ -336,15 +533,23 @@ export async function patchApp(device: Adb,
       manifest_mod: manifestMod,
       allow_no_core_mods: allow_no_core_mods,
       override_core_mod_url: CORE_MOD_OVERRIDE_URL,
+      override_mod_repo_url: MOD_REPO_OVERRIDE_URL,
       remodding,
-      vr_splash_path: splashPath
-  }) as Patched;
+      vr_splash_path: splashPath,
+      skip_player_data: skipPlayerData,
+      core_mods_bundle: bundlePath
+  }, signal) as Patched;
+  Log.debug("Manifest changes: " + JSON.stringify(response.manifest_diff));
 
   if(response.did_remove_dlc) {
     toast.warning("MBF (temporarily) deleted installed DLC while downgrading your game. To get them back, FIRST restart your headset THEN download the DLC in-game.",
         { autoClose: false })
   }
 
+  // The agent may have changed the permissions while patching (e.g. to apply the manifest options), so they are
+  // read from the installed APK rather than copied from before patching.
+  const patchedManifest = await getCurrentManifest(device);
+
   // Return the new mod status assumed after patching
   // (patching should fail if any of this is not the case)
   return {
@@ -352,8 +557,16 @@ export async function patchApp(device: Adb,
       app_info: {
           loader_installed: 'Scotland2',
           version: downgradeToVersion ?? beforePatch.app_info!.version,
-          manifest_xml: manifestMod,
-          obb_present: beforePatch.app_info!.obb_present
+          manifest_xml: patchedManifest,
+          obb_present: beforePatch.app_info!.obb_present,
+          apk_size: beforePatch.app_info!.apk_size,
+          permissions: new AndroidManifest(patchedManifest).getPermissions(),
+          min_sdk_version: beforePatch.app_info!.min_sdk_version,
+          target_sdk_version: beforePatch.app_info!.target_sdk_version,
+          label: beforePatch.app_info!.label,
+          icon_png: beforePatch.app_info!.icon_png,
+          // MBF signs patched APKs with V2 only
+          signature_schemes: ["V2"]
       },
       core_mods: {
           core_mod_install_status: "Ready",
@@ -362,7 +575,10 @@ export async function patchApp(device: Adb,
           is_awaiting_diff: false
       },
       modloader_install_status: "Ready",
-      installed_mods: response.installed_mods
+      installed_mods: response.installed_mods,
+      free_space: beforePatch.free_space,
+      needs_loader_conversion: false,
+      orphaned_obb_size: null
   };
 }
 
@@ -370,11 +586,15 @@ export async function patchApp(device: Adb,
 // Should fix many common issues with an install.
 export async function quickFix(device: Adb,
   beforeFix: ModStatus,
-  wipe_existing_mods: boolean): Promise<ModStatus> {
+  wipe_existing_mods: boolean,
+  coreModsBundle: File | null = null): Promise<ModStatus> {
+  const bundlePath = coreModsBundle === null ? null : await uploadFile(device, coreModsBundle);
   let response = await sendRequest(device, {
       type: 'QuickFix',
       override_core_mod_url: CORE_MOD_OVERRIDE_URL,
-      wipe_existing_mods
+      override_mod_repo_url: MOD_REPO_OVERRIDE_URL,
+      wipe_existing_mods,
+      core_mods_bundle: bundlePath
   });
 
   // Update the mod status to reflect the fixed installation
@@ -388,7 +608,10 @@ export async function quickFix(device: Adb,
           is_awaiting_diff: beforeFix.core_mods!.is_awaiting_diff
       },
       installed_mods: (response as Mods).installed_mods,
-      modloader_install_status: "Ready"
+      modloader_install_status: "Ready",
+      free_space: beforeFix.free_space,
+      needs_loader_conversion: false,
+      orphaned_obb_size: null
   }
 }
 
@@ -398,3 +621,54 @@ export async function fixPlayerData(device: Adb): Promise<boolean> {
 
   return (response as FixedPlayerData).existed
 }
+
+// Deletes the OBBs left behind by a previous install of Beat Saber, giving the number of bytes freed.
+export async function deleteOrphanedObbs(device: Adb): Promise<number> {
+  let response = await sendRequest(device, { type: 'DeleteOrphanedObbs' });
+
+  return (response as DeletedOrphanedObbs).freed_bytes
+}
+
+// Gets the version of the agent, and the protocol version it uses.
+export async function getAgentVersion(device: Adb): Promise<AgentVersion> {
+  let response = await sendRequest(device, { type: 'GetAgentVersion' });
+
+  return response as AgentVersion;
+}
+
+// Checks that the agent can run the commands and write to the directories that it needs to, failing with
+// a `MissingPrivilege` error naming what is missing if not.
+export async function checkPrivileges(device: Adb) {
+  await sendRequest(device, { type: 'CheckPrivileges' });
+}
+
+// Checks the APK, modloader, core mods, mod libraries and OBB, giving the result of each check.
+export async function verifyInstall(device: Adb): Promise<InstallCheck[]> {
+  let response = await sendRequest(device, {
+    type: 'VerifyInstall',
+    override_core_mod_url: CORE_MOD_OVERRIDE_URL
+  });
+
+  return (response as InstallVerified).checks;
+}
+
+// Reads the end of the most recent mod log and crash dump on the Quest, up to `maxBytes` from each.
+export async function readLogs(device: Adb, maxBytes: number): Promise<Logs> {
+  let response = await sendRequest(device, {
+    type: 'ReadLogs',
+    max_bytes: maxBytes
+  });
+
+  return response as Logs;
+}
+
+// Collects the logs, device information, installed mods and installation checks into a ZIP file to attach to bug reports.
+export async function exportDiagnostics(device: Adb): Promise<Blob> {
+  let response = await sendRequest(device, {
+    type: 'ExportDiagnostics',
+    override_core_mod_url: CORE_MOD_OVERRIDE_URL
+  });
+
+  const zipBytes = Uint8Array.from(atob((response as Diagnostics).zip_base64), c => c.charCodeAt(0));
+  return new Blob([zipBytes], { type: "application/zip" });
+}
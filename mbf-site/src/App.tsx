@@ -10,7 +10,7 @@ import { ErrorModal } from './components/Modal';
 import { Bounce, ToastContainer } from 'react-toastify';
 import 'react-toastify/dist/ReactToastify.css';
 import { CornerMenu } from './components/CornerMenu';
-import { setCoreModOverrideUrl } from './Agent';
+import { setCoreModOverrideUrl, setMaxDownloadSpeed, setModRepoOverrideUrl } from './Agent';
 import { Log } from './Logging';
 import { OperationModals } from './components/OperationModals';
 import { OpenLogsButton } from './components/OpenLogsButton';
@@ -58,9 +58,12 @@ async function connect(
 }
 
 // Attempts to invoke mbf-adb-killer to disconnect the ADB server, avoiding the developer working on MBF having to manually do this.
+// If VITE_ADB_CONNECT_ADDRESS is set, mbf-adb-killer will also `adb connect` to this address once the ADB server restarts.
 async function tryDisconnectAdb() {
+  const connectAddress: string | undefined = import.meta.env.VITE_ADB_CONNECT_ADDRESS;
+  const query = connectAddress ? `?connect=${encodeURIComponent(connectAddress)}` : "";
   try {
-    await fetch("http://localhost:25898");
+    await fetch("http://localhost:25898/" + query);
   } catch {
     Log.warn("ADB killer is not running. ADB will have to be killed manually");
   }
@@ -246,6 +249,17 @@ function AppContents() {
     mustEnterUrl = true;
   }
 
+  const modRepoQueryParam: string | null = new URLSearchParams(window.location.search).get("setmodrepo");
+  if(modRepoQueryParam !== null) {
+    setModRepoOverrideUrl(modRepoQueryParam);
+  }
+
+  // Download speed limit in KiB/s, for users on metered or shared connections.
+  const maxDownloadSpeedParam = Number(new URLSearchParams(window.location.search).get("maxdownloadspeed"));
+  if(maxDownloadSpeedParam > 0) {
+    setMaxDownloadSpeed(Math.round(maxDownloadSpeedParam * 1024));
+  }
+
   if (usingOculusBrowser()) {
     return <OculusBrowserMessage />
   } else  if (navigator.usb === undefined) {
@@ -1,5 +1,5 @@
 import { Adb } from '@yume-chan/adb';
-import { getDowngradedManifest, loadModStatus, patchApp, quickFix } from "./Agent";
+import { checkPrivileges, deleteOrphanedObbs, getDowngradedManifest, loadModStatus, patchApp, quickFix } from "./Agent";
 import { ReactNode, useEffect, useState } from 'react';
 import { ModLoader, ModStatus } from './Messages';
 import './css/DeviceModder.css';
@@ -69,7 +69,8 @@ export function DeviceModder(props: DeviceModderProps) {
     const { device, quit } = props;
 
     useEffect(() => {
-        loadModStatus(device)
+        checkPrivileges(device)
+            .then(() => loadModStatus(device))
             .then(loadedModStatus => setModStatus(loadedModStatus))
             .catch(err => quit(err));
     }, [device, quit]);
@@ -89,6 +90,17 @@ export function DeviceModder(props: DeviceModderProps) {
             <h3>Think you have Beat Saber installed?</h3>
             <p>Sometimes, it looks like Beat Saber is installed in your headset, when it actually isn't (a bug in the Meta software).</p>
             <p>This can be fixed by going to the main <b>Applications</b> menu inside your Quest, clicking the 3 dots next to Beat Saber, and clicking <b>Uninstall</b>. Finally, reinstall Beat Saber from the Meta store and refresh this page to try again.</p>
+            {modStatus.orphaned_obb_size !== null && <>
+                <h3>Free up storage</h3>
+                <p>Files from a previous install of Beat Saber are still using {Math.round(modStatus.orphaned_obb_size / (1024 * 1024))} MiB of storage on your Quest.
+                    These are not needed until Beat Saber is reinstalled, when the store will download them again.</p>
+                <button onClick={async () => {
+                    wrapOperation("Deleting old files", "Failed to delete old files", async () => {
+                        await deleteOrphanedObbs(device);
+                        setModStatus({ ...modStatus, orphaned_obb_size: null });
+                    });
+                }}>Delete old files</button>
+            </>}
         </div>
     } else if (modStatus.core_mods === null) {
         return <div className='container mainContainer'>
@@ -98,6 +110,10 @@ export function DeviceModder(props: DeviceModderProps) {
             <p>To mod Beat Saber, MBF needs to download files such as a mod loader and several essential mods.
                 <br />This occurs on your Quest's connection. Please make sure that WiFi is enabled, then refresh the page.</p>
         </div>
+    }  else if (modStatus.needs_loader_conversion && modStatus.app_info.loader_installed !== null) {
+        // The app is not vanilla, so cannot be downgraded, and MBF cannot mod it on top of the old modloader.
+        // This is checked before the version so that the user is told to reinstall vanilla rather than just to update.
+        return <IncompatibleLoader device={device} loader={modStatus.app_info.loader_installed} quit={() => quit(null)} />
     }  else if (!(modStatus.core_mods.supported_versions.includes(modStatus.app_info.version)) && !isDeveloperUrl) {
         // Check if we can downgrade to a supported version
         const downgradeVersions = GetSortedDowngradableVersions(modStatus);
@@ -131,7 +147,15 @@ export function DeviceModder(props: DeviceModderProps) {
         return <NoObb device={device} quit={() => quit(undefined)}/>
     }   else if (modStatus.app_info.loader_installed !== null) {
         let loader = modStatus.app_info.loader_installed;
-        if(loader === 'Scotland2') {
+        if(loader === 'Scotland2' && modStatus.modloader_install_status === "NeedsRepatch") {
+            // The APK contains an outdated libmain.so, which a quick fix cannot replace, so the app must be patched again.
+            return <PatchingMenu
+                quit={quit}
+                device={device}
+                modStatus={modStatus}
+                onCompleted={modStatus => setModStatus(modStatus)}
+                initialDowngradingTo={null} />
+        }   else if(loader === 'Scotland2') {
             return <ValidModLoaderMenu device={device} modStatus={modStatus} setModStatus={setModStatus} quit={() => quit(null)}/>
         }   else    {
             return <IncompatibleLoader device={device} loader={loader} quit={() => quit(null)} />
@@ -301,7 +325,14 @@ function PatchingMenu(props: PatchingMenuProps) {
             setManifest(new AndroidManifest(props.modStatus.app_info!.manifest_xml));
         }   else    {
             getDowngradedManifest(device, downgradingTo)
-                .then(manifest_xml => setManifest(new AndroidManifest(manifest_xml)))
+                .then(downgradedManifest => {
+                    if(!downgradedManifest.downgrade_available) {
+                        props.quit(`Your version of Beat Saber cannot be downgraded to ${downgradingTo}, as it no longer has a downgrade available.`);
+                        return;
+                    }
+
+                    setManifest(new AndroidManifest(downgradedManifest.manifest_xml));
+                })
                 .catch(error => {
                     // TODO: Perhaps revert to "not downgrading" if this error comes up (but only if the latest version is moddable)
                     // This is low priority as this error message should only show up very rarely - there is already a previous check for internet access.
@@ -1,11 +1,14 @@
-import { LogMsg } from "./Messages";
+import { LogMsg, Progress } from "./Messages";
 import { create } from 'zustand';
 
 export interface LogEventStore {
     logEvents: LogMsg[];
     addLogEvent: (msg: LogMsg) => void,
     enableDebugLogs: boolean,
-    setEnableDebugLogs: (enabled: boolean) => void
+    setEnableDebugLogs: (enabled: boolean) => void,
+    // The most recent progress update from the agent, or null if none has been received yet.
+    progress: Progress | null,
+    setProgress: (progress: Progress | null) => void
 }
 
 // Used to globally distribute the MBF log messages.
@@ -13,7 +16,9 @@ export const useLogStore = create<LogEventStore>(set => ({
     logEvents: [],
     enableDebugLogs: import.meta.env.DEV,
     addLogEvent: (msg: LogMsg) => set((state) => ({ logEvents: [...state.logEvents, msg]} )),
-    setEnableDebugLogs: (enabled: boolean) => set(_ => ({ enableDebugLogs: enabled }))
+    setEnableDebugLogs: (enabled: boolean) => set(_ => ({ enableDebugLogs: enabled })),
+    progress: null,
+    setProgress: (progress: Progress | null) => set(_ => ({ progress }))
 }))
 
 // Logging class which provides convenience functions to manipulate the global logging state.
@@ -40,6 +45,10 @@ export class Log {
         }
     }
 
+    static emitProgress(progress: Progress) {
+        useLogStore.getState().setProgress(progress);
+    }
+
     // Gets a large string containing all messages logged to MBF.
     static getLogsAsString(): string {
         let logs = "";
@@ -5,15 +5,42 @@ export interface GetModStatus {
     override_core_mod_url: string | null
 }
 
+export interface GetAgentVersion {
+    type: 'GetAgentVersion'
+}
+
+export interface CheckPrivileges {
+    type: 'CheckPrivileges'
+}
+
+export interface VerifyInstall {
+    type: 'VerifyInstall',
+    override_core_mod_url: string | null
+}
+
 export interface Patch {
     type: 'Patch',
     manifest_mod: string,
+    // Permissions to add as `uses-permission` elements, if not already present in `manifest_mod`
+    extra_permissions?: string[],
+    // If set, overrides `android:debuggable` on the application element
+    debuggable?: boolean | null,
+    // If set, overrides `android:extractNativeLibs` on the application element
+    extract_native_libs?: boolean | null,
     downgrade_to: string | null,
     allow_no_core_mods: boolean,
     override_core_mod_url: string | null,
+    override_mod_repo_url?: string | null,
     // Path to a file containing the splash image, as a PNG
     vr_splash_path: string | null,
-    remodding: boolean
+    remodding: boolean,
+    // Directory to store temporary files in while patching, within an `mbf-patch` subdirectory that is deleted afterwards.
+    // If not set, the temporary directory on the volume with the most free space is used.
+    temp_dir?: string | null,
+    // If true, the player data is not backed up before reinstalling, so the user's progress is lost.
+    skip_player_data?: boolean,
+    // Path to a directory or ZIP containing a core_mods.json and core mod QMODs, to install core mods from without downloading them.
+    core_mods_bundle?: string | null
 }
 
 export interface FixPlayerData {
@@ -22,13 +49,23 @@ export interface FixPlayerData {
 
 export interface SetModsEnabled {
     type: 'SetModsEnabled',
-    statuses: { [id: string]: boolean } 
+    statuses: { [id: string]: boolean },
+    override_mod_repo_url?: string | null
+}
+
+// Maps each mod ID to true to disable it, or false to enable it after it was disabled.
+export interface SetModsDisabled {
+    type: 'SetModsDisabled',
+    statuses: { [id: string]: boolean }
 }
 
 export interface QuickFix {
     type: 'QuickFix',
     override_core_mod_url: string | null,
-    wipe_existing_mods: boolean
+    override_mod_repo_url?: string | null,
+    wipe_existing_mods: boolean,
+    // Path to a directory or ZIP containing a core_mods.json and core mod QMODs, to install core mods from without downloading them.
+    core_mods_bundle?: string | null
 }
 
 export interface RemoveMod {
@@ -36,14 +73,85 @@ export interface RemoveMod {
     id: string
 }
 
+export interface GetUninstallImpact {
+    type: 'GetUninstallImpact',
+    id: string
+}
+
+export interface ReinstallMod {
+    type: 'ReinstallMod',
+    id: string
+}
+
+export interface GetModFiles {
+    type: 'GetModFiles',
+    id: string
+}
+
+export interface GetModConfig {
+    type: 'GetModConfig',
+    id: string
+}
+
+// Saves the config file the mod reads its settings from. A null config removes the file, so the mod uses its defaults.
+export interface SetModConfig {
+    type: 'SetModConfig',
+    id: string,
+    config: object | null
+}
+
+// Restricts which mods may be installed. Core mods are always permitted.
+export interface ModPolicy {
+    // If true, only the mods in mod_ids may be installed. Otherwise, the mods in mod_ids are blocked.
+    allowlist: boolean,
+    mod_ids: string[]
+}
+
+export interface SetModPolicy {
+    type: 'SetModPolicy',
+    // null to remove the policy, allowing any mod to be installed
+    policy: ModPolicy | null
+}
+
+export interface RepairMods {
+    type: 'RepairMods'
+}
+
+export interface GetVersionModDirs {
+    type: 'GetVersionModDirs'
+}
+
+// Deletes the QMODs kept for game versions other than the installed version.
+export interface DeleteOtherVersionMods {
+    type: 'DeleteOtherVersionMods'
+}
+
 export interface Import {
     type: 'Import',
-    from_path: string
+    from_path: string,
+    override_mod_repo_url?: string | null,
+    // The size of the uploaded file in bytes, so that the agent can detect if the upload was interrupted
+    expected_size?: number | null
+}
+
+export interface ImportDirectory {
+    type: 'ImportDirectory',
+    path: string,
+    override_mod_repo_url?: string | null
 }
 
 export interface ImportUrl {
     type: 'ImportUrl',
-    from_url: string
+    from_url: string,
+    override_mod_repo_url?: string | null
+}
+
+export interface DeleteOrphanedObbs {
+    type: 'DeleteOrphanedObbs'
+}
+
+export interface GetCurrentManifest {
+    type: 'GetCurrentManifest'
 }
 
 export interface GetDowngradedManifest {
@@ -51,15 +159,51 @@ export interface GetDowngradedManifest {
     version: string
 }
 
+export interface GetCoreModsForVersion {
+    type: 'GetCoreModsForVersion',
+    version: string,
+    override_core_mod_url?: string | null
+}
+
+export interface ReadLogs {
+    type: 'ReadLogs',
+    // Maximum number of bytes to read from the end of each file
+    max_bytes: number
+}
+
+export interface ExportDiagnostics {
+    type: 'ExportDiagnostics',
+    override_core_mod_url?: string | null
+}
+
 export type Request = GetModStatus | 
     Patch | 
     SetModsEnabled | 
+    SetModsDisabled |
     QuickFix | 
     RemoveMod | 
+    GetUninstallImpact |
+    ReinstallMod |
+    GetModFiles |
+    GetModConfig |
+    SetModConfig |
+    SetModPolicy |
+    RepairMods |
+    GetVersionModDirs |
+    DeleteOtherVersionMods |
     Import | 
+    ImportDirectory |
     ImportUrl | 
     FixPlayerData |
-    GetDowngradedManifest;
+    DeleteOrphanedObbs |
+    GetAgentVersion |
+    CheckPrivileges |
+    VerifyInstall |
+    GetCurrentManifest |
+    GetDowngradedManifest |
+    GetCoreModsForVersion |
+    ReadLogs |
+    ExportDiagnostics;
 
 export interface Mods {
     type: 'Mods',
@@ -72,10 +216,110 @@ export interface ModSyncResult {
     failures: string | null
 }
 
+export interface ModsRepaired {
+    type: 'ModsRepaired',
+    installed_mods: Mod[],
+    // IDs of the mods that had missing files restored
+    repaired: string[],
+    // Maps the IDs of mods that could not be repaired to the reason why
+    unrepairable: Record<string, string>
+}
+
+// The extracted QMODs kept for a particular game version.
+export interface VersionModsDir {
+    game_version: string,
+    // Total size of the QMODs, in bytes
+    size: number,
+    // True if the directory is for the installed game version
+    is_current: boolean
+}
+
+export interface VersionModDirs {
+    type: 'VersionModDirs',
+    dirs: VersionModsDir[]
+}
+
+export interface DeletedOtherVersionMods {
+    type: 'DeletedOtherVersionMods',
+    deleted_versions: string[],
+    freed_bytes: number
+}
+
+export interface UninstallImpact {
+    type: 'UninstallImpact',
+    id: string,
+    // IDs of the mods that uninstalling the mod would uninstall, starting with the mod itself
+    uninstalled_ids: string[]
+}
+
+export interface ModFiles {
+    type: 'ModFiles',
+    id: string,
+    // Absolute paths that the mod installs files to, whether or not they currently exist
+    paths: string[]
+}
+
+export interface ModConfig {
+    type: 'ModConfig',
+    id: string,
+    // Null if the mod has no config file, so uses its default settings
+    config: object | null
+}
+
+export interface ModPolicyResult {
+    type: 'ModPolicy',
+    policy: ModPolicy | null
+}
+
+// A category of failure, allowing steps to recover to be shown that are specific to the failure.
+export type ErrorCode = "NotInstalled" | "NoCoreMods" | "NetworkFailure" | "InsufficientSpace" | "SignatureFailure" | "Cancelled" | "MissingPrivilege" | "IncompatibleProtocol" | "OperationInProgress" | "DependencyConflict" | "Unknown";
+
+// An installed mod that prevented a mod from being upgraded, as it depends on a range of versions excluding the new version.
+// Uninstalling the dependent mod allows the upgrade to proceed.
+export interface DependencyConflictInfo {
+    mod_id: string,
+    rejected_version: string,
+    dependent_id: string,
+    dependent_version: string,
+    version_range: string
+}
+
+// Sent instead of any other response if the request failed.
+export interface AgentError {
+    type: 'Error',
+    code: ErrorCode,
+    // The full error, including its causes.
+    message: string,
+    // If code is DependencyConflict, the installed mods that prevented the upgrade. Otherwise empty.
+    dependency_conflicts: DependencyConflictInfo[]
+}
+
 export interface Patched {
     type: 'Patched',
     installed_mods: Mod[],
-    did_remove_dlc: boolean
+    did_remove_dlc: boolean,
+    // The changes made to the manifest while patching
+    manifest_diff: ManifestDiff
+}
+
+// Elements are identified by their path from the root element, e.g. `manifest/application`,
+// including the `android:name` of the element if it has one, e.g. `manifest/uses-permission[android.permission.INTERNET]`
+export interface ManifestDiff {
+    added_elements: ManifestElement[],
+    removed_elements: ManifestElement[],
+    changed_elements: ChangedElement[]
+}
+
+export interface ManifestElement {
+    path: string,
+    attributes: { [name: string]: string }
+}
+
+export interface ChangedElement {
+    path: string,
+    added_attributes: { [name: string]: string },
+    removed_attributes: { [name: string]: string },
+    changed_attributes: { [name: string]: { old: string, new: string } }
 }
 
 export interface ImportedMod {
@@ -91,7 +335,9 @@ export interface ImportedFileCopy {
 }
 
 export interface ImportedSong {
-    type: 'ImportedSong'
+    type: 'ImportedSong',
+    // The number of songs imported, which is more than one if the ZIP was a song pack.
+    imported_count: number
 }
 
 export interface NonQuestModDetected {
@@ -103,6 +349,15 @@ export interface FixedPlayerData {
     existed: boolean
 }
 
+export interface ImportedDirectory {
+    type: 'ImportedDirectory',
+    installed_mods: Mod[],
+    // Maps the file name of each imported QMOD to the ID of the imported mod
+    imported: Record<string, string>,
+    // Maps the file name of each QMOD that could not be imported to the reason why
+    failures: Record<string, string>
+}
+
 export interface ImportResult {
     result: ImportResultType,
     used_filename: string,
@@ -112,7 +367,7 @@ export interface ImportResult {
 export type ImportResultType = ImportedMod | ImportedFileCopy | ImportedSong | NonQuestModDetected;
 
 // Represents whether a particular part of the modded game is installed and up to date.
-export type InstallStatus = "Ready" | "NeedUpdate" | "Missing";
+export type InstallStatus = "Ready" | "NeedUpdate" | "NeedsRepatch" | "Missing";
 
 export interface ModStatus {
     type: 'ModStatus',
@@ -120,6 +375,19 @@ export interface ModStatus {
     core_mods: CoreModsInfo | null,
     modloader_install_status: InstallStatus,
     installed_mods: Mod[],
+    // Free space on each volume used when patching, e.g. the volumes with the APK and the OBB directory
+    free_space: VolumeSpace[],
+    // True if the app is patched with a modloader other than Scotland2, e.g. QuestLoader.
+    // Vanilla Beat Saber must be reinstalled before the app can be modded or downgraded.
+    needs_loader_conversion: boolean,
+    // If Beat Saber is not installed but OBBs from a previous install remain, their total size in bytes.
+    orphaned_obb_size: number | null
+}
+
+export interface VolumeSpace {
+    // The paths used by MBF that are on this volume
+    paths: string[],
+    free_bytes: number
 }
 
 export interface LogMsg {
@@ -128,12 +396,98 @@ export interface LogMsg {
     level: LogLevel
 }
 
+// Gives the progress of the current stage of a request.
+// Like LogMsg, this is never the final message sent.
+export interface Progress {
+    type: 'Progress',
+    stage: string,
+    // Between 0 and 1, if known
+    fraction: number | null,
+    bytes_done: number | null,
+    bytes_total: number | null,
+    // If the stage processes several items, e.g. downloading several core mods, the ID of the current item.
+    // `bytes_done` and `bytes_total` are then for this item, while `fraction` is for the whole stage.
+    item: string | null
+}
+
+export interface DeletedOrphanedObbs {
+    type: 'DeletedOrphanedObbs',
+    freed_bytes: number
+}
+
+export interface AgentVersion {
+    type: 'AgentVersion',
+    version: string,
+    protocol_version: number
+}
+
+export interface PrivilegesChecked {
+    type: 'PrivilegesChecked'
+}
+
+export interface InstallCheck {
+    name: string,
+    passed: boolean,
+    // Why the check failed, if it did.
+    details: string | null,
+    // What the user should do to fix the installation, if the check failed.
+    remediation: string | null
+}
+
+export interface InstallVerified {
+    type: 'InstallVerified',
+    checks: InstallCheck[]
+}
+
+export interface CurrentManifest {
+    type: 'CurrentManifest',
+    manifest_xml: string
+}
+
 export interface DowngradedManifest {
     type: 'DowngradedManifest',
-    manifest_xml: string
+    manifest_xml: string,
+    // True if a diff exists to downgrade the installed version of the app to the version of the manifest
+    downgrade_available: boolean
 }
 
-export type Response = LogMsg | ModStatus | Mods | ImportResult | FixedPlayerData | DowngradedManifest | Patched | ModSyncResult;
+export interface CoreModsForVersion {
+    type: 'CoreModsForVersion',
+    version: string,
+    // False if the core mod index has no entry for the version, in which case `core_mods` is empty
+    version_present: boolean,
+    core_mods: CoreMod[]
+}
+
+// A core mod, as listed in the core mod index
+export interface CoreMod {
+    id: string,
+    version: string,
+    downloadLink: string,
+    sha256?: string
+}
+
+// The end of a log or crash dump file on the Quest.
+export interface LogFile {
+    path: string,
+    contents: string,
+    // True if the start of the file was cut off
+    truncated: boolean
+}
+
+export interface Logs {
+    type: 'Logs',
+    mod_log: LogFile | null,
+    crash_dump: LogFile | null
+}
+
+export interface Diagnostics {
+    type: 'Diagnostics',
+    // The diagnostics bundle, as a base64 encoded ZIP file.
+    zip_base64: string
+}
+
+export type Response = LogMsg | Progress | ModStatus | Mods | ImportResult | ImportedDirectory | FixedPlayerData | DeletedOrphanedObbs | AgentVersion | PrivilegesChecked | InstallVerified | CurrentManifest | DowngradedManifest | CoreModsForVersion | Patched | ModSyncResult | ModsRepaired | VersionModDirs | DeletedOtherVersionMods | UninstallImpact | ModFiles | ModConfig | ModPolicyResult | Logs | Diagnostics | AgentError;
 
 export interface CoreModsInfo {
     supported_versions: string[],
@@ -148,7 +502,22 @@ export interface AppInfo {
     version: string,
     obb_present: boolean,
     loader_installed: ModLoader | null,
-    manifest_xml: string
+    manifest_xml: string,
+    // The size of the installed APK, in bytes.
+    apk_size: number,
+    // The permissions declared in the manifest of the installed APK.
+    permissions: string[],
+    // The minSdkVersion and targetSdkVersion in the manifest of the installed APK, or null if not given.
+    min_sdk_version: number | null,
+    target_sdk_version: number | null,
+    // The label of the app, or null if it could not be found.
+    label: string | null,
+    // The icon of the app as a base64 encoded PNG, or null if the app has no PNG icon.
+    icon_png: string | null,
+    // The signature schemes the installed APK is signed with. Empty if none could be detected.
+    signature_schemes: SignatureScheme[]
 }
 
+export type SignatureScheme = "V1" | "V2" | "V3";
+
 export type LogLevel = "Error" | "Warn" | "Info" | "Debug" | "Trace";
\ No newline at end of file
@@ -5,7 +5,11 @@ interface Mod {
     version: string,
     is_enabled: boolean,
     game_version: string | null
-    is_core: boolean
+    is_core: boolean,
+    // True if the mod's files have been moved aside so that it can be enabled again without reinstalling.
+    is_disabled: boolean,
+    // The space used by the mod on disk in bytes, including its extracted files and installed files.
+    disk_size: number
 }
 
 interface CoreMod {
@@ -5,12 +5,15 @@ import TrashCan from '../icons/trash.svg';
 import Code from '../icons/code.svg';
 import { YesNoModal } from './Modal';
 import { useState } from 'react';
+import { Log } from '../Logging';
 
 interface ModCardProps {
     mod: Mod,
     gameVersion: string,
     onEnabledChanged: (enabled: boolean) => void,
-    onRemoved: () => void
+    onRemoved: () => void,
+    // Gets the IDs of the mods that removing this mod would uninstall, including this mod.
+    getUninstallImpact: () => Promise<string[]>
 }
 
 function CoreModBadge() {
@@ -35,6 +38,7 @@ function CoreModWarning(props: { mod: Mod }) {
 
 export function ModCard(props: ModCardProps) {
     const [requestRemove, setRequestRemove] = useState(false);
+    const [alsoRemoved, setAlsoRemoved] = useState([] as string[]);
     const [requestDisable, setRequestDisable] = useState(false);
     const [wrongGameVersion, setWrongGameVersion] = useState(false);
     const setEnabled = (enabled: boolean) => {
@@ -54,7 +58,13 @@ export function ModCard(props: ModCardProps) {
         <p className='descriptionText'>{props.mod.description}</p>
 
         <div className='modControls'>
-            <div id="removeMod" onClick={() => setRequestRemove(true)}>
+            <div id="removeMod" onClick={() => {
+                setAlsoRemoved([]);
+                setRequestRemove(true);
+                props.getUninstallImpact()
+                    .then(impacted => setAlsoRemoved(impacted.filter(id => id !== props.mod.id)))
+                    .catch(err => Log.warn("Failed to check which mods removal would uninstall: " + err));
+            }}>
                 <img src={TrashCan} alt="Remove mod icon" />
             </div>
             <Slider on={props.mod.is_enabled} valueChanged={value => {
@@ -85,6 +95,9 @@ export function ModCard(props: ModCardProps) {
             {props.mod.is_core && <CoreModWarning mod={props.mod} />}
 
             <p>Are you sure that you want to remove {props.mod.name} v{props.mod.version}?</p>
+            {alsoRemoved.length > 0 && <p className="warning">
+                Removing {props.mod.name} will also uninstall {alsoRemoved.join(", ")}, as they depend on it.
+            </p>}
         </YesNoModal>
         <YesNoModal title="Disable core mod"
             onYes={() => {
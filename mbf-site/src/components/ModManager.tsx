@@ -6,7 +6,7 @@ import { ModCard } from "./ModCard";
 import UploadIcon from '../icons/upload.svg';
 import ToolsIcon from '../icons/tools-icon.svg';
 import '../css/ModManager.css';
-import { importFile, importUrl, removeMod, setModStatuses } from "../Agent";
+import { getUninstallImpact, importFile, importUrl, removeMod, setModStatuses } from "../Agent";
 import { toast } from "react-toastify";
 import { ModRepoBrowser } from "./ModRepoBrowser";
 import { ImportResult, ImportedMod, ModStatus } from "../Messages";
@@ -134,6 +134,7 @@ function InstalledModsMenu(props: ModMenuProps) {
 						setMods(await removeMod(device, mod.id));
                     });
 				}}
+				getUninstallImpact={() => getUninstallImpact(device, mod.id)}
 				onEnabledChanged={enabled => {
 					const newChanges = { ...changes };
 					newChanges[mod.id] = enabled;
@@ -236,7 +237,11 @@ function AddModsMenu(props: ModMenuProps) {
             Log.info("Successfully copied " + filename + " to " + typedResult.copied_to + " due to request from " + typedResult.mod_id);
             toast.success("Successfully copied " + filename + " to the path specified by " + typedResult.mod_id);
         }   else if(typedResult.type === 'ImportedSong') {
-            toast.success("Successfully imported song " + filename);
+            if(typedResult.imported_count > 1) {
+                toast.success(`Successfully imported ${typedResult.imported_count} songs from ${filename}`);
+            }   else    {
+                toast.success("Successfully imported song " + filename);
+            }
         }   else if(typedResult.type === 'NonQuestModDetected')  {
             toast.error(`${importResult.used_filename} is a PC mod, with the .DLL file extension. You can only install Quest mods with the .QMOD file extension. Get these from the 'Add Mods' tab.`, { autoClose: false })
         }   else    {
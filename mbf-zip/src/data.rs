@@ -72,6 +72,8 @@ pub struct LocalFileHeader {
 
 impl EndOfCentDir {
     pub const HEADER: u32 = 0x06054b50;
+    /// The size of an EOCD, excluding the comment.
+    pub const FIXED_SIZE: usize = 22;
 
     pub fn read(data: &mut impl Read) -> Result<Self> {
         if data.read_u32::<LE>()? != Self::HEADER {
@@ -123,6 +125,8 @@ impl EndOfCentDir {
 
 impl CentDirHeader {
     pub const HEADER: u32 = 0x02014b50;
+    /// The size of a CD file header, excluding the file name, extra field and comment.
+    pub const FIXED_SIZE: usize = 46;
 
     pub fn read(data: &mut impl Read) -> Result<Self> {
         if data.read_u32::<LE>()? != Self::HEADER {
@@ -220,6 +224,11 @@ impl CentDirHeader {
 
 impl LocalFileHeader {
     const HEADER: u32 = 0x04034b50;
+    /// General purpose flag that is set if the CRC-32 and lengths in the LFH are zero, and are instead
+    /// given in a data descriptor after the entry's contents.
+    pub const DATA_DESCRIPTOR_FLAG: u16 = 1 << 3;
+    /// Signature that may (optionally) come at the start of a data descriptor.
+    pub const DATA_DESCRIPTOR_HEADER: u32 = 0x08074b50;
 
     pub fn read(data: &mut impl Read) -> Result<Self> {
         if data.read_u32::<LE>()? != Self::HEADER {
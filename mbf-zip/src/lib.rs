@@ -8,17 +8,60 @@ use std::{
     collections::HashMap,
     fs::File,
     io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
-use self::data::{CentDirHeader, EndOfCentDir, LocalFileHeader};
+use self::{
+    data::{CentDirHeader, EndOfCentDir, LocalFileHeader},
+    shared_reader::SharedFileReader,
+    signing::SigningBlock,
+};
 
 mod data;
+mod read_only;
+mod shared_reader;
 pub mod signing;
 
+pub use read_only::ReadOnlyFile;
+
 /// Minimum version needed to extract ZIP files made by this module
 pub const VERSION_NEEDED_TO_EXTRACT: u16 = 0x0002;
 
+/// "Version made by" written to central directory headers.
+/// The host OS in the upper byte is MS-DOS, as no Unix permissions are written in the external attributes.
+const VERSION_MADE_BY: u16 = VERSION_NEEDED_TO_EXTRACT;
+
+/// Finds the offset of the end of central directory record in a ZIP archive.
+/// The end of the archive is read into memory once and searched for the EOCD header, rather than seeking back one byte at a time.
+fn find_eocd(file: &mut (impl Read + Seek)) -> Result<u64> {
+    // The EOCD can be followed by a comment of up to u16::MAX bytes.
+    const MAX_EOCD_LEN: u64 = EndOfCentDir::FIXED_SIZE as u64 + u16::MAX as u64;
+
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let search_start = file_len.saturating_sub(MAX_EOCD_LEN);
+    file.seek(SeekFrom::Start(search_start))?;
+    let mut search_buf = Vec::with_capacity((file_len - search_start) as usize);
+    file.read_to_end(&mut search_buf)
+        .context("Reading end of archive")?;
+
+    let header = EndOfCentDir::HEADER.to_le_bytes();
+    // Search backwards, as an EOCD with zero comment length at the very end of the file is most likely.
+    for pos in (0..(search_buf.len() + 1).saturating_sub(EndOfCentDir::FIXED_SIZE)).rev() {
+        if search_buf[pos..pos + 4] != header {
+            continue;
+        }
+
+        // The header bytes could be part of the comment of the real EOCD, so check that this EOCD's comment fits in the file.
+        let comment_len = u16::from_le_bytes([search_buf[pos + 20], search_buf[pos + 21]]) as usize;
+        if pos + EndOfCentDir::FIXED_SIZE + comment_len <= search_buf.len() {
+            return Ok(search_start + pos as u64);
+        }
+    }
+
+    Err(anyhow!("No EOCD found in APK"))
+}
+
 /// The CRC-32 algorithm used by the ZIP file format.
 pub const ZIP_CRC: Crc<u32> = Crc::<u32>::new(&Algorithm {
     width: 32,
@@ -62,15 +105,55 @@ pub enum FileCompression {
     Unsupported(u16),
 }
 
+/// The number of entries copied to another archive by [ZipFile::copy_all_entries_to], and the number skipped
+/// because an identical entry already existed.
+#[derive(Copy, Clone, Default)]
+pub struct CopyCounts {
+    pub copied: usize,
+    pub skipped: usize,
+}
+
+/// The changes made to an archive by [ZipFile::apply_changed_entries_from].
+#[derive(Copy, Clone, Default)]
+pub struct ApplyCounts {
+    /// The number of entries that were new or differed from the existing entry, so were copied.
+    pub copied: usize,
+    /// The number of entries that were identical to the existing entry, so were left as is.
+    pub unchanged: usize,
+    /// The number of entries that were deleted as they did not exist in the source archive.
+    pub deleted: usize,
+}
+
+/// Progress through extracting the entries of a ZIP file with [ZipFile::extract_to_directory_with_progress].
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ExtractProgress {
+    pub entries_done: usize,
+    pub entries_total: usize,
+    /// The total uncompressed size of the entries extracted so far.
+    pub bytes_done: u64,
+    /// The total uncompressed size of all entries.
+    pub bytes_total: u64,
+}
+
 pub struct ZipFile<T: Read + Seek> {
     file: T,
     entries: HashMap<String, CentDirHeader>,
     end_of_entries_offset: u32,
+    // Offset of the central directory, as of when the archive was opened or last saved.
+    cent_dir_offset: u32,
     // Alignment of entries created with the STORE compression method
     // Alignment is preferred for non-compressed files in APKs so that they can be MMAP'd directly into
     // memory, improving performance.
     // Typically, already-compressed media files like PNG use the STORE compression method.
     store_aligment: u16,
+    // The bytes of the APK Signing Block, as of when the archive was opened or last saved.
+    signing_block: Option<Vec<u8>>,
+    // If true, `signing_block` is written back verbatim by `save`.
+    keep_signing_block: bool,
+    // If deduplication is enabled, the central directory header of an entry with each combination of CRC-32 and
+    // uncompressed length, so that files written with the same contents can share its data.
+    // Files with a matching CRC-32 and length are still compared byte for byte before sharing data.
+    dedupe_headers: Option<HashMap<(u32, u32), CentDirHeader>>,
 }
 
 impl<T: Read + Seek> ZipFile<T> {
@@ -78,28 +161,24 @@ impl<T: Read + Seek> ZipFile<T> {
     pub fn open(mut file: T) -> Result<Self> {
         let mut buf_file = BufReader::new(&mut file);
 
-        buf_file.seek(SeekFrom::End(-22))?; // Assuming zero comment, this is the latest position for the EOCD header
-
-        // Read backwards until an EOCD header is found.
-        while buf_file.read_u32::<LE>()? != EndOfCentDir::HEADER {
-            if buf_file.stream_position()? == 4 {
-                return Err(anyhow!("No EOCD found in APK"));
-            }
-
-            buf_file.seek(SeekFrom::Current(-8))?;
-        }
-        buf_file.seek(SeekFrom::Current(-4))?; // Seek back before the LFH
-
+        let eocd_offset = find_eocd(&mut buf_file)?;
+        buf_file.seek(SeekFrom::Start(eocd_offset))?;
         let eocd: EndOfCentDir = EndOfCentDir::read(&mut buf_file).context("Invalid EOCD")?;
         buf_file.seek(SeekFrom::Start(eocd.cent_dir_offset as u64))?;
 
         // Read the central directory file headers
         let mut entries = HashMap::new();
         let mut last_lfh_offset = 0;
+        let mut last_crc32 = 0;
+        let mut last_compressed_len = 0;
 
         for _ in 0..eocd.cent_dir_records {
             let cd_record = CentDirHeader::read(&mut buf_file).context("Invalid CD file header")?;
-            last_lfh_offset = last_lfh_offset.max(cd_record.local_header_offset);
+            if cd_record.local_header_offset >= last_lfh_offset {
+                last_lfh_offset = cd_record.local_header_offset;
+                last_crc32 = cd_record.crc32;
+                last_compressed_len = cd_record.compressed_len;
+            }
 
             entries.insert(cd_record.file_name.clone(), cd_record);
         }
@@ -110,14 +189,32 @@ impl<T: Read + Seek> ZipFile<T> {
         buf_file.seek(SeekFrom::Start(last_lfh_offset as u64))?;
         let last_header = LocalFileHeader::read(&mut buf_file)?;
 
+        let end_of_entries_offset =
+            if last_header.flags & LocalFileHeader::DATA_DESCRIPTOR_FLAG != 0 {
+                // The lengths in the LFH are zero, so use the CD length and skip past the data descriptor.
+                buf_file.seek(SeekFrom::Current(last_compressed_len as i64))?;
+                skip_data_descriptor(&mut buf_file, last_crc32)
+                    .context("Reading data descriptor of last entry")?
+            } else {
+                buf_file.stream_position()? + last_header.compressed_len as u64
+            };
+
+        // A malformed signing block could not be kept anyway, so is treated the same as there being no signing block.
+        let signing_block =
+            signing::read_raw_signing_block(&mut buf_file, eocd.cent_dir_offset as u64)
+                .unwrap_or(None);
+
         Ok(Self {
-            end_of_entries_offset: (buf_file.stream_position()?
-                + last_header.compressed_len as u64)
+            end_of_entries_offset: end_of_entries_offset
                 .try_into()
                 .context("ZIP file too large")?,
+            cent_dir_offset: eocd.cent_dir_offset,
             file,
             entries,
             store_aligment: 1,
+            signing_block,
+            keep_signing_block: false,
+            dedupe_headers: None,
         })
     }
 
@@ -129,6 +226,33 @@ impl<T: Read + Seek> ZipFile<T> {
         Ok(cursor.into_inner())
     }
 
+    /// Reads the contents of each of the files with the given names from the ZIP, skipping any that do not exist.
+    /// The files are read in the order that they are stored in the archive, so that the reads are sequential.
+    /// # Returns
+    /// The contents of each file that existed, keyed by file name.
+    pub fn read_files(&mut self, names: &[&str]) -> Result<HashMap<String, Vec<u8>>> {
+        let mut present: Vec<(&str, u32)> = names
+            .iter()
+            .filter_map(|name| {
+                self.entries
+                    .get(*name)
+                    .map(|header| (*name, header.local_header_offset))
+            })
+            .collect();
+        present.sort_by_key(|(_, offset)| *offset);
+        present.dedup();
+
+        let mut contents = HashMap::with_capacity(present.len());
+        for (name, _) in present {
+            let file_contents = self
+                .read_file(name)
+                .with_context(|| format!("Reading {name}"))?;
+            contents.insert(name.to_string(), file_contents);
+        }
+
+        Ok(contents)
+    }
+
     /// Extracts a file from the ZIP to a particular path.
     pub fn extract_file_to(&mut self, name: &str, to: impl AsRef<Path>) -> Result<()> {
         let mut handle = std::fs::OpenOptions::new()
@@ -144,6 +268,41 @@ impl<T: Read + Seek> ZipFile<T> {
 
     /// Extracts all of the files in the ZIP file to the given directory.
     pub fn extract_to_directory(&mut self, to: impl AsRef<Path>) -> Result<()> {
+        self.extract_matching(to, |_| true)
+    }
+
+    /// Extracts the files in the ZIP file with names for which `predicate` returns true to the given directory.
+    /// Each file is extracted to the path given by its name relative to the directory, creating parent directories as needed.
+    pub fn extract_matching(
+        &mut self,
+        to: impl AsRef<Path>,
+        predicate: impl Fn(&str) -> bool,
+    ) -> Result<()> {
+        self.extract_matching_with_progress(to, predicate, |_| {}, None)
+    }
+
+    /// Extracts all of the files in the ZIP file to the given directory, calling `on_progress` after each entry is extracted.
+    /// If `cancel` is set to true, extraction stops before the next entry and an `Err` is returned.
+    /// Files extracted before cancellation (or any other error) are left in place.
+    pub fn extract_to_directory_with_progress(
+        &mut self,
+        to: impl AsRef<Path>,
+        on_progress: impl FnMut(ExtractProgress),
+        cancel: Option<&AtomicBool>,
+    ) -> Result<()> {
+        self.extract_matching_with_progress(to, |_| true, on_progress, cancel)
+    }
+
+    /// Extracts the files with names for which `predicate` returns true, as in [ZipFile::extract_matching],
+    /// reporting progress and allowing cancellation as in [ZipFile::extract_to_directory_with_progress].
+    /// The progress totals only include the matching files.
+    fn extract_matching_with_progress(
+        &mut self,
+        to: impl AsRef<Path>,
+        predicate: impl Fn(&str) -> bool,
+        mut on_progress: impl FnMut(ExtractProgress),
+        cancel: Option<&AtomicBool>,
+    ) -> Result<()> {
         let to = to.as_ref();
 
         // Create a clone of the entry names as a workaround since we need a mutable reference to self in order to extract files
@@ -151,9 +310,20 @@ impl<T: Read + Seek> ZipFile<T> {
         let entries = self
             .entries
             .iter()
-            .map(|(key, _)| key.clone())
+            .filter(|(key, _)| predicate(key))
+            .map(|(key, header)| (key.clone(), header.uncompressed_len as u64))
             .collect::<Vec<_>>();
-        for entry_name in entries.iter() {
+        let mut progress = ExtractProgress {
+            entries_total: entries.len(),
+            bytes_total: entries.iter().map(|(_, size)| size).sum(),
+            ..Default::default()
+        };
+
+        for (entry_name, entry_size) in entries.iter() {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                return Err(anyhow!("Extraction was cancelled"));
+            }
+
             let extract_path = to.join(entry_name);
             if let Some(parent) = extract_path.parent() {
                 std::fs::create_dir_all(parent)
@@ -169,6 +339,10 @@ impl<T: Read + Seek> ZipFile<T> {
 
             self.read_file_contents(entry_name, &mut handle)
                 .context("Reading file contents into extracted file")?;
+
+            progress.entries_done += 1;
+            progress.bytes_done += entry_size;
+            on_progress(progress);
         }
 
         Ok(())
@@ -201,35 +375,32 @@ impl<T: Read + Seek> ZipFile<T> {
     pub fn read_file_contents(&mut self, name: &str, write_to: &mut impl Write) -> Result<()> {
         let (lfh, cdh, mut buf_reader) = self.read_lfh_and_seek_to_contents(name)?;
 
-        // Use CDH for compressed length as LFH may have it set to 0 if this archive uses data descriptors.
-        let mut compressed_contents = (&mut buf_reader).take(cdh.compressed_len as u64);
-
-        match lfh.compression_method {
-            FileCompression::Deflate => {
-                // Limit the bytes to be decompressed
-                let mut decoder = deflate::Decoder::new(compressed_contents);
-
-                std::io::copy(&mut decoder, write_to)?;
-            }
-            FileCompression::Store => {
-                std::io::copy(&mut compressed_contents, write_to)?;
-            }
-            FileCompression::Unsupported(method) => {
-                return Err(anyhow!(
-                    "Compression method `{method}` not supported for reading"
-                ))
-            }
-        };
-
-        Ok(())
+        decompress_contents(&lfh, cdh, &mut buf_reader, write_to)
     }
 
     /// Copies all entries in this ZIP file into `dst_archive`. For each entry, the path is the same in both archives.
-    /// Any files that already exist in `dst_archive` will be overwritten.
-    pub fn copy_all_entries_to(&mut self, dst_archive: &mut ZipFile<File>) -> Result<()> {
+    /// Any files that already exist in `dst_archive` will be overwritten, unless `skip_identical` is true and the existing
+    /// file has the same CRC-32 and uncompressed length, in which case it is left as is.
+    pub fn copy_all_entries_to(
+        &mut self,
+        dst_archive: &mut ZipFile<File>,
+        skip_identical: bool,
+    ) -> Result<CopyCounts> {
         let mut buf_reader = BufReader::new(&mut self.file);
+        let mut counts = CopyCounts::default();
 
         for (src_name, cd_header) in &self.entries {
+            if skip_identical {
+                if let Some(existing) = dst_archive.entries.get(src_name) {
+                    if existing.crc32 == cd_header.crc32
+                        && existing.uncompressed_len == cd_header.uncompressed_len
+                    {
+                        counts.skipped += 1;
+                        continue;
+                    }
+                }
+            }
+
             buf_reader.seek(SeekFrom::Start(cd_header.local_header_offset as u64))?;
 
             let lfh =
@@ -242,9 +413,10 @@ impl<T: Read + Seek> ZipFile<T> {
                 src_name.clone(),
                 dst_archive,
             )?;
+            counts.copied += 1;
         }
 
-        Ok(())
+        Ok(counts)
     }
 
     /// Copies the entry in this ZIP file with name `src_name` to `dst_archive` with name `dst_name`.
@@ -273,6 +445,15 @@ impl<T: Read + Seek> ZipFile<T> {
         dst_cdh.file_name = dst_name.clone();
         lfh.file_name = dst_name.clone();
 
+        // The data descriptor is not copied, so the LFH must instead contain the CRC-32 and lengths.
+        if lfh.flags & LocalFileHeader::DATA_DESCRIPTOR_FLAG != 0 {
+            lfh.flags &= !LocalFileHeader::DATA_DESCRIPTOR_FLAG;
+            dst_cdh.flags &= !LocalFileHeader::DATA_DESCRIPTOR_FLAG;
+            lfh.crc32 = src_cdh.crc32;
+            lfh.compressed_len = src_cdh.compressed_len;
+            lfh.uncompressed_len = src_cdh.uncompressed_len;
+        }
+
         // Locate a position in the destination archive for the new local header.
 
         dst_archive
@@ -305,10 +486,90 @@ impl<T: Read + Seek> ZipFile<T> {
         self.entries.keys().map(String::as_ref)
     }
 
+    /// Gets the size of the archive in bytes, as it would be if saved with [ZipFile::save] now.
+    /// This is the size of the entries, plus the size of the central directory and EOCD, plus the APK Signing Block
+    /// if it is kept.
+    pub fn archive_size(&self) -> u64 {
+        let cent_dir_size: u64 = self
+            .entries
+            .values()
+            .map(|header| {
+                (CentDirHeader::FIXED_SIZE
+                    + header.file_name.len()
+                    + header.extra_field.len()
+                    + header.comment.len()) as u64
+            })
+            .sum();
+
+        let signing_block_size = match (self.keep_signing_block, &self.signing_block) {
+            (true, Some(signing_block)) => signing_block.len() as u64,
+            _ => 0,
+        };
+
+        self.end_of_entries_offset as u64
+            + signing_block_size
+            + cent_dir_size
+            + EndOfCentDir::FIXED_SIZE as u64
+    }
+
+    /// Gets the sum of the uncompressed sizes of every entry in the archive.
+    pub fn total_uncompressed_size(&self) -> u64 {
+        self.entries
+            .values()
+            .map(|header| header.uncompressed_len as u64)
+            .sum()
+    }
+
+    /// Gets the uncompressed size of the entry with the given name, or `None` if no such entry exists.
+    pub fn uncompressed_size(&self, name: &str) -> Option<u64> {
+        self.entries
+            .get(name)
+            .map(|header| header.uncompressed_len as u64)
+    }
+
     /// Returns true if and only if a file exists with name `name`
     pub fn contains_file(&self, name: &str) -> bool {
         self.entries.contains_key(name)
     }
+
+    /// Reads the APK Signing Block of the archive, if it has one.
+    /// Gives an `Err` if a signing block is present but malformed.
+    pub fn read_signing_block(&mut self) -> Result<Option<SigningBlock>> {
+        signing::read_signing_block(&mut self.file, self.cent_dir_offset as u64)
+    }
+
+    /// Gets the bytes of the APK Signing Block of the archive as of when it was opened or last saved,
+    /// including its lengths and footer, if it had one.
+    pub fn raw_signing_block(&self) -> Option<&[u8]> {
+        self.signing_block.as_deref()
+    }
+
+    /// Sets whether [ZipFile::save] should write the APK Signing Block back into the archive verbatim.
+    /// The signing block can be replaced with that of another archive using [ZipFile::set_raw_signing_block].
+    ///
+    /// The signatures in the block cover the contents of every entry and the central directory, so this will only produce
+    /// a validly signed APK if no signed content (entries, or their order) has changed since the block was created.
+    /// Otherwise, the APK will fail to install, and [ZipFile::save_and_sign_v2] should be used instead.
+    pub fn set_keep_signing_block(&mut self, keep: bool) {
+        self.keep_signing_block = keep;
+    }
+
+    /// Replaces the APK Signing Block written by [ZipFile::save] if [ZipFile::set_keep_signing_block] is enabled,
+    /// e.g. with the [ZipFile::raw_signing_block] of the archive the entries were copied from.
+    /// See [ZipFile::set_keep_signing_block] for when this produces a valid signature.
+    pub fn set_raw_signing_block(&mut self, block: Option<Vec<u8>>) {
+        self.signing_block = block;
+    }
+}
+
+// Gets the central directory headers of all entries, sorted by `order` of their names and then by the offset of their data.
+fn headers_in_order<'a>(
+    entries: &'a HashMap<String, CentDirHeader>,
+    order: &dyn Fn(&str) -> i32,
+) -> Vec<&'a CentDirHeader> {
+    let mut cd_headers: Vec<&CentDirHeader> = entries.values().collect();
+    cd_headers.sort_by_key(|header| (order(&header.file_name), header.local_header_offset));
+    cd_headers
 }
 
 // Copies the contents of `from` to `to`, calculating the ZIP CRC-32 of the copied data.
@@ -328,12 +589,220 @@ fn copy_to_with_crc(from: &mut impl Read, to: &mut impl Write) -> Result<u32> {
     }
 }
 
+// A writer that checks whether the bytes written to it match the bytes read from `expected`.
+struct ComparingWriter<R: Read> {
+    expected: R,
+    buffer: Vec<u8>,
+    // False once any written bytes have differed from `expected`.
+    matches: bool,
+}
+
+impl<R: Read> Write for ComparingWriter<R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.matches {
+            self.buffer.resize(buf.len(), 0);
+            self.matches = self.expected.read_exact(&mut self.buffer).is_ok() && self.buffer == buf;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Reads past the data descriptor at the current position of `reader`, returning the position of the first byte after it.
+// `crc32` is the CRC-32 of the entry, used to tell whether the descriptor begins with the optional signature.
+fn skip_data_descriptor(reader: &mut (impl Read + Seek), crc32: u32) -> Result<u64> {
+    let first_field = reader.read_u32::<LE>()?;
+    if first_field == LocalFileHeader::DATA_DESCRIPTOR_HEADER
+        && crc32 != LocalFileHeader::DATA_DESCRIPTOR_HEADER
+    {
+        reader.read_u32::<LE>()?; // The actual CRC-32
+    }
+
+    // Compressed and uncompressed length
+    reader.read_u32::<LE>()?;
+    reader.read_u32::<LE>()?;
+    Ok(reader.stream_position()?)
+}
+
+// Decompresses the contents of an entry with the given headers, writing them to `write_to`.
+// `reader` must be at the first byte of the entry content.
+fn decompress_contents(
+    lfh: &LocalFileHeader,
+    cdh: &CentDirHeader,
+    reader: &mut impl Read,
+    write_to: &mut impl Write,
+) -> Result<()> {
+    // Use CDH for compressed length as LFH may have it set to 0 if this archive uses data descriptors.
+    let mut compressed_contents = reader.take(cdh.compressed_len as u64);
+
+    match lfh.compression_method {
+        FileCompression::Deflate => {
+            // Limit the bytes to be decompressed
+            let mut decoder = deflate::Decoder::new(compressed_contents);
+            std::io::copy(&mut decoder, write_to)?;
+        }
+        FileCompression::Store => {
+            std::io::copy(&mut compressed_contents, write_to)?;
+        }
+        FileCompression::Unsupported(method) => {
+            return Err(anyhow!(
+                "Compression method `{method}` not supported for reading"
+            ))
+        }
+    };
+
+    Ok(())
+}
+
+/// A ZIP archive opened with [ZipFile::open_readonly], which can be read from but not modified.
+pub type ReadOnlyZipFile = ZipFile<ReadOnlyFile>;
+
+impl ZipFile<ReadOnlyFile> {
+    /// Opens the ZIP archive at the given path with read-only permissions.
+    /// The methods that modify an archive, e.g. [ZipFile::write_file] and [ZipFile::save], are not available
+    /// on the returned archive, so it is guaranteed not to be changed. Use this when only inspecting an archive.
+    pub fn open_readonly(path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(path)
+            .context("Opening archive")?;
+        Self::open(ReadOnlyFile::new(file))
+    }
+}
+
 impl ZipFile<File> {
+    /// Creates a new archive with no entries, which is written to `file`.
+    /// Any existing contents of `file` are overwritten as entries are written, and it is truncated once the archive is saved.
+    pub fn create_new(file: File) -> Self {
+        Self {
+            file,
+            entries: HashMap::new(),
+            end_of_entries_offset: 0,
+            cent_dir_offset: 0,
+            store_aligment: 1,
+            signing_block: None,
+            keep_signing_block: false,
+            dedupe_headers: None,
+        }
+    }
+
     /// Sets the alignment for files written with the STORE compression method.
     pub fn set_store_alignment(&mut self, alignment: u16) {
         self.store_aligment = alignment;
     }
 
+    /// Sets whether [ZipFile::write_file] should deduplicate entries.
+    /// When enabled, writing a file with the same contents as an existing entry (or a file written since)
+    /// adds a central directory header that points at the data of that entry, rather than writing the data again.
+    ///
+    /// The data of an entry must directly follow its local file header, so deduplicated entries share the local file
+    /// header of the original entry, which contains the original name. Android (and other readers that check local
+    /// file names against the central directory) will reject such entries, so this **must not** be enabled for APKs.
+    /// Only enable it for archives read by readers that use the central directory alone.
+    pub fn set_dedupe(&mut self, dedupe: bool) {
+        self.dedupe_headers = if dedupe {
+            let mut headers = HashMap::new();
+            for header in self.entries.values() {
+                headers
+                    .entry((header.crc32, header.uncompressed_len))
+                    .or_insert_with(|| header.clone());
+            }
+            Some(headers)
+        } else {
+            None
+        };
+    }
+
+    /// Extracts all of the files in the ZIP file to the given directory, using up to `threads` threads.
+    /// This is faster than [ZipFile::extract_to_directory] for archives with many or large entries.
+    pub fn extract_to_directory_parallel(
+        &self,
+        to: impl AsRef<Path>,
+        threads: usize,
+    ) -> Result<()> {
+        let to = to.as_ref();
+        let entries = self
+            .entries
+            .keys()
+            .map(|name| (name.clone(), to.join(name)))
+            .collect::<Vec<_>>();
+
+        self.extract_files_parallel(&entries, threads)
+    }
+
+    /// Extracts each entry with the given name to the path it is paired with, using up to `threads` threads.
+    /// Any directories needed to contain the extracted files are created. Directory entries are extracted as empty directories.
+    /// If extracting an entry fails, no more entries are extracted, and the error names the entry.
+    pub fn extract_files_parallel(
+        &self,
+        entries: &[(String, PathBuf)],
+        threads: usize,
+    ) -> Result<()> {
+        let next_entry = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let workers = (0..threads.clamp(1, entries.len().max(1)))
+                .map(|_| {
+                    scope.spawn(|| loop {
+                        if failed.load(Ordering::Relaxed) {
+                            return Ok(());
+                        }
+
+                        let (name, to) =
+                            match entries.get(next_entry.fetch_add(1, Ordering::Relaxed)) {
+                                Some(entry) => entry,
+                                None => return Ok(()),
+                            };
+                        if let Err(err) = self.extract_file_shared(name, to) {
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(err.context(format!("Extracting {name}")));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("Extraction thread panicked"))
+        })
+    }
+
+    // Extracts a file from the ZIP to a particular path, creating its parent directory if necessary.
+    // Only takes a shared reference to the archive, so can be used from multiple threads at once.
+    fn extract_file_shared(&self, name: &str, to: &Path) -> Result<()> {
+        let cd_header = match self.entries.get(name) {
+            Some(header) => header,
+            None => return Err(anyhow!("File with name {name} did not exist")),
+        };
+
+        if name.ends_with('/') {
+            std::fs::create_dir_all(to).context("Creating extracted directory")?;
+            return Ok(());
+        }
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent).context("Creating directory to extract ZIP file")?;
+        }
+
+        let mut buf_reader = BufReader::new(SharedFileReader::new(&self.file));
+        buf_reader.seek(SeekFrom::Start(cd_header.local_header_offset as u64))?;
+        let lfh = LocalFileHeader::read(&mut buf_reader).context("Invalid local file header")?;
+
+        let mut handle = std::fs::OpenOptions::new()
+            .truncate(true)
+            .create(true)
+            .write(true)
+            .open(to)
+            .context("Creating extracted file")?;
+
+        decompress_contents(&lfh, cd_header, &mut buf_reader, &mut handle)
+            .context("Reading file contents into extracted file")
+    }
+
     // Creates a field used to align the ZIP entry data to store_alignment
     // `data_offset` is what the offset in the ZIP of the first byte of the data would be,
     // with no alignment field.
@@ -378,6 +847,31 @@ impl ZipFile<File> {
         contents: &mut (impl Read + Seek),
         compression_method: FileCompression,
     ) -> Result<()> {
+        if let Some(dedupe_headers) = &self.dedupe_headers {
+            contents.seek(SeekFrom::Start(0))?;
+            let crc32 = crc_of_stream(&mut *contents).context("Calculating CRC-32 of file")?;
+            let uncompressed_len: u32 = contents
+                .stream_position()?
+                .try_into()
+                .context("Uncompressed file length too big for 32 bit ZIP file")?;
+
+            if let Some(existing) = dedupe_headers.get(&(crc32, uncompressed_len)).cloned() {
+                // Different files can have the same CRC-32 and length, so only share the data if it is identical.
+                if self
+                    .entry_matches(&existing, contents)
+                    .context("Comparing file to existing entry")?
+                {
+                    let central_dir_header = CentDirHeader {
+                        file_name: name.to_string(),
+                        ..existing
+                    };
+
+                    self.entries.insert(name.to_string(), central_dir_header);
+                    return Ok(());
+                }
+            }
+        }
+
         self.file
             .seek(SeekFrom::Start(self.end_of_entries_offset as u64))?;
 
@@ -397,8 +891,14 @@ impl ZipFile<File> {
         ))?;
 
         let data_start = self.file.stream_position()?;
+        // Misaligned STORE entries can't be memory mapped, which breaks loading native libraries from the APK.
+        debug_assert!(
+            compression_method != FileCompression::Store
+                || data_start % self.store_aligment as u64 == 0,
+            "STORE entry {name} has data at {data_start}, which is not aligned to {}",
+            self.store_aligment
+        );
 
-        // TODO: Alignment for entries created with STORE compression method
         contents.seek(SeekFrom::Start(0))?;
         let crc32 = match compression_method {
             FileCompression::Deflate => {
@@ -461,7 +961,7 @@ impl ZipFile<File> {
             .context("Writing local file header")?;
 
         let central_dir_header = CentDirHeader {
-            os_version_made_by: 0, // 0 seems to be accepted as a valid OS, TODO: give actual value?
+            os_version_made_by: VERSION_MADE_BY,
             version_needed: VERSION_NEEDED_TO_EXTRACT,
             flags: 0,
             compression_method,
@@ -477,22 +977,114 @@ impl ZipFile<File> {
             comment: String::new(),
         };
 
+        if let Some(dedupe_headers) = &mut self.dedupe_headers {
+            dedupe_headers
+                .entry((crc32, uncompressed_len))
+                .or_insert_with(|| central_dir_header.clone());
+        }
+
         // Insert/replace the central directory header. (replacing the header will delete an existing file with the same name)
         self.entries.insert(name.to_string(), central_dir_header);
         Ok(())
     }
 
+    // Returns true if the data of the entry with the given header is identical to `contents`, which is read until EOF.
+    fn entry_matches(
+        &mut self,
+        cd_header: &CentDirHeader,
+        contents: &mut (impl Read + Seek),
+    ) -> Result<bool> {
+        let mut buf_reader = BufReader::new(&mut self.file);
+        buf_reader.seek(SeekFrom::Start(cd_header.local_header_offset as u64))?;
+        let lfh = LocalFileHeader::read(&mut buf_reader).context("Invalid local file header")?;
+
+        contents.seek(SeekFrom::Start(0))?;
+        let mut comparer = ComparingWriter {
+            expected: contents,
+            buffer: Vec::new(),
+            matches: true,
+        };
+        decompress_contents(&lfh, cd_header, &mut buf_reader, &mut comparer)?;
+
+        // Check that `contents` does not continue past the end of the entry.
+        Ok(comparer.matches && comparer.expected.read(&mut [0])? == 0)
+    }
+
     // Deletes the file with the given name from the ZIP, if it existed.
     pub fn delete_file(&mut self, name: &str) -> bool {
         self.entries.remove(name).is_some()
     }
 
+    /// Deletes all files with names matching `predicate` from the ZIP.
+    /// As with [ZipFile::delete_file], only the central directory is changed, so this takes effect once the ZIP is saved.
+    /// # Returns
+    /// The number of files deleted.
+    pub fn delete_files_matching(&mut self, predicate: impl Fn(&str) -> bool) -> usize {
+        let entries_before = self.entries.len();
+        self.entries.retain(|name, _| !predicate(name));
+        entries_before - self.entries.len()
+    }
+
+    /// Makes the entries of this archive match those of `src_archive`, copying only the entries that are new or that
+    /// differ from the existing entry, i.e. have a different CRC-32 or uncompressed length. Identical entries are
+    /// left untouched, which is much faster than rewriting the whole archive when only a few entries have changed.
+    /// As with [ZipFile::copy_entry], the data of changed entries is appended, so the space used by the old data is only
+    /// reclaimed if the archive is rewritten.
+    /// # Arguments
+    /// * `src_archive` - The archive to copy changed entries from.
+    /// * `delete_missing` - If true, entries that do not exist in `src_archive` are deleted from this archive.
+    pub fn apply_changed_entries_from(
+        &mut self,
+        src_archive: &mut ZipFile<impl Read + Seek>,
+        delete_missing: bool,
+    ) -> Result<ApplyCounts> {
+        let copy_counts = src_archive
+            .copy_all_entries_to(self, true)
+            .context("Copying changed entries")?;
+
+        let deleted = if delete_missing {
+            self.delete_files_matching(|name| !src_archive.contains_file(name))
+        } else {
+            0
+        };
+
+        Ok(ApplyCounts {
+            copied: copy_counts.copied,
+            unchanged: copy_counts.skipped,
+            deleted,
+        })
+    }
+
     /// Saves the ZIP central directory, while signing the APK with the V2 signature scheme.
     pub fn save_and_sign_v2(&mut self, priv_key: &RsaPrivateKey, cert: &Certificate) -> Result<()> {
+        self.save_and_sign_v2_internal(priv_key, cert, None)
+    }
+
+    /// Saves the ZIP central directory, while signing the APK with the V2 signature scheme.
+    /// The central directory headers are sorted by `order` of their entry names, lowest first. See [ZipFile::save_ordered].
+    pub fn save_and_sign_v2_ordered(
+        &mut self,
+        priv_key: &RsaPrivateKey,
+        cert: &Certificate,
+        order: impl Fn(&str) -> i32,
+    ) -> Result<()> {
+        self.save_and_sign_v2_internal(priv_key, cert, Some(&order))
+    }
+
+    fn save_and_sign_v2_internal(
+        &mut self,
+        priv_key: &RsaPrivateKey,
+        cert: &Certificate,
+        order: Option<&dyn Fn(&str) -> i32>,
+    ) -> Result<()> {
         let mut cd_bytes = Vec::new();
         let mut cd_cursor = Cursor::new(&mut cd_bytes);
 
-        for cd_header in self.entries.values() {
+        let cd_headers = match order {
+            Some(order) => headers_in_order(&self.entries, order),
+            None => self.entries.values().collect(),
+        };
+        for cd_header in cd_headers {
             cd_header.write(&mut cd_cursor)?;
         }
 
@@ -524,9 +1116,14 @@ impl ZipFile<File> {
             .stream_position()?
             .try_into()
             .context("APK file too big")?;
+        self.cent_dir_offset = eocd.cent_dir_offset;
         self.file.write_all(&cd_bytes)?;
         eocd.write(&mut self.file)?;
 
+        self.signing_block =
+            signing::read_raw_signing_block(&mut self.file, self.cent_dir_offset as u64)
+                .context("Reading back signing block")?;
+
         Ok(())
     }
 
@@ -535,14 +1132,48 @@ impl ZipFile<File> {
     /// The CD is NOT automatically saved on drop.
     /// Currently, this project doesn't save any ZIP files without signing them, but this is kept in-case this is needed in the future.
     #[allow(unused)]
-    pub fn save(mut self) -> Result<()> {
+    pub fn save(self) -> Result<()> {
+        self.save_internal(None)
+    }
+
+    /// Saves the ZIP central directory, with the headers sorted by `order` of their entry names, lowest first.
+    /// Entries given the same value by `order` are kept in the order that their data is stored in the archive.
+    /// The data of each entry is stored in the order that it was written with [ZipFile::write_file] or copied,
+    /// so writing entries in the same order as `order` places them in this order in both the data and the CD.
+    ///
+    /// If the APK Signing Block is kept, `order` must keep the headers in order of their data, or the kept signature
+    /// will be invalid.
+    pub fn save_ordered(self, order: impl Fn(&str) -> i32) -> Result<()> {
+        self.save_internal(Some(&order))
+    }
+
+    fn save_internal(mut self, order: Option<&dyn Fn(&str) -> i32>) -> Result<()> {
         // Remove existing CD and EOCD
         self.file.set_len(self.end_of_entries_offset as u64)?;
 
         self.file
             .seek(SeekFrom::Start(self.end_of_entries_offset as u64))?;
+        if let (true, Some(signing_block)) = (self.keep_signing_block, &self.signing_block) {
+            self.file
+                .write_all(signing_block)
+                .context("Saving APK Signing Block")?;
+        }
 
-        for cd_header in self.entries.values() {
+        // The signing block covers the central directory, so the headers must be kept in order of the entries they
+        // refer to, as is the case in almost all archives, for the kept signature to remain valid.
+        let cd_headers = match order {
+            Some(order) => headers_in_order(&self.entries, order),
+            None => {
+                let mut cd_headers: Vec<&CentDirHeader> = self.entries.values().collect();
+                if self.keep_signing_block {
+                    cd_headers.sort_by_key(|header| header.local_header_offset);
+                }
+                cd_headers
+            }
+        };
+
+        let cent_dir_offset = self.file.stream_position()?;
+        for cd_header in cd_headers {
             cd_header
                 .write(&mut self.file)
                 .context("Saving central directory header")?;
@@ -554,10 +1185,10 @@ impl ZipFile<File> {
                 .len()
                 .try_into()
                 .context("Too many ZIP entries")?,
-            cent_dir_size: (self.file.stream_position()? - self.end_of_entries_offset as u64)
+            cent_dir_size: (self.file.stream_position()? - cent_dir_offset)
                 .try_into()
                 .context("Central directory too big")?,
-            cent_dir_offset: self.end_of_entries_offset,
+            cent_dir_offset: cent_dir_offset.try_into().context("ZIP file too large")?,
             comment: Vec::new(),
         };
 
@@ -566,3 +1197,232 @@ impl ZipFile<File> {
         return Ok(());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Creates an empty archive in the temporary directory, returning the archive and its path.
+    fn create_test_archive(name: &str) -> (ZipFile<File>, PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("mbf-zip-test-{}-{name}.zip", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+
+        (ZipFile::create_new(file), path)
+    }
+
+    // Saves `archive`, then reopens it from `path`.
+    fn save_and_reopen(archive: ZipFile<File>, path: &Path) -> ZipFile<File> {
+        archive.save().unwrap();
+        ZipFile::open(File::open(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn deduplicated_entries_extract_under_both_names() {
+        let (mut archive, path) = create_test_archive("dedupe");
+        archive.set_dedupe(true);
+        for name in ["first.txt", "second.txt"] {
+            archive
+                .write_file(
+                    name,
+                    &mut Cursor::new(b"Same contents".to_vec()),
+                    FileCompression::Deflate,
+                )
+                .unwrap();
+        }
+        // Only the first file's data should have been written.
+        let end_of_entries = archive.end_of_entries_offset;
+        assert_eq!(
+            archive.entries["first.txt"].local_header_offset,
+            archive.entries["second.txt"].local_header_offset
+        );
+
+        let mut archive = save_and_reopen(archive, &path);
+        assert_eq!(archive.end_of_entries_offset, end_of_entries);
+        let extract_dir = path.with_extension("extracted");
+        archive
+            .extract_to_directory_parallel(&extract_dir, 2)
+            .unwrap();
+        for name in ["first.txt", "second.txt"] {
+            assert_eq!(archive.read_file(name).unwrap(), b"Same contents");
+            assert_eq!(
+                std::fs::read(extract_dir.join(name)).unwrap(),
+                b"Same contents"
+            );
+        }
+
+        std::fs::remove_dir_all(extract_dir).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dedupe_does_not_share_data_of_different_files() {
+        let (mut archive, path) = create_test_archive("dedupe-collision");
+        archive.set_dedupe(true);
+        archive
+            .write_file(
+                "first.txt",
+                &mut Cursor::new(b"First".to_vec()),
+                FileCompression::Store,
+            )
+            .unwrap();
+
+        // Simulate a CRC-32 collision by making the second file's CRC-32 and length map to the first file.
+        let second = b"Other".to_vec();
+        let second_crc = crc_of_stream(&second[..]).unwrap();
+        let first_header = archive.entries["first.txt"].clone();
+        archive
+            .dedupe_headers
+            .as_mut()
+            .unwrap()
+            .insert((second_crc, second.len() as u32), first_header);
+        archive
+            .write_file(
+                "second.txt",
+                &mut Cursor::new(second),
+                FileCompression::Store,
+            )
+            .unwrap();
+
+        let mut archive = save_and_reopen(archive, &path);
+        assert_eq!(archive.read_file("first.txt").unwrap(), b"First");
+        assert_eq!(archive.read_file("second.txt").unwrap(), b"Other");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn store_entries_are_aligned_for_any_name_length() {
+        let (mut archive, path) = create_test_archive("alignment");
+        archive.set_store_alignment(4);
+        let names = (1..=8).map(|len| "a".repeat(len)).collect::<Vec<_>>();
+        for name in &names {
+            archive
+                .write_file(
+                    name,
+                    &mut Cursor::new(name.as_bytes().to_vec()),
+                    FileCompression::Store,
+                )
+                .unwrap();
+        }
+
+        let mut archive = save_and_reopen(archive, &path);
+        for name in &names {
+            let lfh_offset = archive.entries[name].local_header_offset;
+            archive
+                .file
+                .seek(SeekFrom::Start(lfh_offset as u64))
+                .unwrap();
+            LocalFileHeader::read(&mut archive.file).unwrap();
+            let data_start = archive.file.stream_position().unwrap();
+            assert_eq!(data_start % 4, 0, "Data of {name} is not aligned");
+
+            assert_eq!(archive.read_file(name).unwrap(), name.as_bytes());
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // Builds an archive containing one STORE entry, whose lengths and CRC-32 are given in a data descriptor after its
+    // data rather than in its local file header, as written by streaming ZIP writers.
+    // Returns the archive and the offset of the first byte after the data descriptor.
+    fn build_data_descriptor_archive(contents: &[u8]) -> (Vec<u8>, u32) {
+        let crc32 = crc_bytes(contents);
+        let len = contents.len() as u32;
+
+        let mut data = Vec::new();
+        LocalFileHeader {
+            version_needed: VERSION_NEEDED_TO_EXTRACT,
+            flags: LocalFileHeader::DATA_DESCRIPTOR_FLAG,
+            compression_method: FileCompression::Store,
+            last_modified: 0,
+            crc32: 0,
+            compressed_len: 0,
+            uncompressed_len: 0,
+            file_name: "streamed.txt".to_string(),
+            extra_field: Vec::new(),
+        }
+        .write(&mut data)
+        .unwrap();
+        data.extend_from_slice(contents);
+        for field in [LocalFileHeader::DATA_DESCRIPTOR_HEADER, crc32, len, len] {
+            data.write_u32::<LE>(field).unwrap();
+        }
+
+        let cent_dir_offset = data.len() as u32;
+        CentDirHeader {
+            os_version_made_by: VERSION_MADE_BY,
+            version_needed: VERSION_NEEDED_TO_EXTRACT,
+            flags: LocalFileHeader::DATA_DESCRIPTOR_FLAG,
+            compression_method: FileCompression::Store,
+            last_modified: 0,
+            crc32,
+            compressed_len: len,
+            uncompressed_len: len,
+            file_name: "streamed.txt".to_string(),
+            extra_field: Vec::new(),
+            internal_attrs: 0,
+            external_attrs: 0,
+            local_header_offset: 0,
+            comment: String::new(),
+        }
+        .write(&mut data)
+        .unwrap();
+        let cent_dir_size = data.len() as u32 - cent_dir_offset;
+        EndOfCentDir {
+            cent_dir_records: 1,
+            cent_dir_size,
+            cent_dir_offset,
+            comment: Vec::new(),
+        }
+        .write(&mut data)
+        .unwrap();
+
+        (data, cent_dir_offset)
+    }
+
+    #[test]
+    fn entries_with_data_descriptors_are_kept_when_writing() {
+        let path = std::env::temp_dir().join(format!(
+            "mbf-zip-test-{}-data-descriptor.zip",
+            std::process::id()
+        ));
+        let (archive_data, descriptor_end) = build_data_descriptor_archive(b"Streamed contents");
+        std::fs::write(&path, &archive_data).unwrap();
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut archive = ZipFile::open(file).unwrap();
+        // New entries must be written after the data descriptor, not over it.
+        assert_eq!(archive.end_of_entries_offset, descriptor_end);
+        assert_eq!(
+            archive.read_file("streamed.txt").unwrap(),
+            b"Streamed contents"
+        );
+
+        archive
+            .write_file(
+                "new.txt",
+                &mut Cursor::new(b"New contents".to_vec()),
+                FileCompression::Deflate,
+            )
+            .unwrap();
+        let mut archive = save_and_reopen(archive, &path);
+        assert_eq!(
+            archive.read_file("streamed.txt").unwrap(),
+            b"Streamed contents"
+        );
+        assert_eq!(archive.read_file("new.txt").unwrap(), b"New contents");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
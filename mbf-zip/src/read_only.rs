@@ -0,0 +1,29 @@
+//! A file that can only be read from, used for archives opened with [ZipFile::open_readonly](super::ZipFile::open_readonly).
+
+use std::{
+    fs::File,
+    io::{Read, Result, Seek, SeekFrom},
+};
+
+/// A [File] opened with read-only permissions, which implements [Read] and [Seek] but not [Write](std::io::Write).
+/// The methods of [ZipFile](super::ZipFile) that modify an archive are only available for `ZipFile<File>`,
+/// so they cannot be called on an archive backed by a [ReadOnlyFile].
+pub struct ReadOnlyFile(File);
+
+impl ReadOnlyFile {
+    pub(crate) fn new(file: File) -> Self {
+        Self(file)
+    }
+}
+
+impl Read for ReadOnlyFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for ReadOnlyFile {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.0.seek(pos)
+    }
+}
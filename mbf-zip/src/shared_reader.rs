@@ -0,0 +1,53 @@
+//! A reader over a shared reference to a file, so that one file can be read by several threads at once.
+
+use std::{
+    fs::File,
+    io::{Read, Result, Seek, SeekFrom},
+};
+
+/// Reads from a [File] using positioned reads that do not change the file's cursor.
+/// Each reader keeps its own position, so several can be used at once on the same [File], unlike handles made with
+/// [File::try_clone], which all share one cursor.
+pub struct SharedFileReader<'a> {
+    file: &'a File,
+    position: u64,
+}
+
+impl<'a> SharedFileReader<'a> {
+    pub fn new(file: &'a File) -> Self {
+        Self { file, position: 0 }
+    }
+}
+
+impl Read for SharedFileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        #[cfg(unix)]
+        let bytes_read = std::os::unix::fs::FileExt::read_at(self.file, buf, self.position)?;
+        #[cfg(windows)]
+        let bytes_read = std::os::windows::fs::FileExt::seek_read(self.file, buf, self.position)?;
+
+        self.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for SharedFileReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.file.metadata()?.len().checked_add_signed(offset),
+        };
+
+        match new_position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seeked before the start of the file",
+            )),
+        }
+    }
+}
@@ -3,8 +3,8 @@
 //!
 //! V1 signatures are not supported, so this module cannot be used for APKs that will be installed on any Android version before 7.0.
 
-use anyhow::{Context, Result};
-use byteorder::{ByteOrder, WriteBytesExt, LE};
+use anyhow::{anyhow, Context, Result};
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, LE};
 use rasn_pkix::Certificate;
 use rsa::{
     pkcs1::DecodeRsaPrivateKey,
@@ -14,9 +14,11 @@ use rsa::{
 use std::{
     fs::File,
     io::{Cursor, Read, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
-use super::data::EndOfCentDir;
+use super::{data::EndOfCentDir, shared_reader::SharedFileReader};
 
 /// Writes the v2 signature block to the APK.
 /// The `apk` stream should be seeked to the first byte after the contents of the last ZIP entry.
@@ -75,7 +77,97 @@ pub fn load_cert_and_priv_key(pem_data: &[u8]) -> (Certificate, RsaPrivateKey) {
 const CHUNK_SIZE: u64 = 0x100000;
 const APK_SIG_BLOCK_FOOTER: [u8; 16] = *b"APK Sig Block 42";
 const RSA_PKCS1_15_SHA256: u32 = 0x0103;
-const V2_SIGNATURE_ID: u32 = 0x7109871a;
+/// ID of the V2 signature within the APK Signing Block.
+pub const V2_SIGNATURE_ID: u32 = 0x7109871a;
+/// ID of the V3 signature within the APK Signing Block.
+pub const V3_SIGNATURE_ID: u32 = 0xf05368c0;
+
+/// The contents of an APK Signing Block, which sits directly before the central directory of a signed APK.
+pub struct SigningBlock {
+    /// The ID-value pairs within the block, in the order they appear in the APK.
+    pub pairs: Vec<(u32, Vec<u8>)>,
+}
+
+impl SigningBlock {
+    /// Gets the value of the pair with the given ID, if there is one.
+    pub fn get(&self, id: u32) -> Option<&[u8]> {
+        self.pairs
+            .iter()
+            .find(|(pair_id, _)| *pair_id == id)
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+/// Reads the APK Signing Block preceding the central directory at `cent_dir_offset`.
+/// Returns `None` if there is no signing block, or an `Err` if one is present but is malformed.
+pub(super) fn read_signing_block(
+    apk: &mut (impl Read + Seek),
+    cent_dir_offset: u64,
+) -> Result<Option<SigningBlock>> {
+    let block = match read_raw_signing_block(apk, cent_dir_offset)? {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+
+    // Skip the length at the start, and stop before the length and footer at the end.
+    let pairs_end = (block.len() - 8 - APK_SIG_BLOCK_FOOTER.len()) as u64;
+    let mut reader = Cursor::new(&block);
+    reader.set_position(8);
+
+    let mut pairs = Vec::new();
+    while reader.position() < pairs_end {
+        let pair_len = reader.read_u64::<LE>()?;
+        if pair_len < 4 || reader.position() + pair_len > pairs_end {
+            return Err(anyhow!("Signing block pair had invalid length {pair_len}"));
+        }
+
+        let id = reader.read_u32::<LE>()?;
+        let mut value = vec![0u8; (pair_len - 4) as usize];
+        reader.read_exact(&mut value)?;
+        pairs.push((id, value));
+    }
+
+    Ok(Some(SigningBlock { pairs }))
+}
+
+/// Reads the bytes of the APK Signing Block preceding the central directory at `cent_dir_offset`, from the length
+/// at its start up to and including its footer.
+/// Returns `None` if there is no signing block, or an `Err` if the lengths of the block are invalid.
+pub(super) fn read_raw_signing_block(
+    apk: &mut (impl Read + Seek),
+    cent_dir_offset: u64,
+) -> Result<Option<Vec<u8>>> {
+    // The block ends with its length (8 bytes) followed by the 16 byte footer
+    let footer_len = 8 + APK_SIG_BLOCK_FOOTER.len() as u64;
+    if cent_dir_offset < footer_len {
+        return Ok(None);
+    }
+
+    apk.seek(SeekFrom::Start(cent_dir_offset - footer_len))?;
+    let block_len = apk.read_u64::<LE>()?;
+    let mut footer = [0u8; 16];
+    apk.read_exact(&mut footer)?;
+    if footer != APK_SIG_BLOCK_FOOTER {
+        return Ok(None);
+    }
+
+    // The length at the start/end of the block does not include the first length field.
+    let block_start = cent_dir_offset
+        .checked_sub(block_len + 8)
+        .filter(|_| block_len >= footer_len)
+        .ok_or(anyhow!("Signing block length {block_len} was invalid"))?;
+    apk.seek(SeekFrom::Start(block_start))?;
+    if apk.read_u64::<LE>()? != block_len {
+        return Err(anyhow!(
+            "Signing block lengths at start and end did not match"
+        ));
+    }
+
+    let mut block = vec![0u8; (block_len + 8) as usize];
+    apk.seek(SeekFrom::Start(block_start))?;
+    apk.read_exact(&mut block)?;
+    Ok(Some(block))
+}
 
 // Calculates the digest of contiguous data in a stream, using the chunked method described in the V2 signing documentation.
 // `chunk_buffer.len()` should match `CHUNK_SIZE`
@@ -115,6 +207,63 @@ fn calculate_chunked_digest(
     Ok(chunk_count)
 }
 
+// Calculates the digest of each chunk of the first `length` bytes of `file` in the same way as [calculate_chunked_digest],
+// but using up to `threads` threads, since the digest of each chunk does not depend on any other chunk.
+// The digests are written in order of the chunks, so the output is identical to that of [calculate_chunked_digest].
+fn calculate_chunked_digest_parallel(
+    file: &File,
+    length: u64,
+    output: &mut impl Write,
+    threads: usize,
+) -> Result<u32> {
+    let chunk_count = length.div_ceil(CHUNK_SIZE);
+    let next_chunk = AtomicU64::new(0);
+
+    let mut digests: Vec<(u64, [u8; 32])> = std::thread::scope(|scope| {
+        let workers = (0..threads.clamp(1, chunk_count.max(1) as usize))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut reader = SharedFileReader::new(file);
+                    let mut chunk_buffer = vec![0u8; CHUNK_SIZE as usize];
+                    let mut digests = Vec::new();
+                    loop {
+                        let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        if chunk >= chunk_count {
+                            return Ok::<_, anyhow::Error>(digests);
+                        }
+
+                        let offset = chunk * CHUNK_SIZE;
+                        let mut digest = [0u8; 32];
+                        calculate_chunked_digest(
+                            offset,
+                            CHUNK_SIZE.min(length - offset),
+                            &mut reader,
+                            &mut Cursor::new(&mut digest[..]),
+                            &mut chunk_buffer,
+                        )?;
+                        digests.push((chunk, digest));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Digest thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    digests.sort_unstable_by_key(|(chunk, _)| *chunk);
+    for (_, digest) in digests {
+        output.write_all(&digest)?;
+    }
+
+    Ok(chunk_count as u32)
+}
+
 // Calculates the digest of an APK, based on the chunked contents of the CD, EOCD and file headers/entries.
 fn calculate_apk_digest(
     apk: &mut File,
@@ -135,13 +284,19 @@ fn calculate_apk_digest(
     let mut eocd_stream = Cursor::new(eocd);
 
     // Add the digests of each chunk, keeping track of the overall chunk count
-    chunk_count += calculate_chunked_digest(
-        0,
-        entries_data_length,
-        apk,
-        &mut digests_stream,
-        &mut chunk_buffer,
-    )?;
+    // The entries make up almost all of the APK, so are digested on every available core if there is more than one.
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    chunk_count += if threads > 1 {
+        calculate_chunked_digest_parallel(apk, entries_data_length, &mut digests_stream, threads)?
+    } else {
+        calculate_chunked_digest(
+            0,
+            entries_data_length,
+            apk,
+            &mut digests_stream,
+            &mut chunk_buffer,
+        )?
+    };
     chunk_count += calculate_chunked_digest(
         0,
         central_dir.len() as u64,
@@ -2,4 +2,4 @@
 # To install it, run `cargo install cargo-watch`
 
 Write-Output "Waiting for agent modifications"
-cargo watch -w "$PSScriptRoot\mbf-agent\src\" -w "$PSScriptRoot\mbf-res-man\src\" -s "powershell $PSScriptRoot/build_agent.ps1"
\ No newline at end of file
+cargo watch -w "$PSScriptRoot\mbf-agent\src\" -w "$PSScriptRoot\mbf-res-man\src\" -w "$PSScriptRoot\mbf-axml\src\" -s "powershell $PSScriptRoot/build_agent.ps1"
\ No newline at end of file